@@ -7,7 +7,7 @@ use std::time::UNIX_EPOCH;
 use anyhow::{anyhow, bail, Context as _};
 use base64::prelude::*;
 use chrono::Local;
-use clap::{ArgGroup, Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
 use env_logger::Env;
 use futures::StreamExt;
@@ -15,33 +15,32 @@ use futures::{channel::oneshot, future, pin_mut};
 use mime_guess::mime::APPLICATION_OCTET_STREAM;
 use notify_rust::Notification;
 use presage::libsignal_service::configuration::SignalServers;
-use presage::libsignal_service::content::Reaction;
+use presage::libsignal_service::groups_v2::Role;
 use presage::libsignal_service::pre_keys::PreKeysStore;
 use presage::libsignal_service::prelude::phonenumber::PhoneNumber;
 use presage::libsignal_service::prelude::ProfileKey;
 use presage::libsignal_service::prelude::Uuid;
-use presage::libsignal_service::proto::data_message::Quote;
-use presage::libsignal_service::proto::sync_message::Sent;
 use presage::libsignal_service::protocol::ServiceId;
-use presage::libsignal_service::sender::AttachmentSpec;
 use presage::libsignal_service::zkgroup::GroupMasterKeyBytes;
 use presage::model::contacts::Contact;
-use presage::model::groups::Group;
+use presage::model::groups::{Group, GroupEvent, GroupJoin, GroupUpdate, InviteLink};
 use presage::model::identity::OnNewIdentity;
-use presage::model::messages::Received;
-use presage::proto::receipt_message;
-use presage::proto::EditMessage;
-use presage::proto::ReceiptMessage;
-use presage::proto::SyncMessage;
+use presage::model::messages::{CallEvent, Direction, Message, MessageBody, Quote, Received};
+use presage::proto::sync_message::call_event;
+use presage::proto::typing_message;
+use presage::store::migration::{migrate, MigrationReport};
+use presage::store::search::MessageSearch;
+use presage::store::threads::ThreadSummary;
 use presage::store::ContentExt;
 use presage::{
     libsignal_service::content::{Content, ContentBody, DataMessage, GroupContextV2},
-    manager::{Registered, RegistrationOptions},
+    manager::{AttachmentSource, CancelUpload, Registered, RegistrationOptions},
     store::{Store, Thread},
     Manager,
 };
 use presage_store_sled::MigrationConflictStrategy;
 use presage_store_sled::SledStore;
+use presage_store_sqlite::SqliteStore;
 use tempfile::Builder;
 use tempfile::TempDir;
 use tokio::{
@@ -133,11 +132,16 @@ enum Cmd {
     Receive {
         #[clap(long = "notifications", short = 'n')]
         notifications: bool,
+        /// Acknowledge received messages with delivery receipts
+        #[clap(long)]
+        delivery_receipts: bool,
     },
     #[clap(about = "List groups")]
     ListGroups,
     #[clap(about = "List contacts")]
     ListContacts,
+    #[clap(about = "List conversation threads, most recently active first")]
+    ListThreads,
     #[clap(
         about = "List messages",
         group(
@@ -159,6 +163,59 @@ enum Cmd {
         #[clap(long, help = "start from the following date (UNIX timestamp)")]
         from: Option<u64>,
     },
+    #[clap(
+        about = "Search messages containing all the given words",
+        group(
+            ArgGroup::new("search-thread")
+                .args(&["recipient_uuid", "group_master_key"])
+        )
+    )]
+    Search {
+        query: String,
+        #[clap(
+            long,
+            short = 'u',
+            help = "only search messages with this recipient UUID"
+        )]
+        recipient_uuid: Option<Uuid>,
+        #[clap(
+            long,
+            short = 'k',
+            help = "only search messages of the V2 group with this Master Key (hex string)",
+            value_parser = parse_group_master_key,
+        )]
+        group_master_key: Option<GroupMasterKeyBytes>,
+        #[clap(long, help = "only search messages sent by this UUID")]
+        sender: Option<Uuid>,
+        #[clap(long, help = "start from the following date (UNIX timestamp)")]
+        from: Option<u64>,
+        #[clap(long, help = "stop at the following date (UNIX timestamp)")]
+        until: Option<u64>,
+    },
+    #[clap(
+        about = "Mark messages of a thread as read, sending read receipts",
+        group(
+            ArgGroup::new("mark-read")
+                .required(true)
+                .args(&["recipient_uuid", "group_master_key"])
+        )
+    )]
+    MarkRead {
+        #[clap(long, short = 'u', help = "recipient UUID")]
+        recipient_uuid: Option<Uuid>,
+        #[clap(
+            long,
+            short = 'k',
+            help = "Master Key of the V2 group (hex string)",
+            value_parser = parse_group_master_key,
+        )]
+        group_master_key: Option<GroupMasterKeyBytes>,
+        #[clap(
+            long,
+            help = "mark messages up to this date as read (UNIX timestamp), defaults to the last message"
+        )]
+        until: Option<u64>,
+    },
     #[clap(about = "List downloaded sticker packs")]
     ListStickerPacks,
     #[clap(about = "Get a single contact by UUID")]
@@ -192,9 +249,39 @@ enum Cmd {
         #[clap(long = "attach", help = "Path to a file to attach, can be repeated")]
         attachment_filepath: Vec<PathBuf>,
     },
+    #[clap(about = "Join a group with its invite link, or ask to join it")]
+    JoinGroup {
+        #[clap(long, help = "Invite link of the group (https://signal.group/#...)", value_parser = parse_invite_link)]
+        link: InviteLink,
+    },
+    #[clap(about = "Leave a group")]
+    LeaveGroup {
+        #[clap(long, short = 'k', help = "Master Key of the V2 group (hex string)", value_parser = parse_group_master_key)]
+        master_key: GroupMasterKeyBytes,
+    },
     SyncContacts,
     #[clap(about = "Print various statistics useful for debugging")]
     Stats,
+    #[clap(
+        about = "Copy all data (keys, sessions, contacts, groups, messages, ...) to another store",
+        long_about = "Copy all data to another store, so an account can switch storage backends without linking again. The destination store must not be registered."
+    )]
+    Migrate {
+        #[clap(long, value_enum, help = "Backend of the store to migrate from")]
+        from: StoreBackend,
+        #[clap(long, help = "Path of the store to migrate from")]
+        from_path: PathBuf,
+        #[clap(long, value_enum, help = "Backend of the store to migrate to")]
+        to: StoreBackend,
+        #[clap(long, help = "Path of the store to migrate to")]
+        to_path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StoreBackend {
+    Sled,
+    Sqlite,
 }
 
 enum Recipient {
@@ -209,6 +296,10 @@ fn parse_group_master_key(value: &str) -> anyhow::Result<GroupMasterKeyBytes> {
         .map_err(|_| anyhow::format_err!("master key should be 32 bytes long"))
 }
 
+fn parse_invite_link(value: &str) -> anyhow::Result<InviteLink> {
+    Ok(InviteLink::parse(value)?)
+}
+
 fn attachments_tmp_dir() -> anyhow::Result<TempDir> {
     let attachments_tmp_dir = Builder::new().prefix("presage-attachments").tempdir()?;
     info!(
@@ -227,6 +318,16 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
+    if let Cmd::Migrate {
+        from,
+        from_path,
+        to,
+        to_path,
+    } = args.subcommand
+    {
+        return migrate_store(from, from_path, to, to_path, args.passphrase).await;
+    }
+
     let db_path = args.db_path.unwrap_or_else(|| {
         ProjectDirs::from("org", "whisperfish", "presage")
             .unwrap()
@@ -234,6 +335,9 @@ async fn main() -> anyhow::Result<()> {
             .into()
     });
     debug!(db_path =% db_path.display(), "opening config database");
+    // next to the database, which keeps where downloaded attachments are
+    let mut attachments_dir = db_path.clone().into_os_string();
+    attachments_dir.push("-attachments");
     let config_store = SledStore::open_with_passphrase(
         db_path,
         args.passphrase,
@@ -241,7 +345,60 @@ async fn main() -> anyhow::Result<()> {
         OnNewIdentity::Trust,
     )
     .await?;
-    run(args.subcommand, config_store).await
+    run(args.subcommand, config_store, attachments_dir.into()).await
+}
+
+async fn open_sled_store(
+    db_path: PathBuf,
+    passphrase: Option<String>,
+) -> anyhow::Result<SledStore> {
+    Ok(SledStore::open_with_passphrase(
+        db_path,
+        passphrase,
+        MigrationConflictStrategy::Raise,
+        OnNewIdentity::Trust,
+    )
+    .await?)
+}
+
+async fn migrate_store(
+    from: StoreBackend,
+    from_path: PathBuf,
+    to: StoreBackend,
+    to_path: PathBuf,
+    passphrase: Option<String>,
+) -> anyhow::Result<()> {
+    let report = match (from, to) {
+        (StoreBackend::Sled, StoreBackend::Sled) => {
+            let source = open_sled_store(from_path, passphrase.clone()).await?;
+            let mut destination = open_sled_store(to_path, passphrase).await?;
+            migrate(&source, &mut destination).await?
+        }
+        (StoreBackend::Sled, StoreBackend::Sqlite) => {
+            let source = open_sled_store(from_path, passphrase).await?;
+            let mut destination = SqliteStore::open(to_path, OnNewIdentity::Trust).await?;
+            migrate(&source, &mut destination).await?
+        }
+        (StoreBackend::Sqlite, StoreBackend::Sled) => {
+            let source = SqliteStore::open(from_path, OnNewIdentity::Trust).await?;
+            let mut destination = open_sled_store(to_path, passphrase).await?;
+            migrate(&source, &mut destination).await?
+        }
+        (StoreBackend::Sqlite, StoreBackend::Sqlite) => {
+            let source = SqliteStore::open(from_path, OnNewIdentity::Trust).await?;
+            let mut destination = SqliteStore::open(to_path, OnNewIdentity::Trust).await?;
+            migrate(&source, &mut destination).await?
+        }
+    };
+    print_migration_report(&report);
+    Ok(())
+}
+
+fn print_migration_report(report: &MigrationReport) {
+    println!("Migrated records:");
+    for (category, count) in report.iter() {
+        println!("  {category}: {count}");
+    }
 }
 
 async fn send<S: Store>(
@@ -272,9 +429,13 @@ async fn send<S: Store>(
     while let Some(content) = messages.next().await {
         match content {
             Received::QueueEmpty => break,
-            Received::Contacts => continue,
-            Received::Content(content) => {
-                process_incoming_message(manager, attachments_tmp_dir.path(), false, &content).await
+            Received::Contacts
+            | Received::Content(_)
+            | Received::Receipt { .. }
+            | Received::Typing { .. }
+            | Received::GroupUpdate { .. } => continue,
+            Received::Message(message) => {
+                process_incoming_message(manager, attachments_tmp_dir.path(), false, &message).await
             }
         }
     }
@@ -317,14 +478,14 @@ async fn process_incoming_message<S: Store>(
     manager: &mut Manager<S, Registered>,
     attachments_tmp_dir: &Path,
     notifications: bool,
-    content: &Content,
+    message: &Message,
 ) {
-    print_message(manager, notifications, content).await;
+    print_message(manager, notifications, message).await;
 
-    let sender = content.metadata.sender.raw_uuid();
-    if let ContentBody::DataMessage(DataMessage { attachments, .. }) = &content.body {
+    let sender = message.sender;
+    if let MessageBody::Attachment { attachments, .. } = &message.body {
         for attachment_pointer in attachments {
-            let Ok(attachment_data) = manager.get_attachment(attachment_pointer).await else {
+            let Ok(attachment) = manager.get_attachment(attachment_pointer).await else {
                 warn!("failed to fetch attachment");
                 continue;
             };
@@ -341,7 +502,7 @@ async fn process_incoming_message<S: Store>(
                 .clone()
                 .unwrap_or_else(|| Local::now().format("%Y-%m-%d-%H-%M-%s").to_string());
             let file_path = attachments_tmp_dir.join(format!("presage-{filename}.{extension}",));
-            match fs::write(&file_path, &attachment_data).await {
+            match fs::copy(&attachment.path, &file_path).await {
                 Ok(_) => info!(%sender, file_path =% file_path.display(), "saved attachment"),
                 Err(error) => error!(
                     %sender,
@@ -354,74 +515,166 @@ async fn process_incoming_message<S: Store>(
     }
 }
 
+async fn format_contact<S: Store>(uuid: &Uuid, manager: &Manager<S, Registered>) -> String {
+    manager
+        .store()
+        .contact_by_id(uuid)
+        .await
+        .ok()
+        .flatten()
+        .filter(|c| !c.name.is_empty())
+        .map(|c| format!("{}: {}", c.name, uuid))
+        .unwrap_or_else(|| uuid.to_string())
+}
+
+/// Describes a group update, like "Alice added Bob"
+async fn format_group_update<S: Store>(
+    update: &GroupUpdate,
+    manager: &Manager<S, Registered>,
+) -> String {
+    let editor = format_contact(&update.editor, manager).await;
+    let mut events = Vec::with_capacity(update.events.len());
+    for event in &update.events {
+        let event = match event {
+            GroupEvent::MemberAdded(uuid) => {
+                format!("added {}", format_contact(uuid, manager).await)
+            }
+            GroupEvent::MemberRemoved(uuid) if *uuid == update.editor => "left".to_string(),
+            GroupEvent::MemberRemoved(uuid) => {
+                format!("removed {}", format_contact(uuid, manager).await)
+            }
+            GroupEvent::MemberRoleChanged { uuid, role } => format!(
+                "made {} {}",
+                format_contact(uuid, manager).await,
+                if *role == Role::Administrator {
+                    "an admin"
+                } else {
+                    "a member"
+                }
+            ),
+            GroupEvent::MemberInvited(uuid) => {
+                format!("invited {}", format_contact(uuid, manager).await)
+            }
+            GroupEvent::InviteRevoked(uuid) => {
+                format!(
+                    "revoked the invite of {}",
+                    format_contact(uuid, manager).await
+                )
+            }
+            GroupEvent::InviteAccepted(_) => "accepted the invite".to_string(),
+            GroupEvent::JoinRequested(_) => "asked to join".to_string(),
+            GroupEvent::JoinRequestDenied(uuid) => format!(
+                "denied the request of {} to join",
+                format_contact(uuid, manager).await
+            ),
+            GroupEvent::JoinRequestApproved(uuid) => format!(
+                "approved the request of {} to join",
+                format_contact(uuid, manager).await
+            ),
+            GroupEvent::TitleChanged(title) => format!("changed the title to \"{title}\""),
+            GroupEvent::AvatarChanged => "changed the avatar".to_string(),
+            GroupEvent::DescriptionChanged(Some(description)) => {
+                format!("changed the description to \"{description}\"")
+            }
+            GroupEvent::DescriptionChanged(None) => "removed the description".to_string(),
+            GroupEvent::TimerChanged(Some(seconds)) => {
+                format!("set the disappearing messages timer to {seconds}s")
+            }
+            GroupEvent::TimerChanged(None) => "disabled disappearing messages".to_string(),
+            GroupEvent::AccessControlChanged => "changed who can edit the group".to_string(),
+            GroupEvent::InviteLinkReset => "reset the invite link".to_string(),
+            GroupEvent::AnnouncementsOnlyChanged(true) => {
+                "allowed only admins to send messages".to_string()
+            }
+            GroupEvent::AnnouncementsOnlyChanged(false) => {
+                "allowed all members to send messages".to_string()
+            }
+        };
+        events.push(event);
+    }
+
+    if events.is_empty() {
+        format!("{editor} updated the group to revision {}", update.revision)
+    } else {
+        format!("{editor} {}", events.join(", "))
+    }
+}
+
+/// Prints a message from the store, if it is one
+async fn print_stored_message<S: Store>(manager: &Manager<S, Registered>, content: &Content) {
+    let own_aci = manager.registration_data().service_ids.aci;
+    if let Some(message) = Message::from_content(content, own_aci) {
+        print_message(manager, false, &message).await;
+    }
+}
+
 async fn print_message<S: Store>(
     manager: &Manager<S, Registered>,
     notifications: bool,
-    content: &Content,
+    message: &Message,
 ) {
-    let Ok(thread) = Thread::try_from(content) else {
-        warn!("failed to derive thread from content");
-        return;
-    };
-
-    async fn format_data_message<S: Store>(
-        thread: &Thread,
-        data_message: &DataMessage,
-        manager: &Manager<S, Registered>,
-    ) -> Option<String> {
-        match data_message {
-            DataMessage {
+    fn format_body(body: &MessageBody) -> String {
+        match body {
+            MessageBody::Text {
+                text,
                 quote:
                     Some(Quote {
                         text: Some(quoted_text),
                         ..
                     }),
-                body: Some(body),
                 ..
-            } => Some(format!("Answer to message \"{quoted_text}\": {body}")),
-            DataMessage {
-                reaction:
-                    Some(Reaction {
-                        target_sent_timestamp: Some(ts),
-                        emoji: Some(emoji),
-                        ..
-                    }),
+            } => format!("Answer to message \"{quoted_text}\": {text}"),
+            MessageBody::Text { text, .. } => text.clone(),
+            MessageBody::Attachment {
+                attachments,
+                caption,
                 ..
             } => {
-                let Ok(Some(message)) = manager.store().message(thread, *ts).await else {
-                    warn!(%thread, sent_at = ts, "no message found in thread");
-                    return None;
-                };
-
-                let ContentBody::DataMessage(DataMessage {
-                    body: Some(body), ..
-                }) = message.body
-                else {
-                    warn!("message reacted to has no body");
-                    return None;
-                };
-
-                Some(format!("Reacted with {emoji} to message: \"{body}\""))
+                let caption = caption.as_deref().unwrap_or_default();
+                format!("{} attachment(s) {caption}", attachments.len())
+            }
+            MessageBody::Sticker(_) => "Sticker".to_string(),
+            MessageBody::Reaction {
+                emoji,
+                target_sent_timestamp,
+                remove: false,
+                ..
+            } => format!("Reacted with {emoji} to message sent at {target_sent_timestamp}"),
+            MessageBody::Reaction {
+                emoji,
+                target_sent_timestamp,
+                remove: true,
+                ..
+            } => format!("Removed reaction {emoji} to message sent at {target_sent_timestamp}"),
+            MessageBody::Edit { body, .. } => format!("Edited: {}", format_body(body)),
+            MessageBody::Delete {
+                target_sent_timestamp,
+            } => format!("Deleted message sent at {target_sent_timestamp}"),
+            MessageBody::GroupUpdate { revision } => {
+                format!("Group updated to revision {revision}")
+            }
+            MessageBody::Call(CallEvent::Offer { video: true, .. }) => "Video call".to_string(),
+            MessageBody::Call(CallEvent::Offer { video: false, .. }) => "Voice call".to_string(),
+            MessageBody::Call(CallEvent::Answer { .. }) => "Answered the call".to_string(),
+            MessageBody::Call(CallEvent::Busy { .. }) => "Busy in another call".to_string(),
+            MessageBody::Call(CallEvent::Hangup { .. }) => "Hung up".to_string(),
+            MessageBody::Call(CallEvent::Synced { event, .. }) => match event {
+                call_event::Event::Accepted => "Call accepted on another device".to_string(),
+                call_event::Event::NotAccepted => "Call declined on another device".to_string(),
+                call_event::Event::Delete => "Call deleted on another device".to_string(),
+                call_event::Event::Observed | call_event::Event::UnknownAction => {
+                    "Call ended".to_string()
+                }
+            },
+            MessageBody::ExpirationTimerUpdate { seconds: 0 } => {
+                "Disabled disappearing messages".to_string()
+            }
+            MessageBody::ExpirationTimerUpdate { seconds } => {
+                format!("Set disappearing messages timer to {seconds}s")
             }
-            DataMessage {
-                body: Some(body), ..
-            } => Some(body.to_string()),
-            _ => Some("Empty data message".to_string()),
         }
     }
 
-    async fn format_contact<S: Store>(uuid: &Uuid, manager: &Manager<S, Registered>) -> String {
-        manager
-            .store()
-            .contact_by_id(uuid)
-            .await
-            .ok()
-            .flatten()
-            .filter(|c| !c.name.is_empty())
-            .map(|c| format!("{}: {}", c.name, uuid))
-            .unwrap_or_else(|| uuid.to_string())
-    }
-
     async fn format_group<S: Store>(key: [u8; 32], manager: &Manager<S, Registered>) -> String {
         manager
             .store()
@@ -433,104 +686,88 @@ async fn print_message<S: Store>(
             .unwrap_or_else(|| "<missing group>".to_string())
     }
 
-    enum Msg<'a> {
-        Received(&'a Thread, String),
-        Sent(&'a Thread, String),
-    }
+    let Message {
+        thread,
+        sender,
+        timestamp: ts,
+        direction,
+        body,
+    } = message;
 
-    if let Some(msg) = match &content.body {
-        ContentBody::NullMessage(_) => Some(Msg::Received(
-            &thread,
-            "Null message (for example deleted)".to_string(),
-        )),
-        ContentBody::DataMessage(data_message) => {
-            format_data_message(&thread, data_message, manager)
-                .await
-                .map(|body| Msg::Received(&thread, body))
+    // show mentions with the names of the mentioned contacts
+    let mut body = body.clone();
+    if let MessageBody::Text {
+        text, body_ranges, ..
+    }
+    | MessageBody::Attachment {
+        caption: Some(text),
+        body_ranges,
+        ..
+    } = &mut body
+    {
+        match manager.render_mentions(text, body_ranges).await {
+            Ok(rendered) => *text = rendered,
+            Err(error) => warn!(%error, "failed to render mentions"),
         }
-        ContentBody::EditMessage(EditMessage {
-            data_message: Some(data_message),
-            ..
-        }) => format_data_message(&thread, data_message, manager)
-            .await
-            .map(|body| Msg::Received(&thread, body)),
-        ContentBody::EditMessage(EditMessage { .. }) => None,
-        ContentBody::SynchronizeMessage(SyncMessage {
-            sent:
-                Some(Sent {
-                    message: Some(data_message),
-                    ..
-                }),
-            ..
-        }) => format_data_message(&thread, data_message, manager)
-            .await
-            .map(|body| Msg::Sent(&thread, body)),
-        ContentBody::SynchronizeMessage(SyncMessage {
-            sent:
-                Some(Sent {
-                    edit_message:
-                        Some(EditMessage {
-                            data_message: Some(data_message),
-                            ..
-                        }),
-                    ..
-                }),
+    }
+
+    let body = match &body {
+        MessageBody::Reaction {
+            emoji,
+            target_sent_timestamp,
+            remove: false,
             ..
-        }) => format_data_message(&thread, data_message, manager)
+        } => match manager
+            .store()
+            .message(thread, *target_sent_timestamp)
             .await
-            .map(|body| Msg::Sent(&thread, body)),
-        ContentBody::SynchronizeMessage(SyncMessage { .. }) => None,
-        ContentBody::CallMessage(_) => Some(Msg::Received(&thread, "is calling!".into())),
-        ContentBody::TypingMessage(_) => Some(Msg::Received(&thread, "is typing...".into())),
-        ContentBody::ReceiptMessage(ReceiptMessage {
-            r#type: receipt_type,
-            timestamp,
-        }) => Some(Msg::Received(
-            &thread,
-            format!(
-                "got {:?} receipt for messages sent at {timestamp:?}",
-                receipt_message::Type::try_from(receipt_type.unwrap_or_default()).unwrap()
-            ),
-        )),
-        ContentBody::StoryMessage(story) => {
-            Some(Msg::Received(&thread, format!("new story: {story:?}")))
+        {
+            Ok(Some(target)) => match target.text() {
+                Some(text) => format!("Reacted with {emoji} to message: \"{text}\""),
+                None => format_body(&body),
+            },
+            _ => {
+                warn!(%thread, sent_at = target_sent_timestamp, "no message found in thread");
+                format_body(&body)
+            }
+        },
+        MessageBody::GroupUpdate { .. } => match manager.group_update(thread, *ts).await {
+            Ok(Some(update)) => format_group_update(&update, manager).await,
+            _ => format_body(&body),
+        },
+        body => format_body(body),
+    };
+
+    let prefix = match (thread, direction) {
+        (Thread::Contact(sender), Direction::Incoming) => {
+            let contact = format_contact(sender, manager).await;
+            format!("From {contact} @ {ts}: ")
         }
-        ContentBody::PniSignatureMessage(_) => {
-            Some(Msg::Received(&thread, "got PNI signature message".into()))
+        (Thread::Contact(recipient), Direction::Outgoing) => {
+            let contact = format_contact(recipient, manager).await;
+            format!("To {contact} @ {ts}")
         }
-    } {
-        let ts = content.timestamp();
-        let (prefix, body) = match msg {
-            Msg::Received(Thread::Contact(sender), body) => {
-                let contact = format_contact(sender, manager).await;
-                (format!("From {contact} @ {ts}: "), body)
-            }
-            Msg::Sent(Thread::Contact(recipient), body) => {
-                let contact = format_contact(recipient, manager).await;
-                (format!("To {contact} @ {ts}"), body)
-            }
-            Msg::Received(Thread::Group(key), body) => {
-                let sender = format_contact(&content.metadata.sender.raw_uuid(), manager).await;
-                let group = format_group(*key, manager).await;
-                (format!("From {sender} to group {group} @ {ts}: "), body)
-            }
-            Msg::Sent(Thread::Group(key), body) => {
-                let group = format_group(*key, manager).await;
-                (format!("To group {group} @ {ts}"), body)
-            }
-        };
+        (Thread::Group(key), Direction::Incoming) => {
+            let sender = format_contact(sender, manager).await;
+            let group = format_group(*key, manager).await;
+            format!("From {sender} to group {group} @ {ts}: ")
+        }
+        (Thread::Group(key), Direction::Outgoing) => {
+            let group = format_group(*key, manager).await;
+            format!("To group {group} @ {ts}")
+        }
+    };
 
-        println!("{prefix} / {body}");
+    println!("{prefix} / {body}");
 
-        if notifications {
-            if let Err(error) = Notification::new()
-                .summary(&prefix)
-                .body(&body)
-                .icon("presage")
-                .show()
-            {
-                error!(%error, "failed to display desktop notification");
-            }
+    if notifications {
+        if let Err(error) = Notification::new()
+            .summary(&prefix)
+            .body(&body)
+            .icon("presage")
+            .show()
+        {
+            error!(%error, "failed to display desktop notification");
         }
     }
 }
@@ -545,27 +782,72 @@ async fn receive<S: Store>(
         .await
         .context("failed to initialize messages stream")?;
     pin_mut!(messages);
+    let expired_messages = manager.expire_messages();
+    pin_mut!(expired_messages);
 
-    while let Some(content) = messages.next().await {
+    loop {
+        let content = tokio::select! {
+            content = messages.next() => match content {
+                Some(content) => content,
+                None => break,
+            },
+            Some(expired) = expired_messages.next() => {
+                println!(
+                    "{}: disappearing message sent at {} expired",
+                    expired.thread, expired.timestamp
+                );
+                continue;
+            }
+        };
         match content {
             Received::QueueEmpty => println!("done with synchronization"),
             Received::Contacts => println!("got contacts synchronization"),
-            Received::Content(content) => {
+            Received::Message(message) => {
                 process_incoming_message(
                     manager,
                     attachments_tmp_dir.path(),
                     notifications,
-                    &content,
+                    &message,
                 )
                 .await
             }
+            Received::Content(content) => debug!(?content, "skipping content"),
+            Received::Receipt {
+                recipient,
+                state,
+                messages,
+            } => {
+                for (thread, timestamp) in messages {
+                    println!("{thread}: message sent at {timestamp} is {state:?} by {recipient}");
+                }
+            }
+            Received::Typing {
+                thread,
+                sender,
+                action,
+            } => match action {
+                typing_message::Action::Started => println!("{thread}: {sender} is typing..."),
+                typing_message::Action::Stopped => println!("{thread}: {sender} stopped typing"),
+            },
+            Received::GroupUpdate {
+                thread,
+                timestamp,
+                update,
+            } => {
+                let update = format_group_update(&update, manager).await;
+                println!("{thread}: group updated @ {timestamp}: {update}");
+            }
         }
     }
 
     Ok(())
 }
 
-async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
+async fn run<S: Store>(
+    subcommand: Cmd,
+    config_store: S,
+    attachments_dir: PathBuf,
+) -> anyhow::Result<()> {
     match subcommand {
         Cmd::Register {
             servers,
@@ -665,8 +947,13 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
                 );
             }
         }
-        Cmd::Receive { notifications } => {
+        Cmd::Receive {
+            notifications,
+            delivery_receipts,
+        } => {
             let mut manager = Manager::load_registered(config_store).await?;
+            manager.set_attachments_dir(attachments_dir);
+            manager.set_delivery_receipts(delivery_receipts);
             receive(&mut manager, notifications).await?;
         }
         Cmd::Send {
@@ -675,6 +962,7 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
             attachment_filepath,
         } => {
             let mut manager = Manager::load_registered(config_store).await?;
+            manager.set_attachments_dir(attachments_dir);
             let attachments = upload_attachments(attachment_filepath, &manager).await?;
             let data_message = DataMessage {
                 body: Some(message),
@@ -690,6 +978,7 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
             attachment_filepath,
         } => {
             let mut manager = Manager::load_registered(config_store).await?;
+            manager.set_attachments_dir(attachments_dir);
             let attachments = upload_attachments(attachment_filepath, &manager).await?;
             let data_message = DataMessage {
                 body: Some(message),
@@ -732,6 +1021,26 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
             };
             println!("{profile:#?}");
         }
+        Cmd::JoinGroup { link } => {
+            let mut manager = Manager::load_registered(config_store).await?;
+            let preview = manager.group_join_info(&link).await?;
+            println!("{}: {} members", preview.title, preview.member_count);
+            match manager.join_group(&link).await? {
+                GroupJoin::Joined(group) => println!(
+                    "joined {} at revision {}",
+                    hex::encode(link.master_key),
+                    group.revision
+                ),
+                GroupJoin::Requested => {
+                    println!("asked to join, an administrator of the group needs to approve")
+                }
+            }
+        }
+        Cmd::LeaveGroup { master_key } => {
+            let mut manager = Manager::load_registered(config_store).await?;
+            let group = manager.leave_group(master_key).await?;
+            println!("left {}", group.title);
+        }
         Cmd::ListGroups => {
             let manager = Manager::load_registered(config_store).await?;
             for group in manager.store().groups().await? {
@@ -770,6 +1079,44 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
                 println!("{uuid} / {phone_number:?} / {name}");
             }
         }
+        Cmd::MarkRead {
+            recipient_uuid,
+            group_master_key,
+            until,
+        } => {
+            let mut manager = Manager::load_registered(config_store).await?;
+            let thread = match (group_master_key, recipient_uuid) {
+                (Some(master_key), _) => Thread::Group(master_key),
+                (_, Some(uuid)) => Thread::Contact(uuid),
+                _ => unreachable!(),
+            };
+            let until = match until {
+                Some(until) => until,
+                None => manager
+                    .store()
+                    .thread_activity(&thread)
+                    .await?
+                    .map(|activity| activity.last_message_timestamp)
+                    .unwrap_or_default(),
+            };
+            manager.mark_read(&thread, until).await?;
+        }
+        Cmd::ListThreads => {
+            let manager = Manager::load_registered(config_store).await?;
+            for summary in manager.store().threads().await?.flatten() {
+                let ThreadSummary {
+                    thread,
+                    title,
+                    activity,
+                } = summary;
+                println!(
+                    "{thread} / {title} / {} unread / {}: {}",
+                    activity.unread_count,
+                    activity.last_message_timestamp,
+                    activity.last_message_preview.unwrap_or_default(),
+                );
+            }
+        }
         Cmd::ListStickerPacks => {
             let manager = Manager::load_registered(config_store).await?;
             for sticker_pack in manager.store().sticker_packs().await? {
@@ -840,7 +1187,11 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
                 match content {
                     Received::QueueEmpty => break,
                     Received::Contacts => println!("got contacts! thank you, come again."),
-                    Received::Content(_) => print!("."),
+                    Received::Message(_)
+                    | Received::Content(_)
+                    | Received::Receipt { .. }
+                    | Received::Typing { .. }
+                    | Received::GroupUpdate { .. } => print!("."),
                 }
             }
         }
@@ -861,7 +1212,37 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
                 .await?
                 .filter_map(Result::ok)
             {
-                print_message(&manager, false, &msg).await;
+                print_stored_message(&manager, &msg).await;
+            }
+        }
+        Cmd::Search {
+            query,
+            recipient_uuid,
+            group_master_key,
+            sender,
+            from,
+            until,
+        } => {
+            let manager = Manager::load_registered(config_store).await?;
+            let thread = match (group_master_key, recipient_uuid) {
+                (Some(master_key), _) => Some(Thread::Group(master_key)),
+                (_, Some(uuid)) => Some(Thread::Contact(uuid)),
+                _ => None,
+            };
+            let search = MessageSearch {
+                query,
+                thread,
+                sender,
+                from,
+                until,
+            };
+            for (_, msg) in manager
+                .store()
+                .search_messages(&search)
+                .await?
+                .filter_map(Result::ok)
+            {
+                print_stored_message(&manager, &msg).await;
             }
         }
         Cmd::Stats => {
@@ -912,6 +1293,7 @@ async fn run<S: Store>(subcommand: Cmd, config_store: S) -> anyhow::Result<()> {
 
             println!("{stats:#?}")
         }
+        Cmd::Migrate { .. } => bail!("store migrations are run before opening any store"),
     }
     Ok(())
 }
@@ -920,39 +1302,26 @@ async fn upload_attachments<S: Store>(
     attachment_filepath: Vec<PathBuf>,
     manager: &Manager<S, Registered>,
 ) -> Result<Vec<presage::proto::AttachmentPointer>, anyhow::Error> {
-    let attachment_specs: Vec<_> = attachment_filepath
-        .into_iter()
-        .filter_map(|path| {
-            let data = std::fs::read(&path).ok()?;
-            Some((
-                AttachmentSpec {
-                    content_type: mime_guess::from_path(&path)
-                        .first()
-                        .unwrap_or(APPLICATION_OCTET_STREAM)
-                        .to_string(),
-                    length: data.len(),
-                    file_name: path.file_name().map(|s| s.to_string_lossy().to_string()),
-                    preview: None,
-                    voice_note: None,
-                    borderless: None,
-                    width: None,
-                    height: None,
-                    caption: None,
-                    blur_hash: None,
-                },
-                data,
-            ))
-        })
-        .collect();
-
-    let attachments: Result<Vec<_>, _> = manager
-        .upload_attachments(attachment_specs)
-        .await?
-        .into_iter()
-        .collect();
-
-    let attachments = attachments?;
-    Ok(attachments)
+    let cancel = CancelUpload::new();
+    let uploads = attachment_filepath.into_iter().map(|path| {
+        let cancel = &cancel;
+        async move {
+            let content_type = mime_guess::from_path(&path)
+                .first()
+                .unwrap_or(APPLICATION_OCTET_STREAM)
+                .to_string();
+            let source = AttachmentSource::from_file(&path, content_type)
+                .await
+                .with_context(|| format!("failed to open attachment {}", path.display()))?;
+            let progress = |progress| debug!(path = %path.display(), ?progress, "uploading");
+            let attachment = manager
+                .upload_attachment_from(source, progress, cancel)
+                .await?;
+            Ok::<_, anyhow::Error>(attachment)
+        }
+    });
+
+    future::try_join_all(uploads).await
 }
 
 fn parse_base64_profile_key(s: &str) -> anyhow::Result<ProfileKey> {
@@ -0,0 +1,597 @@
+use std::{collections::BTreeMap, ops::RangeBounds};
+
+use presage::{
+    libsignal_service::{
+        prelude::{Content, ProfileKey, Uuid},
+        zkgroup::GroupMasterKeyBytes,
+        Profile,
+    },
+    model::{
+        contacts::Contact,
+        groups::Group,
+        messages::{DeliveryState, MessageReaction},
+    },
+    store::{
+        expiry::ExpiringMessage,
+        search::MessageSearch,
+        threads::{ThreadActivity, ThreadSummary},
+        ContentExt, ContentsStore, StickerPack, Thread,
+    },
+    AvatarBytes,
+};
+use tracing::{debug, trace};
+
+use crate::{MemoryStore, MemoryStoreError};
+
+impl ContentsStore for MemoryStore {
+    type ContentsStoreError = MemoryStoreError;
+
+    type ContactsIter = std::vec::IntoIter<Result<Contact, Self::ContentsStoreError>>;
+
+    type GroupsIter =
+        std::vec::IntoIter<Result<(GroupMasterKeyBytes, Group), Self::ContentsStoreError>>;
+
+    type MessagesIter = std::vec::IntoIter<Result<Content, Self::ContentsStoreError>>;
+
+    type StickerPacksIter = std::vec::IntoIter<Result<StickerPack, Self::ContentsStoreError>>;
+
+    type ThreadsIter = std::vec::IntoIter<Result<ThreadSummary, Self::ContentsStoreError>>;
+
+    type ProfileKeysIter = std::vec::IntoIter<Result<(Uuid, ProfileKey), Self::ContentsStoreError>>;
+
+    type SearchResultsIter =
+        std::vec::IntoIter<Result<(Thread, Content), Self::ContentsStoreError>>;
+
+    type ExpiringMessagesIter =
+        std::vec::IntoIter<Result<ExpiringMessage, Self::ContentsStoreError>>;
+
+    async fn clear_profiles(&mut self) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        data.profiles.clear();
+        data.profile_keys.clear();
+        data.profile_avatars.clear();
+        Ok(())
+    }
+
+    async fn clear_contents(&mut self) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        data.contacts.clear();
+        data.groups.clear();
+        data.threads.clear();
+        data.thread_activity.clear();
+        data.delivery_states.clear();
+        data.expiries.clear();
+        data.reactions.clear();
+        data.revisions.clear();
+        Ok(())
+    }
+
+    async fn clear_messages(&mut self) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        data.threads.clear();
+        data.thread_activity.clear();
+        data.delivery_states.clear();
+        data.expiries.clear();
+        data.reactions.clear();
+        data.revisions.clear();
+        Ok(())
+    }
+
+    async fn clear_thread(&mut self, thread: &Thread) -> Result<(), Self::ContentsStoreError> {
+        trace!(%thread, "clearing thread");
+        let mut data = self.write();
+        data.threads.remove(thread);
+        data.thread_activity.remove(thread);
+        data.delivery_states.remove(thread);
+        data.expiries.remove(thread);
+        data.reactions.remove(thread);
+        data.revisions.remove(thread);
+        Ok(())
+    }
+
+    async fn save_message(
+        &self,
+        thread: &Thread,
+        message: Content,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let ts = message.timestamp();
+        trace!(%thread, ts, "storing a message with thread");
+        let mut data = self.write();
+        let own_aci = data.registration.as_ref().map(|r| r.service_ids.aci);
+        let mut activity = data.thread_activity.remove(thread).unwrap_or_default();
+        let replaced = data
+            .threads
+            .entry(thread.clone())
+            .or_default()
+            .insert(ts, message.clone())
+            .is_some();
+        activity.record(&message, replaced, own_aci);
+        data.thread_activity.insert(thread.clone(), activity);
+        Ok(())
+    }
+
+    async fn delete_message(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<bool, Self::ContentsStoreError> {
+        let mut data = self.write();
+        let Some(messages) = data.threads.get_mut(thread) else {
+            return Ok(false);
+        };
+        if messages.remove(&timestamp).is_none() {
+            return Ok(false);
+        }
+        let remaining = messages.values().next_back().cloned();
+        if let Some(delivery_states) = data.delivery_states.get_mut(thread) {
+            delivery_states.remove(&timestamp);
+        }
+        if let Some(expiries) = data.expiries.get_mut(thread) {
+            expiries.remove(&timestamp);
+        }
+        if let Some(reactions) = data.reactions.get_mut(thread) {
+            reactions.remove(&timestamp);
+        }
+        if let Some(revisions) = data.revisions.get_mut(thread) {
+            revisions.remove(&timestamp);
+        }
+        if let Some(activity) = data.thread_activity.get_mut(thread) {
+            if activity.last_message_timestamp == timestamp {
+                activity.set_last_message(remaining.as_ref());
+            }
+        }
+        Ok(true)
+    }
+
+    async fn message(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<Option<Content>, Self::ContentsStoreError> {
+        Ok(self
+            .read()
+            .threads
+            .get(thread)
+            .and_then(|messages| messages.get(&timestamp))
+            .cloned())
+    }
+
+    async fn messages(
+        &self,
+        thread: &Thread,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Self::MessagesIter, Self::ContentsStoreError> {
+        let messages: Vec<_> = self
+            .read()
+            .threads
+            .get(thread)
+            .map(|messages| {
+                messages
+                    .range(range)
+                    .map(|(_, message)| Ok(message.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        debug!(%thread, count = messages.len(), "loaded messages");
+        Ok(messages.into_iter())
+    }
+
+    async fn threads(&self) -> Result<Self::ThreadsIter, Self::ContentsStoreError> {
+        let activities: Vec<_> = {
+            let data = self.read();
+            data.threads
+                .iter()
+                .filter(|(_, messages)| !messages.is_empty())
+                .map(|(thread, _)| (thread.clone(), data.thread_activity.get(thread).cloned()))
+                .collect()
+        };
+        let mut threads = Vec::with_capacity(activities.len());
+        for (thread, activity) in activities {
+            let title = self.thread_title(&thread).await?;
+            threads.push(ThreadSummary {
+                thread,
+                title,
+                activity: activity.unwrap_or_default(),
+            });
+        }
+        threads.sort_by_key(|summary| std::cmp::Reverse(summary.activity.last_message_timestamp));
+        Ok(threads.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
+    async fn delivery_states(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, DeliveryState>, Self::ContentsStoreError> {
+        Ok(self
+            .read()
+            .delivery_states
+            .get(thread)
+            .and_then(|messages| messages.get(&timestamp))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn update_delivery_state(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        recipient: Uuid,
+        state: DeliveryState,
+    ) -> Result<bool, Self::ContentsStoreError> {
+        let mut data = self.write();
+        let states = data
+            .delivery_states
+            .entry(thread.clone())
+            .or_default()
+            .entry(timestamp)
+            .or_default();
+        if states
+            .get(&recipient)
+            .is_some_and(|current| *current >= state)
+        {
+            return Ok(false);
+        }
+        states.insert(recipient, state);
+        Ok(true)
+    }
+
+    async fn reactions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, MessageReaction>, Self::ContentsStoreError> {
+        Ok(self
+            .read()
+            .reactions
+            .get(thread)
+            .and_then(|messages| messages.get(&timestamp))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn update_reaction(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        author: Uuid,
+        reaction: Option<MessageReaction>,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        let reactions = data
+            .reactions
+            .entry(thread.clone())
+            .or_default()
+            .entry(timestamp)
+            .or_default();
+        match reaction {
+            Some(reaction) => {
+                reactions.insert(author, reaction);
+            }
+            None => {
+                reactions.remove(&author);
+            }
+        }
+        Ok(())
+    }
+
+    async fn message_revisions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<Vec<Content>, Self::ContentsStoreError> {
+        Ok(self
+            .read()
+            .revisions
+            .get(thread)
+            .and_then(|messages| messages.get(&timestamp))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn add_message_revision(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        revision: Content,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        let is_saved = data
+            .threads
+            .get(thread)
+            .is_some_and(|messages| messages.contains_key(&timestamp));
+        if is_saved {
+            data.revisions
+                .entry(thread.clone())
+                .or_default()
+                .entry(timestamp)
+                .or_default()
+                .push(revision);
+        }
+        Ok(())
+    }
+
+    async fn set_message_expiry(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        expires_at: u64,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        let is_saved = data
+            .threads
+            .get(thread)
+            .is_some_and(|messages| messages.contains_key(&timestamp));
+        if is_saved {
+            data.expiries
+                .entry(thread.clone())
+                .or_default()
+                .entry(timestamp)
+                .or_insert(expires_at);
+        }
+        Ok(())
+    }
+
+    async fn expiring_messages(
+        &self,
+    ) -> Result<Self::ExpiringMessagesIter, Self::ContentsStoreError> {
+        let mut messages: Vec<_> = self
+            .read()
+            .expiries
+            .iter()
+            .flat_map(|(thread, expiries)| {
+                expiries
+                    .iter()
+                    .map(|(&timestamp, &expires_at)| ExpiringMessage {
+                        thread: thread.clone(),
+                        timestamp,
+                        expires_at,
+                    })
+            })
+            .collect();
+        messages.sort_by_key(|message| (message.expires_at, message.timestamp));
+        Ok(messages.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
+    async fn thread_activity(
+        &self,
+        thread: &Thread,
+    ) -> Result<Option<ThreadActivity>, Self::ContentsStoreError> {
+        Ok(self.read().thread_activity.get(thread).cloned())
+    }
+
+    async fn mark_thread_read(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let mut data = self.write();
+        let own_aci = data.registration.as_ref().map(|r| r.service_ids.aci);
+        let data = &mut *data;
+        let Some(activity) = data.thread_activity.get_mut(thread) else {
+            return Ok(());
+        };
+        let later_messages = data.threads.get(thread).into_iter().flat_map(|messages| {
+            messages
+                .range(timestamp.saturating_add(1)..)
+                .map(|(_, m)| m)
+        });
+        activity.mark_read(timestamp, later_messages, own_aci);
+        trace!(%thread, timestamp, unread_count = activity.unread_count, "marked thread as read");
+        Ok(())
+    }
+
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+    ) -> Result<Self::SearchResultsIter, Self::ContentsStoreError> {
+        let data = self.read();
+        let mut results: Vec<(Thread, Content)> = data
+            .threads
+            .iter()
+            .filter(|(thread, _)| search.thread.as_ref().map_or(true, |t| t == *thread))
+            .flat_map(|(thread, messages)| {
+                messages
+                    .values()
+                    .filter(|message| search.matches(thread, message))
+                    .map(|message| (thread.clone(), message.clone()))
+            })
+            .collect();
+        results.sort_by_key(|(_, message)| std::cmp::Reverse(message.timestamp()));
+        debug!(count = results.len(), "found messages");
+        Ok(results.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
+    async fn clear_contacts(&mut self) -> Result<(), Self::ContentsStoreError> {
+        self.write().contacts.clear();
+        Ok(())
+    }
+
+    async fn save_contact(&mut self, contact: &Contact) -> Result<(), Self::ContentsStoreError> {
+        self.write()
+            .contacts
+            .insert(contact.uuid, copy_contact(contact));
+        debug!("saved contact");
+        Ok(())
+    }
+
+    async fn contacts(&self) -> Result<Self::ContactsIter, Self::ContentsStoreError> {
+        let contacts: Vec<_> = self
+            .read()
+            .contacts
+            .values()
+            .map(|contact| Ok(copy_contact(contact)))
+            .collect();
+        Ok(contacts.into_iter())
+    }
+
+    async fn contact_by_id(&self, id: &Uuid) -> Result<Option<Contact>, Self::ContentsStoreError> {
+        Ok(self.read().contacts.get(id).map(copy_contact))
+    }
+
+    async fn clear_groups(&mut self) -> Result<(), Self::ContentsStoreError> {
+        self.write().groups.clear();
+        Ok(())
+    }
+
+    async fn save_group(
+        &self,
+        master_key: GroupMasterKeyBytes,
+        group: impl Into<Group>,
+    ) -> Result<(), Self::ContentsStoreError> {
+        self.write().groups.insert(master_key, group.into());
+        Ok(())
+    }
+
+    async fn groups(&self) -> Result<Self::GroupsIter, Self::ContentsStoreError> {
+        let groups: Vec<_> = self
+            .read()
+            .groups
+            .iter()
+            .map(|(master_key, group)| Ok((*master_key, group.clone())))
+            .collect();
+        Ok(groups.into_iter())
+    }
+
+    async fn group(
+        &self,
+        master_key: GroupMasterKeyBytes,
+    ) -> Result<Option<Group>, Self::ContentsStoreError> {
+        Ok(self.read().groups.get(&master_key).cloned())
+    }
+
+    async fn save_group_avatar(
+        &self,
+        master_key: GroupMasterKeyBytes,
+        avatar: &AvatarBytes,
+    ) -> Result<(), Self::ContentsStoreError> {
+        self.write()
+            .group_avatars
+            .insert(master_key, avatar.clone());
+        Ok(())
+    }
+
+    async fn group_avatar(
+        &self,
+        master_key: GroupMasterKeyBytes,
+    ) -> Result<Option<AvatarBytes>, Self::ContentsStoreError> {
+        Ok(self.read().group_avatars.get(&master_key).cloned())
+    }
+
+    async fn upsert_profile_key(
+        &mut self,
+        uuid: &Uuid,
+        key: ProfileKey,
+    ) -> Result<bool, Self::ContentsStoreError> {
+        Ok(self.write().profile_keys.insert(*uuid, key).is_some())
+    }
+
+    async fn profile_key(
+        &self,
+        uuid: &Uuid,
+    ) -> Result<Option<ProfileKey>, Self::ContentsStoreError> {
+        Ok(self.read().profile_keys.get(uuid).copied())
+    }
+
+    async fn profile_keys(&self) -> Result<Self::ProfileKeysIter, Self::ContentsStoreError> {
+        let profile_keys: Vec<_> = self
+            .read()
+            .profile_keys
+            .iter()
+            .map(|(uuid, key)| Ok((*uuid, *key)))
+            .collect();
+        Ok(profile_keys.into_iter())
+    }
+
+    async fn save_profile(
+        &mut self,
+        uuid: Uuid,
+        key: ProfileKey,
+        profile: Profile,
+    ) -> Result<(), Self::ContentsStoreError> {
+        self.write()
+            .profiles
+            .insert((uuid, key.get_bytes()), profile);
+        Ok(())
+    }
+
+    async fn profile(
+        &self,
+        uuid: Uuid,
+        key: ProfileKey,
+    ) -> Result<Option<Profile>, Self::ContentsStoreError> {
+        Ok(self.read().profiles.get(&(uuid, key.get_bytes())).cloned())
+    }
+
+    async fn save_profile_avatar(
+        &mut self,
+        uuid: Uuid,
+        key: ProfileKey,
+        avatar: &AvatarBytes,
+    ) -> Result<(), Self::ContentsStoreError> {
+        self.write()
+            .profile_avatars
+            .insert((uuid, key.get_bytes()), avatar.clone());
+        Ok(())
+    }
+
+    async fn profile_avatar(
+        &self,
+        uuid: Uuid,
+        key: ProfileKey,
+    ) -> Result<Option<AvatarBytes>, Self::ContentsStoreError> {
+        Ok(self
+            .read()
+            .profile_avatars
+            .get(&(uuid, key.get_bytes()))
+            .cloned())
+    }
+
+    async fn add_sticker_pack(
+        &mut self,
+        pack: &StickerPack,
+    ) -> Result<(), Self::ContentsStoreError> {
+        self.write()
+            .sticker_packs
+            .insert(pack.id.clone(), pack.clone());
+        Ok(())
+    }
+
+    async fn sticker_pack(
+        &self,
+        id: &[u8],
+    ) -> Result<Option<StickerPack>, Self::ContentsStoreError> {
+        Ok(self.read().sticker_packs.get(id).cloned())
+    }
+
+    async fn remove_sticker_pack(&mut self, id: &[u8]) -> Result<bool, Self::ContentsStoreError> {
+        Ok(self.write().sticker_packs.remove(id).is_some())
+    }
+
+    async fn sticker_packs(&self) -> Result<Self::StickerPacksIter, Self::ContentsStoreError> {
+        let packs: Vec<_> = self
+            .read()
+            .sticker_packs
+            .values()
+            .map(|pack| Ok(pack.clone()))
+            .collect();
+        Ok(packs.into_iter())
+    }
+}
+
+/// Copies a contact, except for its avatar attachment which no store keeps around
+fn copy_contact(contact: &Contact) -> Contact {
+    Contact {
+        uuid: contact.uuid,
+        phone_number: contact.phone_number.clone(),
+        name: contact.name.clone(),
+        color: contact.color.clone(),
+        verified: contact.verified.clone(),
+        profile_key: contact.profile_key.clone(),
+        expire_timer: contact.expire_timer,
+        expire_timer_version: contact.expire_timer_version,
+        inbox_position: contact.inbox_position,
+        archived: contact.archived,
+        avatar: None,
+    }
+}
@@ -0,0 +1,17 @@
+use presage::{libsignal_service::protocol::SignalProtocolError, store::StoreError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryStoreError {
+    #[error("libsignal-protocol error: {0}")]
+    Protocol(#[from] SignalProtocolError),
+}
+
+impl StoreError for MemoryStoreError {}
+
+impl From<MemoryStoreError> for SignalProtocolError {
+    fn from(error: MemoryStoreError) -> Self {
+        match error {
+            MemoryStoreError::Protocol(error) => error,
+        }
+    }
+}
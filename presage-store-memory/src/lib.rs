@@ -0,0 +1,219 @@
+//! A [`Store`] keeping everything in memory
+//!
+//! Nothing is ever persisted: all data is lost when the last clone of a [`MemoryStore`] is
+//! dropped. This is useful for tests and short-lived clients that do not need a database.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use presage::{
+    libsignal_service::{
+        prelude::{Content, ProfileKey, Uuid},
+        protocol::IdentityKeyPair,
+        zkgroup::GroupMasterKeyBytes,
+        Profile,
+    },
+    manager::RegistrationData,
+    model::{
+        contacts::Contact,
+        groups::Group,
+        identity::OnNewIdentity,
+        messages::{DeliveryState, MessageReaction},
+    },
+    store::{
+        attachments::{AttachmentId, DownloadedAttachment},
+        threads::ThreadActivity,
+        AttachmentsStore, ContentsStore, StateStore, StickerPack, Store, Thread,
+    },
+    AvatarBytes,
+};
+use protocol::{IdentityType, MemoryProtocolStore, ProtocolData};
+
+mod content;
+mod error;
+mod protocol;
+
+pub use error::MemoryStoreError;
+
+#[derive(Clone)]
+pub struct MemoryStore {
+    data: Arc<RwLock<MemoryData>>,
+    /// Whether to trust new identities automatically (for instance, when a somebody's phone has changed)
+    trust_new_identities: OnNewIdentity,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    registration: Option<RegistrationData>,
+    aci: ProtocolData,
+    pni: ProtocolData,
+
+    contacts: BTreeMap<Uuid, Contact>,
+    groups: BTreeMap<GroupMasterKeyBytes, Group>,
+    group_avatars: HashMap<GroupMasterKeyBytes, AvatarBytes>,
+    threads: HashMap<Thread, BTreeMap<u64, Content>>,
+    thread_activity: HashMap<Thread, ThreadActivity>,
+    delivery_states: HashMap<Thread, BTreeMap<u64, BTreeMap<Uuid, DeliveryState>>>,
+    expiries: HashMap<Thread, BTreeMap<u64, u64>>,
+    reactions: HashMap<Thread, BTreeMap<u64, BTreeMap<Uuid, MessageReaction>>>,
+    revisions: HashMap<Thread, BTreeMap<u64, Vec<Content>>>,
+    profile_keys: HashMap<Uuid, ProfileKey>,
+    profiles: HashMap<(Uuid, [u8; 32]), Profile>,
+    profile_avatars: HashMap<(Uuid, [u8; 32]), AvatarBytes>,
+    sticker_packs: BTreeMap<Vec<u8>, StickerPack>,
+    attachments: HashMap<AttachmentId, DownloadedAttachment>,
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("trust_new_identities", &self.trust_new_identities)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MemoryStore {
+    /// Creates a new empty store
+    pub fn new(trust_new_identities: OnNewIdentity) -> Self {
+        Self {
+            data: Default::default(),
+            trust_new_identities,
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<MemoryData> {
+        self.data.read().expect("poisoned rwlock")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<MemoryData> {
+        self.data.write().expect("poisoned rwlock")
+    }
+}
+
+impl Store for MemoryStore {
+    type Error = MemoryStoreError;
+
+    type AciStore = MemoryProtocolStore;
+
+    type PniStore = MemoryProtocolStore;
+
+    async fn clear(&mut self) -> Result<(), MemoryStoreError> {
+        self.clear_registration().await?;
+        self.clear_contents().await?;
+        self.clear_attachments().await?;
+
+        Ok(())
+    }
+
+    fn aci_protocol_store(&self) -> Self::AciStore {
+        MemoryProtocolStore {
+            store: self.clone(),
+            identity: IdentityType::Aci,
+        }
+    }
+
+    fn pni_protocol_store(&self) -> Self::PniStore {
+        MemoryProtocolStore {
+            store: self.clone(),
+            identity: IdentityType::Pni,
+        }
+    }
+}
+
+impl StateStore for MemoryStore {
+    type StateStoreError = MemoryStoreError;
+
+    async fn load_registration_data(
+        &self,
+    ) -> Result<Option<RegistrationData>, Self::StateStoreError> {
+        Ok(self.read().registration.clone())
+    }
+
+    async fn set_aci_identity_key_pair(
+        &self,
+        key_pair: IdentityKeyPair,
+    ) -> Result<(), Self::StateStoreError> {
+        self.write().aci.identity_key_pair = Some(key_pair);
+        Ok(())
+    }
+
+    async fn set_pni_identity_key_pair(
+        &self,
+        key_pair: IdentityKeyPair,
+    ) -> Result<(), Self::StateStoreError> {
+        self.write().pni.identity_key_pair = Some(key_pair);
+        Ok(())
+    }
+
+    async fn save_registration_data(
+        &mut self,
+        state: &RegistrationData,
+    ) -> Result<(), Self::StateStoreError> {
+        self.write().registration = Some(state.clone());
+        Ok(())
+    }
+
+    async fn is_registered(&self) -> bool {
+        self.read().registration.is_some()
+    }
+
+    async fn clear_registration(&mut self) -> Result<(), Self::StateStoreError> {
+        // drop registration data and all keys
+        {
+            let mut data = self.write();
+            data.registration = None;
+            data.aci.clear();
+            data.pni.clear();
+        }
+
+        // drop all saved profile (+avatars) and profile keys
+        self.clear_profiles().await?;
+
+        Ok(())
+    }
+}
+
+impl AttachmentsStore for MemoryStore {
+    type AttachmentsStoreError = MemoryStoreError;
+
+    async fn attachment(
+        &self,
+        id: &AttachmentId,
+    ) -> Result<Option<DownloadedAttachment>, MemoryStoreError> {
+        Ok(self.read().attachments.get(id).cloned())
+    }
+
+    async fn save_attachment(
+        &mut self,
+        id: &AttachmentId,
+        attachment: &DownloadedAttachment,
+    ) -> Result<(), MemoryStoreError> {
+        self.write()
+            .attachments
+            .insert(id.clone(), attachment.clone());
+        Ok(())
+    }
+
+    async fn remove_attachment(&mut self, id: &AttachmentId) -> Result<bool, MemoryStoreError> {
+        Ok(self.write().attachments.remove(id).is_some())
+    }
+
+    async fn clear_attachments(&mut self) -> Result<(), MemoryStoreError> {
+        self.write().attachments.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_conformance() {
+        presage::store::testing::run_all(|| async { MemoryStore::new(OnNewIdentity::Reject) })
+            .await;
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use presage::{
+    libsignal_service::{
+        pre_keys::{KyberPreKeyStoreExt, PreKeysStore},
+        prelude::{IdentityKeyStore, SessionStoreExt, Uuid},
+        protocol::{
+            Direction, IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord,
+            KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeyStore, ProtocolAddress, ProtocolStore,
+            SenderKeyRecord, SenderKeyStore, ServiceId, SessionRecord, SessionStore,
+            SignalProtocolError as ProtocolError, SignedPreKeyId, SignedPreKeyRecord,
+            SignedPreKeyStore,
+        },
+        push_service::DEFAULT_DEVICE_ID,
+    },
+    model::identity::OnNewIdentity,
+    proto::verified,
+    store::{save_trusted_identity_message, ProtocolStoreExt, StateStore},
+};
+use tracing::{trace, warn};
+
+use crate::MemoryStore;
+
+/// Which of our identities a protocol store is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityType {
+    Aci,
+    Pni,
+}
+
+/// Protocol data of one of our identities
+#[derive(Default)]
+pub(crate) struct ProtocolData {
+    pub(crate) identity_key_pair: Option<IdentityKeyPair>,
+    /// Serialized session records by address name and device id
+    sessions: BTreeMap<(String, u32), Vec<u8>>,
+    identities: HashMap<(String, u32), IdentityKey>,
+    pre_keys: BTreeMap<u32, PreKeyRecord>,
+    signed_pre_keys: BTreeMap<u32, SignedPreKeyRecord>,
+    kyber_pre_keys: BTreeMap<u32, KyberPreKey>,
+    /// Serialized sender key records by address name, device id and distribution id
+    sender_keys: HashMap<(String, u32, Uuid), Vec<u8>>,
+}
+
+impl ProtocolData {
+    /// Drops our keys and sessions, but keeps the identities of others, like the sled store
+    pub(crate) fn clear(&mut self) {
+        *self = Self {
+            identities: std::mem::take(&mut self.identities),
+            ..Default::default()
+        };
+    }
+}
+
+struct KyberPreKey {
+    record: KyberPreKeyRecord,
+    is_last_resort: bool,
+    stale_at: Option<DateTime<Utc>>,
+}
+
+fn address_key(address: &ProtocolAddress) -> (String, u32) {
+    (address.name().to_owned(), address.device_id().into())
+}
+
+#[derive(Clone)]
+pub struct MemoryProtocolStore {
+    pub(crate) store: MemoryStore,
+    pub(crate) identity: IdentityType,
+}
+
+impl MemoryProtocolStore {
+    fn with_data<R>(&self, f: impl FnOnce(&ProtocolData) -> R) -> R {
+        let data = self.store.read();
+        match self.identity {
+            IdentityType::Aci => f(&data.aci),
+            IdentityType::Pni => f(&data.pni),
+        }
+    }
+
+    fn with_data_mut<R>(&self, f: impl FnOnce(&mut ProtocolData) -> R) -> R {
+        let mut data = self.store.write();
+        match self.identity {
+            IdentityType::Aci => f(&mut data.aci),
+            IdentityType::Pni => f(&mut data.pni),
+        }
+    }
+}
+
+impl ProtocolStore for MemoryProtocolStore {}
+
+#[async_trait(?Send)]
+impl SessionStore for MemoryProtocolStore {
+    async fn load_session(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<SessionRecord>, ProtocolError> {
+        let record = self.with_data(|data| data.sessions.get(&address_key(address)).cloned());
+        trace!(%address, session_exists = record.is_some(), "loading session");
+        record
+            .map(|record| SessionRecord::deserialize(&record))
+            .transpose()
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<(), ProtocolError> {
+        trace!(%address, "storing session");
+        let record = record.serialize()?;
+        self.with_data_mut(|data| data.sessions.insert(address_key(address), record));
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl SessionStoreExt for MemoryProtocolStore {
+    async fn get_sub_device_sessions(&self, name: &ServiceId) -> Result<Vec<u32>, ProtocolError> {
+        let name = name.service_id_string();
+        Ok(self.with_data(|data| {
+            data.sessions
+                .keys()
+                .filter(|(address, device_id)| *address == name && *device_id != DEFAULT_DEVICE_ID)
+                .map(|(_, device_id)| *device_id)
+                .collect()
+        }))
+    }
+
+    async fn delete_session(&self, address: &ProtocolAddress) -> Result<(), ProtocolError> {
+        trace!(%address, "deleting session");
+        self.with_data_mut(|data| data.sessions.remove(&address_key(address)));
+        Ok(())
+    }
+
+    async fn delete_all_sessions(&self, address: &ServiceId) -> Result<usize, ProtocolError> {
+        let name = address.service_id_string();
+        Ok(self.with_data_mut(|data| {
+            let count = data.sessions.len();
+            data.sessions.retain(|(address, _), _| *address != name);
+            count - data.sessions.len()
+        }))
+    }
+}
+
+#[async_trait(?Send)]
+impl PreKeyStore for MemoryProtocolStore {
+    async fn get_pre_key(&self, prekey_id: PreKeyId) -> Result<PreKeyRecord, ProtocolError> {
+        self.with_data(|data| data.pre_keys.get(&u32::from(prekey_id)).cloned())
+            .ok_or(ProtocolError::InvalidPreKeyId)
+    }
+
+    async fn save_pre_key(
+        &mut self,
+        prekey_id: PreKeyId,
+        record: &PreKeyRecord,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| data.pre_keys.insert(prekey_id.into(), record.clone()));
+        Ok(())
+    }
+
+    async fn remove_pre_key(&mut self, prekey_id: PreKeyId) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| data.pre_keys.remove(&u32::from(prekey_id)));
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl PreKeysStore for MemoryProtocolStore {
+    async fn next_pre_key_id(&self) -> Result<u32, ProtocolError> {
+        Ok(self.with_data(|data| next_key_id(&data.pre_keys)))
+    }
+
+    async fn next_signed_pre_key_id(&self) -> Result<u32, ProtocolError> {
+        Ok(self.with_data(|data| next_key_id(&data.signed_pre_keys)))
+    }
+
+    async fn next_pq_pre_key_id(&self) -> Result<u32, ProtocolError> {
+        Ok(self.with_data(|data| next_key_id(&data.kyber_pre_keys)))
+    }
+
+    async fn signed_pre_keys_count(&self) -> Result<usize, ProtocolError> {
+        Ok(self.with_data(|data| data.signed_pre_keys.len()))
+    }
+
+    async fn kyber_pre_keys_count(&self, last_resort: bool) -> Result<usize, ProtocolError> {
+        Ok(self.with_data(|data| {
+            data.kyber_pre_keys
+                .values()
+                .filter(|key| key.is_last_resort == last_resort)
+                .count()
+        }))
+    }
+}
+
+fn next_key_id<V>(keys: &BTreeMap<u32, V>) -> u32 {
+    keys.last_key_value().map_or(0, |(id, _)| id + 1)
+}
+
+#[async_trait(?Send)]
+impl SignedPreKeyStore for MemoryProtocolStore {
+    async fn get_signed_pre_key(
+        &self,
+        signed_prekey_id: SignedPreKeyId,
+    ) -> Result<SignedPreKeyRecord, ProtocolError> {
+        self.with_data(|data| {
+            data.signed_pre_keys
+                .get(&u32::from(signed_prekey_id))
+                .cloned()
+        })
+        .ok_or(ProtocolError::InvalidSignedPreKeyId)
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        signed_prekey_id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| {
+            data.signed_pre_keys
+                .insert(signed_prekey_id.into(), record.clone())
+        });
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl KyberPreKeyStore for MemoryProtocolStore {
+    async fn get_kyber_pre_key(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<KyberPreKeyRecord, ProtocolError> {
+        self.with_data(|data| {
+            data.kyber_pre_keys
+                .get(&u32::from(kyber_prekey_id))
+                .map(|key| key.record.clone())
+        })
+        .ok_or(ProtocolError::InvalidKyberPreKeyId)
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| {
+            data.kyber_pre_keys.insert(
+                kyber_prekey_id.into(),
+                KyberPreKey {
+                    record: record.clone(),
+                    is_last_resort: false,
+                    stale_at: None,
+                },
+            )
+        });
+        Ok(())
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<(), ProtocolError> {
+        // one-time keys are removed, last-resort keys are kept until they are rotated
+        self.with_data_mut(|data| {
+            let id = u32::from(kyber_prekey_id);
+            if data
+                .kyber_pre_keys
+                .get(&id)
+                .is_some_and(|key| !key.is_last_resort)
+            {
+                data.kyber_pre_keys.remove(&id);
+                trace!(%kyber_prekey_id, "removed kyber pre-key");
+            }
+        });
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl KyberPreKeyStoreExt for MemoryProtocolStore {
+    async fn store_last_resort_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), ProtocolError> {
+        trace!(%kyber_prekey_id, "store_last_resort_kyber_pre_key");
+        self.with_data_mut(|data| {
+            data.kyber_pre_keys.insert(
+                kyber_prekey_id.into(),
+                KyberPreKey {
+                    record: record.clone(),
+                    is_last_resort: true,
+                    stale_at: None,
+                },
+            )
+        });
+        Ok(())
+    }
+
+    async fn load_last_resort_kyber_pre_keys(
+        &self,
+    ) -> Result<Vec<KyberPreKeyRecord>, ProtocolError> {
+        trace!("load_last_resort_kyber_pre_keys");
+        Ok(self.with_data(|data| {
+            data.kyber_pre_keys
+                .values()
+                .filter(|key| key.is_last_resort)
+                .map(|key| key.record.clone())
+                .collect()
+        }))
+    }
+
+    async fn remove_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| data.kyber_pre_keys.remove(&u32::from(kyber_prekey_id)));
+        Ok(())
+    }
+
+    /// Analogous to markAllOneTimeKyberPreKeysStaleIfNecessary
+    async fn mark_all_one_time_kyber_pre_keys_stale_if_necessary(
+        &mut self,
+        stale_time: DateTime<Utc>,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| {
+            data.kyber_pre_keys
+                .values_mut()
+                .filter(|key| !key.is_last_resort && key.stale_at.is_none())
+                .for_each(|key| key.stale_at = Some(stale_time));
+        });
+        Ok(())
+    }
+
+    /// Analogue of deleteAllStaleOneTimeKyberPreKeys
+    async fn delete_all_stale_one_time_kyber_pre_keys(
+        &mut self,
+        threshold: DateTime<Utc>,
+        min_count: usize,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| {
+            // always keep the `min_count` most recent one-time keys around
+            let stale_ids: Vec<u32> = data
+                .kyber_pre_keys
+                .iter()
+                .rev()
+                .filter(|(_, key)| !key.is_last_resort)
+                .skip(min_count)
+                .filter(|(_, key)| key.stale_at.is_some_and(|stale_at| stale_at < threshold))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in stale_ids {
+                data.kyber_pre_keys.remove(&id);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl IdentityKeyStore for MemoryProtocolStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair, ProtocolError> {
+        trace!("getting identity_key_pair");
+        self.with_data(|data| data.identity_key_pair)
+            .ok_or_else(|| {
+                ProtocolError::InvalidState(
+                    "get_identity_key_pair",
+                    "no identity key pair found".to_owned(),
+                )
+            })
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32, ProtocolError> {
+        let data =
+            self.store
+                .load_registration_data()
+                .await?
+                .ok_or(ProtocolError::InvalidState(
+                    "failed to load registration ID",
+                    "no registration data".into(),
+                ))?;
+        match self.identity {
+            IdentityType::Aci => Ok(data.registration_id),
+            IdentityType::Pni => data.pni_registration_id.ok_or(ProtocolError::InvalidState(
+                "failed to load registration ID",
+                "no PNI registration ID".into(),
+            )),
+        }
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<bool, ProtocolError> {
+        trace!("saving identity");
+        let previous_identity =
+            self.with_data_mut(|data| data.identities.insert(address_key(address), *identity));
+
+        save_trusted_identity_message(
+            &self.store,
+            address,
+            *identity,
+            if previous_identity.is_some() {
+                verified::State::Unverified
+            } else {
+                verified::State::Default
+            },
+        )
+        .await?;
+
+        // like the sled store, which does not tell replaced identities apart
+        Ok(true)
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        _direction: Direction,
+    ) -> Result<bool, ProtocolError> {
+        match self.get_identity(address).await? {
+            None => {
+                // when we encounter a new identity, we trust it by default
+                warn!(%address, "trusting new identity");
+                Ok(true)
+            }
+            // when we encounter some identity we know, we need to decide whether we trust it or not
+            Some(left_identity_key) => {
+                if left_identity_key == *identity {
+                    Ok(true)
+                } else {
+                    match self.store.trust_new_identities {
+                        OnNewIdentity::Trust => Ok(true),
+                        OnNewIdentity::Reject => Ok(false),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn get_identity(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<IdentityKey>, ProtocolError> {
+        Ok(self.with_data(|data| data.identities.get(&address_key(address)).copied()))
+    }
+}
+
+#[async_trait(?Send)]
+impl SenderKeyStore for MemoryProtocolStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<(), ProtocolError> {
+        let (name, device_id) = address_key(sender);
+        let record = record.serialize()?;
+        self.with_data_mut(|data| {
+            data.sender_keys
+                .insert((name, device_id, distribution_id), record)
+        });
+        Ok(())
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>, ProtocolError> {
+        let (name, device_id) = address_key(sender);
+        self.with_data(|data| {
+            data.sender_keys
+                .get(&(name, device_id, distribution_id))
+                .map(|record| SenderKeyRecord::deserialize(record))
+        })
+        .transpose()
+    }
+}
+
+impl ProtocolStoreExt for MemoryProtocolStore {
+    async fn all_sessions(&self) -> Result<Vec<(ProtocolAddress, SessionRecord)>, ProtocolError> {
+        self.with_data(|data| {
+            data.sessions
+                .iter()
+                .map(|((name, device_id), record)| {
+                    Ok((
+                        ProtocolAddress::new(name.clone(), (*device_id).into()),
+                        SessionRecord::deserialize(record)?,
+                    ))
+                })
+                .collect()
+        })
+    }
+
+    async fn all_identities(&self) -> Result<Vec<(ProtocolAddress, IdentityKey)>, ProtocolError> {
+        Ok(self.with_data(|data| {
+            data.identities
+                .iter()
+                .map(|((name, device_id), identity_key)| {
+                    (
+                        ProtocolAddress::new(name.clone(), (*device_id).into()),
+                        *identity_key,
+                    )
+                })
+                .collect()
+        }))
+    }
+
+    async fn restore_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity_key: &IdentityKey,
+    ) -> Result<(), ProtocolError> {
+        self.with_data_mut(|data| data.identities.insert(address_key(address), *identity_key));
+        Ok(())
+    }
+
+    async fn all_pre_keys(&self) -> Result<Vec<PreKeyRecord>, ProtocolError> {
+        Ok(self.with_data(|data| data.pre_keys.values().cloned().collect()))
+    }
+
+    async fn all_signed_pre_keys(&self) -> Result<Vec<SignedPreKeyRecord>, ProtocolError> {
+        Ok(self.with_data(|data| data.signed_pre_keys.values().cloned().collect()))
+    }
+
+    async fn all_kyber_pre_keys(
+        &self,
+        last_resort: bool,
+    ) -> Result<Vec<KyberPreKeyRecord>, ProtocolError> {
+        Ok(self.with_data(|data| {
+            data.kyber_pre_keys
+                .values()
+                .filter(|key| key.is_last_resort == last_resort)
+                .map(|key| key.record.clone())
+                .collect()
+        }))
+    }
+
+    async fn all_sender_keys(
+        &self,
+    ) -> Result<Vec<(ProtocolAddress, Uuid, SenderKeyRecord)>, ProtocolError> {
+        self.with_data(|data| {
+            data.sender_keys
+                .iter()
+                .map(|((name, device_id, distribution_id), record)| {
+                    Ok((
+                        ProtocolAddress::new(name.clone(), (*device_id).into()),
+                        *distribution_id,
+                        SenderKeyRecord::deserialize(record)?,
+                    ))
+                })
+                .collect()
+        })
+    }
+}
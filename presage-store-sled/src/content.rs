@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     ops::{Bound, RangeBounds, RangeFull},
     sync::Arc,
 };
@@ -10,15 +11,24 @@ use presage::{
         zkgroup::{profiles::ProfileKey, GroupMasterKeyBytes},
         Profile,
     },
-    model::{contacts::Contact, groups::Group},
-    store::{ContentExt, ContentsStore, StickerPack, Thread},
+    model::{
+        contacts::Contact,
+        groups::Group,
+        messages::{DeliveryState, MessageReaction},
+    },
+    store::{
+        expiry::ExpiringMessage,
+        search::{tokenize, MessageSearch},
+        threads::{ThreadActivity, ThreadSummary},
+        ContentExt, ContentsStore, StateStore, StickerPack, Thread,
+    },
     AvatarBytes,
 };
 use prost::Message;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use sled::IVec;
-use tracing::{debug, trace};
+use sled::{Batch, IVec};
+use tracing::{debug, trace, warn};
 
 use crate::{protobuf::ContentProto, SledStore, SledStoreError};
 
@@ -30,6 +40,25 @@ const SLED_TREE_GROUP_AVATARS: &str = "group_avatars";
 const SLED_TREE_GROUPS: &str = "groups";
 const SLED_TREE_PROFILES: &str = "profiles";
 const SLED_TREE_THREADS_PREFIX: &str = "threads";
+/// Maps the name of each messages tree to its [`IndexedThread`], since tree names are hashes
+///
+/// The name starts with the threads prefix, so the index is dropped along with all messages.
+const SLED_TREE_THREADS_INDEX: &str = "threads_index";
+/// Full-text index of messages, see [`SledStore::index_message`]
+///
+/// Entries are never removed when a message is deleted or replaced: stale entries are filtered
+/// out when searching. Like the threads index, it is dropped along with all messages.
+const SLED_TREE_SEARCH_INDEX: &str = "threads_search_index";
+/// Delivery states of our sent messages, keyed by the name of their messages tree and timestamp
+const SLED_TREE_DELIVERY_STATES: &str = "threads_delivery_states";
+/// Reactions to messages, keyed by the name of their messages tree and timestamp
+const SLED_TREE_REACTIONS: &str = "threads_reactions";
+/// Previous revisions of edited messages, keyed by the name of their messages tree and timestamp
+const SLED_TREE_REVISIONS: &str = "threads_revisions";
+/// Expiry time of disappearing messages, keyed by the name of their messages tree and timestamp
+const SLED_TREE_EXPIRIES: &str = "threads_expiries";
+/// Disappearing messages ordered by expiry time, see [`expiring_messages_key`]
+const SLED_TREE_EXPIRING_MESSAGES: &str = "threads_expiring_messages";
 
 impl ContentsStore for SledStore {
     type ContentsStoreError = SledStoreError;
@@ -38,6 +67,10 @@ impl ContentsStore for SledStore {
     type GroupsIter = SledGroupsIter;
     type MessagesIter = SledMessagesIter;
     type StickerPacksIter = SledStickerPacksIter;
+    type ThreadsIter = std::vec::IntoIter<Result<ThreadSummary, SledStoreError>>;
+    type ProfileKeysIter = std::vec::IntoIter<Result<(Uuid, ProfileKey), SledStoreError>>;
+    type SearchResultsIter = std::vec::IntoIter<Result<(Thread, Content), SledStoreError>>;
+    type ExpiringMessagesIter = std::vec::IntoIter<Result<ExpiringMessage, SledStoreError>>;
 
     async fn clear_profiles(&mut self) -> Result<(), Self::ContentsStoreError> {
         let db = self.write();
@@ -156,8 +189,33 @@ impl ContentsStore for SledStore {
     async fn clear_thread(&mut self, thread: &Thread) -> Result<(), SledStoreError> {
         trace!(%thread, "clearing thread");
 
+        let tree = messages_thread_tree_name(thread);
+        self.remove(SLED_TREE_THREADS_INDEX, &tree)?;
+
         let db = self.write();
-        db.drop_tree(messages_thread_tree_name(thread))?;
+        let delivery_states = db.open_tree(SLED_TREE_DELIVERY_STATES)?;
+        for key in delivery_states.scan_prefix(&tree).keys() {
+            delivery_states.remove(key?)?;
+        }
+        let reactions = db.open_tree(SLED_TREE_REACTIONS)?;
+        for key in reactions.scan_prefix(&tree).keys() {
+            reactions.remove(key?)?;
+        }
+        let revisions = db.open_tree(SLED_TREE_REVISIONS)?;
+        for key in revisions.scan_prefix(&tree).keys() {
+            revisions.remove(key?)?;
+        }
+        let expiries = db.open_tree(SLED_TREE_EXPIRIES)?;
+        let expiring_messages = db.open_tree(SLED_TREE_EXPIRING_MESSAGES)?;
+        for entry in expiries.scan_prefix(&tree) {
+            let (key, value) = entry?;
+            let expires_at: u64 = self.decrypt_value(value)?;
+            let timestamp =
+                u64::from_be_bytes(key[tree.len()..].try_into().expect("8 bytes timestamp"));
+            expiring_messages.remove(expiring_messages_key(expires_at, &tree, timestamp))?;
+            expiries.remove(key)?;
+        }
+        db.drop_tree(tree)?;
         db.flush()?;
 
         Ok(())
@@ -170,10 +228,22 @@ impl ContentsStore for SledStore {
         let tree = messages_thread_tree_name(thread);
         let key = ts.to_be_bytes();
 
-        let proto: ContentProto = message.into();
+        self.index_message(&tree, thread, &message)?;
+
+        let proto: ContentProto = message.clone().into();
         let value = proto.encode_to_vec();
 
-        self.insert(&tree, key, value)?;
+        let replaced = self.insert(&tree, key, value)?;
+
+        let own_aci = self
+            .load_registration_data()
+            .await?
+            .map(|data| data.service_ids.aci);
+        let mut indexed: IndexedThread = self
+            .get(SLED_TREE_THREADS_INDEX, &tree)?
+            .unwrap_or_else(|| IndexedThread::new(thread.clone()));
+        indexed.activity.record(&message, replaced, own_aci);
+        self.insert(SLED_TREE_THREADS_INDEX, &tree, indexed)?;
 
         Ok(())
     }
@@ -184,7 +254,32 @@ impl ContentsStore for SledStore {
         timestamp: u64,
     ) -> Result<bool, SledStoreError> {
         let tree = messages_thread_tree_name(thread);
-        self.remove(&tree, timestamp.to_be_bytes())
+        if !self.remove(&tree, timestamp.to_be_bytes())? {
+            return Ok(false);
+        }
+        self.remove(SLED_TREE_DELIVERY_STATES, message_key(&tree, timestamp))?;
+        self.remove(SLED_TREE_REACTIONS, message_key(&tree, timestamp))?;
+        self.remove(SLED_TREE_REVISIONS, message_key(&tree, timestamp))?;
+        let expires_at: Option<u64> =
+            self.get(SLED_TREE_EXPIRIES, message_key(&tree, timestamp))?;
+        if let Some(expires_at) = expires_at {
+            self.remove(SLED_TREE_EXPIRIES, message_key(&tree, timestamp))?;
+            self.remove(
+                SLED_TREE_EXPIRING_MESSAGES,
+                expiring_messages_key(expires_at, &tree, timestamp),
+            )?;
+        }
+
+        let indexed: Option<IndexedThread> = self.get(SLED_TREE_THREADS_INDEX, &tree)?;
+        if let Some(mut indexed) = indexed {
+            if indexed.activity.last_message_timestamp == timestamp {
+                let remaining = self.messages(thread, ..).await?.next_back().transpose()?;
+                indexed.activity.set_last_message(remaining.as_ref());
+                self.insert(SLED_TREE_THREADS_INDEX, &tree, indexed)?;
+            }
+        }
+
+        Ok(true)
     }
 
     async fn message(
@@ -236,6 +331,271 @@ impl ContentsStore for SledStore {
         })
     }
 
+    async fn threads(&self) -> Result<Self::ThreadsIter, SledStoreError> {
+        let mut threads = Vec::new();
+        for entry in self.iter_with_keys(SLED_TREE_THREADS_INDEX)? {
+            let (tree, IndexedThread { thread, activity }) = entry?;
+            if self.read().open_tree(tree)?.is_empty() {
+                continue;
+            }
+            let title = self.thread_title(&thread).await?;
+            threads.push(ThreadSummary {
+                thread,
+                title,
+                activity,
+            });
+        }
+        threads.sort_by_key(|summary| std::cmp::Reverse(summary.activity.last_message_timestamp));
+        Ok(threads.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
+    async fn delivery_states(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, DeliveryState>, SledStoreError> {
+        let key = message_key(&messages_thread_tree_name(thread), timestamp);
+        Ok(self
+            .get(SLED_TREE_DELIVERY_STATES, key)?
+            .unwrap_or_default())
+    }
+
+    async fn update_delivery_state(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        recipient: Uuid,
+        state: DeliveryState,
+    ) -> Result<bool, SledStoreError> {
+        let key = message_key(&messages_thread_tree_name(thread), timestamp);
+        let mut states: BTreeMap<Uuid, DeliveryState> = self
+            .get(SLED_TREE_DELIVERY_STATES, &key)?
+            .unwrap_or_default();
+        if states
+            .get(&recipient)
+            .is_some_and(|current| *current >= state)
+        {
+            return Ok(false);
+        }
+        states.insert(recipient, state);
+        self.insert(SLED_TREE_DELIVERY_STATES, key, states)?;
+        trace!(%thread, timestamp, %recipient, ?state, "updated delivery state");
+        Ok(true)
+    }
+
+    async fn reactions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, MessageReaction>, SledStoreError> {
+        let key = message_key(&messages_thread_tree_name(thread), timestamp);
+        Ok(self.get(SLED_TREE_REACTIONS, key)?.unwrap_or_default())
+    }
+
+    async fn update_reaction(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        author: Uuid,
+        reaction: Option<MessageReaction>,
+    ) -> Result<(), SledStoreError> {
+        let key = message_key(&messages_thread_tree_name(thread), timestamp);
+        let mut reactions: BTreeMap<Uuid, MessageReaction> =
+            self.get(SLED_TREE_REACTIONS, &key)?.unwrap_or_default();
+        match reaction {
+            Some(reaction) => {
+                reactions.insert(author, reaction);
+            }
+            None => {
+                reactions.remove(&author);
+            }
+        }
+        if reactions.is_empty() {
+            self.remove(SLED_TREE_REACTIONS, key)?;
+        } else {
+            self.insert(SLED_TREE_REACTIONS, key, reactions)?;
+        }
+        trace!(%thread, timestamp, %author, "updated reaction");
+        Ok(())
+    }
+
+    async fn message_revisions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<Vec<Content>, SledStoreError> {
+        let key = message_key(&messages_thread_tree_name(thread), timestamp);
+        let revisions: Vec<Vec<u8>> = self.get(SLED_TREE_REVISIONS, key)?.unwrap_or_default();
+        revisions
+            .iter()
+            .map(|revision| ContentProto::decode(revision.as_slice())?.try_into())
+            .collect()
+    }
+
+    async fn add_message_revision(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        revision: Content,
+    ) -> Result<(), SledStoreError> {
+        let tree = messages_thread_tree_name(thread);
+        if !self
+            .read()
+            .open_tree(&tree)?
+            .contains_key(timestamp.to_be_bytes())?
+        {
+            return Ok(());
+        }
+        let key = message_key(&tree, timestamp);
+        let mut revisions: Vec<Vec<u8>> = self.get(SLED_TREE_REVISIONS, &key)?.unwrap_or_default();
+        let proto: ContentProto = revision.into();
+        revisions.push(proto.encode_to_vec());
+        self.insert(SLED_TREE_REVISIONS, key, revisions)?;
+        trace!(%thread, timestamp, "saved message revision");
+        Ok(())
+    }
+
+    async fn set_message_expiry(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        expires_at: u64,
+    ) -> Result<(), SledStoreError> {
+        let tree = messages_thread_tree_name(thread);
+        let key = message_key(&tree, timestamp);
+        let current: Option<u64> = self.get(SLED_TREE_EXPIRIES, &key)?;
+        if current.is_some()
+            || !self
+                .read()
+                .open_tree(&tree)?
+                .contains_key(timestamp.to_be_bytes())?
+        {
+            return Ok(());
+        }
+        self.insert(SLED_TREE_EXPIRIES, key, expires_at)?;
+        self.insert(
+            SLED_TREE_EXPIRING_MESSAGES,
+            expiring_messages_key(expires_at, &tree, timestamp),
+            thread,
+        )?;
+        trace!(%thread, timestamp, expires_at, "started disappearing timer");
+        Ok(())
+    }
+
+    async fn expiring_messages(&self) -> Result<Self::ExpiringMessagesIter, SledStoreError> {
+        let messages: Vec<_> = self
+            .iter_with_keys(SLED_TREE_EXPIRING_MESSAGES)?
+            .map(|entry| {
+                let (key, thread) = entry?;
+                // key is the expiry time, the name of the messages tree and the timestamp
+                let (expires_at, rest) = key.split_at(8);
+                let (_, timestamp) = rest.split_at(rest.len() - 8);
+                Ok(ExpiringMessage {
+                    thread,
+                    timestamp: u64::from_be_bytes(timestamp.try_into().expect("8 bytes timestamp")),
+                    expires_at: u64::from_be_bytes(
+                        expires_at.try_into().expect("8 bytes timestamp"),
+                    ),
+                })
+            })
+            .collect();
+        Ok(messages.into_iter())
+    }
+
+    async fn thread_activity(
+        &self,
+        thread: &Thread,
+    ) -> Result<Option<ThreadActivity>, SledStoreError> {
+        let indexed: Option<IndexedThread> =
+            self.get(SLED_TREE_THREADS_INDEX, messages_thread_tree_name(thread))?;
+        Ok(indexed.map(|indexed| indexed.activity))
+    }
+
+    async fn mark_thread_read(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<(), SledStoreError> {
+        let tree = messages_thread_tree_name(thread);
+        let indexed: Option<IndexedThread> = self.get(SLED_TREE_THREADS_INDEX, &tree)?;
+        let Some(mut indexed) = indexed else {
+            return Ok(());
+        };
+        if timestamp <= indexed.activity.last_read_timestamp {
+            return Ok(());
+        }
+
+        let own_aci = self
+            .load_registration_data()
+            .await?
+            .map(|data| data.service_ids.aci);
+        let later_messages = self
+            .messages(thread, timestamp.saturating_add(1)..)
+            .await?
+            .collect::<Result<Vec<_>, _>>()?;
+        indexed
+            .activity
+            .mark_read(timestamp, &later_messages, own_aci);
+        trace!(%thread, timestamp, unread_count = indexed.activity.unread_count, "marked thread as read");
+        self.insert(SLED_TREE_THREADS_INDEX, &tree, indexed)?;
+
+        Ok(())
+    }
+
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+    ) -> Result<Self::SearchResultsIter, SledStoreError> {
+        let terms = search.terms();
+        let Some((first_term, other_terms)) = terms.split_first() else {
+            return Ok(Vec::new().into_iter());
+        };
+        let other_terms: Vec<_> = other_terms
+            .iter()
+            .map(|term| self.search_term_key(term))
+            .collect();
+        let thread_tree = search.thread.as_ref().map(messages_thread_tree_name);
+
+        let index = self.read().open_tree(SLED_TREE_SEARCH_INDEX)?;
+        let mut results = Vec::new();
+        for entry in index.scan_prefix(self.search_term_key(first_term)) {
+            let (key, value) = entry?;
+            // key is the term followed by the timestamp and the name of the messages tree
+            let (ts, tree) = key[32..].split_at(8);
+            let ts = u64::from_be_bytes(ts.try_into().expect("8 bytes timestamp"));
+            if !search.in_range(ts)
+                || thread_tree
+                    .as_ref()
+                    .is_some_and(|thread_tree| thread_tree.as_bytes() != tree)
+            {
+                continue;
+            }
+
+            let mut has_all_terms = true;
+            for term in &other_terms {
+                if !index.contains_key([term.as_slice(), &key[32..]].concat())? {
+                    has_all_terms = false;
+                    break;
+                }
+            }
+            if !has_all_terms {
+                continue;
+            }
+
+            let thread: Thread = self.decrypt_value(value)?;
+            match self.message(&thread, ts).await? {
+                Some(content) if search.matches(&thread, &content) => {
+                    results.push((thread, content))
+                }
+                _ => trace!(%thread, ts, "skipping stale search index entry"),
+            }
+        }
+
+        results.sort_by_key(|(_, content)| std::cmp::Reverse(content.timestamp()));
+        debug!(count = results.len(), "found messages");
+        Ok(results.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
     async fn upsert_profile_key(
         &mut self,
         uuid: &Uuid,
@@ -248,6 +608,20 @@ impl ContentsStore for SledStore {
         self.get(SLED_TREE_PROFILE_KEYS, uuid.as_bytes())
     }
 
+    async fn profile_keys(&self) -> Result<Self::ProfileKeysIter, SledStoreError> {
+        let profile_keys: Vec<_> = self
+            .iter_with_keys(SLED_TREE_PROFILE_KEYS)?
+            .map(|entry| {
+                let (uuid, key) = entry?;
+                let uuid = Uuid::from_slice(&uuid).map_err(|_| {
+                    SledStoreError::InvalidKey(String::from_utf8_lossy(&uuid).into_owned())
+                })?;
+                Ok((uuid, key))
+            })
+            .collect();
+        Ok(profile_keys.into_iter())
+    }
+
     async fn save_profile(
         &mut self,
         uuid: Uuid,
@@ -468,6 +842,128 @@ impl DoubleEndedIterator for SledMessagesIter {
     }
 }
 
+/// Entry of the threads index
+#[derive(Serialize, Deserialize)]
+struct IndexedThread {
+    thread: Thread,
+    activity: ThreadActivity,
+}
+
+impl IndexedThread {
+    fn new(thread: Thread) -> Self {
+        Self {
+            thread,
+            activity: Default::default(),
+        }
+    }
+}
+
+impl SledStore {
+    /// Builds the index of threads for messages saved before it existed
+    ///
+    /// Messages trees are named by hashing their thread, so only trees of threads we can still
+    /// guess (from contacts, groups, profile keys and our own ACI) end up in the index.
+    pub(crate) async fn index_threads(&self) -> Result<(), SledStoreError> {
+        let mut threads = Vec::new();
+        for contact in self.contacts().await? {
+            threads.push(Thread::Contact(contact?.uuid));
+        }
+        for group in self.groups().await? {
+            threads.push(Thread::Group(group?.0));
+        }
+        for profile_key in self.profile_keys().await? {
+            threads.push(Thread::Contact(profile_key?.0));
+        }
+        if let Some(registration_data) = self.load_registration_data().await? {
+            threads.push(Thread::Contact(registration_data.service_ids.aci));
+        }
+
+        let tree_names = self.read().tree_names();
+        let num_trees = tree_names
+            .iter()
+            .filter(|name| name.starts_with(format!("{SLED_TREE_THREADS_PREFIX}:").as_bytes()))
+            .count();
+
+        let mut num_indexed = 0;
+        for thread in threads {
+            let tree = messages_thread_tree_name(&thread);
+            if !tree_names
+                .iter()
+                .any(|name| name.as_ref() == tree.as_bytes())
+            {
+                continue;
+            }
+            // all messages saved so far are considered read
+            let mut indexed = IndexedThread::new(thread.clone());
+            let last_message = self.messages(&thread, ..).await?.next_back().transpose()?;
+            indexed.activity.set_last_message(last_message.as_ref());
+            if !self.insert(SLED_TREE_THREADS_INDEX, &tree, indexed)? {
+                num_indexed += 1;
+            }
+        }
+
+        if num_indexed < num_trees {
+            warn!(
+                num_trees,
+                num_indexed, "some message threads could not be indexed and won't be listed"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Adds all words of a message to the full-text index
+    fn index_message(
+        &self,
+        tree: &str,
+        thread: &Thread,
+        content: &Content,
+    ) -> Result<(), SledStoreError> {
+        let Some(text) = content.text() else {
+            return Ok(());
+        };
+        let mut terms = tokenize(text);
+        terms.sort_unstable();
+        terms.dedup();
+
+        let suffix = [&content.timestamp().to_be_bytes()[..], tree.as_bytes()].concat();
+        let value = self.encrypt_value(thread)?;
+        let mut batch = Batch::default();
+        for term in terms {
+            batch.insert(
+                [&self.search_term_key(&term)[..], &suffix].concat(),
+                value.clone(),
+            );
+        }
+
+        let db = self.write();
+        db.open_tree(SLED_TREE_SEARCH_INDEX)?.apply_batch(batch)?;
+        db.flush()?;
+        Ok(())
+    }
+
+    /// Builds the full-text index for messages saved before it existed
+    pub(crate) async fn index_messages(&self) -> Result<(), SledStoreError> {
+        for thread in self.threads().await? {
+            let thread = thread?.thread;
+            let tree = messages_thread_tree_name(&thread);
+            for content in self.messages(&thread, ..).await? {
+                self.index_message(&tree, &thread, &content?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Key of a term in the full-text index, hashed so the index does not leak words
+    fn search_term_key(&self, term: &str) -> [u8; 32] {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = self.cipher.as_ref() {
+            return cipher.hash_key(SLED_TREE_SEARCH_INDEX, term.as_bytes());
+        }
+        Sha256::digest(term.as_bytes()).into()
+    }
+}
+
 fn messages_thread_tree_name(t: &Thread) -> String {
     use base64::prelude::*;
     let key = match t {
@@ -483,3 +979,18 @@ fn messages_thread_tree_name(t: &Thread) -> String {
     hasher.update(key.as_bytes());
     format!("{SLED_TREE_THREADS_PREFIX}:{:x}", hasher.finalize())
 }
+
+/// Key of a message in the trees holding data about each message, like delivery states
+fn message_key(tree: &str, timestamp: u64) -> Vec<u8> {
+    [tree.as_bytes(), &timestamp.to_be_bytes()].concat()
+}
+
+/// Key of a disappearing message in the tree ordered by expiry time
+fn expiring_messages_key(expires_at: u64, tree: &str, timestamp: u64) -> Vec<u8> {
+    [
+        &expires_at.to_be_bytes()[..],
+        tree.as_bytes(),
+        &timestamp.to_be_bytes(),
+    ]
+    .concat()
+}
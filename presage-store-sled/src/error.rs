@@ -24,6 +24,8 @@ pub enum SledStoreError {
     GroupDecryption,
     #[error("No UUID")]
     NoUuid,
+    #[error("invalid key in tree: {0}")]
+    InvalidKey(String),
     #[error("Unsupported message content")]
     UnsupportedContent,
 }
@@ -17,7 +17,10 @@ use presage::{
     },
     manager::RegistrationData,
     model::identity::OnNewIdentity,
-    store::{ContentsStore, StateStore, Store},
+    store::{
+        attachments::{AttachmentId, DownloadedAttachment},
+        AttachmentsStore, ContentsStore, StateStore, Store,
+    },
 };
 use protocol::{AciSledStore, PniSledStore, SledProtocolStore, SledTrees};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -33,6 +36,7 @@ pub use error::SledStoreError;
 use sled::IVec;
 
 const SLED_TREE_STATE: &str = "state";
+const SLED_TREE_ATTACHMENTS: &str = "attachments";
 
 const SLED_KEY_REGISTRATION: &str = "registration";
 const SLED_KEY_SCHEMA_VERSION: &str = "schema_version";
@@ -74,9 +78,13 @@ pub enum SchemaVersion {
     V4 = 4,
     /// ACI and PNI identity key pairs are moved into dedicated storage keys from registration data
     V5 = 5,
-    #[default]
     /// Reset pre-keys after fixing persistence
     V6 = 6,
+    /// Index of threads, since messages trees are named by hashing their thread
+    V7 = 7,
+    #[default]
+    /// Full-text index of messages
+    V8 = 8,
 }
 
 impl SchemaVersion {
@@ -93,6 +101,8 @@ impl SchemaVersion {
             4 => SchemaVersion::V4,
             5 => SchemaVersion::V5,
             6 => SchemaVersion::V6,
+            7 => SchemaVersion::V7,
+            8 => SchemaVersion::V8,
             _ => unreachable!("oops, this not supposed to happen!"),
         })
     }
@@ -250,6 +260,17 @@ impl SledStore {
             .flat_map(|res| res.map(|(_, value)| self.decrypt_value::<V>(value))))
     }
 
+    /// Same as [`SledStore::iter`], but also yields the key of each value
+    fn iter_with_keys<'a, V: DeserializeOwned + 'a>(
+        &'a self,
+        tree: &str,
+    ) -> Result<impl Iterator<Item = Result<(IVec, V), SledStoreError>> + 'a, SledStoreError> {
+        Ok(self.read().open_tree(tree)?.iter().map(|res| {
+            let (key, value) = res?;
+            Ok((key, self.decrypt_value(value)?))
+        }))
+    }
+
     fn insert<K, V>(&self, tree: &str, key: K, value: V) -> Result<bool, SledStoreError>
     where
         K: AsRef<[u8]>,
@@ -423,6 +444,14 @@ async fn migrate(
                         debug!(tree_name, num_keys_before, num_keys_after, "migrated keys");
                     }
                 }
+                SchemaVersion::V7 => {
+                    debug!("migrating from schema v6 to v7: indexing threads");
+                    store.index_threads().await?;
+                }
+                SchemaVersion::V8 => {
+                    debug!("migrating from schema v7 to v8: indexing messages for search");
+                    store.index_messages().await?;
+                }
                 _ => return Err(SledStoreError::MigrationConflict),
             }
 
@@ -513,6 +542,37 @@ impl StateStore for SledStore {
     }
 }
 
+impl AttachmentsStore for SledStore {
+    type AttachmentsStoreError = SledStoreError;
+
+    async fn attachment(
+        &self,
+        id: &AttachmentId,
+    ) -> Result<Option<DownloadedAttachment>, SledStoreError> {
+        self.get(SLED_TREE_ATTACHMENTS, id.as_str())
+    }
+
+    async fn save_attachment(
+        &mut self,
+        id: &AttachmentId,
+        attachment: &DownloadedAttachment,
+    ) -> Result<(), SledStoreError> {
+        self.insert(SLED_TREE_ATTACHMENTS, id.as_str(), attachment)?;
+        Ok(())
+    }
+
+    async fn remove_attachment(&mut self, id: &AttachmentId) -> Result<bool, SledStoreError> {
+        self.remove(SLED_TREE_ATTACHMENTS, id.as_str())
+    }
+
+    async fn clear_attachments(&mut self) -> Result<(), SledStoreError> {
+        let db = self.write();
+        db.drop_tree(SLED_TREE_ATTACHMENTS)?;
+        db.flush()?;
+        Ok(())
+    }
+}
+
 impl Store for SledStore {
     type Error = SledStoreError;
     type AciStore = SledProtocolStore<AciSledStore>;
@@ -521,6 +581,7 @@ impl Store for SledStore {
     async fn clear(&mut self) -> Result<(), SledStoreError> {
         self.clear_registration().await?;
         self.clear_contents().await?;
+        self.clear_attachments().await?;
 
         Ok(())
     }
@@ -563,9 +624,12 @@ mod tests {
                 SchemaVersion::V4,
                 SchemaVersion::V5,
                 SchemaVersion::V6,
+                SchemaVersion::V7,
+                SchemaVersion::V8,
             ]
         )
     }
+
     #[derive(Debug, Clone)]
     struct Thread(presage::store::Thread);
 
@@ -683,4 +747,9 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_store_conformance() {
+        presage::store::testing::run_all(|| async { SledStore::temporary().unwrap() }).await;
+    }
 }
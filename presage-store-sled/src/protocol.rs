@@ -16,7 +16,7 @@ use presage::{
         session_store::SessionStoreExt,
     },
     proto::verified,
-    store::{save_trusted_identity_message, StateStore},
+    store::{save_trusted_identity_message, ProtocolStoreExt, StateStore},
 };
 use sled::Batch;
 use tracing::{error, trace, warn};
@@ -654,6 +654,114 @@ impl<T: SledTrees> SenderKeyStore for SledProtocolStore<T> {
     }
 }
 
+impl<T: SledTrees> ProtocolStoreExt for SledProtocolStore<T> {
+    async fn all_sessions(
+        &self,
+    ) -> Result<Vec<(ProtocolAddress, SessionRecord)>, SignalProtocolError> {
+        self.store
+            .iter_with_keys(T::sessions())?
+            .map(|entry| {
+                let (key, record): (_, Vec<u8>) = entry?;
+                Ok((parse_address(&key)?, SessionRecord::deserialize(&record)?))
+            })
+            .collect()
+    }
+
+    async fn all_identities(
+        &self,
+    ) -> Result<Vec<(ProtocolAddress, IdentityKey)>, SignalProtocolError> {
+        self.store
+            .iter_with_keys(T::identities())?
+            .map(|entry| {
+                let (key, identity_key): (_, Vec<u8>) = entry?;
+                Ok((parse_address(&key)?, IdentityKey::decode(&identity_key)?))
+            })
+            .collect()
+    }
+
+    async fn restore_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity_key: &IdentityKey,
+    ) -> Result<(), SignalProtocolError> {
+        self.store.insert(
+            T::identities(),
+            address.to_string(),
+            identity_key.serialize(),
+        )?;
+        Ok(())
+    }
+
+    async fn all_pre_keys(&self) -> Result<Vec<PreKeyRecord>, SignalProtocolError> {
+        self.store
+            .iter(T::pre_keys())?
+            .map(|record: Result<Vec<u8>, SledStoreError>| Ok(PreKeyRecord::deserialize(&record?)?))
+            .collect()
+    }
+
+    async fn all_signed_pre_keys(&self) -> Result<Vec<SignedPreKeyRecord>, SignalProtocolError> {
+        self.store
+            .iter(T::signed_pre_keys())?
+            .map(|record: Result<Vec<u8>, SledStoreError>| {
+                Ok(SignedPreKeyRecord::deserialize(&record?)?)
+            })
+            .collect()
+    }
+
+    async fn all_kyber_pre_keys(
+        &self,
+        last_resort: bool,
+    ) -> Result<Vec<KyberPreKeyRecord>, SignalProtocolError> {
+        let tree = if last_resort {
+            T::kyber_pre_keys_last_resort()
+        } else {
+            T::kyber_pre_keys()
+        };
+        self.store
+            .iter(tree)?
+            .map(|record: Result<Vec<u8>, SledStoreError>| {
+                Ok(KyberPreKeyRecord::deserialize(&record?)?)
+            })
+            .collect()
+    }
+
+    async fn all_sender_keys(
+        &self,
+    ) -> Result<Vec<(ProtocolAddress, Uuid, SenderKeyRecord)>, SignalProtocolError> {
+        self.store
+            .iter_with_keys(T::sender_keys())?
+            .map(|entry| {
+                let (key, record): (_, Vec<u8>) = entry?;
+                let (address, distribution_id) = std::str::from_utf8(&key)
+                    .ok()
+                    .and_then(|key| key.rsplit_once('/'))
+                    .ok_or_else(|| invalid_key(&key))?;
+                let distribution_id =
+                    Uuid::parse_str(distribution_id).map_err(|_| invalid_key(&key))?;
+                Ok((
+                    parse_address(address.as_bytes())?,
+                    distribution_id,
+                    SenderKeyRecord::deserialize(&record)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Parses keys of the sessions and identities trees, formatted as `name.device_id`
+fn parse_address(key: &[u8]) -> Result<ProtocolAddress, SledStoreError> {
+    let (name, device_id) = std::str::from_utf8(key)
+        .ok()
+        .and_then(|key| key.rsplit_once('.'))
+        .ok_or_else(|| invalid_key(key))?;
+    let device_id: u32 = device_id.parse().map_err(|_| invalid_key(key))?;
+    Ok(ProtocolAddress::new(name.to_owned(), device_id.into()))
+}
+
+fn invalid_key(key: &[u8]) -> SledStoreError {
+    SledStoreError::InvalidKey(String::from_utf8_lossy(key).into_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use core::fmt;
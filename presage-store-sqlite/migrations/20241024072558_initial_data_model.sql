@@ -0,0 +1,157 @@
+-- Key/value table used for the state store (registration data, identity key pairs, ...)
+CREATE TABLE config (
+    key TEXT PRIMARY KEY NOT NULL,
+    value BLOB NOT NULL
+);
+
+-- Protocol stores
+--
+-- All tables are shared between the ACI and PNI protocol stores, rows are discriminated by the
+-- `identity` column.
+
+CREATE TABLE sessions (
+    address TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+
+    PRIMARY KEY (address, device_id, identity)
+);
+
+CREATE TABLE identities (
+    address TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+
+    PRIMARY KEY (address, device_id, identity)
+);
+
+CREATE TABLE pre_keys (
+    id INTEGER NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+
+    PRIMARY KEY (id, identity)
+);
+
+CREATE TABLE signed_pre_keys (
+    id INTEGER NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+
+    PRIMARY KEY (id, identity)
+);
+
+CREATE TABLE kyber_pre_keys (
+    id INTEGER NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+    is_last_resort BOOLEAN NOT NULL DEFAULT FALSE,
+    -- UNIX timestamp (in milliseconds) at which a one-time key was marked as stale
+    stale_timestamp INTEGER,
+
+    PRIMARY KEY (id, identity)
+);
+
+CREATE TABLE sender_keys (
+    address TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    distribution_id TEXT NOT NULL,
+    identity TEXT NOT NULL CHECK (identity IN ('aci', 'pni')),
+    record BLOB NOT NULL,
+
+    PRIMARY KEY (address, device_id, distribution_id, identity)
+);
+
+-- Contents store
+
+CREATE TABLE contacts (
+    uuid BLOB PRIMARY KEY NOT NULL,
+    phone_number TEXT,
+    name TEXT NOT NULL,
+    color TEXT,
+    -- protobuf encoded `Verified` message
+    verified BLOB NOT NULL,
+    profile_key BLOB NOT NULL,
+    expire_timer INTEGER NOT NULL,
+    expire_timer_version INTEGER NOT NULL DEFAULT 2,
+    inbox_position INTEGER NOT NULL,
+    archived BOOLEAN NOT NULL
+);
+
+CREATE TABLE groups (
+    master_key BLOB PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    revision INTEGER NOT NULL DEFAULT 0,
+    invite_link_password BLOB,
+    -- JSON encoded `AccessControl`
+    access_control BLOB,
+    avatar TEXT NOT NULL,
+    description TEXT,
+    -- JSON encoded lists of members
+    members BLOB NOT NULL,
+    pending_members BLOB NOT NULL,
+    requesting_members BLOB NOT NULL,
+    disappearing_messages_timer INTEGER
+);
+
+CREATE TABLE group_avatars (
+    master_key BLOB PRIMARY KEY NOT NULL,
+    bytes BLOB NOT NULL
+);
+
+CREATE TABLE profile_keys (
+    uuid BLOB PRIMARY KEY NOT NULL,
+    key BLOB NOT NULL
+);
+
+CREATE TABLE profiles (
+    uuid BLOB NOT NULL,
+    key BLOB NOT NULL,
+    -- JSON encoded `Profile`
+    data BLOB NOT NULL,
+
+    PRIMARY KEY (uuid, key)
+);
+
+CREATE TABLE profile_avatars (
+    uuid BLOB NOT NULL,
+    key BLOB NOT NULL,
+    bytes BLOB NOT NULL,
+
+    PRIMARY KEY (uuid, key)
+);
+
+CREATE TABLE sticker_packs (
+    id BLOB PRIMARY KEY NOT NULL,
+    key BLOB NOT NULL,
+    -- JSON encoded `StickerPackManifest`
+    manifest BLOB NOT NULL
+);
+
+CREATE TABLE threads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recipient_id BLOB UNIQUE,
+    group_master_key BLOB UNIQUE,
+
+    CHECK ((recipient_id IS NULL) <> (group_master_key IS NULL))
+);
+
+CREATE TABLE thread_messages (
+    ts INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+
+    sender_service_id TEXT NOT NULL,
+    sender_device INTEGER NOT NULL,
+    destination_service_id TEXT NOT NULL,
+    needs_receipt BOOLEAN NOT NULL,
+    unidentified_sender BOOLEAN NOT NULL,
+    server_guid BLOB,
+
+    -- protobuf encoded `Content` body
+    content_body BLOB NOT NULL,
+
+    PRIMARY KEY (thread_id, ts),
+    FOREIGN KEY (thread_id) REFERENCES threads (id) ON DELETE CASCADE
+);
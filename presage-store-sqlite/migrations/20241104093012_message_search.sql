@@ -0,0 +1,16 @@
+-- Full-text index of the text of messages
+--
+-- Rows are inserted by the store when saving a message, since the text has to be extracted from
+-- the protobuf encoded body, and deleted along with messages by the trigger below.
+
+CREATE VIRTUAL TABLE thread_messages_fts USING fts5(
+    body,
+    thread_id UNINDEXED,
+    ts UNINDEXED,
+    -- same word splitting as `presage::store::search::tokenize`
+    tokenize = "unicode61 remove_diacritics 0"
+);
+
+CREATE TRIGGER thread_messages_fts_delete AFTER DELETE ON thread_messages BEGIN
+    DELETE FROM thread_messages_fts WHERE thread_id = old.thread_id AND ts = old.ts;
+END;
@@ -0,0 +1,16 @@
+-- Recent activity of threads, maintained by the store when saving messages
+--
+-- Messages saved so far are considered read.
+
+ALTER TABLE threads ADD COLUMN last_message_ts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE threads ADD COLUMN last_message_preview TEXT;
+ALTER TABLE threads ADD COLUMN unread_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE threads SET last_message_ts = COALESCE(
+    (SELECT MAX(ts) FROM thread_messages WHERE thread_id = threads.id),
+    0
+);
+UPDATE threads SET last_message_preview = (
+    SELECT substr(body, 1, 100) FROM thread_messages_fts
+    WHERE thread_id = threads.id AND ts = threads.last_message_ts
+);
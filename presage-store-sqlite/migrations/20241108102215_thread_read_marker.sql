@@ -0,0 +1,3 @@
+-- Timestamp up to which all messages of a thread were read
+
+ALTER TABLE threads ADD COLUMN last_read_ts INTEGER NOT NULL DEFAULT 0;
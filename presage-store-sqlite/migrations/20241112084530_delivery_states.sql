@@ -0,0 +1,18 @@
+-- Delivery state of our sent messages, for each of their recipients
+--
+-- Rows are deleted along with messages by the trigger below.
+
+CREATE TABLE thread_message_delivery_states (
+    ts INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+    recipient_id BLOB NOT NULL,
+    -- 0 = sent, 1 = delivered, 2 = read, 3 = viewed
+    state INTEGER NOT NULL,
+
+    PRIMARY KEY (thread_id, ts, recipient_id),
+    FOREIGN KEY (thread_id) REFERENCES threads (id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER thread_message_delivery_states_delete AFTER DELETE ON thread_messages BEGIN
+    DELETE FROM thread_message_delivery_states WHERE thread_id = old.thread_id AND ts = old.ts;
+END;
@@ -0,0 +1,19 @@
+-- Expiry time of disappearing messages, once their timer was started
+--
+-- Rows are deleted along with messages by the trigger below.
+
+CREATE TABLE thread_message_expiries (
+    ts INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+    -- milliseconds since the Unix epoch
+    expires_at INTEGER NOT NULL,
+
+    PRIMARY KEY (thread_id, ts),
+    FOREIGN KEY (thread_id) REFERENCES threads (id) ON DELETE CASCADE
+);
+
+CREATE INDEX thread_message_expiries_expires_at ON thread_message_expiries (expires_at);
+
+CREATE TRIGGER thread_message_expiries_delete AFTER DELETE ON thread_messages BEGIN
+    DELETE FROM thread_message_expiries WHERE thread_id = old.thread_id AND ts = old.ts;
+END;
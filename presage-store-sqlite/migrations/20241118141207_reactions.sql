@@ -0,0 +1,21 @@
+-- Reactions to messages, at most one per author and message
+--
+-- Removed reactions are kept (with `removed` set) so that older reactions arriving later are
+-- ignored. Rows are deleted along with messages by the trigger below.
+
+CREATE TABLE thread_message_reactions (
+    ts INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+    author_id BLOB NOT NULL,
+    emoji TEXT NOT NULL,
+    -- timestamp of the message carrying the reaction
+    reaction_ts INTEGER NOT NULL,
+    removed BOOLEAN NOT NULL DEFAULT FALSE,
+
+    PRIMARY KEY (thread_id, ts, author_id),
+    FOREIGN KEY (thread_id) REFERENCES threads (id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER thread_message_reactions_delete AFTER DELETE ON thread_messages BEGIN
+    DELETE FROM thread_message_reactions WHERE thread_id = old.thread_id AND ts = old.ts;
+END;
@@ -0,0 +1,29 @@
+-- Previous revisions of edited messages, in the order they were added
+--
+-- Rows are deleted along with messages by the trigger below.
+
+CREATE TABLE thread_message_revisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts INTEGER NOT NULL,
+    thread_id INTEGER NOT NULL,
+
+    -- timestamp of the revision itself
+    revision_ts INTEGER NOT NULL,
+    sender_service_id TEXT NOT NULL,
+    sender_device INTEGER NOT NULL,
+    destination_service_id TEXT NOT NULL,
+    needs_receipt BOOLEAN NOT NULL,
+    unidentified_sender BOOLEAN NOT NULL,
+    server_guid BLOB,
+
+    -- protobuf encoded `Content` body
+    content_body BLOB NOT NULL,
+
+    FOREIGN KEY (thread_id) REFERENCES threads (id) ON DELETE CASCADE
+);
+
+CREATE INDEX thread_message_revisions_message ON thread_message_revisions (thread_id, ts);
+
+CREATE TRIGGER thread_message_revisions_delete AFTER DELETE ON thread_messages BEGIN
+    DELETE FROM thread_message_revisions WHERE thread_id = old.thread_id AND ts = old.ts;
+END;
@@ -0,0 +1,8 @@
+-- Downloaded attachments, by hex-encoded digest of their ciphertext.
+-- Attachments are decrypted to files, only their location and size are kept.
+
+CREATE TABLE attachments (
+    id TEXT PRIMARY KEY NOT NULL,
+    path TEXT NOT NULL,
+    size INTEGER NOT NULL
+);
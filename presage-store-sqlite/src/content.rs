@@ -1,217 +1,1301 @@
-use std::marker::PhantomData;
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+};
 
 use presage::{
-    libsignal_service::{prelude::Content, zkgroup::GroupMasterKeyBytes},
-    model::{contacts::Contact, groups::Group},
-    store::{ContentsStore, StickerPack},
+    libsignal_service::{
+        content::{Content, Metadata},
+        prelude::{phonenumber, ProfileKey, ProtobufMessage, Timer, Uuid},
+        proto,
+        protocol::ServiceId,
+        zkgroup::GroupMasterKeyBytes,
+        Profile,
+    },
+    model::{
+        contacts::Contact,
+        groups::Group,
+        messages::{DeliveryState, MessageReaction},
+    },
+    store::{
+        expiry::ExpiringMessage,
+        search::MessageSearch,
+        threads::{ThreadActivity, ThreadSummary},
+        ContentExt, ContentsStore, StateStore, StickerPack, Thread,
+    },
+    AvatarBytes,
 };
+use sqlx::{FromRow, SqliteConnection};
+use tracing::{debug, trace};
 
 use crate::{SqliteStore, SqliteStoreError};
 
 impl ContentsStore for SqliteStore {
     type ContentsStoreError = SqliteStoreError;
 
-    type ContactsIter = DummyIter<Result<Contact, Self::ContentsStoreError>>;
+    type ContactsIter = std::vec::IntoIter<Result<Contact, Self::ContentsStoreError>>;
+
+    type GroupsIter =
+        std::vec::IntoIter<Result<(GroupMasterKeyBytes, Group), Self::ContentsStoreError>>;
+
+    type MessagesIter = std::vec::IntoIter<Result<Content, Self::ContentsStoreError>>;
+
+    type StickerPacksIter = std::vec::IntoIter<Result<StickerPack, Self::ContentsStoreError>>;
 
-    type GroupsIter = DummyIter<Result<(GroupMasterKeyBytes, Group), Self::ContentsStoreError>>;
+    type ThreadsIter = std::vec::IntoIter<Result<ThreadSummary, Self::ContentsStoreError>>;
 
-    type MessagesIter = DummyIter<Result<Content, Self::ContentsStoreError>>;
+    type ProfileKeysIter = std::vec::IntoIter<Result<(Uuid, ProfileKey), Self::ContentsStoreError>>;
 
-    type StickerPacksIter = DummyIter<Result<StickerPack, Self::ContentsStoreError>>;
+    type SearchResultsIter =
+        std::vec::IntoIter<Result<(Thread, Content), Self::ContentsStoreError>>;
+
+    type ExpiringMessagesIter =
+        std::vec::IntoIter<Result<ExpiringMessage, Self::ContentsStoreError>>;
 
     async fn clear_profiles(&mut self) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let mut tx = self.db.begin().await?;
+        sqlx::query("DELETE FROM profiles")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM profile_keys")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM profile_avatars")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
     }
 
     async fn clear_contents(&mut self) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let mut tx = self.db.begin().await?;
+        sqlx::query("DELETE FROM contacts")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM groups").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM threads").execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(())
     }
 
     async fn clear_messages(&mut self) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let mut tx = self.db.begin().await?;
+        sqlx::query("DELETE FROM thread_messages")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_delivery_states")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_expiries")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_reactions")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_revisions")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE threads SET
+                last_message_ts = 0,
+                last_message_preview = NULL,
+                unread_count = 0,
+                last_read_ts = 0",
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
     }
 
-    async fn clear_thread(
-        &mut self,
-        thread: &presage::store::Thread,
-    ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+    async fn clear_thread(&mut self, thread: &Thread) -> Result<(), Self::ContentsStoreError> {
+        trace!(%thread, "clearing thread");
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(());
+        };
+        let mut tx = self.db.begin().await?;
+        sqlx::query("DELETE FROM thread_messages WHERE thread_id = ?")
+            .bind(thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_delivery_states WHERE thread_id = ?")
+            .bind(thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_expiries WHERE thread_id = ?")
+            .bind(thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_reactions WHERE thread_id = ?")
+            .bind(thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM thread_message_revisions WHERE thread_id = ?")
+            .bind(thread_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE threads SET
+                last_message_ts = 0,
+                last_message_preview = NULL,
+                unread_count = 0,
+                last_read_ts = 0
+            WHERE id = ?",
+        )
+        .bind(thread_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
     }
 
     async fn save_message(
         &self,
-        thread: &presage::store::Thread,
-        message: presage::libsignal_service::prelude::Content,
+        thread: &Thread,
+        message: Content,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let ts = message.timestamp();
+        trace!(%thread, ts, "storing a message with thread");
+
+        let thread_id = self.get_or_create_thread_id(thread).await?;
+        let own_aci = self
+            .load_registration_data()
+            .await?
+            .map(|data| data.service_ids.aci);
+
+        let mut tx = self.db.begin().await?;
+        let replaced: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM thread_messages WHERE thread_id = ? AND ts = ?)",
+        )
+        .bind(thread_id)
+        .bind(ts as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut activity = load_thread_activity(&mut tx, thread_id).await?;
+        activity.record(&message, replaced, own_aci);
+        update_thread_activity(&mut tx, thread_id, activity).await?;
+
+        let text = message.text().map(ToOwned::to_owned);
+        let Content { metadata, body } = message;
+        sqlx::query(
+            "INSERT OR REPLACE INTO thread_messages (
+                ts,
+                thread_id,
+                sender_service_id,
+                sender_device,
+                destination_service_id,
+                needs_receipt,
+                unidentified_sender,
+                server_guid,
+                content_body
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(ts as i64)
+        .bind(thread_id)
+        .bind(metadata.sender.service_id_string())
+        .bind(metadata.sender_device)
+        .bind(metadata.destination.service_id_string())
+        .bind(metadata.needs_receipt)
+        .bind(metadata.unidentified_sender)
+        .bind(metadata.server_guid.map(|guid| guid.as_bytes().to_vec()))
+        .bind(body.into_proto().encode_to_vec())
+        .execute(&mut *tx)
+        .await?;
+
+        // a replaced message does not fire the delete trigger
+        sqlx::query("DELETE FROM thread_messages_fts WHERE thread_id = ? AND ts = ?")
+            .bind(thread_id)
+            .bind(ts as i64)
+            .execute(&mut *tx)
+            .await?;
+        if let Some(text) = text {
+            sqlx::query("INSERT INTO thread_messages_fts (body, thread_id, ts) VALUES (?, ?, ?)")
+                .bind(text)
+                .bind(thread_id)
+                .bind(ts as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
     }
 
     async fn delete_message(
         &mut self,
-        thread: &presage::store::Thread,
+        thread: &Thread,
         timestamp: u64,
     ) -> Result<bool, Self::ContentsStoreError> {
-        todo!()
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(false);
+        };
+        let mut tx = self.db.begin().await?;
+        let result = sqlx::query("DELETE FROM thread_messages WHERE thread_id = ? AND ts = ?")
+            .bind(thread_id)
+            .bind(timestamp as i64)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let mut activity = load_thread_activity(&mut tx, thread_id).await?;
+        if activity.last_message_timestamp == timestamp {
+            let remaining: Option<SqlMessage> = sqlx::query_as(
+                "SELECT * FROM thread_messages WHERE thread_id = ? ORDER BY ts DESC LIMIT 1",
+            )
+            .bind(thread_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let remaining: Option<Content> = remaining.map(TryInto::try_into).transpose()?;
+            activity.set_last_message(remaining.as_ref());
+            update_thread_activity(&mut tx, thread_id, activity).await?;
+        }
+        tx.commit().await?;
+
+        Ok(true)
     }
 
     async fn message(
         &self,
-        thread: &presage::store::Thread,
+        thread: &Thread,
         timestamp: u64,
-    ) -> Result<Option<presage::libsignal_service::prelude::Content>, Self::ContentsStoreError>
-    {
-        todo!()
+    ) -> Result<Option<Content>, Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(None);
+        };
+        let message: Option<SqlMessage> =
+            sqlx::query_as("SELECT * FROM thread_messages WHERE thread_id = ? AND ts = ?")
+                .bind(thread_id)
+                .bind(timestamp as i64)
+                .fetch_optional(&self.db)
+                .await?;
+        message.map(TryInto::try_into).transpose()
     }
 
     async fn messages(
         &self,
-        thread: &presage::store::Thread,
-        range: impl std::ops::RangeBounds<u64>,
+        thread: &Thread,
+        range: impl RangeBounds<u64>,
     ) -> Result<Self::MessagesIter, Self::ContentsStoreError> {
-        todo!()
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(Vec::new().into_iter());
+        };
+
+        let start = match range.start_bound() {
+            Bound::Included(start) => timestamp_bound(*start),
+            Bound::Excluded(start) => timestamp_bound(*start).saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => timestamp_bound(*end),
+            Bound::Excluded(end) => timestamp_bound(*end).saturating_sub(1),
+            Bound::Unbounded => i64::MAX,
+        };
+
+        let messages: Vec<SqlMessage> = sqlx::query_as(
+            "SELECT * FROM thread_messages
+            WHERE thread_id = ? AND ts >= ? AND ts <= ?
+            ORDER BY ts ASC",
+        )
+        .bind(thread_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.db)
+        .await?;
+        debug!(%thread, count = messages.len(), "loaded messages");
+
+        Ok(messages
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Vec<_>>()
+            .into_iter())
     }
 
-    async fn clear_contacts(&mut self) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+    async fn threads(&self) -> Result<Self::ThreadsIter, Self::ContentsStoreError> {
+        let rows: Vec<SqlThreadSummary> = sqlx::query_as(
+            "SELECT
+                recipient_id,
+                group_master_key,
+                last_message_ts,
+                last_message_preview,
+                unread_count,
+                last_read_ts
+            FROM threads
+            WHERE EXISTS (SELECT 1 FROM thread_messages WHERE thread_id = threads.id)
+            ORDER BY last_message_ts DESC",
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut threads = Vec::with_capacity(rows.len());
+        for SqlThreadSummary { thread, activity } in rows {
+            let thread: Thread = thread.try_into()?;
+            let title = self.thread_title(&thread).await?;
+            threads.push(Ok(ThreadSummary {
+                thread,
+                title,
+                activity: activity.into(),
+            }));
+        }
+        Ok(threads.into_iter())
+    }
+
+    async fn delivery_states(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, DeliveryState>, Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(BTreeMap::new());
+        };
+        let rows: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+            "SELECT recipient_id, state FROM thread_message_delivery_states
+            WHERE thread_id = ? AND ts = ?",
+        )
+        .bind(thread_id)
+        .bind(timestamp as i64)
+        .fetch_all(&self.db)
+        .await?;
+        rows.into_iter()
+            .map(|(recipient, state)| -> Result<_, SqliteStoreError> {
+                Ok((
+                    Uuid::from_slice(&recipient)?,
+                    delivery_state_from_sql(state)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn update_delivery_state(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        recipient: Uuid,
+        state: DeliveryState,
+    ) -> Result<bool, Self::ContentsStoreError> {
+        let thread_id = self.get_or_create_thread_id(thread).await?;
+        let result = sqlx::query(
+            "INSERT INTO thread_message_delivery_states (ts, thread_id, recipient_id, state)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (thread_id, ts, recipient_id) DO UPDATE SET state = excluded.state WHERE excluded.state > state",
+        )
+        .bind(timestamp as i64)
+        .bind(thread_id)
+        .bind(recipient.as_bytes().as_slice())
+        .bind(state as i64)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn reactions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<BTreeMap<Uuid, MessageReaction>, Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(BTreeMap::new());
+        };
+        let rows: Vec<(Vec<u8>, String, i64, bool)> = sqlx::query_as(
+            "SELECT author_id, emoji, reaction_ts, removed FROM thread_message_reactions
+            WHERE thread_id = ? AND ts = ?",
+        )
+        .bind(thread_id)
+        .bind(timestamp as i64)
+        .fetch_all(&self.db)
+        .await?;
+        rows.into_iter()
+            .map(
+                |(author, emoji, reaction_ts, removed)| -> Result<_, SqliteStoreError> {
+                    Ok((
+                        Uuid::from_slice(&author)?,
+                        MessageReaction {
+                            emoji,
+                            timestamp: reaction_ts as u64,
+                            removed,
+                        },
+                    ))
+                },
+            )
+            .collect()
     }
 
-    async fn save_contact(
+    async fn update_reaction(
         &mut self,
-        contacts: &presage::model::contacts::Contact,
+        thread: &Thread,
+        timestamp: u64,
+        author: Uuid,
+        reaction: Option<MessageReaction>,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let thread_id = self.get_or_create_thread_id(thread).await?;
+        match reaction {
+            Some(MessageReaction {
+                emoji,
+                timestamp: reaction_ts,
+                removed,
+            }) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO thread_message_reactions
+                        (ts, thread_id, author_id, emoji, reaction_ts, removed)
+                    VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(timestamp as i64)
+                .bind(thread_id)
+                .bind(author.as_bytes().as_slice())
+                .bind(emoji)
+                .bind(reaction_ts as i64)
+                .bind(removed)
+                .execute(&self.db)
+                .await?;
+            }
+            None => {
+                sqlx::query(
+                    "DELETE FROM thread_message_reactions
+                    WHERE thread_id = ? AND ts = ? AND author_id = ?",
+                )
+                .bind(thread_id)
+                .bind(timestamp as i64)
+                .bind(author.as_bytes().as_slice())
+                .execute(&self.db)
+                .await?;
+            }
+        }
+        Ok(())
     }
 
-    async fn contacts(&self) -> Result<Self::ContactsIter, Self::ContentsStoreError> {
-        todo!()
+    async fn message_revisions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<Vec<Content>, Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(Vec::new());
+        };
+        let revisions: Vec<SqlMessage> = sqlx::query_as(
+            "SELECT
+                revision_ts AS ts,
+                sender_service_id,
+                sender_device,
+                destination_service_id,
+                needs_receipt,
+                unidentified_sender,
+                server_guid,
+                content_body
+            FROM thread_message_revisions
+            WHERE thread_id = ? AND ts = ?
+            ORDER BY id ASC",
+        )
+        .bind(thread_id)
+        .bind(timestamp as i64)
+        .fetch_all(&self.db)
+        .await?;
+        revisions.into_iter().map(TryInto::try_into).collect()
+    }
+
+    async fn add_message_revision(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        revision: Content,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(());
+        };
+        let Content { metadata, body } = revision;
+        sqlx::query(
+            "INSERT INTO thread_message_revisions (
+                ts,
+                thread_id,
+                revision_ts,
+                sender_service_id,
+                sender_device,
+                destination_service_id,
+                needs_receipt,
+                unidentified_sender,
+                server_guid,
+                content_body
+            )
+            SELECT ts, thread_id, ?, ?, ?, ?, ?, ?, ?, ? FROM thread_messages
+            WHERE thread_id = ? AND ts = ?",
+        )
+        .bind(metadata.timestamp as i64)
+        .bind(metadata.sender.service_id_string())
+        .bind(metadata.sender_device)
+        .bind(metadata.destination.service_id_string())
+        .bind(metadata.needs_receipt)
+        .bind(metadata.unidentified_sender)
+        .bind(metadata.server_guid.map(|guid| guid.as_bytes().to_vec()))
+        .bind(body.into_proto().encode_to_vec())
+        .bind(thread_id)
+        .bind(timestamp as i64)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_message_expiry(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        expires_at: u64,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT OR IGNORE INTO thread_message_expiries (ts, thread_id, expires_at)
+            SELECT ts, thread_id, ? FROM thread_messages WHERE thread_id = ? AND ts = ?",
+        )
+        .bind(expires_at as i64)
+        .bind(thread_id)
+        .bind(timestamp as i64)
+        .execute(&self.db)
+        .await?;
+        Ok(())
     }
 
-    async fn contact_by_id(
+    async fn expiring_messages(
         &self,
-        id: &presage::libsignal_service::prelude::Uuid,
-    ) -> Result<Option<presage::model::contacts::Contact>, Self::ContentsStoreError> {
-        todo!()
+    ) -> Result<Self::ExpiringMessagesIter, Self::ContentsStoreError> {
+        let rows: Vec<SqlExpiringMessage> = sqlx::query_as(
+            "SELECT
+                threads.recipient_id,
+                threads.group_master_key,
+                thread_message_expiries.ts,
+                thread_message_expiries.expires_at
+            FROM thread_message_expiries
+            JOIN threads ON threads.id = thread_message_expiries.thread_id
+            ORDER BY thread_message_expiries.expires_at ASC, thread_message_expiries.ts ASC",
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| -> Result<ExpiringMessage, SqliteStoreError> {
+                Ok(ExpiringMessage {
+                    thread: row.thread.try_into()?,
+                    timestamp: row.ts as u64,
+                    expires_at: row.expires_at as u64,
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    async fn thread_activity(
+        &self,
+        thread: &Thread,
+    ) -> Result<Option<ThreadActivity>, Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(None);
+        };
+        let mut connection = self.db.acquire().await?;
+        Ok(Some(
+            load_thread_activity(&mut connection, thread_id).await?,
+        ))
+    }
+
+    async fn mark_thread_read(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<(), Self::ContentsStoreError> {
+        let Some(thread_id) = self.thread_id(thread).await? else {
+            return Ok(());
+        };
+        let own_aci = self
+            .load_registration_data()
+            .await?
+            .map(|data| data.service_ids.aci);
+
+        let mut tx = self.db.begin().await?;
+        let mut activity = load_thread_activity(&mut tx, thread_id).await?;
+        if timestamp <= activity.last_read_timestamp {
+            return Ok(());
+        }
+        let later_messages: Vec<SqlMessage> =
+            sqlx::query_as("SELECT * FROM thread_messages WHERE thread_id = ? AND ts > ?")
+                .bind(thread_id)
+                .bind(timestamp as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+        let later_messages = later_messages
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<Content>, _>>()?;
+        activity.mark_read(timestamp, &later_messages, own_aci);
+        trace!(%thread, timestamp, unread_count = activity.unread_count, "marked thread as read");
+        update_thread_activity(&mut tx, thread_id, activity).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+    ) -> Result<Self::SearchResultsIter, Self::ContentsStoreError> {
+        let terms = search.terms();
+        if terms.is_empty() {
+            return Ok(Vec::new().into_iter());
+        }
+        // every term is a quoted string, so they are all required and never parsed as operators
+        let query = terms
+            .iter()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let thread_id = match &search.thread {
+            Some(thread) => match self.thread_id(thread).await? {
+                Some(thread_id) => Some(thread_id),
+                None => return Ok(Vec::new().into_iter()),
+            },
+            None => None,
+        };
+
+        let results: Vec<SqlSearchResult> = sqlx::query_as(
+            "SELECT threads.recipient_id, threads.group_master_key, thread_messages.*
+            FROM thread_messages_fts
+            JOIN thread_messages
+                ON thread_messages.thread_id = thread_messages_fts.thread_id
+                AND thread_messages.ts = thread_messages_fts.ts
+            JOIN threads ON threads.id = thread_messages.thread_id
+            WHERE thread_messages_fts MATCH ?
+                AND (? IS NULL OR thread_messages.thread_id = ?)
+                AND thread_messages.ts >= ? AND thread_messages.ts <= ?
+            ORDER BY thread_messages.ts DESC",
+        )
+        .bind(query)
+        .bind(thread_id)
+        .bind(thread_id)
+        .bind(search.from.map_or(0, timestamp_bound))
+        .bind(search.until.map_or(i64::MAX, timestamp_bound))
+        .fetch_all(&self.db)
+        .await?;
+        debug!(count = results.len(), "found messages");
+
+        Ok(results
+            .into_iter()
+            .map(|result| -> Result<(Thread, Content), SqliteStoreError> {
+                Ok((result.thread.try_into()?, result.message.try_into()?))
+            })
+            .filter(|result| {
+                // the sender is only known once the message is decoded
+                result
+                    .as_ref()
+                    .map_or(true, |(thread, content)| search.matches(thread, content))
+            })
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    async fn clear_contacts(&mut self) -> Result<(), Self::ContentsStoreError> {
+        sqlx::query("DELETE FROM contacts")
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn save_contact(&mut self, contact: &Contact) -> Result<(), Self::ContentsStoreError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO contacts (
+                uuid,
+                phone_number,
+                name,
+                color,
+                verified,
+                profile_key,
+                expire_timer,
+                expire_timer_version,
+                inbox_position,
+                archived
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(contact.uuid.as_bytes().as_slice())
+        .bind(contact.phone_number.as_ref().map(|p| p.to_string()))
+        .bind(&contact.name)
+        .bind(&contact.color)
+        .bind(contact.verified.encode_to_vec())
+        .bind(&contact.profile_key)
+        .bind(contact.expire_timer)
+        .bind(contact.expire_timer_version)
+        .bind(contact.inbox_position)
+        .bind(contact.archived)
+        .execute(&self.db)
+        .await?;
+        debug!("saved contact");
+        Ok(())
+    }
+
+    async fn contacts(&self) -> Result<Self::ContactsIter, Self::ContentsStoreError> {
+        let contacts: Vec<SqlContact> = sqlx::query_as("SELECT * FROM contacts")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(contacts
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    async fn contact_by_id(&self, id: &Uuid) -> Result<Option<Contact>, Self::ContentsStoreError> {
+        let contact: Option<SqlContact> = sqlx::query_as("SELECT * FROM contacts WHERE uuid = ?")
+            .bind(id.as_bytes().as_slice())
+            .fetch_optional(&self.db)
+            .await?;
+        contact.map(TryInto::try_into).transpose()
     }
 
     async fn clear_groups(&mut self) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        sqlx::query("DELETE FROM groups").execute(&self.db).await?;
+        Ok(())
     }
 
     async fn save_group(
         &self,
-        master_key: presage::libsignal_service::zkgroup::GroupMasterKeyBytes,
-        group: impl Into<presage::model::groups::Group>,
+        master_key: GroupMasterKeyBytes,
+        group: impl Into<Group>,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        let group = group.into();
+        sqlx::query(
+            "INSERT OR REPLACE INTO groups (
+                master_key,
+                title,
+                revision,
+                invite_link_password,
+                access_control,
+                avatar,
+                description,
+                members,
+                pending_members,
+                requesting_members,
+                disappearing_messages_timer
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(master_key.as_slice())
+        .bind(&group.title)
+        .bind(group.revision)
+        .bind(&group.invite_link_password)
+        .bind(
+            group
+                .access_control
+                .as_ref()
+                .map(serde_json::to_vec)
+                .transpose()?,
+        )
+        .bind(&group.avatar)
+        .bind(&group.description)
+        .bind(serde_json::to_vec(&group.members)?)
+        .bind(serde_json::to_vec(&group.pending_members)?)
+        .bind(serde_json::to_vec(&group.requesting_members)?)
+        .bind(
+            group
+                .disappearing_messages_timer
+                .map(|timer| timer.duration),
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
     }
 
     async fn groups(&self) -> Result<Self::GroupsIter, Self::ContentsStoreError> {
-        todo!()
+        let groups: Vec<SqlGroup> = sqlx::query_as("SELECT * FROM groups")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(groups
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Vec<_>>()
+            .into_iter())
     }
 
     async fn group(
         &self,
-        master_key: presage::libsignal_service::zkgroup::GroupMasterKeyBytes,
-    ) -> Result<Option<presage::model::groups::Group>, Self::ContentsStoreError> {
-        todo!()
+        master_key: GroupMasterKeyBytes,
+    ) -> Result<Option<Group>, Self::ContentsStoreError> {
+        let group: Option<SqlGroup> = sqlx::query_as("SELECT * FROM groups WHERE master_key = ?")
+            .bind(master_key.as_slice())
+            .fetch_optional(&self.db)
+            .await?;
+        group
+            .map(|group| group.try_into().map(|(_, group)| group))
+            .transpose()
     }
 
     async fn save_group_avatar(
         &self,
-        master_key: presage::libsignal_service::zkgroup::GroupMasterKeyBytes,
-        avatar: &presage::AvatarBytes,
+        master_key: GroupMasterKeyBytes,
+        avatar: &AvatarBytes,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        sqlx::query("INSERT OR REPLACE INTO group_avatars (master_key, bytes) VALUES (?, ?)")
+            .bind(master_key.as_slice())
+            .bind(avatar)
+            .execute(&self.db)
+            .await?;
+        Ok(())
     }
 
     async fn group_avatar(
         &self,
-        master_key: presage::libsignal_service::zkgroup::GroupMasterKeyBytes,
-    ) -> Result<Option<presage::AvatarBytes>, Self::ContentsStoreError> {
-        todo!()
+        master_key: GroupMasterKeyBytes,
+    ) -> Result<Option<AvatarBytes>, Self::ContentsStoreError> {
+        Ok(
+            sqlx::query_scalar("SELECT bytes FROM group_avatars WHERE master_key = ?")
+                .bind(master_key.as_slice())
+                .fetch_optional(&self.db)
+                .await?,
+        )
     }
 
     async fn upsert_profile_key(
         &mut self,
-        uuid: &presage::libsignal_service::prelude::Uuid,
-        key: presage::libsignal_service::prelude::ProfileKey,
+        uuid: &Uuid,
+        key: ProfileKey,
     ) -> Result<bool, Self::ContentsStoreError> {
-        todo!()
+        let mut tx = self.db.begin().await?;
+        let replaced: Option<i64> = sqlx::query_scalar("SELECT 1 FROM profile_keys WHERE uuid = ?")
+            .bind(uuid.as_bytes().as_slice())
+            .fetch_optional(&mut *tx)
+            .await?;
+        sqlx::query("INSERT OR REPLACE INTO profile_keys (uuid, key) VALUES (?, ?)")
+            .bind(uuid.as_bytes().as_slice())
+            .bind(key.get_bytes().as_slice())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(replaced.is_some())
     }
 
     async fn profile_key(
         &self,
-        uuid: &presage::libsignal_service::prelude::Uuid,
-    ) -> Result<Option<presage::libsignal_service::prelude::ProfileKey>, Self::ContentsStoreError>
-    {
-        todo!()
+        uuid: &Uuid,
+    ) -> Result<Option<ProfileKey>, Self::ContentsStoreError> {
+        let key: Option<Vec<u8>> =
+            sqlx::query_scalar("SELECT key FROM profile_keys WHERE uuid = ?")
+                .bind(uuid.as_bytes().as_slice())
+                .fetch_optional(&self.db)
+                .await?;
+        key.map(|key| {
+            key.try_into()
+                .map(ProfileKey::create)
+                .map_err(|_| SqliteStoreError::InvalidProfileKey)
+        })
+        .transpose()
+    }
+
+    async fn profile_keys(&self) -> Result<Self::ProfileKeysIter, Self::ContentsStoreError> {
+        let keys: Vec<(Vec<u8>, Vec<u8>)> = sqlx::query_as("SELECT uuid, key FROM profile_keys")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(keys
+            .into_iter()
+            .map(|(uuid, key)| {
+                Ok((
+                    Uuid::from_slice(&uuid)?,
+                    key.try_into()
+                        .map(ProfileKey::create)
+                        .map_err(|_| SqliteStoreError::InvalidProfileKey)?,
+                ))
+            })
+            .collect::<Vec<_>>()
+            .into_iter())
     }
 
     async fn save_profile(
         &mut self,
-        uuid: presage::libsignal_service::prelude::Uuid,
-        key: presage::libsignal_service::prelude::ProfileKey,
-        profile: presage::libsignal_service::Profile,
+        uuid: Uuid,
+        key: ProfileKey,
+        profile: Profile,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        sqlx::query("INSERT OR REPLACE INTO profiles (uuid, key, data) VALUES (?, ?, ?)")
+            .bind(uuid.as_bytes().as_slice())
+            .bind(key.get_bytes().as_slice())
+            .bind(serde_json::to_vec(&profile)?)
+            .execute(&self.db)
+            .await?;
+        Ok(())
     }
 
     async fn profile(
         &self,
-        uuid: presage::libsignal_service::prelude::Uuid,
-        key: presage::libsignal_service::prelude::ProfileKey,
-    ) -> Result<Option<presage::libsignal_service::Profile>, Self::ContentsStoreError> {
-        todo!()
+        uuid: Uuid,
+        key: ProfileKey,
+    ) -> Result<Option<Profile>, Self::ContentsStoreError> {
+        let data: Option<Vec<u8>> =
+            sqlx::query_scalar("SELECT data FROM profiles WHERE uuid = ? AND key = ?")
+                .bind(uuid.as_bytes().as_slice())
+                .bind(key.get_bytes().as_slice())
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(data.map(|data| serde_json::from_slice(&data)).transpose()?)
     }
 
     async fn save_profile_avatar(
         &mut self,
-        uuid: presage::libsignal_service::prelude::Uuid,
-        key: presage::libsignal_service::prelude::ProfileKey,
-        profile: &presage::AvatarBytes,
+        uuid: Uuid,
+        key: ProfileKey,
+        avatar: &AvatarBytes,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        sqlx::query("INSERT OR REPLACE INTO profile_avatars (uuid, key, bytes) VALUES (?, ?, ?)")
+            .bind(uuid.as_bytes().as_slice())
+            .bind(key.get_bytes().as_slice())
+            .bind(avatar)
+            .execute(&self.db)
+            .await?;
+        Ok(())
     }
 
     async fn profile_avatar(
         &self,
-        uuid: presage::libsignal_service::prelude::Uuid,
-        key: presage::libsignal_service::prelude::ProfileKey,
-    ) -> Result<Option<presage::AvatarBytes>, Self::ContentsStoreError> {
-        todo!()
+        uuid: Uuid,
+        key: ProfileKey,
+    ) -> Result<Option<AvatarBytes>, Self::ContentsStoreError> {
+        Ok(
+            sqlx::query_scalar("SELECT bytes FROM profile_avatars WHERE uuid = ? AND key = ?")
+                .bind(uuid.as_bytes().as_slice())
+                .bind(key.get_bytes().as_slice())
+                .fetch_optional(&self.db)
+                .await?,
+        )
     }
 
     async fn add_sticker_pack(
         &mut self,
-        pack: &presage::store::StickerPack,
+        pack: &StickerPack,
     ) -> Result<(), Self::ContentsStoreError> {
-        todo!()
+        sqlx::query("INSERT OR REPLACE INTO sticker_packs (id, key, manifest) VALUES (?, ?, ?)")
+            .bind(&pack.id)
+            .bind(&pack.key)
+            .bind(serde_json::to_vec(&pack.manifest)?)
+            .execute(&self.db)
+            .await?;
+        Ok(())
     }
 
     async fn sticker_pack(
         &self,
         id: &[u8],
-    ) -> Result<Option<presage::store::StickerPack>, Self::ContentsStoreError> {
-        todo!()
+    ) -> Result<Option<StickerPack>, Self::ContentsStoreError> {
+        let pack: Option<SqlStickerPack> =
+            sqlx::query_as("SELECT * FROM sticker_packs WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+        pack.map(TryInto::try_into).transpose()
     }
 
     async fn remove_sticker_pack(&mut self, id: &[u8]) -> Result<bool, Self::ContentsStoreError> {
-        todo!()
+        let result = sqlx::query("DELETE FROM sticker_packs WHERE id = ?")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
     }
 
     async fn sticker_packs(&self) -> Result<Self::StickerPacksIter, Self::ContentsStoreError> {
-        todo!()
+        let packs: Vec<SqlStickerPack> = sqlx::query_as("SELECT * FROM sticker_packs")
+            .fetch_all(&self.db)
+            .await?;
+        Ok(packs
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+}
+
+impl SqliteStore {
+    /// Returns the identifier of a thread, if any message was ever stored in it
+    async fn thread_id(&self, thread: &Thread) -> Result<Option<i64>, SqliteStoreError> {
+        let thread_id = match thread {
+            Thread::Contact(uuid) => {
+                sqlx::query_scalar("SELECT id FROM threads WHERE recipient_id = ?")
+                    .bind(uuid.as_bytes().as_slice())
+                    .fetch_optional(&self.db)
+                    .await?
+            }
+            Thread::Group(master_key) => {
+                sqlx::query_scalar("SELECT id FROM threads WHERE group_master_key = ?")
+                    .bind(master_key.as_slice())
+                    .fetch_optional(&self.db)
+                    .await?
+            }
+        };
+        Ok(thread_id)
+    }
+
+    async fn get_or_create_thread_id(&self, thread: &Thread) -> Result<i64, SqliteStoreError> {
+        if let Some(thread_id) = self.thread_id(thread).await? {
+            return Ok(thread_id);
+        }
+
+        let query = match thread {
+            Thread::Contact(uuid) => sqlx::query("INSERT INTO threads (recipient_id) VALUES (?)")
+                .bind(uuid.as_bytes().to_vec()),
+            Thread::Group(master_key) => {
+                sqlx::query("INSERT INTO threads (group_master_key) VALUES (?)")
+                    .bind(master_key.to_vec())
+            }
+        };
+        Ok(query.execute(&self.db).await?.last_insert_rowid())
+    }
+}
+
+#[derive(FromRow)]
+struct SqlThread {
+    recipient_id: Option<Vec<u8>>,
+    group_master_key: Option<Vec<u8>>,
+}
+
+impl TryFrom<SqlThread> for Thread {
+    type Error = SqliteStoreError;
+
+    fn try_from(thread: SqlThread) -> Result<Self, Self::Error> {
+        match (thread.recipient_id, thread.group_master_key) {
+            (Some(uuid), _) => Ok(Thread::Contact(Uuid::from_slice(&uuid)?)),
+            (None, Some(master_key)) => Ok(Thread::Group(
+                master_key
+                    .try_into()
+                    .map_err(|_| SqliteStoreError::InvalidGroupMasterKey)?,
+            )),
+            (None, None) => unreachable!("enforced by a CHECK constraint"),
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct SqlThreadActivity {
+    last_message_ts: i64,
+    last_message_preview: Option<String>,
+    unread_count: i64,
+    last_read_ts: i64,
+}
+
+impl From<SqlThreadActivity> for ThreadActivity {
+    fn from(activity: SqlThreadActivity) -> Self {
+        Self {
+            last_message_timestamp: activity.last_message_ts as u64,
+            last_message_preview: activity.last_message_preview,
+            unread_count: activity.unread_count as u32,
+            last_read_timestamp: activity.last_read_ts as u64,
+        }
+    }
+}
+
+fn delivery_state_from_sql(state: i64) -> Result<DeliveryState, SqliteStoreError> {
+    Ok(match state {
+        0 => DeliveryState::Sent,
+        1 => DeliveryState::Delivered,
+        2 => DeliveryState::Read,
+        3 => DeliveryState::Viewed,
+        _ => return Err(SqliteStoreError::InvalidDeliveryState(state)),
+    })
+}
+
+/// Bound of a range of timestamps, stored as `i64`: larger timestamps are clamped to the last one
+fn timestamp_bound(timestamp: u64) -> i64 {
+    i64::try_from(timestamp).unwrap_or(i64::MAX)
+}
+
+async fn load_thread_activity(
+    connection: &mut SqliteConnection,
+    thread_id: i64,
+) -> Result<ThreadActivity, SqliteStoreError> {
+    let activity: SqlThreadActivity = sqlx::query_as(
+        "SELECT last_message_ts, last_message_preview, unread_count, last_read_ts
+        FROM threads WHERE id = ?",
+    )
+    .bind(thread_id)
+    .fetch_one(connection)
+    .await?;
+    Ok(activity.into())
+}
+
+async fn update_thread_activity(
+    connection: &mut SqliteConnection,
+    thread_id: i64,
+    activity: ThreadActivity,
+) -> Result<(), SqliteStoreError> {
+    sqlx::query(
+        "UPDATE threads SET
+            last_message_ts = ?,
+            last_message_preview = ?,
+            unread_count = ?,
+            last_read_ts = ?
+        WHERE id = ?",
+    )
+    .bind(activity.last_message_timestamp as i64)
+    .bind(activity.last_message_preview)
+    .bind(activity.unread_count)
+    .bind(activity.last_read_timestamp as i64)
+    .bind(thread_id)
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+#[derive(FromRow)]
+struct SqlThreadSummary {
+    #[sqlx(flatten)]
+    thread: SqlThread,
+    #[sqlx(flatten)]
+    activity: SqlThreadActivity,
+}
+
+#[derive(FromRow)]
+struct SqlExpiringMessage {
+    #[sqlx(flatten)]
+    thread: SqlThread,
+    ts: i64,
+    expires_at: i64,
+}
+
+#[derive(FromRow)]
+struct SqlSearchResult {
+    #[sqlx(flatten)]
+    thread: SqlThread,
+    #[sqlx(flatten)]
+    message: SqlMessage,
+}
+
+#[derive(FromRow)]
+struct SqlMessage {
+    ts: i64,
+    sender_service_id: String,
+    sender_device: u32,
+    destination_service_id: String,
+    needs_receipt: bool,
+    unidentified_sender: bool,
+    server_guid: Option<Vec<u8>>,
+    content_body: Vec<u8>,
+}
+
+impl TryFrom<SqlMessage> for Content {
+    type Error = SqliteStoreError;
+
+    fn try_from(message: SqlMessage) -> Result<Self, Self::Error> {
+        let metadata = Metadata {
+            sender: parse_service_id(&message.sender_service_id)?,
+            destination: parse_service_id(&message.destination_service_id)?,
+            sender_device: message.sender_device,
+            server_guid: message
+                .server_guid
+                .map(|guid| Uuid::from_slice(&guid))
+                .transpose()?,
+            timestamp: message.ts as u64,
+            needs_receipt: message.needs_receipt,
+            unidentified_sender: message.unidentified_sender,
+        };
+        let body = proto::Content::decode(message.content_body.as_slice())?;
+        Content::from_proto(body, metadata).map_err(|_| SqliteStoreError::UnsupportedContent)
+    }
+}
+
+fn parse_service_id(service_id: &str) -> Result<ServiceId, SqliteStoreError> {
+    ServiceId::parse_from_service_id_string(service_id)
+        .ok_or_else(|| SqliteStoreError::InvalidServiceId(service_id.to_owned()))
+}
+
+#[derive(FromRow)]
+struct SqlContact {
+    uuid: Vec<u8>,
+    phone_number: Option<String>,
+    name: String,
+    color: Option<String>,
+    verified: Vec<u8>,
+    profile_key: Vec<u8>,
+    expire_timer: u32,
+    expire_timer_version: u32,
+    inbox_position: u32,
+    archived: bool,
+}
+
+impl TryFrom<SqlContact> for Contact {
+    type Error = SqliteStoreError;
+
+    fn try_from(contact: SqlContact) -> Result<Self, Self::Error> {
+        Ok(Contact {
+            uuid: Uuid::from_slice(&contact.uuid)?,
+            phone_number: contact
+                .phone_number
+                .map(|p| phonenumber::parse(None, p))
+                .transpose()?,
+            name: contact.name,
+            color: contact.color,
+            verified: proto::Verified::decode(contact.verified.as_slice())?,
+            profile_key: contact.profile_key,
+            expire_timer: contact.expire_timer,
+            expire_timer_version: contact.expire_timer_version,
+            inbox_position: contact.inbox_position,
+            archived: contact.archived,
+            avatar: None,
+        })
+    }
+}
+
+#[derive(FromRow)]
+struct SqlGroup {
+    master_key: Vec<u8>,
+    title: String,
+    revision: u32,
+    invite_link_password: Option<Vec<u8>>,
+    access_control: Option<Vec<u8>>,
+    avatar: String,
+    description: Option<String>,
+    members: Vec<u8>,
+    pending_members: Vec<u8>,
+    requesting_members: Vec<u8>,
+    disappearing_messages_timer: Option<u32>,
+}
+
+impl TryFrom<SqlGroup> for (GroupMasterKeyBytes, Group) {
+    type Error = SqliteStoreError;
+
+    fn try_from(group: SqlGroup) -> Result<Self, Self::Error> {
+        let master_key = group
+            .master_key
+            .try_into()
+            .map_err(|_| SqliteStoreError::InvalidGroupMasterKey)?;
+        Ok((
+            master_key,
+            Group {
+                title: group.title,
+                avatar: group.avatar,
+                disappearing_messages_timer: group
+                    .disappearing_messages_timer
+                    .map(|duration| Timer { duration }),
+                access_control: group
+                    .access_control
+                    .map(|data| serde_json::from_slice(&data))
+                    .transpose()?,
+                revision: group.revision,
+                members: serde_json::from_slice(&group.members)?,
+                pending_members: serde_json::from_slice(&group.pending_members)?,
+                requesting_members: serde_json::from_slice(&group.requesting_members)?,
+                invite_link_password: group.invite_link_password.unwrap_or_default(),
+                description: group.description,
+            },
+        ))
     }
 }
 
-pub struct DummyIter<T> {
-    _data: PhantomData<T>,
+#[derive(FromRow)]
+struct SqlStickerPack {
+    id: Vec<u8>,
+    key: Vec<u8>,
+    manifest: Vec<u8>,
 }
 
-impl<T> Iterator for DummyIter<T> {
-    type Item = T;
+impl TryFrom<SqlStickerPack> for StickerPack {
+    type Error = SqliteStoreError;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+    fn try_from(pack: SqlStickerPack) -> Result<Self, Self::Error> {
+        Ok(StickerPack {
+            id: pack.id,
+            key: pack.key,
+            manifest: serde_json::from_slice(&pack.manifest)?,
+        })
     }
 }
@@ -1,11 +1,47 @@
-use presage::store::StoreError;
+use presage::{
+    libsignal_service::{
+        prelude::{phonenumber, UuidError},
+        protocol::SignalProtocolError,
+    },
+    store::StoreError,
+};
+use tracing::error;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SqliteStoreError {
     #[error("database migration is not supported")]
     MigrationConflict,
+    #[error("database migration failed: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
     #[error("data store error: {0}")]
     Db(#[from] sqlx::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Prost error: {0}")]
+    ProtobufDecode(#[from] prost::DecodeError),
+    #[error("UUID decoding error: {0}")]
+    Uuid(#[from] UuidError),
+    #[error("phone number parsing error: {0}")]
+    PhoneNumber(#[from] phonenumber::ParseError),
+    #[error("libsignal-protocol error: {0}")]
+    Protocol(#[from] SignalProtocolError),
+    #[error("invalid service ID: {0}")]
+    InvalidServiceId(String),
+    #[error("invalid group master key")]
+    InvalidGroupMasterKey,
+    #[error("invalid profile key")]
+    InvalidProfileKey,
+    #[error("invalid delivery state: {0}")]
+    InvalidDeliveryState(i64),
+    #[error("Unsupported message content")]
+    UnsupportedContent,
 }
 
 impl StoreError for SqliteStoreError {}
+
+impl From<SqliteStoreError> for SignalProtocolError {
+    fn from(error: SqliteStoreError) -> Self {
+        error!(%error, "presage store error");
+        Self::InvalidState("presage store error", error.to_string())
+    }
+}
@@ -1,12 +1,15 @@
-#![allow(warnings)]
-
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use presage::{
+    libsignal_service::protocol::IdentityKeyPair,
+    manager::RegistrationData,
     model::identity::OnNewIdentity,
-    store::{StateStore, Store},
+    store::{
+        attachments::{AttachmentId, DownloadedAttachment},
+        AttachmentsStore, ContentsStore, StateStore, Store,
+    },
 };
-use protocol::SqliteProtocolStore;
+use protocol::{IdentityType, SqliteProtocolStore};
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 
 mod content;
@@ -15,26 +18,90 @@ mod protocol;
 
 pub use error::SqliteStoreError;
 
+const CONFIG_KEY_REGISTRATION: &str = "registration";
+const CONFIG_KEY_ACI_IDENTITY_KEY_PAIR: &str = "aci_identity_key_pair";
+const CONFIG_KEY_PNI_IDENTITY_KEY_PAIR: &str = "pni_identity_key_pair";
+
 #[derive(Debug, Clone)]
 pub struct SqliteStore {
-    db: SqlitePool,
+    pub(crate) db: SqlitePool,
     /// Whether to trust new identities automatically (for instance, when a somebody's phone has changed)
-    trust_new_identities: OnNewIdentity,
+    pub(crate) trust_new_identities: OnNewIdentity,
 }
 
 impl SqliteStore {
+    /// Opens (or creates) the database at `db_path` and runs all pending schema migrations.
     pub async fn open(
         db_path: impl AsRef<Path>,
         trust_new_identities: OnNewIdentity,
     ) -> Result<Self, SqliteStoreError> {
-        let connect_options = SqliteConnectOptions::new().filename(db_path);
+        let connect_options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .create_if_missing(true);
         let pool = SqlitePool::connect_with(connect_options).await?;
 
+        Self::with_pool(pool, trust_new_identities).await
+    }
+
+    async fn with_pool(
+        pool: SqlitePool,
+        trust_new_identities: OnNewIdentity,
+    ) -> Result<Self, SqliteStoreError> {
+        sqlx::migrate!().run(&pool).await?;
+
         Ok(Self {
             db: pool,
             trust_new_identities,
         })
     }
+
+    #[cfg(test)]
+    async fn temporary() -> Result<Self, SqliteStoreError> {
+        use std::str::FromStr;
+
+        use sqlx::sqlite::SqlitePoolOptions;
+
+        // every connection to an in-memory database opens a new database, so make sure
+        // that the pool only ever holds a single connection which is never closed
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+            .await?;
+
+        Self::with_pool(pool, OnNewIdentity::Reject).await
+    }
+
+    async fn config(&self, key: &str) -> Result<Option<Vec<u8>>, SqliteStoreError> {
+        Ok(sqlx::query_scalar("SELECT value FROM config WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    async fn set_config(&self, key: &str, value: Vec<u8>) -> Result<(), SqliteStoreError> {
+        sqlx::query("INSERT OR REPLACE INTO config (key, value) VALUES (?, ?)")
+            .bind(key)
+            .bind(value)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn identity_key_pair(
+        &self,
+        identity: IdentityType,
+    ) -> Result<Option<IdentityKeyPair>, SqliteStoreError> {
+        let key = match identity {
+            IdentityType::Aci => CONFIG_KEY_ACI_IDENTITY_KEY_PAIR,
+            IdentityType::Pni => CONFIG_KEY_PNI_IDENTITY_KEY_PAIR,
+        };
+        let Some(key_bytes) = self.config(key).await? else {
+            return Ok(None);
+        };
+        Ok(Some(IdentityKeyPair::try_from(key_bytes.as_slice())?))
+    }
 }
 
 impl Store for SqliteStore {
@@ -45,18 +112,24 @@ impl Store for SqliteStore {
     type PniStore = SqliteProtocolStore;
 
     async fn clear(&mut self) -> Result<(), SqliteStoreError> {
-        todo!()
+        self.clear_registration().await?;
+        self.clear_contents().await?;
+        self.clear_attachments().await?;
+
+        Ok(())
     }
 
     fn aci_protocol_store(&self) -> Self::AciStore {
         SqliteProtocolStore {
             store: self.clone(),
+            identity: IdentityType::Aci,
         }
     }
 
     fn pni_protocol_store(&self) -> Self::PniStore {
         SqliteProtocolStore {
             store: self.clone(),
+            identity: IdentityType::Pni,
         }
     }
 }
@@ -66,36 +139,128 @@ impl StateStore for SqliteStore {
 
     async fn load_registration_data(
         &self,
-    ) -> Result<Option<presage::manager::RegistrationData>, Self::StateStoreError> {
-        todo!()
+    ) -> Result<Option<RegistrationData>, Self::StateStoreError> {
+        self.config(CONFIG_KEY_REGISTRATION)
+            .await?
+            .map(|data| serde_json::from_slice(&data))
+            .transpose()
+            .map_err(From::from)
     }
 
     async fn set_aci_identity_key_pair(
         &self,
-        key_pair: presage::libsignal_service::protocol::IdentityKeyPair,
+        key_pair: IdentityKeyPair,
     ) -> Result<(), Self::StateStoreError> {
-        todo!()
+        self.set_config(
+            CONFIG_KEY_ACI_IDENTITY_KEY_PAIR,
+            key_pair.serialize().into(),
+        )
+        .await
     }
 
     async fn set_pni_identity_key_pair(
         &self,
-        key_pair: presage::libsignal_service::protocol::IdentityKeyPair,
+        key_pair: IdentityKeyPair,
     ) -> Result<(), Self::StateStoreError> {
-        todo!()
+        self.set_config(
+            CONFIG_KEY_PNI_IDENTITY_KEY_PAIR,
+            key_pair.serialize().into(),
+        )
+        .await
     }
 
     async fn save_registration_data(
         &mut self,
-        state: &presage::manager::RegistrationData,
+        state: &RegistrationData,
     ) -> Result<(), Self::StateStoreError> {
-        todo!()
+        self.set_config(CONFIG_KEY_REGISTRATION, serde_json::to_vec(state)?)
+            .await
     }
 
     async fn is_registered(&self) -> bool {
-        todo!()
+        self.load_registration_data()
+            .await
+            .unwrap_or_default()
+            .is_some()
     }
 
     async fn clear_registration(&mut self) -> Result<(), Self::StateStoreError> {
-        todo!()
+        // drop registration data (includes identity keys)
+        sqlx::query("DELETE FROM config").execute(&self.db).await?;
+
+        // drop all saved profile (+avatars) and profile keys
+        self.clear_profiles().await?;
+
+        // drop all keys
+        self.aci_protocol_store().clear(true).await?;
+        self.pni_protocol_store().clear(true).await?;
+
+        Ok(())
+    }
+}
+
+impl AttachmentsStore for SqliteStore {
+    type AttachmentsStoreError = SqliteStoreError;
+
+    async fn attachment(
+        &self,
+        id: &AttachmentId,
+    ) -> Result<Option<DownloadedAttachment>, SqliteStoreError> {
+        let row: Option<(String, i64)> =
+            sqlx::query_as("SELECT path, size FROM attachments WHERE id = ?")
+                .bind(id.as_str())
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(row.map(|(path, size)| DownloadedAttachment {
+            path: PathBuf::from(path),
+            size: size as u64,
+        }))
+    }
+
+    async fn save_attachment(
+        &mut self,
+        id: &AttachmentId,
+        attachment: &DownloadedAttachment,
+    ) -> Result<(), SqliteStoreError> {
+        sqlx::query("INSERT OR REPLACE INTO attachments (id, path, size) VALUES (?, ?, ?)")
+            .bind(id.as_str())
+            .bind(attachment.path.to_string_lossy().into_owned())
+            .bind(attachment.size as i64)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_attachment(&mut self, id: &AttachmentId) -> Result<bool, SqliteStoreError> {
+        let result = sqlx::query("DELETE FROM attachments WHERE id = ?")
+            .bind(id.as_str())
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn clear_attachments(&mut self) -> Result<(), SqliteStoreError> {
+        sqlx::query("DELETE FROM attachments")
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrations_are_idempotent() -> anyhow::Result<()> {
+        let db = SqliteStore::temporary().await?;
+        sqlx::migrate!().run(&db.db).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_conformance() {
+        presage::store::testing::run_all(|| async { SqliteStore::temporary().await.unwrap() })
+            .await;
     }
 }
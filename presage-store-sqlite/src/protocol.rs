@@ -1,22 +1,94 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use presage::libsignal_service::{
-    pre_keys::{KyberPreKeyStoreExt, PreKeysStore},
-    prelude::{IdentityKeyStore, SessionStoreExt, Uuid},
-    protocol::{
-        Direction, IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord,
-        KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeyStore, ProtocolAddress, ProtocolStore,
-        SenderKeyRecord, SenderKeyStore, ServiceId, SessionRecord, SessionStore,
-        SignalProtocolError as ProtocolError, SignedPreKeyId, SignedPreKeyRecord,
-        SignedPreKeyStore,
+use presage::{
+    libsignal_service::{
+        pre_keys::{KyberPreKeyStoreExt, PreKeysStore},
+        prelude::{IdentityKeyStore, SessionStoreExt, Uuid},
+        protocol::{
+            Direction, GenericSignedPreKey, IdentityKey, IdentityKeyPair, KyberPreKeyId,
+            KyberPreKeyRecord, KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeyStore,
+            ProtocolAddress, ProtocolStore, SenderKeyRecord, SenderKeyStore, ServiceId,
+            SessionRecord, SessionStore, SignalProtocolError as ProtocolError, SignedPreKeyId,
+            SignedPreKeyRecord, SignedPreKeyStore,
+        },
+        push_service::DEFAULT_DEVICE_ID,
     },
+    model::identity::OnNewIdentity,
+    proto::verified,
+    store::{save_trusted_identity_message, ProtocolStoreExt, StateStore},
 };
+use tracing::{trace, warn};
 
-use crate::SqliteStore;
+use crate::{SqliteStore, SqliteStoreError};
+
+/// Which of our identities a protocol store is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityType {
+    Aci,
+    Pni,
+}
+
+impl IdentityType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aci => "aci",
+            Self::Pni => "pni",
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct SqliteProtocolStore {
     pub(crate) store: SqliteStore,
+    pub(crate) identity: IdentityType,
+}
+
+impl SqliteProtocolStore {
+    /// Drops our keys, and sessions if asked to, but keeps the identities of others, like the
+    /// sled store
+    pub(crate) async fn clear(&self, clear_sessions: bool) -> Result<(), SqliteStoreError> {
+        let mut tables = vec![
+            "pre_keys",
+            "signed_pre_keys",
+            "kyber_pre_keys",
+            "sender_keys",
+        ];
+        if clear_sessions {
+            tables.push("sessions");
+        }
+
+        let mut tx = self.store.db.begin().await?;
+        for table in tables {
+            sqlx::query(&format!("DELETE FROM {table} WHERE identity = ?"))
+                .bind(self.identity.as_str())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn next_key_id(&self, table: &str) -> Result<u32, ProtocolError> {
+        let max_id: Option<u32> =
+            sqlx::query_scalar(&format!("SELECT MAX(id) FROM {table} WHERE identity = ?"))
+                .bind(self.identity.as_str())
+                .fetch_one(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        Ok(max_id.map_or(0, |id| id + 1))
+    }
+}
+
+/// Converts database errors into errors understood by libsignal
+trait SqlxResultExt<T> {
+    fn into_protocol_error(self) -> Result<T, ProtocolError>;
+}
+
+impl<T> SqlxResultExt<T> for Result<T, sqlx::Error> {
+    fn into_protocol_error(self) -> Result<T, ProtocolError> {
+        self.map_err(|error| SqliteStoreError::from(error).into())
+    }
 }
 
 impl ProtocolStore for SqliteProtocolStore {}
@@ -28,7 +100,19 @@ impl SessionStore for SqliteProtocolStore {
         &self,
         address: &ProtocolAddress,
     ) -> Result<Option<SessionRecord>, ProtocolError> {
-        todo!()
+        let record: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT record FROM sessions WHERE address = ? AND device_id = ? AND identity = ?",
+        )
+        .bind(address.name())
+        .bind(u32::from(address.device_id()))
+        .bind(self.identity.as_str())
+        .fetch_optional(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        trace!(%address, session_exists = record.is_some(), "loading session");
+        record
+            .map(|record| SessionRecord::deserialize(&record))
+            .transpose()
     }
 
     /// Set the entry for `address` to the value of `record`.
@@ -37,7 +121,19 @@ impl SessionStore for SqliteProtocolStore {
         address: &ProtocolAddress,
         record: &SessionRecord,
     ) -> Result<(), ProtocolError> {
-        todo!();
+        trace!(%address, "storing session");
+        sqlx::query(
+            "INSERT OR REPLACE INTO sessions (address, device_id, identity, record)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(address.name())
+        .bind(u32::from(address.device_id()))
+        .bind(self.identity.as_str())
+        .bind(record.serialize()?)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 }
 
@@ -47,12 +143,28 @@ impl SessionStoreExt for SqliteProtocolStore {
     ///
     /// This should return every device except for the main device [DEFAULT_DEVICE_ID].
     async fn get_sub_device_sessions(&self, name: &ServiceId) -> Result<Vec<u32>, ProtocolError> {
-        todo!()
+        sqlx::query_scalar(
+            "SELECT device_id FROM sessions WHERE address = ? AND device_id != ? AND identity = ?",
+        )
+        .bind(name.service_id_string())
+        .bind(DEFAULT_DEVICE_ID)
+        .bind(self.identity.as_str())
+        .fetch_all(&self.store.db)
+        .await
+        .into_protocol_error()
     }
 
     /// Remove a session record for a recipient ID + device ID tuple.
     async fn delete_session(&self, address: &ProtocolAddress) -> Result<(), ProtocolError> {
-        todo!()
+        trace!(%address, "deleting session");
+        sqlx::query("DELETE FROM sessions WHERE address = ? AND device_id = ? AND identity = ?")
+            .bind(address.name())
+            .bind(u32::from(address.device_id()))
+            .bind(self.identity.as_str())
+            .execute(&self.store.db)
+            .await
+            .into_protocol_error()?;
+        Ok(())
     }
 
     /// Remove the session records corresponding to all devices of a recipient
@@ -60,7 +172,13 @@ impl SessionStoreExt for SqliteProtocolStore {
     ///
     /// Returns the number of deleted sessions.
     async fn delete_all_sessions(&self, address: &ServiceId) -> Result<usize, ProtocolError> {
-        todo!()
+        let result = sqlx::query("DELETE FROM sessions WHERE address = ? AND identity = ?")
+            .bind(address.service_id_string())
+            .bind(self.identity.as_str())
+            .execute(&self.store.db)
+            .await
+            .into_protocol_error()?;
+        Ok(result.rows_affected() as usize)
     }
 }
 
@@ -68,7 +186,15 @@ impl SessionStoreExt for SqliteProtocolStore {
 impl PreKeyStore for SqliteProtocolStore {
     /// Look up the pre-key corresponding to `prekey_id`.
     async fn get_pre_key(&self, prekey_id: PreKeyId) -> Result<PreKeyRecord, ProtocolError> {
-        todo!()
+        let record: Vec<u8> =
+            sqlx::query_scalar("SELECT record FROM pre_keys WHERE id = ? AND identity = ?")
+                .bind(u32::from(prekey_id))
+                .bind(self.identity.as_str())
+                .fetch_optional(&self.store.db)
+                .await
+                .into_protocol_error()?
+                .ok_or(ProtocolError::InvalidPreKeyId)?;
+        PreKeyRecord::deserialize(&record)
     }
 
     /// Set the entry for `prekey_id` to the value of `record`.
@@ -77,12 +203,25 @@ impl PreKeyStore for SqliteProtocolStore {
         prekey_id: PreKeyId,
         record: &PreKeyRecord,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query("INSERT OR REPLACE INTO pre_keys (id, identity, record) VALUES (?, ?, ?)")
+            .bind(u32::from(prekey_id))
+            .bind(self.identity.as_str())
+            .bind(record.serialize()?)
+            .execute(&self.store.db)
+            .await
+            .into_protocol_error()?;
+        Ok(())
     }
 
     /// Remove the entry for `prekey_id`.
     async fn remove_pre_key(&mut self, prekey_id: PreKeyId) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query("DELETE FROM pre_keys WHERE id = ? AND identity = ?")
+            .bind(u32::from(prekey_id))
+            .bind(self.identity.as_str())
+            .execute(&self.store.db)
+            .await
+            .into_protocol_error()?;
+        Ok(())
     }
 }
 
@@ -90,27 +229,41 @@ impl PreKeyStore for SqliteProtocolStore {
 impl PreKeysStore for SqliteProtocolStore {
     /// ID of the next pre key
     async fn next_pre_key_id(&self) -> Result<u32, ProtocolError> {
-        todo!()
+        self.next_key_id("pre_keys").await
     }
 
     /// ID of the next signed pre key
     async fn next_signed_pre_key_id(&self) -> Result<u32, ProtocolError> {
-        todo!()
+        self.next_key_id("signed_pre_keys").await
     }
 
     /// ID of the next PQ pre key
     async fn next_pq_pre_key_id(&self) -> Result<u32, ProtocolError> {
-        todo!()
+        self.next_key_id("kyber_pre_keys").await
     }
 
     /// number of signed pre-keys we currently have in store
     async fn signed_pre_keys_count(&self) -> Result<usize, ProtocolError> {
-        todo!()
+        let count: u32 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM signed_pre_keys WHERE identity = ?")
+                .bind(self.identity.as_str())
+                .fetch_one(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        Ok(count as usize)
     }
 
     /// number of kyber pre-keys we currently have in store
     async fn kyber_pre_keys_count(&self, last_resort: bool) -> Result<usize, ProtocolError> {
-        todo!()
+        let count: u32 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM kyber_pre_keys WHERE identity = ? AND is_last_resort = ?",
+        )
+        .bind(self.identity.as_str())
+        .bind(last_resort)
+        .fetch_one(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(count as usize)
     }
 }
 
@@ -121,7 +274,15 @@ impl SignedPreKeyStore for SqliteProtocolStore {
         &self,
         signed_prekey_id: SignedPreKeyId,
     ) -> Result<SignedPreKeyRecord, ProtocolError> {
-        todo!()
+        let record: Vec<u8> =
+            sqlx::query_scalar("SELECT record FROM signed_pre_keys WHERE id = ? AND identity = ?")
+                .bind(u32::from(signed_prekey_id))
+                .bind(self.identity.as_str())
+                .fetch_optional(&self.store.db)
+                .await
+                .into_protocol_error()?
+                .ok_or(ProtocolError::InvalidSignedPreKeyId)?;
+        SignedPreKeyRecord::deserialize(&record)
     }
 
     /// Set the entry for `signed_prekey_id` to the value of `record`.
@@ -130,7 +291,16 @@ impl SignedPreKeyStore for SqliteProtocolStore {
         signed_prekey_id: SignedPreKeyId,
         record: &SignedPreKeyRecord,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query(
+            "INSERT OR REPLACE INTO signed_pre_keys (id, identity, record) VALUES (?, ?, ?)",
+        )
+        .bind(u32::from(signed_prekey_id))
+        .bind(self.identity.as_str())
+        .bind(record.serialize()?)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 }
 
@@ -141,7 +311,15 @@ impl KyberPreKeyStore for SqliteProtocolStore {
         &self,
         kyber_prekey_id: KyberPreKeyId,
     ) -> Result<KyberPreKeyRecord, ProtocolError> {
-        todo!()
+        let record: Vec<u8> =
+            sqlx::query_scalar("SELECT record FROM kyber_pre_keys WHERE id = ? AND identity = ?")
+                .bind(u32::from(kyber_prekey_id))
+                .bind(self.identity.as_str())
+                .fetch_optional(&self.store.db)
+                .await
+                .into_protocol_error()?
+                .ok_or(ProtocolError::InvalidKyberPreKeyId)?;
+        KyberPreKeyRecord::deserialize(&record)
     }
 
     /// Set the entry for `kyber_prekey_id` to the value of `record`.
@@ -150,7 +328,17 @@ impl KyberPreKeyStore for SqliteProtocolStore {
         kyber_prekey_id: KyberPreKeyId,
         record: &KyberPreKeyRecord,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query(
+            "INSERT OR REPLACE INTO kyber_pre_keys (id, identity, record, is_last_resort)
+            VALUES (?, ?, ?, FALSE)",
+        )
+        .bind(u32::from(kyber_prekey_id))
+        .bind(self.identity.as_str())
+        .bind(record.serialize()?)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 
     /// Mark the entry for `kyber_prekey_id` as "used".
@@ -159,7 +347,19 @@ impl KyberPreKeyStore for SqliteProtocolStore {
         &mut self,
         kyber_prekey_id: KyberPreKeyId,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        // one-time keys are removed, last-resort keys are kept until they are rotated
+        let result = sqlx::query(
+            "DELETE FROM kyber_pre_keys WHERE id = ? AND identity = ? AND NOT is_last_resort",
+        )
+        .bind(u32::from(kyber_prekey_id))
+        .bind(self.identity.as_str())
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        if result.rows_affected() > 0 {
+            trace!(%kyber_prekey_id, "removed kyber pre-key");
+        }
+        Ok(())
     }
 }
 
@@ -170,20 +370,48 @@ impl KyberPreKeyStoreExt for SqliteProtocolStore {
         kyber_prekey_id: KyberPreKeyId,
         record: &KyberPreKeyRecord,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        trace!(%kyber_prekey_id, "store_last_resort_kyber_pre_key");
+        sqlx::query(
+            "INSERT OR REPLACE INTO kyber_pre_keys (id, identity, record, is_last_resort)
+            VALUES (?, ?, ?, TRUE)",
+        )
+        .bind(u32::from(kyber_prekey_id))
+        .bind(self.identity.as_str())
+        .bind(record.serialize()?)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 
     async fn load_last_resort_kyber_pre_keys(
         &self,
     ) -> Result<Vec<KyberPreKeyRecord>, ProtocolError> {
-        todo!()
+        trace!("load_last_resort_kyber_pre_keys");
+        let records: Vec<Vec<u8>> = sqlx::query_scalar(
+            "SELECT record FROM kyber_pre_keys WHERE identity = ? AND is_last_resort",
+        )
+        .bind(self.identity.as_str())
+        .fetch_all(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        records
+            .iter()
+            .map(|record| KyberPreKeyRecord::deserialize(record))
+            .collect()
     }
 
     async fn remove_kyber_pre_key(
         &mut self,
         kyber_prekey_id: KyberPreKeyId,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query("DELETE FROM kyber_pre_keys WHERE id = ? AND identity = ?")
+            .bind(u32::from(kyber_prekey_id))
+            .bind(self.identity.as_str())
+            .execute(&self.store.db)
+            .await
+            .into_protocol_error()?;
+        Ok(())
     }
 
     /// Analogous to markAllOneTimeKyberPreKeysStaleIfNecessary
@@ -191,7 +419,16 @@ impl KyberPreKeyStoreExt for SqliteProtocolStore {
         &mut self,
         stale_time: DateTime<Utc>,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query(
+            "UPDATE kyber_pre_keys SET stale_timestamp = ?
+            WHERE identity = ? AND NOT is_last_resort AND stale_timestamp IS NULL",
+        )
+        .bind(stale_time.timestamp_millis())
+        .bind(self.identity.as_str())
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 
     /// Analogue of deleteAllStaleOneTimeKyberPreKeys
@@ -200,7 +437,24 @@ impl KyberPreKeyStoreExt for SqliteProtocolStore {
         threshold: DateTime<Utc>,
         min_count: usize,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        // always keep the `min_count` most recent one-time keys around
+        sqlx::query(
+            "DELETE FROM kyber_pre_keys
+            WHERE identity = ?1 AND NOT is_last_resort AND stale_timestamp < ?2
+            AND id NOT IN (
+                SELECT id FROM kyber_pre_keys
+                WHERE identity = ?1 AND NOT is_last_resort
+                ORDER BY id DESC
+                LIMIT ?3
+            )",
+        )
+        .bind(self.identity.as_str())
+        .bind(threshold.timestamp_millis())
+        .bind(min_count as i64)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 }
 
@@ -208,7 +462,16 @@ impl KyberPreKeyStoreExt for SqliteProtocolStore {
 impl IdentityKeyStore for SqliteProtocolStore {
     /// Return the single specific identity the store is assumed to represent, with private key.
     async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair, ProtocolError> {
-        todo!()
+        trace!("getting identity_key_pair");
+        self.store
+            .identity_key_pair(self.identity)
+            .await?
+            .ok_or_else(|| {
+                ProtocolError::InvalidState(
+                    "get_identity_key_pair",
+                    "no identity key pair found".to_owned(),
+                )
+            })
     }
 
     /// Return a [u32] specific to this store instance.
@@ -220,7 +483,21 @@ impl IdentityKeyStore for SqliteProtocolStore {
     /// may be the same, but the store registration id returned by this method should
     /// be regenerated.
     async fn get_local_registration_id(&self) -> Result<u32, ProtocolError> {
-        todo!()
+        let data =
+            self.store
+                .load_registration_data()
+                .await?
+                .ok_or(ProtocolError::InvalidState(
+                    "failed to load registration ID",
+                    "no registration data".into(),
+                ))?;
+        match self.identity {
+            IdentityType::Aci => Ok(data.registration_id),
+            IdentityType::Pni => data.pni_registration_id.ok_or(ProtocolError::InvalidState(
+                "failed to load registration ID",
+                "no PNI registration ID".into(),
+            )),
+        }
     }
 
     // TODO: make this into an enum instead of a bool!
@@ -233,7 +510,35 @@ impl IdentityKeyStore for SqliteProtocolStore {
         address: &ProtocolAddress,
         identity: &IdentityKey,
     ) -> Result<bool, ProtocolError> {
-        todo!()
+        trace!("saving identity");
+        let previous_identity = self.get_identity(address).await?;
+
+        sqlx::query(
+            "INSERT OR REPLACE INTO identities (address, device_id, identity, record)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(address.name())
+        .bind(u32::from(address.device_id()))
+        .bind(self.identity.as_str())
+        .bind(identity.serialize().into_vec())
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+
+        save_trusted_identity_message(
+            &self.store,
+            address,
+            *identity,
+            if previous_identity.is_some() {
+                verified::State::Unverified
+            } else {
+                verified::State::Default
+            },
+        )
+        .await?;
+
+        // like the sled store, which does not tell replaced identities apart
+        Ok(true)
     }
 
     /// Return whether an identity is trusted for the role specified by `direction`.
@@ -241,9 +546,26 @@ impl IdentityKeyStore for SqliteProtocolStore {
         &self,
         address: &ProtocolAddress,
         identity: &IdentityKey,
-        direction: Direction,
+        _direction: Direction,
     ) -> Result<bool, ProtocolError> {
-        todo!()
+        match self.get_identity(address).await? {
+            None => {
+                // when we encounter a new identity, we trust it by default
+                warn!(%address, "trusting new identity");
+                Ok(true)
+            }
+            // when we encounter some identity we know, we need to decide whether we trust it or not
+            Some(left_identity_key) => {
+                if left_identity_key == *identity {
+                    Ok(true)
+                } else {
+                    match self.store.trust_new_identities {
+                        OnNewIdentity::Trust => Ok(true),
+                        OnNewIdentity::Reject => Ok(false),
+                    }
+                }
+            }
+        }
     }
 
     /// Return the public identity for the given `address`, if known.
@@ -251,7 +573,18 @@ impl IdentityKeyStore for SqliteProtocolStore {
         &self,
         address: &ProtocolAddress,
     ) -> Result<Option<IdentityKey>, ProtocolError> {
-        todo!()
+        let record: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT record FROM identities WHERE address = ? AND device_id = ? AND identity = ?",
+        )
+        .bind(address.name())
+        .bind(u32::from(address.device_id()))
+        .bind(self.identity.as_str())
+        .fetch_optional(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        record
+            .map(|record| IdentityKey::decode(&record))
+            .transpose()
     }
 }
 
@@ -265,7 +598,19 @@ impl SenderKeyStore for SqliteProtocolStore {
         // TODO: pass this by value!
         record: &SenderKeyRecord,
     ) -> Result<(), ProtocolError> {
-        todo!()
+        sqlx::query(
+            "INSERT OR REPLACE INTO sender_keys (address, device_id, distribution_id, identity, record)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(sender.name())
+        .bind(u32::from(sender.device_id()))
+        .bind(distribution_id.to_string())
+        .bind(self.identity.as_str())
+        .bind(record.serialize()?)
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
     }
 
     /// Look up the entry corresponding to `(sender, distribution_id)`.
@@ -274,6 +619,144 @@ impl SenderKeyStore for SqliteProtocolStore {
         sender: &ProtocolAddress,
         distribution_id: Uuid,
     ) -> Result<Option<SenderKeyRecord>, ProtocolError> {
-        todo!()
+        let record: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT record FROM sender_keys
+            WHERE address = ? AND device_id = ? AND distribution_id = ? AND identity = ?",
+        )
+        .bind(sender.name())
+        .bind(u32::from(sender.device_id()))
+        .bind(distribution_id.to_string())
+        .bind(self.identity.as_str())
+        .fetch_optional(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        record
+            .map(|record| SenderKeyRecord::deserialize(&record))
+            .transpose()
+    }
+}
+
+impl ProtocolStoreExt for SqliteProtocolStore {
+    async fn all_sessions(&self) -> Result<Vec<(ProtocolAddress, SessionRecord)>, ProtocolError> {
+        let rows: Vec<(String, u32, Vec<u8>)> =
+            sqlx::query_as("SELECT address, device_id, record FROM sessions WHERE identity = ?")
+                .bind(self.identity.as_str())
+                .fetch_all(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        rows.into_iter()
+            .map(|(address, device_id, record)| {
+                Ok((
+                    ProtocolAddress::new(address, device_id.into()),
+                    SessionRecord::deserialize(&record)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn all_identities(&self) -> Result<Vec<(ProtocolAddress, IdentityKey)>, ProtocolError> {
+        let rows: Vec<(String, u32, Vec<u8>)> =
+            sqlx::query_as("SELECT address, device_id, record FROM identities WHERE identity = ?")
+                .bind(self.identity.as_str())
+                .fetch_all(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        rows.into_iter()
+            .map(|(address, device_id, record)| {
+                Ok((
+                    ProtocolAddress::new(address, device_id.into()),
+                    IdentityKey::decode(&record)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn restore_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity_key: &IdentityKey,
+    ) -> Result<(), ProtocolError> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO identities (address, device_id, identity, record)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(address.name())
+        .bind(u32::from(address.device_id()))
+        .bind(self.identity.as_str())
+        .bind(identity_key.serialize().into_vec())
+        .execute(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        Ok(())
+    }
+
+    async fn all_pre_keys(&self) -> Result<Vec<PreKeyRecord>, ProtocolError> {
+        let records: Vec<Vec<u8>> =
+            sqlx::query_scalar("SELECT record FROM pre_keys WHERE identity = ? ORDER BY id")
+                .bind(self.identity.as_str())
+                .fetch_all(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        records
+            .iter()
+            .map(|record| PreKeyRecord::deserialize(record))
+            .collect()
+    }
+
+    async fn all_signed_pre_keys(&self) -> Result<Vec<SignedPreKeyRecord>, ProtocolError> {
+        let records: Vec<Vec<u8>> =
+            sqlx::query_scalar("SELECT record FROM signed_pre_keys WHERE identity = ? ORDER BY id")
+                .bind(self.identity.as_str())
+                .fetch_all(&self.store.db)
+                .await
+                .into_protocol_error()?;
+        records
+            .iter()
+            .map(|record| SignedPreKeyRecord::deserialize(record))
+            .collect()
+    }
+
+    async fn all_kyber_pre_keys(
+        &self,
+        last_resort: bool,
+    ) -> Result<Vec<KyberPreKeyRecord>, ProtocolError> {
+        let records: Vec<Vec<u8>> = sqlx::query_scalar(
+            "SELECT record FROM kyber_pre_keys
+            WHERE identity = ? AND is_last_resort = ?
+            ORDER BY id",
+        )
+        .bind(self.identity.as_str())
+        .bind(last_resort)
+        .fetch_all(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        records
+            .iter()
+            .map(|record| KyberPreKeyRecord::deserialize(record))
+            .collect()
+    }
+
+    async fn all_sender_keys(
+        &self,
+    ) -> Result<Vec<(ProtocolAddress, Uuid, SenderKeyRecord)>, ProtocolError> {
+        let rows: Vec<(String, u32, String, Vec<u8>)> = sqlx::query_as(
+            "SELECT address, device_id, distribution_id, record FROM sender_keys
+            WHERE identity = ?",
+        )
+        .bind(self.identity.as_str())
+        .fetch_all(&self.store.db)
+        .await
+        .into_protocol_error()?;
+        rows.into_iter()
+            .map(|(address, device_id, distribution_id, record)| {
+                let distribution_id =
+                    Uuid::parse_str(&distribution_id).map_err(SqliteStoreError::from)?;
+                Ok((
+                    ProtocolAddress::new(address, device_id.into()),
+                    distribution_id,
+                    SenderKeyRecord::deserialize(&record)?,
+                ))
+            })
+            .collect()
     }
 }
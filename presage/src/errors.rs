@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use libsignal_service::prelude::MessageSenderError;
 use libsignal_service::{models::ParseContactError, protocol::SignalProtocolError};
 
+use crate::model::groups::GroupChangesError;
 use crate::store::StoreError;
 
 /// The error type of Signal manager
@@ -21,6 +22,8 @@ pub enum Error<S: std::error::Error> {
     TryFromSliceError(#[from] std::array::TryFromSliceError),
     #[error("phone number parsing error: {0}")]
     PhoneNumberError(#[from] libsignal_service::prelude::phonenumber::ParseError),
+    #[error("protobuf decoding error: {0}")]
+    ProtobufDecodeError(#[from] prost::DecodeError),
     #[error("UUID decoding error: {0}")]
     UuidError(#[from] libsignal_service::prelude::UuidError),
     #[error("libsignal-protocol error: {0}")]
@@ -53,8 +56,24 @@ pub enum Error<S: std::error::Error> {
     AttachmentCipherError(#[from] libsignal_service::attachment_cipher::AttachmentCipherError),
     #[error("unknown group")]
     UnknownGroup,
+    #[error("the server did not return the change of the group")]
+    MissingGroupChange,
+    #[error("failed to apply group changes: {0}")]
+    GroupChangesError(#[from] GroupChangesError),
+    #[error("invalid profile key credential")]
+    InvalidProfileKeyCredential,
+    #[error("joining this group by its invite link is disabled")]
+    InviteLinkDisabled,
+    #[error("we are not invited to this group")]
+    NotInvitedToGroup,
+    #[error("we are not a member of this group")]
+    NotAGroupMember,
     #[error("unknown recipient")]
     UnknownRecipient,
+    #[error("unknown message")]
+    UnknownMessage,
+    #[error("this message was not sent by us")]
+    NotOwnMessage,
     #[error("timeout: {0}")]
     Timeout(#[from] tokio::time::error::Elapsed),
     #[error("store error: {0}")]
@@ -65,6 +84,12 @@ pub enum Error<S: std::error::Error> {
     RequestingCodeForbidden(libsignal_service::push_service::RegistrationSessionMetadataResponse),
     #[error("attachment sha256 checksum did not match")]
     UnexpectedAttachmentChecksum,
+    #[error("attachment is {actual} bytes long, expected {expected} bytes")]
+    UnexpectedAttachmentLength { expected: u64, actual: u64 },
+    #[error("attachment upload was cancelled")]
+    AttachmentUploadCancelled,
+    #[error("no directory to download attachments to, see Manager::set_attachments_dir")]
+    NoAttachmentsDir,
     #[error("Unverified registration session (i.e. wrong verification code)")]
     UnverifiedRegistrationSession,
     #[error("profile cipher error")]
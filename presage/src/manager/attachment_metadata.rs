@@ -0,0 +1,91 @@
+//! Metadata of outgoing image attachments
+//!
+//! Official clients use the dimensions of an image to lay it out before it is downloaded, and
+//! show its blurhash in the meantime. Images are decoded in pure Rust, other attachments are left
+//! untouched.
+
+use image::{imageops::FilterType, DynamicImage};
+use libsignal_service::sender::AttachmentSpec;
+use tracing::debug;
+
+/// Images are scaled down to this size before computing their blurhash
+const BLURHASH_MAX_SIZE: u32 = 64;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Fills the dimensions and blurhash of an image attachment, unless they are already set
+pub(crate) fn fill(spec: &mut AttachmentSpec, contents: &[u8]) {
+    if !spec.content_type.starts_with("image/") {
+        return;
+    }
+    let image = match image::load_from_memory(contents) {
+        Ok(image) => image,
+        Err(error) => {
+            debug!(%error, content_type = %spec.content_type, "failed to decode image attachment");
+            return;
+        }
+    };
+
+    spec.width.get_or_insert(image.width());
+    spec.height.get_or_insert(image.height());
+    if spec.blur_hash.is_none() {
+        spec.blur_hash = blur_hash(&image);
+    }
+}
+
+fn blur_hash(image: &DynamicImage) -> Option<String> {
+    let thumbnail = image
+        .resize(BLURHASH_MAX_SIZE, BLURHASH_MAX_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    let (components_x, components_y) = BLURHASH_COMPONENTS;
+    blurhash::encode(
+        components_x,
+        components_y,
+        thumbnail.width(),
+        thumbnail.height(),
+        thumbnail.as_raw(),
+    )
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use super::*;
+
+    fn spec(content_type: &str) -> AttachmentSpec {
+        AttachmentSpec {
+            content_type: content_type.to_owned(),
+            length: 0,
+            file_name: None,
+            preview: None,
+            voice_note: None,
+            borderless: None,
+            width: None,
+            height: None,
+            caption: None,
+            blur_hash: None,
+        }
+    }
+
+    #[test]
+    fn fill_image_metadata() {
+        let image = RgbImage::from_fn(120, 80, |x, _| Rgb([(x * 2) as u8, 64, 128]));
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        let mut image_spec = spec("image/png");
+        fill(&mut image_spec, &png);
+        assert_eq!(image_spec.width, Some(120));
+        assert_eq!(image_spec.height, Some(80));
+        assert!(image_spec.blur_hash.is_some());
+
+        let mut other_spec = spec("application/octet-stream");
+        fill(&mut other_spec, &png);
+        assert_eq!(other_spec.width, None);
+        assert_eq!(other_spec.blur_hash, None);
+    }
+}
@@ -0,0 +1,1012 @@
+//! Downloads and uploads of attachments
+//!
+//! Ciphertexts are streamed to a file in the attachments directory and hashed as they arrive, so
+//! that large attachments are never held in memory and an interrupted download leaves a partial
+//! file behind, which is resumed with a range request. Once the digest of the ciphertext is
+//! checked, the attachment is decrypted to another file, whose location is saved in the
+//! [`AttachmentsStore`](crate::store::AttachmentsStore).
+//!
+//! Uploads read an [`AttachmentSource`] in chunks, which are encrypted and sent to the CDN one at a
+//! time, reporting [`UploadProgress`] and checking for cancellation between chunks.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use futures::channel::{mpsc, oneshot};
+use futures::{AsyncRead, AsyncReadExt, Stream, TryStreamExt};
+use hmac::{Hmac, Mac};
+use libsignal_service::attachment_cipher::AttachmentCipherError;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::proto::attachment_pointer::{
+    AttachmentIdentifier, Flags as AttachmentPointerFlags,
+};
+use libsignal_service::proto::AttachmentPointer;
+use libsignal_service::push_service::{HttpAuthOverride, PushService, ServiceError};
+use libsignal_service::sender::AttachmentSpec;
+use rand::RngCore;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, Method, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, trace};
+
+use crate::store::attachments::AttachmentId;
+use crate::Error;
+
+/// Default number of attachments downloaded at the same time
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// A multiple of the AES block size, so that ciphertexts are decrypted chunk by chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const IV_LEN: usize = 16;
+const BLOCK_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+
+/// State shared by all the downloads of attachments of a manager
+#[derive(Clone)]
+pub(crate) struct AttachmentDownloads {
+    dir: Option<PathBuf>,
+    permits: Arc<Semaphore>,
+    in_progress: Arc<std::sync::Mutex<HashMap<AttachmentId, Arc<Mutex<()>>>>>,
+}
+
+impl Default for AttachmentDownloads {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            permits: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_DOWNLOADS)),
+            in_progress: Default::default(),
+        }
+    }
+}
+
+impl AttachmentDownloads {
+    pub(crate) fn set_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.dir = Some(dir.into());
+    }
+
+    /// Downloads already started keep their permit from the previous limit
+    pub(crate) fn set_max_concurrent(&mut self, max: usize) {
+        self.permits = Arc::new(Semaphore::new(max.max(1)));
+    }
+
+    /// Waits until no other download of the attachment is in progress
+    pub(crate) async fn lock(&self, id: &AttachmentId) -> OwnedMutexGuard<()> {
+        let lock = self
+            .in_progress
+            .lock()
+            .expect("poisoned mutex")
+            .entry(id.clone())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
+    /// Forgets about an attachment once it is downloaded (or failed to download)
+    ///
+    /// The lock is kept as long as other calls wait for it, so that they do not download the
+    /// attachment concurrently with later calls.
+    pub(crate) fn unlock(&self, id: &AttachmentId, guard: OwnedMutexGuard<()>) {
+        let mut in_progress = self.in_progress.lock().expect("poisoned mutex");
+        drop(guard);
+        if in_progress
+            .get(id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            in_progress.remove(id);
+        }
+    }
+
+    pub(crate) async fn permit(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed")
+    }
+
+    /// Where the ciphertext of an attachment is downloaded, and where it is decrypted to
+    pub(crate) fn files(&self, id: &AttachmentId) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_ref()?;
+        Some((dir.join(format!("{id}.encrypted")), dir.join(id.as_str())))
+    }
+}
+
+/// Outcome of [`resume`]
+pub(crate) enum Partial {
+    /// The file holds the whole ciphertext, its digest matches
+    Complete,
+    /// The file holds the first bytes of the ciphertext, hashed into the given hasher
+    Incomplete { len: u64, hasher: Sha256 },
+}
+
+/// Hashes what a previous download left in `path`
+pub(crate) async fn resume(path: &Path, expected_digest: &[u8]) -> std::io::Result<Partial> {
+    let mut hasher = Sha256::new();
+    let mut len = 0;
+    match File::open(path).await {
+        Ok(mut file) => {
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                len += n as u64;
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            fs::create_dir_all(path.parent().unwrap_or(path)).await?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    if len > 0 && hasher.clone().finalize()[..] == *expected_digest {
+        debug!(len, "attachment was already downloaded");
+        return Ok(Partial::Complete);
+    }
+    Ok(Partial::Incomplete { len, hasher })
+}
+
+/// The ciphertext of an attachment served by the CDN, from byte `start` on
+pub(crate) struct Ciphertext {
+    stream: Pin<Box<dyn AsyncRead + Send>>,
+    start: u64,
+}
+
+/// Requests the ciphertext of an attachment from the CDN, from byte `offset` on
+///
+/// The CDN may ignore the range and serve the whole ciphertext, see [`download`].
+pub(crate) async fn request(
+    service: &mut PushService,
+    attachment_pointer: &AttachmentPointer,
+    offset: u64,
+) -> Result<Ciphertext, ServiceError> {
+    let path = match &attachment_pointer.attachment_identifier {
+        Some(AttachmentIdentifier::CdnId(id)) if offset > 0 => format!("attachments/{id}"),
+        Some(AttachmentIdentifier::CdnKey(key)) if offset > 0 => format!("attachments/{key}"),
+        _ => {
+            let stream = service.get_attachment(attachment_pointer).await?;
+            return Ok(Ciphertext {
+                stream: Box::pin(stream),
+                start: 0,
+            });
+        }
+    };
+
+    let range = format!("bytes={offset}-");
+    let response = service
+        .request(
+            Method::GET,
+            Endpoint::Cdn(attachment_pointer.cdn_number()),
+            &path,
+            &[("Range", &range)],
+            HttpAuthOverride::Unidentified,
+        )?
+        .send()
+        .await
+        .map_err(ServiceError::from)?;
+
+    // the partial file is as long as the ciphertext, yet its digest does not match: there is
+    // nothing left to download, and checking the digest discards the file
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(Ciphertext {
+            stream: Box::pin(futures::io::empty()),
+            start: offset,
+        });
+    }
+
+    let response = response.error_for_status().map_err(ServiceError::from)?;
+    let start = if response.status() == StatusCode::PARTIAL_CONTENT {
+        offset
+    } else {
+        debug!(
+            offset,
+            "range ignored, downloading the whole attachment again"
+        );
+        0
+    };
+    let stream = response
+        .bytes_stream()
+        .map_err(std::io::Error::other)
+        .into_async_read();
+    Ok(Ciphertext {
+        stream: Box::pin(stream),
+        start,
+    })
+}
+
+/// Appends the ciphertext to the `len` bytes already in `path`
+///
+/// Bytes of the ciphertext which are already in the file, when the CDN served more than asked
+/// for, are skipped instead of being written a second time. Returns the digest of the whole
+/// ciphertext.
+pub(crate) async fn download(
+    path: &Path,
+    ciphertext: Ciphertext,
+    len: u64,
+    mut hasher: Sha256,
+) -> std::io::Result<Vec<u8>> {
+    let Ciphertext { mut stream, start } = ciphertext;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+
+    let mut skip = len.saturating_sub(start);
+    let mut written = 0u64;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        let skipped = skip.min(n as u64) as usize;
+        skip -= skipped as u64;
+        let chunk = &buf[skipped..n];
+        if !chunk.is_empty() {
+            hasher.update(chunk);
+            file.write_all(chunk).await?;
+            written += chunk.len() as u64;
+        }
+    }
+    file.flush().await?;
+    trace!(
+        resumed_at = len,
+        start,
+        written,
+        "downloaded encrypted attachment"
+    );
+
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Decrypts the ciphertext in `encrypted` to `decrypted`, returns the length of the plaintext
+///
+/// The ciphertext is an IV, the plaintext encrypted with AES-256-CBC and the HMAC-SHA256 of both.
+/// It is read twice, to check its MAC before writing any plaintext. Plaintexts are padded with
+/// zeros to hide their length, which is given by `size` when known.
+pub(crate) async fn decrypt<S: std::error::Error>(
+    encrypted: &Path,
+    decrypted: &Path,
+    key: &[u8; 64],
+    size: Option<u64>,
+) -> Result<u64, Error<S>> {
+    let (aes_key, mac_key) = key.split_at(32);
+    let len = fs::metadata(encrypted).await?.len();
+    let body_len = len
+        .checked_sub((IV_LEN + MAC_LEN) as u64)
+        .filter(|body_len| *body_len > 0 && body_len % BLOCK_LEN as u64 == 0)
+        .ok_or(AttachmentCipherError::PaddingError)?;
+
+    let mut file = File::open(encrypted).await?;
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut mac = Hmac::<Sha256>::new_from_slice(mac_key).expect("HMAC takes keys of any size");
+    let mut remaining = len - MAC_LEN as u64;
+    while remaining > 0 {
+        let n = remaining.min(CHUNK_SIZE as u64) as usize;
+        file.read_exact(&mut buf[..n]).await?;
+        mac.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    let mut their_mac = [0; MAC_LEN];
+    file.read_exact(&mut their_mac).await?;
+    mac.verify_slice(&their_mac)
+        .map_err(|_| AttachmentCipherError::MacError)?;
+
+    file.rewind().await?;
+    let mut iv = [0; IV_LEN];
+    file.read_exact(&mut iv).await?;
+    let mut decryptor = Aes256CbcDec::new(
+        GenericArray::from_slice(aes_key),
+        GenericArray::from_slice(&iv),
+    );
+
+    let mut plaintext_file = File::create(decrypted).await?;
+    let mut written = 0;
+    let mut remaining = body_len;
+    while remaining > 0 {
+        let n = remaining.min(CHUNK_SIZE as u64) as usize;
+        let chunk = &mut buf[..n];
+        file.read_exact(chunk).await?;
+        for block in chunk.chunks_exact_mut(BLOCK_LEN) {
+            decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        remaining -= n as u64;
+
+        let plaintext = if remaining == 0 {
+            unpad(chunk)?
+        } else {
+            &*chunk
+        };
+        let unpadded = size.map_or(plaintext.len() as u64, |size| {
+            size.saturating_sub(written).min(plaintext.len() as u64)
+        });
+        let plaintext = &plaintext[..unpadded as usize];
+        plaintext_file.write_all(plaintext).await?;
+        written += plaintext.len() as u64;
+    }
+    plaintext_file.flush().await?;
+
+    Ok(written)
+}
+
+/// Strips the PKCS#7 padding from the end of the plaintext
+fn unpad(plaintext: &[u8]) -> Result<&[u8], AttachmentCipherError> {
+    let padding = plaintext.last().copied().unwrap_or_default() as usize;
+    if padding == 0
+        || padding > BLOCK_LEN.min(plaintext.len())
+        || plaintext[plaintext.len() - padding..]
+            .iter()
+            .any(|&b| b as usize != padding)
+    {
+        return Err(AttachmentCipherError::PaddingError);
+    }
+    Ok(&plaintext[..plaintext.len() - padding])
+}
+
+/// An attachment to upload, read from a file or any reader of known length
+pub struct AttachmentSource {
+    pub(crate) spec: AttachmentSpec,
+    reader: Pin<Box<dyn tokio::io::AsyncRead + Send>>,
+}
+
+impl fmt::Debug for AttachmentSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AttachmentSource")
+            .field("content_type", &self.spec.content_type)
+            .field("length", &self.spec.length)
+            .field("file_name", &self.spec.file_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AttachmentSource {
+    /// Reads the attachment from `reader`, which must yield exactly `spec.length` bytes
+    pub fn from_reader(
+        spec: AttachmentSpec,
+        reader: impl tokio::io::AsyncRead + Send + 'static,
+    ) -> Self {
+        Self {
+            spec,
+            reader: Box::pin(reader),
+        }
+    }
+
+    /// Reads the attachment from a file
+    ///
+    /// The length and file name of the spec are taken from the file.
+    pub async fn from_file(
+        path: impl AsRef<Path>,
+        content_type: impl Into<String>,
+    ) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).await?;
+        let length = file.metadata().await?.len() as usize;
+        let spec = AttachmentSpec {
+            content_type: content_type.into(),
+            length,
+            file_name: path.file_name().map(|s| s.to_string_lossy().to_string()),
+            preview: None,
+            voice_note: None,
+            borderless: None,
+            width: None,
+            height: None,
+            caption: None,
+            blur_hash: None,
+        };
+        Ok(Self::from_reader(spec, file))
+    }
+
+    /// Marks the attachment as a voice note, shown with an inline player by official clients
+    pub fn voice_note(mut self) -> Self {
+        self.spec.voice_note = Some(true);
+        self
+    }
+
+    pub fn spec(&self) -> &AttachmentSpec {
+        &self.spec
+    }
+
+    pub fn spec_mut(&mut self) -> &mut AttachmentSpec {
+        &mut self.spec
+    }
+
+    /// Fills the metadata of images, see [`fill_metadata`]
+    ///
+    /// Images are decoded as a whole, so they are read in memory first. Other attachments are
+    /// left untouched.
+    pub(crate) async fn fill_metadata(&mut self) -> std::io::Result<()> {
+        if !cfg!(feature = "image-metadata") || !self.spec.content_type.starts_with("image/") {
+            return Ok(());
+        }
+        let mut contents = Vec::with_capacity(self.spec.length);
+        // a longer source is caught while encrypting it
+        (&mut self.reader)
+            .take(self.spec.length as u64 + 1)
+            .read_to_end(&mut contents)
+            .await?;
+        fill_metadata(&mut self.spec, &contents);
+        self.reader = Box::pin(std::io::Cursor::new(contents));
+        Ok(())
+    }
+
+    /// Encrypts the attachment as it is read, see [`decrypt`] for the format of the ciphertext
+    ///
+    /// Reading stops between chunks once the upload is cancelled, or when the source turns out
+    /// not to be as long as its spec.
+    pub(crate) fn encrypt(self, cancel: &CancelUpload) -> EncryptedAttachment {
+        let mut key = [0; 64];
+        let mut iv = [0; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        rand::thread_rng().fill_bytes(&mut iv);
+        let (aes_key, mac_key) = key.split_at(32);
+
+        let length = self.spec.length as u64;
+        let padded_length = padded_len(length);
+        let (sent, sent_receiver) = mpsc::unbounded();
+        let (digest, digest_receiver) = oneshot::channel();
+        let encryption = Encryption {
+            reader: self.reader,
+            cancel: cancel.clone(),
+            length,
+            unread: length,
+            source_read: false,
+            padding: padded_length - length,
+            iv: iv.to_vec(),
+            encryptor: Aes256CbcEnc::new(
+                GenericArray::from_slice(aes_key),
+                GenericArray::from_slice(&iv),
+            ),
+            mac: Hmac::new_from_slice(mac_key).expect("HMAC takes keys of any size"),
+            digest: Sha256::new(),
+            sent: 0,
+            sent_sender: sent,
+            digest_sender: Some(digest),
+        };
+
+        EncryptedAttachment {
+            spec: self.spec,
+            key,
+            length: encrypted_len(padded_length),
+            chunks: Box::pin(futures::stream::try_unfold(
+                encryption,
+                Encryption::next_chunk,
+            )),
+            sent: sent_receiver,
+            digest: digest_receiver,
+        }
+    }
+}
+
+/// Length of an attachment padded with zeros, which hides its exact length
+fn padded_len(len: u64) -> u64 {
+    const MIN_PADDED_LEN: u64 = 541;
+    let padded_len = 1.05f64.powf((len as f64).log(1.05).ceil()).floor() as u64;
+    padded_len.max(len).max(MIN_PADDED_LEN)
+}
+
+/// Length of the ciphertext of a plaintext padded to `padded_len`, with its IV, its PKCS#7 padding
+/// of one to a full block, and its MAC
+fn encrypted_len(padded_len: u64) -> u64 {
+    let blocks = padded_len / BLOCK_LEN as u64 + 1;
+    IV_LEN as u64 + blocks * BLOCK_LEN as u64 + MAC_LEN as u64
+}
+
+/// Why an upload stopped before the whole attachment was read
+#[derive(Debug)]
+pub(crate) enum Interrupted {
+    Cancelled,
+    UnexpectedLength { expected: u64, actual: u64 },
+}
+
+impl<S: std::error::Error> From<Interrupted> for Error<S> {
+    fn from(interrupted: Interrupted) -> Self {
+        match interrupted {
+            Interrupted::Cancelled => Error::AttachmentUploadCancelled,
+            Interrupted::UnexpectedLength { expected, actual } => {
+                Error::UnexpectedAttachmentLength { expected, actual }
+            }
+        }
+    }
+}
+
+/// An attachment being encrypted as its body is sent, see [`AttachmentSource::encrypt`]
+pub(crate) struct EncryptedAttachment {
+    pub(crate) spec: AttachmentSpec,
+    pub(crate) key: [u8; 64],
+    /// Length of the ciphertext
+    pub(crate) length: u64,
+    pub(crate) chunks: Pin<Box<dyn Stream<Item = std::io::Result<Vec<u8>>> + Send>>,
+    /// Bytes of the ciphertext handed to the HTTP client so far
+    pub(crate) sent: mpsc::UnboundedReceiver<u64>,
+    /// Digest of the ciphertext, once it is entirely read
+    pub(crate) digest: oneshot::Receiver<Result<Vec<u8>, Interrupted>>,
+}
+
+/// State of the encryption of an attachment, one chunk at a time
+struct Encryption {
+    reader: Pin<Box<dyn tokio::io::AsyncRead + Send>>,
+    cancel: CancelUpload,
+    length: u64,
+    /// Bytes left to read from the source
+    unread: u64,
+    /// Whether the source was checked to end after `length` bytes
+    source_read: bool,
+    /// Zeros left to pad the source with
+    padding: u64,
+    /// Sent in clear before the first chunk
+    iv: Vec<u8>,
+    encryptor: Aes256CbcEnc,
+    mac: Hmac<Sha256>,
+    digest: Sha256,
+    sent: u64,
+    sent_sender: mpsc::UnboundedSender<u64>,
+    /// Taken once the whole attachment is read, or reading it stopped
+    digest_sender: Option<oneshot::Sender<Result<Vec<u8>, Interrupted>>>,
+}
+
+impl Encryption {
+    async fn next_chunk(mut self) -> std::io::Result<Option<(Vec<u8>, Self)>> {
+        let Some(digest_sender) = self.digest_sender.take() else {
+            return Ok(None);
+        };
+        if self.cancel.is_cancelled() {
+            return Err(Self::interrupt(digest_sender, Interrupted::Cancelled));
+        }
+
+        let mut chunk = std::mem::take(&mut self.iv);
+        let plaintext_start = chunk.len();
+        chunk.resize(plaintext_start + CHUNK_SIZE, 0);
+        let mut filled = 0;
+        while filled < CHUNK_SIZE && self.unread > 0 {
+            let max = (CHUNK_SIZE - filled).min(self.unread as usize);
+            let n = self
+                .reader
+                .read(&mut chunk[plaintext_start + filled..][..max])
+                .await?;
+            if n == 0 {
+                let interrupted = Interrupted::UnexpectedLength {
+                    expected: self.length,
+                    actual: self.length - self.unread,
+                };
+                return Err(Self::interrupt(digest_sender, interrupted));
+            }
+            filled += n;
+            self.unread -= n as u64;
+        }
+        if self.unread == 0 && !self.source_read {
+            self.source_read = true;
+            if self.reader.read(&mut [0]).await? > 0 {
+                let interrupted = Interrupted::UnexpectedLength {
+                    expected: self.length,
+                    actual: self.length + 1,
+                };
+                return Err(Self::interrupt(digest_sender, interrupted));
+            }
+        }
+        // the buffer is zeroed, and padding only starts after the last byte of the source
+        let zeros = (CHUNK_SIZE - filled).min(self.padding as usize);
+        filled += zeros;
+        self.padding -= zeros as u64;
+        chunk.truncate(plaintext_start + filled);
+
+        let last = self.unread == 0 && self.padding == 0;
+        if last {
+            let pkcs7_padding = BLOCK_LEN - filled % BLOCK_LEN;
+            chunk.resize(chunk.len() + pkcs7_padding, pkcs7_padding as u8);
+        }
+        for block in chunk[plaintext_start..].chunks_exact_mut(BLOCK_LEN) {
+            self.encryptor
+                .encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        self.mac.update(&chunk);
+        if last {
+            let mac = self.mac.clone().finalize().into_bytes();
+            chunk.extend_from_slice(&mac);
+        }
+        self.digest.update(&chunk);
+
+        self.sent += chunk.len() as u64;
+        let _ = self.sent_sender.unbounded_send(self.sent);
+        if last {
+            let _ = digest_sender.send(Ok(self.digest.clone().finalize().to_vec()));
+        } else {
+            self.digest_sender = Some(digest_sender);
+        }
+        Ok(Some((chunk, self)))
+    }
+
+    fn interrupt(
+        digest_sender: oneshot::Sender<Result<Vec<u8>, Interrupted>>,
+        interrupted: Interrupted,
+    ) -> std::io::Error {
+        let error = std::io::Error::other(format!("attachment upload stopped: {interrupted:?}"));
+        let _ = digest_sender.send(Err(interrupted));
+        error
+    }
+}
+
+/// Form to upload an attachment to the CDN, see [`upload`]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UploadForm {
+    pub(crate) attachment_id: u64,
+    #[serde(flatten)]
+    cdn: CdnForm,
+}
+
+/// Fields of a form to upload a file to the CDN, see [`upload_to_cdn`]
+#[derive(Deserialize)]
+pub(crate) struct CdnForm {
+    pub(crate) key: String,
+    pub(crate) credential: String,
+    pub(crate) acl: String,
+    pub(crate) algorithm: String,
+    pub(crate) date: String,
+    pub(crate) policy: String,
+    pub(crate) signature: String,
+}
+
+/// Requests a form to upload an attachment to the CDN
+pub(crate) async fn upload_form<S: std::error::Error>(
+    service: &mut PushService,
+) -> Result<UploadForm, Error<S>> {
+    let response = service
+        .request(
+            Method::GET,
+            Endpoint::Service,
+            "/v2/attachments/form/upload",
+            &[],
+            HttpAuthOverride::NoOverride,
+        )?
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(ServiceError::from)?;
+    let form = response.bytes().await.map_err(ServiceError::from)?;
+    Ok(serde_json::from_slice(&form)?)
+}
+
+/// Uploads the ciphertext of an attachment to the CDN, as its chunks are read
+pub(crate) async fn upload(
+    service: &mut PushService,
+    form: UploadForm,
+    chunks: Pin<Box<dyn Stream<Item = std::io::Result<Vec<u8>>> + Send>>,
+    length: u64,
+) -> Result<(), ServiceError> {
+    let file = Part::stream_with_length(Body::wrap_stream(chunks), length);
+    upload_to_cdn(service, "attachments/", form.cdn, file).await
+}
+
+/// Uploads a file to the CDN, under the path given with its upload form
+pub(crate) async fn upload_to_cdn(
+    service: &mut PushService,
+    path: &str,
+    form: CdnForm,
+    file: Part,
+) -> Result<(), ServiceError> {
+    let multipart = Form::new()
+        .text("acl", form.acl)
+        .text("key", form.key)
+        .text("policy", form.policy)
+        .text("Content-Type", "application/octet-stream")
+        .text("x-amz-algorithm", form.algorithm)
+        .text("x-amz-credential", form.credential)
+        .text("x-amz-date", form.date)
+        .text("x-amz-signature", form.signature)
+        .part("file", file);
+    service
+        .request(
+            Method::POST,
+            Endpoint::Cdn(0),
+            path,
+            &[],
+            HttpAuthOverride::Unidentified,
+        )?
+        .multipart(multipart)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(ServiceError::from)?;
+    Ok(())
+}
+
+/// Points to an attachment uploaded to the CDN with the id of its upload form
+pub(crate) fn pointer(
+    spec: AttachmentSpec,
+    key: [u8; 64],
+    digest: Vec<u8>,
+    cdn_id: u64,
+) -> AttachmentPointer {
+    let mut flags = 0;
+    if spec.voice_note == Some(true) {
+        flags |= AttachmentPointerFlags::VoiceMessage as u32;
+    }
+    if spec.borderless == Some(true) {
+        flags |= AttachmentPointerFlags::Borderless as u32;
+    }
+    let upload_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis() as u64;
+
+    AttachmentPointer {
+        content_type: Some(spec.content_type),
+        key: Some(key.to_vec()),
+        size: Some(spec.length as u32),
+        thumbnail: spec.preview,
+        digest: Some(digest),
+        file_name: spec.file_name,
+        flags: Some(flags),
+        width: spec.width,
+        height: spec.height,
+        caption: spec.caption,
+        blur_hash: spec.blur_hash,
+        upload_timestamp: Some(upload_timestamp),
+        cdn_number: Some(0),
+        attachment_identifier: Some(AttachmentIdentifier::CdnId(cdn_id)),
+        ..Default::default()
+    }
+}
+
+/// Fills the dimensions and blurhash of image attachments, with the `image-metadata` feature
+#[cfg_attr(not(feature = "image-metadata"), allow(unused_variables))]
+pub(crate) fn fill_metadata(spec: &mut AttachmentSpec, contents: &[u8]) {
+    #[cfg(feature = "image-metadata")]
+    super::attachment_metadata::fill(spec, contents);
+}
+
+/// Progress of an attachment upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadProgress {
+    /// Bytes of the encrypted attachment sent so far, out of its length
+    Uploading { sent: u64, length: u64 },
+    /// The attachment is uploaded
+    Done,
+}
+
+/// Cancels attachment uploads from another task
+///
+/// Clones cancel the same uploads. Uploads stop before sending their next chunk.
+#[derive(Debug, Clone, Default)]
+pub struct CancelUpload(Arc<AtomicBool>);
+
+impl CancelUpload {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{poll, task::Poll, StreamExt};
+    use libsignal_service::attachment_cipher::encrypt_in_place;
+
+    use super::*;
+
+    fn ciphertext(bytes: &'static [u8], start: u64) -> Ciphertext {
+        Ciphertext {
+            stream: Box::pin(futures::io::Cursor::new(bytes)),
+            start,
+        }
+    }
+
+    fn source(contents: &'static [u8], length: usize) -> AttachmentSource {
+        let spec = AttachmentSpec {
+            content_type: "application/octet-stream".to_owned(),
+            length,
+            file_name: None,
+            preview: None,
+            voice_note: None,
+            borderless: None,
+            width: None,
+            height: None,
+            caption: None,
+            blur_hash: None,
+        };
+        AttachmentSource::from_reader(spec, contents)
+    }
+
+    #[tokio::test]
+    async fn unlock_keeps_the_lock_of_waiters() {
+        let downloads = AttachmentDownloads::default();
+        let id = AttachmentId::from_digest(b"digest");
+
+        let first = downloads.lock(&id).await;
+        let mut second = pin!(downloads.lock(&id));
+        assert!(poll!(&mut second).is_pending());
+
+        downloads.unlock(&id, first);
+        let second = second.await;
+        let mut third = pin!(downloads.lock(&id));
+        assert!(poll!(&mut third).is_pending());
+
+        downloads.unlock(&id, second);
+        let Poll::Ready(third) = poll!(&mut third) else {
+            panic!("the third call should get the lock");
+        };
+        downloads.unlock(&id, third);
+        assert!(downloads.in_progress.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resume_download() {
+        const ATTACHMENT: &[u8] = b"the whole ciphertext of an attachment";
+        let digest = Sha256::digest(ATTACHMENT).to_vec();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("attachment.encrypted");
+
+        for served_from in [12, 0] {
+            fs::write(&path, &ATTACHMENT[..12]).await.unwrap();
+            let Partial::Incomplete { len, hasher } = resume(&path, &digest).await.unwrap() else {
+                panic!("the attachment is not downloaded yet");
+            };
+            assert_eq!(len, 12);
+
+            let served = ciphertext(&ATTACHMENT[served_from as usize..], served_from);
+            let downloaded = download(&path, served, len, hasher).await.unwrap();
+            assert_eq!(downloaded, digest);
+            assert_eq!(fs::read(&path).await.unwrap(), ATTACHMENT);
+            assert!(matches!(
+                resume(&path, &digest).await.unwrap(),
+                Partial::Complete
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn decrypt_attachment() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = dir.path().join("attachment.encrypted");
+        let decrypted = dir.path().join("attachment");
+        let key = [7; 64];
+
+        // spans several chunks, and ends with a full block of padding
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE as u32).map(|i| i as u8).collect();
+        let mut ciphertext = plaintext.clone();
+        encrypt_in_place([3; IV_LEN], key, &mut ciphertext);
+        fs::write(&encrypted, &ciphertext).await.unwrap();
+
+        let size = decrypt::<std::io::Error>(&encrypted, &decrypted, &key, None)
+            .await
+            .unwrap();
+        assert_eq!(size, plaintext.len() as u64);
+        assert_eq!(fs::read(&decrypted).await.unwrap(), plaintext);
+
+        // without the zeros padding the plaintext
+        let unpadded = plaintext.len() - 100;
+        let size = decrypt::<std::io::Error>(&encrypted, &decrypted, &key, Some(unpadded as u64))
+            .await
+            .unwrap();
+        assert_eq!(size, unpadded as u64);
+        assert_eq!(fs::read(&decrypted).await.unwrap(), plaintext[..unpadded]);
+
+        *ciphertext.last_mut().unwrap() ^= 1;
+        fs::write(&encrypted, &ciphertext).await.unwrap();
+        let result = decrypt::<std::io::Error>(&encrypted, &decrypted, &key, None).await;
+        assert!(matches!(
+            result,
+            Err(Error::AttachmentCipherError(
+                AttachmentCipherError::MacError
+            ))
+        ));
+    }
+
+    #[test]
+    fn unpad_plaintext() {
+        assert_eq!(unpad(b"attachment\x02\x02").unwrap(), b"attachment");
+        assert!(unpad(&[16; 16]).unwrap().is_empty());
+
+        let bad_paddings: [&[u8]; 4] = [b"attachment\x00", b"attachment\x01\x02", &[17; 32], b""];
+        for plaintext in bad_paddings {
+            assert!(matches!(
+                unpad(plaintext),
+                Err(AttachmentCipherError::PaddingError)
+            ));
+        }
+    }
+
+    #[test]
+    fn padded_lengths() {
+        assert_eq!(padded_len(0), 541);
+        assert_eq!(padded_len(541), 541);
+        let mut previous = 541;
+        for len in [542, 1000, 65_536, 10_000_000] {
+            let padded = padded_len(len);
+            assert!(
+                padded >= len && padded >= previous,
+                "{len} padded to {padded}"
+            );
+            // at most 5% of padding
+            assert!(padded <= len + len / 20 + 1, "{len} padded to {padded}");
+            previous = padded;
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypt_attachment() {
+        const ATTACHMENT: &[u8] = &[42; 3 * CHUNK_SIZE + 100];
+        let encrypted = source(ATTACHMENT, ATTACHMENT.len()).encrypt(&CancelUpload::new());
+        let EncryptedAttachment {
+            key,
+            length,
+            chunks,
+            sent,
+            digest,
+            ..
+        } = encrypted;
+
+        let ciphertext: Vec<u8> = chunks.map(Result::unwrap).concat().await;
+        assert_eq!(ciphertext.len() as u64, length);
+        assert_eq!(sent.collect::<Vec<_>>().await.last(), Some(&length));
+        assert_eq!(
+            digest.await.unwrap().unwrap(),
+            Sha256::digest(&ciphertext).to_vec()
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = dir.path().join("attachment.encrypted");
+        let decrypted = dir.path().join("attachment");
+        fs::write(&encrypted, &ciphertext).await.unwrap();
+        let size = ATTACHMENT.len() as u64;
+        decrypt::<std::io::Error>(&encrypted, &decrypted, &key, Some(size))
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), ATTACHMENT);
+    }
+
+    #[tokio::test]
+    async fn stop_encrypting_attachment() {
+        let shorter = source(b"attachment", 20).encrypt(&CancelUpload::new());
+        let longer = source(b"attachment", 5).encrypt(&CancelUpload::new());
+        let cancel = CancelUpload::new();
+        let cancelled = source(b"attachment", 10).encrypt(&cancel);
+        cancel.cancel();
+
+        for (encrypted, expected) in [
+            (shorter, Some((20, 10))),
+            (longer, Some((5, 6))),
+            (cancelled, None),
+        ] {
+            let errors: Vec<_> = encrypted
+                .chunks
+                .filter_map(|chunk| async move { chunk.err() })
+                .collect()
+                .await;
+            assert_eq!(errors.len(), 1);
+            match (encrypted.digest.await.unwrap(), expected) {
+                (Err(Interrupted::UnexpectedLength { expected, actual }), Some(lengths)) => {
+                    assert_eq!((expected, actual), lengths)
+                }
+                (Err(Interrupted::Cancelled), None) => {}
+                (outcome, _) => panic!("unexpected outcome {outcome:?}"),
+            }
+        }
+    }
+}
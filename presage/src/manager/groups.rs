@@ -0,0 +1,911 @@
+//! Changes made to groups by us
+//!
+//! Groups are kept encrypted by the storage service, with the secret params derived from their
+//! master key. They are created with `PUT /v1/groups/` and changed with `PATCH /v1/groups/`: a
+//! change is a set of `GroupChange.Actions` for the next revision, which the server checks
+//! against the access control of the group before signing it. The signed change is applied to the
+//! stored group, and sent to the members in the group context of a data message.
+//!
+//! Adding a member presents a credential of their profile key, which the server issues along with
+//! their profile. Users whose profile key we do not know are invited instead, and become members
+//! once they accept the invitation.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::*;
+use bytes::Bytes;
+use libsignal_service::configuration::Endpoint;
+use libsignal_service::groups_v2::Role;
+use libsignal_service::prelude::{AccessControl, ProtobufMessage};
+use libsignal_service::proto::access_control::AccessRequired;
+use libsignal_service::proto::group_attribute_blob::Content as Blob;
+use libsignal_service::proto::group_change::actions::{
+    AddMemberAction, AddPendingMemberAction, AddRequestingMemberAction, DeleteMemberAction,
+    DeletePendingMemberAction, DeleteRequestingMemberAction,
+    ModifyAddFromInviteLinkAccessControlAction, ModifyAttributesAccessControlAction,
+    ModifyAvatarAction, ModifyDescriptionAction, ModifyDisappearingMessagesTimerAction,
+    ModifyMemberRoleAction, ModifyMembersAccessControlAction, ModifyTitleAction,
+    PromotePendingMemberAction, PromoteRequestingMemberAction,
+};
+use libsignal_service::proto::group_change::Actions;
+use libsignal_service::proto::{
+    self, AvatarUploadAttributes, GroupAttributeBlob, GroupChange, GroupChangeResponse,
+};
+use libsignal_service::protocol::{Aci, ServiceId};
+use libsignal_service::push_service::{HttpAuth, HttpAuthOverride, PushService, ServiceError};
+use libsignal_service::zkgroup::groups::GroupSecretParams;
+use libsignal_service::zkgroup::profiles::{
+    ExpiringProfileKeyCredential, ExpiringProfileKeyCredentialResponse, ProfileKey,
+};
+use libsignal_service::zkgroup::{self, ServerPublicParams, Timestamp};
+use rand::{thread_rng, RngCore};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::multipart::Part;
+use reqwest::Method;
+use serde::Deserialize;
+
+use super::attachments::{self, CdnForm};
+use crate::model::groups::GroupJoinInfo;
+use crate::Error;
+
+const GROUPS_PATH: &str = "/v1/groups/";
+
+/// A user added to a group, see [`new_group`] and [`Change::NewMember`]
+#[derive(Debug, Clone)]
+pub(crate) enum NewMember {
+    /// Adds a member, with the presentation of their profile key credential
+    Add { presentation: Vec<u8>, role: Role },
+    /// Invites a user whose profile key we do not know
+    Invite { service_id: ServiceId, role: Role },
+}
+
+/// A change we request to a group, see [`actions`]
+#[derive(Debug, Clone)]
+pub(crate) enum Change {
+    NewMember(NewMember),
+    DeleteMember(Aci),
+    ModifyMemberRole {
+        aci: Aci,
+        role: Role,
+    },
+    PromoteRequestingMember {
+        aci: Aci,
+        role: Role,
+    },
+    DeleteRequestingMember(Aci),
+    DeletePendingMember(ServiceId),
+    /// Accepts our invitation, with the presentation of our profile key credential
+    PromotePendingMember {
+        presentation: Vec<u8>,
+    },
+    /// Joins with the invite link, with the presentation of our profile key credential
+    JoinFromInviteLink {
+        presentation: Vec<u8>,
+    },
+    /// Requests to join with the invite link, with the presentation of our profile key credential
+    RequestToJoin {
+        presentation: Vec<u8>,
+    },
+    Title(String),
+    /// Removes the description when empty
+    Description(String),
+    /// The key of the encrypted avatar on the CDN, empty to remove the avatar
+    Avatar(String),
+    /// Disappearing messages timer in seconds, 0 to disable it
+    Timer(u32),
+    AttributesAccess(AccessRequired),
+    MembersAccess(AccessRequired),
+    AddFromInviteLinkAccess(AccessRequired),
+}
+
+/// Encrypts the contents of a group, as members of the group see them
+pub(crate) struct GroupEncryption {
+    secret_params: GroupSecretParams,
+}
+
+impl GroupEncryption {
+    pub(crate) fn new(secret_params: GroupSecretParams) -> Self {
+        Self { secret_params }
+    }
+
+    pub(crate) fn service_id(&self, service_id: impl Into<ServiceId>) -> Vec<u8> {
+        zkgroup::serialize(&self.secret_params.encrypt_service_id(service_id.into()))
+    }
+
+    pub(crate) fn title(&self, title: &str) -> Vec<u8> {
+        self.blob(Blob::Title(title.to_owned()))
+    }
+
+    pub(crate) fn description(&self, description: &str) -> Vec<u8> {
+        self.blob(Blob::DescriptionText(description.to_owned()))
+    }
+
+    pub(crate) fn timer(&self, seconds: u32) -> Vec<u8> {
+        self.blob(Blob::DisappearingMessagesDuration(seconds))
+    }
+
+    pub(crate) fn avatar(&self, avatar: &[u8]) -> Vec<u8> {
+        self.blob(Blob::Avatar(avatar.to_vec()))
+    }
+
+    fn decrypt_blob(&self, blob: &[u8]) -> Option<Blob> {
+        let blob = self.secret_params.decrypt_blob_with_padding(blob).ok()?;
+        GroupAttributeBlob::decode(blob.as_slice()).ok()?.content
+    }
+
+    fn blob(&self, content: Blob) -> Vec<u8> {
+        let blob = GroupAttributeBlob {
+            content: Some(content),
+        };
+        let mut randomness = [0; 32];
+        thread_rng().fill_bytes(&mut randomness);
+        self.secret_params
+            .encrypt_blob_with_padding(randomness, &blob.encode_to_vec(), 0)
+    }
+}
+
+/// Actions of the changes to bring a group to `revision`, the one after its current revision
+pub(crate) fn actions(
+    encryption: &GroupEncryption,
+    revision: u32,
+    changes: Vec<Change>,
+) -> Actions {
+    let mut actions = Actions {
+        revision,
+        ..Default::default()
+    };
+    for change in changes {
+        match change {
+            Change::NewMember(NewMember::Add { presentation, role }) => {
+                actions.add_members.push(AddMemberAction {
+                    added: Some(proto::Member {
+                        role: role as i32,
+                        presentation,
+                        ..Default::default()
+                    }),
+                    join_from_invite_link: false,
+                })
+            }
+            Change::NewMember(NewMember::Invite { service_id, role }) => {
+                actions.add_pending_members.push(AddPendingMemberAction {
+                    added: Some(proto::PendingMember {
+                        member: Some(proto::Member {
+                            user_id: encryption.service_id(service_id),
+                            role: role as i32,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                })
+            }
+            Change::DeleteMember(aci) => actions.delete_members.push(DeleteMemberAction {
+                deleted_user_id: encryption.service_id(aci),
+            }),
+            Change::ModifyMemberRole { aci, role } => {
+                actions.modify_member_roles.push(ModifyMemberRoleAction {
+                    user_id: encryption.service_id(aci),
+                    role: role as i32,
+                })
+            }
+            Change::PromoteRequestingMember { aci, role } => actions
+                .promote_requesting_members
+                .push(PromoteRequestingMemberAction {
+                    user_id: encryption.service_id(aci),
+                    role: role as i32,
+                }),
+            Change::DeleteRequestingMember(aci) => {
+                actions
+                    .delete_requesting_members
+                    .push(DeleteRequestingMemberAction {
+                        deleted_user_id: encryption.service_id(aci),
+                    })
+            }
+            Change::DeletePendingMember(service_id) => {
+                actions
+                    .delete_pending_members
+                    .push(DeletePendingMemberAction {
+                        deleted_user_id: encryption.service_id(service_id),
+                    })
+            }
+            Change::PromotePendingMember { presentation } => {
+                actions
+                    .promote_pending_members
+                    .push(PromotePendingMemberAction {
+                        presentation,
+                        ..Default::default()
+                    })
+            }
+            Change::JoinFromInviteLink { presentation } => {
+                actions.add_members.push(AddMemberAction {
+                    added: Some(proto::Member {
+                        role: Role::Default as i32,
+                        presentation,
+                        ..Default::default()
+                    }),
+                    join_from_invite_link: true,
+                })
+            }
+            Change::RequestToJoin { presentation } => {
+                actions
+                    .add_requesting_members
+                    .push(AddRequestingMemberAction {
+                        added: Some(proto::RequestingMember {
+                            presentation,
+                            ..Default::default()
+                        }),
+                    })
+            }
+            Change::Title(title) => {
+                actions.modify_title = Some(ModifyTitleAction {
+                    title: encryption.title(&title),
+                })
+            }
+            Change::Description(description) => {
+                let description = if description.is_empty() {
+                    Vec::new()
+                } else {
+                    encryption.description(&description)
+                };
+                actions.modify_description = Some(ModifyDescriptionAction { description })
+            }
+            Change::Avatar(avatar) => actions.modify_avatar = Some(ModifyAvatarAction { avatar }),
+            Change::Timer(seconds) => {
+                actions.modify_disappearing_messages_timer =
+                    Some(ModifyDisappearingMessagesTimerAction {
+                        timer: encryption.timer(seconds),
+                    })
+            }
+            Change::AttributesAccess(access) => {
+                actions.modify_attributes_access = Some(ModifyAttributesAccessControlAction {
+                    attributes_access: access as i32,
+                })
+            }
+            Change::MembersAccess(access) => {
+                actions.modify_member_access = Some(ModifyMembersAccessControlAction {
+                    members_access: access as i32,
+                })
+            }
+            Change::AddFromInviteLinkAccess(access) => {
+                actions.modify_add_from_invite_link_access =
+                    Some(ModifyAddFromInviteLinkAccessControlAction {
+                        add_from_invite_link_access: access as i32,
+                    })
+            }
+        }
+    }
+    actions
+}
+
+/// A new group at revision 0, with us as its administrator
+///
+/// Members are added with the presentation of their profile key credential, like us, or invited.
+/// Only members may change the attributes and the members of the group, and joining by link is
+/// disabled.
+pub(crate) fn new_group(
+    secret_params: &GroupSecretParams,
+    title: &str,
+    avatar: String,
+    members: Vec<NewMember>,
+) -> proto::Group {
+    let encryption = GroupEncryption::new(*secret_params);
+    let mut group = proto::Group {
+        public_key: zkgroup::serialize(&secret_params.get_public_params()),
+        title: encryption.title(title),
+        avatar,
+        access_control: Some(proto::AccessControl {
+            attributes: AccessRequired::Member as i32,
+            members: AccessRequired::Member as i32,
+            add_from_invite_link: AccessRequired::Unsatisfiable as i32,
+        }),
+        revision: 0,
+        ..Default::default()
+    };
+    for member in members {
+        match member {
+            NewMember::Add { presentation, role } => group.members.push(proto::Member {
+                role: role as i32,
+                presentation,
+                ..Default::default()
+            }),
+            NewMember::Invite { service_id, role } => {
+                group.pending_members.push(proto::PendingMember {
+                    member: Some(proto::Member {
+                        user_id: encryption.service_id(service_id),
+                        role: role as i32,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+    group
+}
+
+/// The change to join a group with its invite link, directly or by requesting to join
+///
+/// Returns `None` when joining by link is disabled.
+pub(crate) fn join_change(
+    add_from_invite_link: AccessRequired,
+    presentation: Vec<u8>,
+) -> Option<Change> {
+    match add_from_invite_link {
+        AccessRequired::Any => Some(Change::JoinFromInviteLink { presentation }),
+        AccessRequired::Administrator => Some(Change::RequestToJoin { presentation }),
+        _ => None,
+    }
+}
+
+/// Decrypts the preview of a group
+pub(crate) fn join_info(
+    encryption: &GroupEncryption,
+    join_info: proto::GroupJoinInfo,
+) -> GroupJoinInfo {
+    let title = match encryption.decrypt_blob(&join_info.title) {
+        Some(Blob::Title(title)) => title,
+        _ => String::new(),
+    };
+    let description = match encryption.decrypt_blob(&join_info.description) {
+        Some(Blob::DescriptionText(description)) if !description.is_empty() => Some(description),
+        _ => None,
+    };
+    GroupJoinInfo {
+        title,
+        description,
+        avatar: join_info.avatar,
+        member_count: join_info.member_count,
+        revision: join_info.revision,
+        add_from_invite_link: AccessRequired::try_from(join_info.add_from_invite_link)
+            .unwrap_or(AccessRequired::Unknown),
+        pending_admin_approval: join_info.pending_admin_approval,
+    }
+}
+
+/// Changes from the access control of a group to another, one per modified access right
+pub(crate) fn access_control_changes(
+    from: Option<&AccessControl>,
+    to: &AccessControl,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if from.map(|from| from.attributes) != Some(to.attributes) {
+        changes.push(Change::AttributesAccess(to.attributes));
+    }
+    if from.map(|from| from.members) != Some(to.members) {
+        changes.push(Change::MembersAccess(to.members));
+    }
+    if from.map(|from| from.add_from_invite_link) != Some(to.add_from_invite_link) {
+        changes.push(Change::AddFromInviteLinkAccess(to.add_from_invite_link));
+    }
+    changes
+}
+
+/// Creates a group, authenticated for it
+pub(crate) async fn create(
+    service: &mut PushService,
+    auth: HttpAuth,
+    group: proto::Group,
+) -> Result<(), ServiceError> {
+    request(
+        service,
+        Method::PUT,
+        GROUPS_PATH,
+        auth,
+        Some(group.encode_to_vec()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Changes a group, and returns the change signed by the server
+///
+/// Users who are not members yet join with the password of the invite link of the group.
+pub(crate) async fn modify<S: std::error::Error>(
+    service: &mut PushService,
+    auth: HttpAuth,
+    actions: Actions,
+    invite_link_password: Option<&[u8]>,
+) -> Result<GroupChange, Error<S>> {
+    let path = match invite_link_password {
+        Some(password) => format!(
+            "{GROUPS_PATH}?inviteLinkPassword={}",
+            BASE64_URL_SAFE_NO_PAD.encode(password)
+        ),
+        None => GROUPS_PATH.to_owned(),
+    };
+    let response = request(
+        service,
+        Method::PATCH,
+        &path,
+        auth,
+        Some(actions.encode_to_vec()),
+    )
+    .await?;
+    GroupChangeResponse::decode(response)?
+        .group_change
+        .ok_or(Error::MissingGroupChange)
+}
+
+/// Fetches the preview of a group with the password of its invite link
+pub(crate) async fn fetch_join_info<S: std::error::Error>(
+    service: &mut PushService,
+    auth: HttpAuth,
+    invite_link_password: &[u8],
+) -> Result<proto::GroupJoinInfo, Error<S>> {
+    let path = format!(
+        "{GROUPS_PATH}join/{}",
+        BASE64_URL_SAFE_NO_PAD.encode(invite_link_password)
+    );
+    let response = request(service, Method::GET, &path, auth, None).await?;
+    Ok(proto::GroupJoinInfo::decode(response)?)
+}
+
+/// Fetches the signed changes of a group from a revision on
+///
+/// The server may only return the first changes, the next ones are fetched from the revision
+/// following the last returned change.
+pub(crate) async fn fetch_logs<S: std::error::Error>(
+    service: &mut PushService,
+    auth: HttpAuth,
+    from_revision: u32,
+) -> Result<proto::GroupChanges, Error<S>> {
+    let path = format!("{GROUPS_PATH}logs/{from_revision}");
+    let response = request(service, Method::GET, &path, auth, None).await?;
+    Ok(proto::GroupChanges::decode(response)?)
+}
+
+/// Uploads the encrypted avatar of a group to the CDN, and returns its key
+pub(crate) async fn upload_avatar<S: std::error::Error>(
+    service: &mut PushService,
+    auth: HttpAuth,
+    encrypted_avatar: Vec<u8>,
+) -> Result<String, Error<S>> {
+    let form = request(
+        service,
+        Method::GET,
+        &format!("{GROUPS_PATH}avatar/form"),
+        auth,
+        None,
+    )
+    .await?;
+    let form = AvatarUploadAttributes::decode(form)?;
+    let key = form.key.clone();
+    attachments::upload_to_cdn(service, "", form.into(), Part::bytes(encrypted_avatar)).await?;
+    Ok(key)
+}
+
+/// Sends a request about a group to the storage service, authenticated for the group
+async fn request(
+    service: &mut PushService,
+    method: Method,
+    path: &str,
+    auth: HttpAuth,
+    body: Option<Vec<u8>>,
+) -> Result<Bytes, ServiceError> {
+    let mut request = service.request(
+        method,
+        Endpoint::Storage,
+        path,
+        &[],
+        HttpAuthOverride::Identified(auth),
+    )?;
+    if let Some(body) = body {
+        request = request
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .body(body);
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(ServiceError::from)?;
+    response.bytes().await.map_err(ServiceError::from)
+}
+
+impl From<AvatarUploadAttributes> for CdnForm {
+    fn from(form: AvatarUploadAttributes) -> Self {
+        Self {
+            key: form.key,
+            credential: form.credential,
+            acl: form.acl,
+            algorithm: form.algorithm,
+            date: form.date,
+            policy: form.policy,
+            signature: form.signature,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProfileResponse {
+    credential: Option<String>,
+}
+
+/// Fetches the credential of the profile key of a user, to present it to a group
+///
+/// The credential is only issued for the current profile key of the user.
+pub(crate) async fn profile_key_credential<S: std::error::Error>(
+    service: &mut PushService,
+    server_public_params: &ServerPublicParams,
+    aci: Aci,
+    profile_key: ProfileKey,
+) -> Result<ExpiringProfileKeyCredential, Error<S>> {
+    let mut randomness = [0; 32];
+    thread_rng().fill_bytes(&mut randomness);
+    let context = server_public_params.create_profile_key_credential_request_context(
+        randomness,
+        aci,
+        profile_key,
+    );
+    let version = zkgroup::serialize(&profile_key.get_profile_key_version(aci));
+    let version = String::from_utf8(version).map_err(|_| Error::InvalidProfileKeyCredential)?;
+    let credential_request = hex::encode(zkgroup::serialize(&context.get_request()));
+    let path = format!(
+        "/v1/profile/{}/{version}/{credential_request}?credentialType=expiringProfileKey",
+        aci.service_id_string()
+    );
+
+    let response = service
+        .request(
+            Method::GET,
+            Endpoint::Service,
+            &path,
+            &[],
+            HttpAuthOverride::NoOverride,
+        )?
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(ServiceError::from)?;
+    let profile: ProfileResponse =
+        serde_json::from_slice(&response.bytes().await.map_err(ServiceError::from)?)?;
+    let credential = BASE64_STANDARD.decode(
+        profile
+            .credential
+            .ok_or(Error::InvalidProfileKeyCredential)?,
+    )?;
+    let credential: ExpiringProfileKeyCredentialResponse =
+        zkgroup::deserialize(&credential).map_err(|_| Error::InvalidProfileKeyCredential)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs();
+    server_public_params
+        .receive_expiring_profile_key_credential(
+            &context,
+            &credential,
+            Timestamp::from_epoch_seconds(now),
+        )
+        .map_err(|_| Error::InvalidProfileKeyCredential)
+}
+
+/// Presents the credential of the profile key of a user to a group
+pub(crate) fn presentation(
+    server_public_params: &ServerPublicParams,
+    secret_params: &GroupSecretParams,
+    credential: ExpiringProfileKeyCredential,
+) -> Vec<u8> {
+    let mut randomness = [0; 32];
+    thread_rng().fill_bytes(&mut randomness);
+    let presentation = server_public_params.create_expiring_profile_key_credential_presentation(
+        randomness,
+        *secret_params,
+        credential,
+    );
+    zkgroup::serialize(&presentation)
+}
+
+#[cfg(test)]
+mod tests {
+    use libsignal_service::prelude::Uuid;
+    use libsignal_service::protocol::Pni;
+    use libsignal_service::zkgroup::groups::UuidCiphertext;
+
+    use super::*;
+
+    const ALICE: Uuid = Uuid::from_u128(1);
+    const BOB: Uuid = Uuid::from_u128(2);
+
+    fn secret_params() -> GroupSecretParams {
+        GroupSecretParams::generate([7; 32])
+    }
+
+    fn decrypt_service_id(secret_params: &GroupSecretParams, user_id: &[u8]) -> ServiceId {
+        let ciphertext: UuidCiphertext = zkgroup::deserialize(user_id).unwrap();
+        secret_params.decrypt_service_id(ciphertext).unwrap()
+    }
+
+    fn decrypt_blob(secret_params: &GroupSecretParams, blob: &[u8]) -> Blob {
+        let blob = secret_params.decrypt_blob_with_padding(blob).unwrap();
+        GroupAttributeBlob::decode(blob.as_slice())
+            .unwrap()
+            .content
+            .unwrap()
+    }
+
+    #[test]
+    fn encrypt_membership_changes() {
+        let secret_params = secret_params();
+        let actions = actions(
+            &GroupEncryption::new(secret_params),
+            5,
+            vec![
+                Change::NewMember(NewMember::Add {
+                    presentation: vec![1, 2, 3],
+                    role: Role::Default,
+                }),
+                Change::NewMember(NewMember::Invite {
+                    service_id: Pni::from(BOB).into(),
+                    role: Role::Default,
+                }),
+                Change::DeleteMember(Aci::from(BOB)),
+                Change::ModifyMemberRole {
+                    aci: Aci::from(ALICE),
+                    role: Role::Administrator,
+                },
+                Change::PromoteRequestingMember {
+                    aci: Aci::from(BOB),
+                    role: Role::Default,
+                },
+                Change::DeleteRequestingMember(Aci::from(ALICE)),
+                Change::DeletePendingMember(Pni::from(ALICE).into()),
+            ],
+        );
+
+        assert_eq!(actions.revision, 5);
+        let added = actions.add_members[0].added.as_ref().unwrap();
+        assert_eq!(added.presentation, vec![1, 2, 3]);
+        assert_eq!(added.role, Role::Default as i32);
+        assert!(!actions.add_members[0].join_from_invite_link);
+
+        let invited = actions.add_pending_members[0]
+            .added
+            .as_ref()
+            .and_then(|pending| pending.member.as_ref())
+            .unwrap();
+        assert_eq!(
+            decrypt_service_id(&secret_params, &invited.user_id),
+            Pni::from(BOB).into()
+        );
+        assert_eq!(
+            decrypt_service_id(&secret_params, &actions.delete_members[0].deleted_user_id),
+            Aci::from(BOB).into()
+        );
+        let modified = &actions.modify_member_roles[0];
+        assert_eq!(
+            decrypt_service_id(&secret_params, &modified.user_id),
+            Aci::from(ALICE).into()
+        );
+        assert_eq!(modified.role, Role::Administrator as i32);
+        assert_eq!(
+            decrypt_service_id(
+                &secret_params,
+                &actions.promote_requesting_members[0].user_id
+            ),
+            Aci::from(BOB).into()
+        );
+        assert_eq!(
+            decrypt_service_id(
+                &secret_params,
+                &actions.delete_requesting_members[0].deleted_user_id
+            ),
+            Aci::from(ALICE).into()
+        );
+        assert_eq!(
+            decrypt_service_id(
+                &secret_params,
+                &actions.delete_pending_members[0].deleted_user_id
+            ),
+            Pni::from(ALICE).into()
+        );
+        assert!(actions.modify_title.is_none());
+    }
+
+    #[test]
+    fn encrypt_attribute_changes() {
+        let secret_params = secret_params();
+        let actions = actions(
+            &GroupEncryption::new(secret_params),
+            2,
+            vec![
+                Change::Title("Title".into()),
+                Change::Description(String::new()),
+                Change::Avatar("avatar-key".into()),
+                Change::Timer(3600),
+                Change::MembersAccess(AccessRequired::Administrator),
+            ],
+        );
+
+        assert_eq!(actions.revision, 2);
+        assert!(matches!(
+            decrypt_blob(&secret_params, &actions.modify_title.unwrap().title),
+            Blob::Title(title) if title == "Title"
+        ));
+        assert!(actions.modify_description.unwrap().description.is_empty());
+        assert_eq!(actions.modify_avatar.unwrap().avatar, "avatar-key");
+        assert!(matches!(
+            decrypt_blob(
+                &secret_params,
+                &actions.modify_disappearing_messages_timer.unwrap().timer
+            ),
+            Blob::DisappearingMessagesDuration(3600)
+        ));
+        assert_eq!(
+            actions.modify_member_access.unwrap().members_access,
+            AccessRequired::Administrator as i32
+        );
+        assert!(actions.modify_attributes_access.is_none());
+        assert!(actions.add_members.is_empty());
+
+        let description = super::actions(
+            &GroupEncryption::new(secret_params),
+            3,
+            vec![Change::Description("About".into())],
+        )
+        .modify_description
+        .unwrap()
+        .description;
+        assert!(matches!(
+            decrypt_blob(&secret_params, &description),
+            Blob::DescriptionText(text) if text == "About"
+        ));
+    }
+
+    #[test]
+    fn only_changed_access_rights() {
+        let access_control = AccessControl {
+            attributes: AccessRequired::Member,
+            members: AccessRequired::Member,
+            add_from_invite_link: AccessRequired::Unsatisfiable,
+        };
+        let changes = access_control_changes(
+            Some(&access_control),
+            &AccessControl {
+                members: AccessRequired::Administrator,
+                ..access_control.clone()
+            },
+        );
+        assert!(matches!(
+            changes.as_slice(),
+            [Change::MembersAccess(AccessRequired::Administrator)]
+        ));
+        assert!(access_control_changes(Some(&access_control), &access_control).is_empty());
+        // unknown access rights are all set
+        assert_eq!(access_control_changes(None, &access_control).len(), 3);
+    }
+
+    #[test]
+    fn join_with_invite_link() {
+        let secret_params = secret_params();
+        let encryption = GroupEncryption::new(secret_params);
+        assert!(matches!(
+            join_change(AccessRequired::Any, vec![1]),
+            Some(Change::JoinFromInviteLink { .. })
+        ));
+        assert!(matches!(
+            join_change(AccessRequired::Administrator, vec![1]),
+            Some(Change::RequestToJoin { .. })
+        ));
+        for disabled in [AccessRequired::Unsatisfiable, AccessRequired::Unknown] {
+            assert!(join_change(disabled, vec![1]).is_none());
+        }
+
+        let actions = actions(
+            &encryption,
+            4,
+            vec![Change::JoinFromInviteLink {
+                presentation: vec![1, 2, 3],
+            }],
+        );
+        assert!(actions.add_members[0].join_from_invite_link);
+        let added = actions.add_members[0].added.as_ref().unwrap();
+        assert_eq!(added.presentation, vec![1, 2, 3]);
+        assert_eq!(added.role, Role::Default as i32);
+        let requested = super::actions(
+            &encryption,
+            4,
+            vec![Change::RequestToJoin {
+                presentation: vec![1, 2, 3],
+            }],
+        );
+        assert_eq!(
+            requested.add_requesting_members[0]
+                .added
+                .as_ref()
+                .unwrap()
+                .presentation,
+            vec![1, 2, 3]
+        );
+        assert!(requested.add_members.is_empty());
+    }
+
+    #[test]
+    fn decrypt_join_info() {
+        let secret_params = secret_params();
+        let encryption = GroupEncryption::new(secret_params);
+        let preview = join_info(
+            &encryption,
+            proto::GroupJoinInfo {
+                title: encryption.title("Group"),
+                description: encryption.description("About"),
+                avatar: "avatar-key".into(),
+                member_count: 3,
+                add_from_invite_link: AccessRequired::Administrator as i32,
+                revision: 8,
+                pending_admin_approval: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            preview,
+            GroupJoinInfo {
+                title: "Group".into(),
+                description: Some("About".into()),
+                avatar: "avatar-key".into(),
+                member_count: 3,
+                revision: 8,
+                add_from_invite_link: AccessRequired::Administrator,
+                pending_admin_approval: true,
+            }
+        );
+
+        // attributes encrypted for another group are left out
+        let other = GroupEncryption::new(GroupSecretParams::generate([8; 32]));
+        let preview = join_info(
+            &encryption,
+            proto::GroupJoinInfo {
+                title: other.title("Other"),
+                ..Default::default()
+            },
+        );
+        assert_eq!(preview.title, "");
+        assert_eq!(preview.description, None);
+    }
+
+    #[test]
+    fn encrypt_new_group() {
+        let secret_params = secret_params();
+        let group = new_group(
+            &secret_params,
+            "Group",
+            "avatar-key".into(),
+            vec![
+                NewMember::Add {
+                    presentation: vec![1, 2, 3],
+                    role: Role::Administrator,
+                },
+                NewMember::Invite {
+                    service_id: Aci::from(BOB).into(),
+                    role: Role::Default,
+                },
+            ],
+        );
+
+        assert_eq!(group.revision, 0);
+        assert_eq!(
+            group.public_key,
+            zkgroup::serialize(&secret_params.get_public_params())
+        );
+        assert!(matches!(
+            decrypt_blob(&secret_params, &group.title),
+            Blob::Title(title) if title == "Group"
+        ));
+        assert_eq!(group.avatar, "avatar-key");
+        assert_eq!(group.members.len(), 1);
+        assert_eq!(group.members[0].role, Role::Administrator as i32);
+        assert_eq!(group.members[0].presentation, vec![1, 2, 3]);
+        let invited = group.pending_members[0].member.as_ref().unwrap();
+        assert_eq!(
+            decrypt_service_id(&secret_params, &invited.user_id),
+            Aci::from(BOB).into()
+        );
+        assert_eq!(
+            group.access_control,
+            Some(proto::AccessControl {
+                attributes: AccessRequired::Member as i32,
+                members: AccessRequired::Member as i32,
+                add_from_invite_link: AccessRequired::Unsatisfiable as i32,
+            })
+        );
+    }
+}
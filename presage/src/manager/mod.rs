@@ -1,12 +1,17 @@
 //! Signal manager and its states
 
+#[cfg(feature = "image-metadata")]
+mod attachment_metadata;
+mod attachments;
 mod confirmation;
+mod groups;
 mod linking;
 mod registered;
 mod registration;
 
 use std::fmt;
 
+pub use self::attachments::{AttachmentSource, CancelUpload, UploadProgress};
 pub use self::confirmation::Confirmation;
 pub use self::linking::Linking;
 pub use self::registered::{Registered, RegistrationData, RegistrationType};
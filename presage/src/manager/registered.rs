@@ -1,21 +1,26 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::path::PathBuf;
+use std::pin::pin;
 use std::sync::{Arc, OnceLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use futures::{future, AsyncReadExt, Stream, StreamExt};
-use libsignal_service::attachment_cipher::decrypt_in_place;
 use libsignal_service::configuration::{ServiceConfiguration, SignalServers, SignalingKey};
 use libsignal_service::content::{Content, ContentBody, DataMessageFlags, Metadata};
-use libsignal_service::groups_v2::{decrypt_group, GroupsManager, InMemoryCredentialsCache};
+use libsignal_service::groups_v2::{
+    decrypt_group, GroupChanges, GroupsManager, InMemoryCredentialsCache, Role,
+};
 use libsignal_service::messagepipe::{Incoming, MessagePipe, ServiceCredentials};
 use libsignal_service::prelude::phonenumber::PhoneNumber;
-use libsignal_service::prelude::{MessageSenderError, ProtobufMessage, Uuid};
+use libsignal_service::prelude::{AccessControl, MessageSenderError, ProtobufMessage, Uuid};
 use libsignal_service::profile_cipher::ProfileCipher;
-use libsignal_service::proto::data_message::Delete;
+use libsignal_service::proto::data_message::{quote, Delete, Quote, Reaction};
 use libsignal_service::proto::{
+    receipt_message,
     sync_message::{self, sticker_pack_operation, StickerPackOperation},
-    AttachmentPointer, DataMessage, EditMessage, GroupContextV2, NullMessage, SyncMessage,
-    Verified,
+    typing_message, AttachmentPointer, BodyRange, DataMessage, EditMessage, GroupContextV2,
+    NullMessage, ReceiptMessage, SyncMessage, TypingMessage, Verified,
 };
 use libsignal_service::protocol::{
     Aci, IdentityKeyStore, SenderCertificate, ServiceId, ServiceIdKind,
@@ -26,29 +31,52 @@ use libsignal_service::push_service::{
     WhoAmIResponse, DEFAULT_DEVICE_ID,
 };
 use libsignal_service::receiver::MessageReceiver;
-use libsignal_service::sender::{AttachmentSpec, AttachmentUploadError};
+use libsignal_service::sender::{AttachmentSpec, AttachmentUploadError, SentMessage};
 use libsignal_service::sticker_cipher::derive_key;
 use libsignal_service::unidentified_access::UnidentifiedAccess;
 use libsignal_service::utils::serde_signaling_key;
 use libsignal_service::websocket::SignalWebSocket;
 use libsignal_service::zkgroup::groups::{GroupMasterKey, GroupSecretParams};
 use libsignal_service::zkgroup::profiles::ProfileKey;
+use libsignal_service::zkgroup::GroupMasterKeyBytes;
 use libsignal_service::{cipher, AccountManager, Profile, ServiceIdExt};
 use rand::rngs::ThreadRng;
-use rand::thread_rng;
+use rand::{thread_rng, RngCore};
 use serde::{Deserialize, Serialize};
-use sha2::Digest;
 use tokio::sync::Mutex;
+use tokio::time::{Interval, MissedTickBehavior};
 use tracing::{debug, error, info, trace, warn};
 use url::Url;
 
+use super::attachments::{
+    self, AttachmentDownloads, AttachmentSource, CancelUpload, EncryptedAttachment, Partial,
+    UploadProgress,
+};
+use super::groups::{self, Change, GroupEncryption, NewMember};
 use crate::model::contacts::Contact;
+use crate::model::messages::{DeliveryState, Message, MessageReaction};
+use crate::model::text;
 use crate::serde::serde_profile_key;
-use crate::store::{ContentsStore, Sticker, StickerPack, StickerPackManifest, Store, Thread};
-use crate::{model::groups::Group, AvatarBytes, Error, Manager};
+use crate::store::attachments::{AttachmentId, DownloadedAttachment};
+use crate::store::expiry::{expires_at, ExpiringMessage};
+use crate::store::threads::is_unread;
+use crate::store::{
+    AttachmentsStore, ContentExt, ContentsStore, Sticker, StickerPack, StickerPackManifest, Store,
+    Thread,
+};
+use crate::{
+    model::groups::{
+        Group, GroupChangesError, GroupJoin, GroupJoinInfo, GroupUpdate, InviteLink, PendingMember,
+    },
+    model::ServiceIdType,
+    AvatarBytes, Error, Manager,
+};
 
 pub use crate::model::messages::Received;
 
+/// Longest time [`Manager::expire_messages`] waits before checking for newly started timers
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 type ServiceCipher<S> = cipher::ServiceCipher<S>;
 type MessageSender<S> = libsignal_service::prelude::MessageSender<S, ThreadRng>;
 
@@ -66,6 +94,8 @@ pub struct Registered {
     pub(crate) identified_websocket: Arc<Mutex<Option<SignalWebSocket>>>,
     pub(crate) unidentified_websocket: Arc<Mutex<Option<SignalWebSocket>>>,
     pub(crate) unidentified_sender_certificate: Option<SenderCertificate>,
+    pub(crate) delivery_receipts: bool,
+    pub(crate) attachment_downloads: AttachmentDownloads,
 
     pub(crate) data: RegistrationData,
 }
@@ -84,6 +114,8 @@ impl Registered {
             identified_websocket: Default::default(),
             unidentified_websocket: Default::default(),
             unidentified_sender_certificate: Default::default(),
+            delivery_receipts: false,
+            attachment_downloads: Default::default(),
             data,
         }
     }
@@ -162,6 +194,30 @@ impl<S: Store> Manager<S, Registered> {
         &self.state.data
     }
 
+    /// Enables or disables sending delivery receipts from [`Manager::receive_messages`].
+    ///
+    /// When enabled, received messages whose sender asked for a receipt are acknowledged in
+    /// batches, once the queue of pending messages is empty. Disabled by default.
+    pub fn set_delivery_receipts(&mut self, enabled: bool) {
+        self.state.delivery_receipts = enabled;
+    }
+
+    /// Sets the directory where [`Manager::get_attachment`] downloads and decrypts attachments.
+    ///
+    /// It must be set before downloading attachments, usually next to the data of the store: the
+    /// store keeps the location of decrypted attachments, and interrupted downloads are resumed
+    /// from the files left in this directory.
+    pub fn set_attachments_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.state.attachment_downloads.set_dir(dir);
+    }
+
+    /// Sets how many attachments [`Manager::get_attachment`] downloads at the same time.
+    ///
+    /// Other downloads wait for one of them to finish. Defaults to 4.
+    pub fn set_max_concurrent_downloads(&mut self, max: usize) {
+        self.state.attachment_downloads.set_max_concurrent(max);
+    }
+
     /// Returns a clone of a cached push service (with credentials).
     ///
     /// If no service is yet cached, it will create and cache one.
@@ -466,6 +522,7 @@ impl<S: Store> Manager<S, Registered> {
             .await
             .ok()
             .flatten()
+            .filter(|avatar| !avatar.is_empty())
         {
             return Ok(Some(avatar));
         }
@@ -499,6 +556,616 @@ impl<S: Store> Manager<S, Registered> {
         Ok(avatar)
     }
 
+    /// Decodes the group change carried by a message of a group thread, see
+    /// [`Received::GroupUpdate`]
+    ///
+    /// Returns `None` if the message is unknown or does not change the group.
+    pub async fn group_update(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<Option<GroupUpdate>, Error<S::Error>> {
+        let Some(content) = self.store.message(thread, timestamp).await? else {
+            return Ok(None);
+        };
+        let Some(group_context) = content.data_message().and_then(|m| m.group_v2.as_ref()) else {
+            return Ok(None);
+        };
+        let groups_manager = self.groups_manager()?;
+        Ok(decrypt_group_changes(&groups_manager, group_context)
+            .as_ref()
+            .map(GroupUpdate::from))
+    }
+
+    /// Creates a group with us as its administrator, and returns its master key
+    ///
+    /// Users whose profile key we know are added as members, the others are invited and join the
+    /// group once they accept the invitation. The avatar, if any, is uploaded along with the
+    /// group. Members and invited users are told about the group with a message in its thread.
+    pub async fn create_group(
+        &mut self,
+        title: &str,
+        members: &[Uuid],
+        avatar: Option<&[u8]>,
+    ) -> Result<GroupMasterKeyBytes, Error<S::Error>> {
+        let mut master_key_bytes = [0; 32];
+        thread_rng().fill_bytes(&mut master_key_bytes);
+        let secret_params = group_secret_params(master_key_bytes);
+
+        let own_aci = self.state.data.service_ids.aci();
+        let presentation = self
+            .profile_key_presentation(&secret_params, own_aci, self.state.data.profile_key())
+            .await?;
+        let mut new_members = vec![NewMember::Add {
+            presentation,
+            role: Role::Administrator,
+        }];
+        for &uuid in members
+            .iter()
+            .filter(|&&uuid| uuid != self.state.data.service_ids.aci)
+        {
+            new_members.push(self.new_member(&secret_params, uuid, Role::Default).await?);
+        }
+
+        let mut groups_manager = self.groups_manager()?;
+        let mut service = self.identified_push_service();
+        let avatar = match avatar {
+            Some(avatar) => {
+                let auth = groups_manager
+                    .get_authorization_for_today(&mut thread_rng(), secret_params)
+                    .await?;
+                let avatar = GroupEncryption::new(secret_params).avatar(avatar);
+                groups::upload_avatar::<S::Error>(&mut service, auth, avatar).await?
+            }
+            None => String::new(),
+        };
+        let auth = groups_manager
+            .get_authorization_for_today(&mut thread_rng(), secret_params)
+            .await?;
+        let group = groups::new_group(&secret_params, title, avatar, new_members);
+        groups::create(&mut service, auth, group).await?;
+
+        let group = upsert_group(&self.store, &mut groups_manager, &master_key_bytes, &0)
+            .await?
+            .ok_or(Error::UnknownGroup)?;
+        let group_context = GroupContextV2 {
+            master_key: Some(master_key_bytes.to_vec()),
+            revision: Some(0),
+            group_change: None,
+        };
+        self.send_group_update(master_key_bytes, &group, group_context, &[])
+            .await?;
+        Ok(master_key_bytes)
+    }
+
+    /// Adds users to a group, or invites them when we do not know their profile key
+    ///
+    /// Users who requested to join the group are approved instead, like with
+    /// [`Manager::approve_group_requests`].
+    pub async fn add_group_members(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuids: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let secret_params = group_secret_params(master_key_bytes);
+        let mut changes = Vec::new();
+        for &uuid in uuids {
+            if group.members.iter().any(|m| m.uuid == uuid)
+                || group.pending_members.iter().any(|m| m.uuid == uuid)
+            {
+                continue;
+            }
+            if group.requesting_members.iter().any(|m| m.uuid == uuid) {
+                changes.push(Change::PromoteRequestingMember {
+                    aci: uuid.into(),
+                    role: Role::Default,
+                });
+            } else {
+                let new_member = self.new_member(&secret_params, uuid, Role::Default).await?;
+                changes.push(Change::NewMember(new_member));
+            }
+        }
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Removes members from a group, who are told about it like the remaining members
+    pub async fn remove_group_members(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuids: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let removed: Vec<Uuid> = group
+            .members
+            .iter()
+            .map(|m| m.uuid)
+            .filter(|uuid| uuids.contains(uuid))
+            .collect();
+        let changes = removed
+            .iter()
+            .map(|&uuid| Change::DeleteMember(uuid.into()))
+            .collect();
+        self.change_group(master_key_bytes, group, changes, &removed)
+            .await
+    }
+
+    /// Changes the role of a member of a group, to make them an administrator or a regular member
+    pub async fn set_group_member_role(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuid: Uuid,
+        role: Role,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let changes = group
+            .members
+            .iter()
+            .filter(|m| m.uuid == uuid && m.role != role)
+            .map(|_| Change::ModifyMemberRole {
+                aci: uuid.into(),
+                role,
+            })
+            .collect();
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Approves requests to join a group, making the requesting users members
+    pub async fn approve_group_requests(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuids: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let changes = group
+            .requesting_members
+            .iter()
+            .filter(|m| uuids.contains(&m.uuid))
+            .map(|m| Change::PromoteRequestingMember {
+                aci: m.uuid.into(),
+                role: Role::Default,
+            })
+            .collect();
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Denies requests to join a group
+    pub async fn deny_group_requests(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuids: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let changes = group
+            .requesting_members
+            .iter()
+            .filter(|m| uuids.contains(&m.uuid))
+            .map(|m| Change::DeleteRequestingMember(m.uuid.into()))
+            .collect();
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Revokes invitations to join a group, the invited users are told about it
+    pub async fn revoke_group_invites(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        uuids: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let revoked: Vec<&PendingMember> = group
+            .pending_members
+            .iter()
+            .filter(|m| uuids.contains(&m.uuid))
+            .collect();
+        let changes = revoked
+            .iter()
+            .map(|m| Change::DeletePendingMember(m.service_id()))
+            .collect();
+        let removed: Vec<Uuid> = revoked
+            .iter()
+            .filter(|m| m.service_id_type == ServiceIdType::AccountIdentity)
+            .map(|m| m.uuid)
+            .collect();
+        self.change_group(master_key_bytes, group, changes, &removed)
+            .await
+    }
+
+    /// Changes the title of a group
+    pub async fn set_group_title(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        title: &str,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let changes = if group.title != title {
+            vec![Change::Title(title.to_owned())]
+        } else {
+            Vec::new()
+        };
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Changes the description of a group, or removes it
+    pub async fn set_group_description(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        description: Option<&str>,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let description = description.unwrap_or_default();
+        let changes = if group.description.as_deref().unwrap_or_default() != description {
+            vec![Change::Description(description.to_owned())]
+        } else {
+            Vec::new()
+        };
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Changes the avatar of a group, or removes it
+    ///
+    /// The avatar is encrypted and uploaded first, and kept in the store like the avatars
+    /// retrieved with [`Manager::retrieve_group_avatar`].
+    pub async fn set_group_avatar(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        avatar: Option<&[u8]>,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let key = match avatar {
+            Some(avatar) => {
+                let secret_params = group_secret_params(master_key_bytes);
+                let auth = self
+                    .groups_manager()?
+                    .get_authorization_for_today(&mut thread_rng(), secret_params)
+                    .await?;
+                let avatar = GroupEncryption::new(secret_params).avatar(avatar);
+                groups::upload_avatar::<S::Error>(&mut self.identified_push_service(), auth, avatar)
+                    .await?
+            }
+            None if group.avatar.is_empty() => return Ok(group),
+            None => String::new(),
+        };
+        let group = self
+            .change_group(master_key_bytes, group, vec![Change::Avatar(key)], &[])
+            .await?;
+        // an empty avatar is not returned, see `Manager::retrieve_group_avatar`
+        let avatar = avatar.map(<[u8]>::to_vec).unwrap_or_default();
+        self.store
+            .save_group_avatar(master_key_bytes, &avatar)
+            .await?;
+        Ok(group)
+    }
+
+    /// Changes the disappearing messages timer of a group, in seconds, 0 disabling it
+    pub async fn set_group_disappearing_messages_timer(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        seconds: u32,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let current = group
+            .disappearing_messages_timer
+            .as_ref()
+            .map(|timer| timer.duration)
+            .unwrap_or_default();
+        let changes = if current != seconds {
+            vec![Change::Timer(seconds)]
+        } else {
+            Vec::new()
+        };
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Changes who may edit the attributes of a group, who may add members, and whether users
+    /// with its invite link join directly or need the approval of an administrator
+    pub async fn set_group_access_control(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        access_control: AccessControl,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let changes =
+            groups::access_control_changes(group.access_control.as_ref(), &access_control);
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Previews the group of an invite link, before joining it
+    pub async fn group_join_info(
+        &self,
+        invite_link: &InviteLink,
+    ) -> Result<GroupJoinInfo, Error<S::Error>> {
+        let secret_params = group_secret_params(invite_link.master_key);
+        let auth = self
+            .groups_manager()?
+            .get_authorization_for_today(&mut thread_rng(), secret_params)
+            .await?;
+        let join_info = groups::fetch_join_info::<S::Error>(
+            &mut self.identified_push_service(),
+            auth,
+            &invite_link.password,
+        )
+        .await?;
+        Ok(groups::join_info(
+            &GroupEncryption::new(secret_params),
+            join_info,
+        ))
+    }
+
+    /// Joins the group of an invite link, or requests to join it when an administrator must
+    /// approve new members
+    ///
+    /// Members are told when we join the group.
+    pub async fn join_group(
+        &mut self,
+        invite_link: &InviteLink,
+    ) -> Result<GroupJoin, Error<S::Error>> {
+        let join_info = self.group_join_info(invite_link).await?;
+        if join_info.pending_admin_approval {
+            return Ok(GroupJoin::Requested);
+        }
+
+        let master_key_bytes = invite_link.master_key;
+        let presentation = self
+            .profile_key_presentation(
+                &group_secret_params(master_key_bytes),
+                self.state.data.service_ids.aci(),
+                self.state.data.profile_key(),
+            )
+            .await?;
+        let change = groups::join_change(join_info.add_from_invite_link, presentation)
+            .ok_or(Error::InviteLinkDisabled)?;
+        let requested = matches!(change, Change::RequestToJoin { .. });
+        let group_context = self
+            .request_group_change(
+                master_key_bytes,
+                join_info.revision + 1,
+                vec![change],
+                Some(&invite_link.password),
+            )
+            .await?;
+        if requested {
+            return Ok(GroupJoin::Requested);
+        }
+        let group = self
+            .apply_group_change(master_key_bytes, group_context, &[])
+            .await?;
+        Ok(GroupJoin::Joined(group))
+    }
+
+    /// Accepts an invitation to join a group, making us a member
+    ///
+    /// Only invitations of our ACI can be accepted, not the ones of our phone number identity.
+    pub async fn accept_group_invitation(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let own_aci = self.state.data.service_ids.aci;
+        if !group.pending_members.iter().any(|m| m.uuid == own_aci) {
+            return Err(Error::NotInvitedToGroup);
+        }
+        let presentation = self
+            .profile_key_presentation(
+                &group_secret_params(master_key_bytes),
+                own_aci.into(),
+                self.state.data.profile_key(),
+            )
+            .await?;
+        let changes = vec![Change::PromotePendingMember { presentation }];
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Declines an invitation to join a group
+    pub async fn decline_group_invitation(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let own_aci = self.state.data.service_ids.aci;
+        let Some(invitation) = group.pending_members.iter().find(|m| m.uuid == own_aci) else {
+            return Err(Error::NotInvitedToGroup);
+        };
+        let changes = vec![Change::DeletePendingMember(invitation.service_id())];
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// Leaves a group, whose members are told about it
+    ///
+    /// The group is kept in the store.
+    pub async fn leave_group(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+    ) -> Result<Group, Error<S::Error>> {
+        let group = self.latest_group(master_key_bytes).await?;
+        let own_aci = self.state.data.service_ids.aci;
+        if !group.members.iter().any(|m| m.uuid == own_aci) {
+            return Err(Error::NotAGroupMember);
+        }
+        let changes = vec![Change::DeleteMember(own_aci.into())];
+        self.change_group(master_key_bytes, group, changes, &[])
+            .await
+    }
+
+    /// The group as the server has it, to change it from its latest revision
+    async fn latest_group(
+        &self,
+        master_key_bytes: GroupMasterKeyBytes,
+    ) -> Result<Group, Error<S::Error>> {
+        let mut groups_manager = self.groups_manager()?;
+        // no stored group is that recent, the group is always fetched again
+        upsert_group(
+            &self.store,
+            &mut groups_manager,
+            &master_key_bytes,
+            &u32::MAX,
+        )
+        .await?
+        .ok_or(Error::UnknownGroup)
+    }
+
+    /// Adds a user to a group, or invites them when we cannot present their profile key credential
+    async fn new_member(
+        &self,
+        secret_params: &GroupSecretParams,
+        uuid: Uuid,
+        role: Role,
+    ) -> Result<NewMember, Error<S::Error>> {
+        let aci = Aci::from(uuid);
+        if let Some(profile_key) = self.store.profile_key(&uuid).await? {
+            match self
+                .profile_key_presentation(secret_params, aci, profile_key)
+                .await
+            {
+                Ok(presentation) => return Ok(NewMember::Add { presentation, role }),
+                Err(error) => debug!(%error, %uuid, "no profile key credential, inviting instead"),
+            }
+        }
+        Ok(NewMember::Invite {
+            service_id: aci.into(),
+            role,
+        })
+    }
+
+    /// Presents the credential of the profile key of a user to a group
+    async fn profile_key_presentation(
+        &self,
+        secret_params: &GroupSecretParams,
+        aci: Aci,
+        profile_key: ProfileKey,
+    ) -> Result<Vec<u8>, Error<S::Error>> {
+        let server_public_params = self
+            .state
+            .service_configuration()
+            .zkgroup_server_public_params;
+        let credential = groups::profile_key_credential::<S::Error>(
+            &mut self.identified_push_service(),
+            &server_public_params,
+            aci,
+            profile_key,
+        )
+        .await?;
+        Ok(groups::presentation(
+            &server_public_params,
+            secret_params,
+            credential,
+        ))
+    }
+
+    /// Requests changes to a group, applies them to the stored group and tells its members
+    ///
+    /// The changes are made to the next revision of the group, which is left untouched if there
+    /// are no changes. `removed` are the users who are no longer members, told about it too.
+    async fn change_group(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        group: Group,
+        changes: Vec<Change>,
+        removed: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        if changes.is_empty() {
+            return Ok(group);
+        }
+        let group_context = self
+            .request_group_change(master_key_bytes, group.revision + 1, changes, None)
+            .await?;
+        self.apply_group_change(master_key_bytes, group_context, removed)
+            .await
+    }
+
+    /// Requests changes to a group, and returns the change signed by the server in the context
+    /// of the group
+    async fn request_group_change(
+        &self,
+        master_key_bytes: GroupMasterKeyBytes,
+        revision: u32,
+        changes: Vec<Change>,
+        invite_link_password: Option<&[u8]>,
+    ) -> Result<GroupContextV2, Error<S::Error>> {
+        let secret_params = group_secret_params(master_key_bytes);
+        let actions = groups::actions(&GroupEncryption::new(secret_params), revision, changes);
+        let auth = self
+            .groups_manager()?
+            .get_authorization_for_today(&mut thread_rng(), secret_params)
+            .await?;
+        let group_change = groups::modify::<S::Error>(
+            &mut self.identified_push_service(),
+            auth,
+            actions,
+            invite_link_password,
+        )
+        .await?;
+
+        Ok(GroupContextV2 {
+            master_key: Some(master_key_bytes.to_vec()),
+            revision: Some(revision),
+            group_change: Some(group_change.encode_to_vec()),
+        })
+    }
+
+    /// Applies a change we made to the stored group, and tells the members of the group
+    async fn apply_group_change(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        group_context: GroupContextV2,
+        removed: &[Uuid],
+    ) -> Result<Group, Error<S::Error>> {
+        let mut groups_manager = self.groups_manager()?;
+        let changes = decrypt_group_changes(&groups_manager, &group_context);
+        let group = update_group(
+            &self.store,
+            &mut self.identified_push_service(),
+            &mut groups_manager,
+            &group_context,
+            changes.as_ref(),
+        )
+        .await?
+        .ok_or(Error::UnknownGroup)?;
+        self.send_group_update(master_key_bytes, &group, group_context, removed)
+            .await?;
+        Ok(group)
+    }
+
+    /// Sends the context of a change of a group in its thread
+    ///
+    /// Like official clients, the change is sent to the members, to the invited users and to the
+    /// `removed` users, who could not tell what happened otherwise.
+    async fn send_group_update(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        group: &Group,
+        group_context: GroupContextV2,
+        removed: &[Uuid],
+    ) -> Result<(), Error<S::Error>> {
+        let mut others = removed.to_vec();
+        others.extend(
+            group
+                .pending_members
+                .iter()
+                .filter(|m| m.service_id_type == ServiceIdType::AccountIdentity)
+                .map(|m| m.uuid),
+        );
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+        let message = DataMessage {
+            group_v2: Some(group_context),
+            ..Default::default()
+        };
+        self.send_to_group(master_key_bytes, message.into(), timestamp, &others)
+            .await
+    }
+
     pub async fn retrieve_profile_avatar_by_uuid(
         &mut self,
         uuid: Uuid,
@@ -588,9 +1255,21 @@ impl<S: Store> Manager<S, Registered> {
             service_cipher_aci: ServiceCipher<AciStore>,
             service_cipher_pni: ServiceCipher<PniStore>,
             groups_manager: GroupsManager<InMemoryCredentialsCache>,
+            own_aci: Uuid,
+            receipts_sender: Option<MessageSender<AciStore>>,
+            pending_receipts: HashMap<ServiceId, PendingReceipts>,
+            receipts_retry: Interval,
+            queue_empty: bool,
         }
 
         let push_service = self.identified_push_service();
+        let receipts_sender = if self.state.delivery_receipts {
+            Some(self.new_message_sender().await?)
+        } else {
+            None
+        };
+        let mut receipts_retry = tokio::time::interval(DELIVERY_RECEIPT_RETRY_INTERVAL);
+        receipts_retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         let init = StreamState {
             store: self.store.clone(),
@@ -601,13 +1280,30 @@ impl<S: Store> Manager<S, Registered> {
             service_cipher_aci: self.new_service_cipher_aci(),
             service_cipher_pni: self.new_service_cipher_pni(),
             groups_manager: self.groups_manager()?,
+            own_aci: self.state.data.service_ids.aci,
+            receipts_sender,
+            pending_receipts: HashMap::new(),
+            receipts_retry,
+            queue_empty: false,
         };
 
         debug!("starting to consume incoming message stream");
 
         Ok(futures::stream::unfold(init, |mut state| async move {
             loop {
-                match state.encrypted_messages.next().await {
+                // failed delivery receipts are retried even when no message comes in
+                let retry_receipts = state.queue_empty && !state.pending_receipts.is_empty();
+                let next = tokio::select! {
+                    next = state.encrypted_messages.next() => Some(next),
+                    _ = state.receipts_retry.tick(), if retry_receipts => None,
+                };
+                let Some(next) = next else {
+                    if let Some(sender) = &mut state.receipts_sender {
+                        send_delivery_receipts::<S>(sender, &mut state.pending_receipts).await;
+                    }
+                    continue;
+                };
+                match next {
                     Some(Ok(Incoming::Envelope(envelope))) => {
                         let envelope = {
                             // the permit is released at the end of the block (impl Drop)
@@ -714,14 +1410,78 @@ impl<S: Store> Manager<S, Registered> {
                                     }
                                 }
 
+                                // typing indicators
+                                if let ContentBody::TypingMessage(typing) = &content.body {
+                                    let sender = content.metadata.sender.raw_uuid();
+                                    let thread = match &typing.group_id {
+                                        None => Ok(Some(Thread::Contact(sender))),
+                                        Some(group_id) => {
+                                            find_group_thread(&state.store, group_id).await
+                                        }
+                                    };
+                                    match thread {
+                                        Ok(Some(thread)) => {
+                                            return Some((
+                                                Received::Typing {
+                                                    thread,
+                                                    sender,
+                                                    action: typing.action(),
+                                                },
+                                                state,
+                                            ));
+                                        }
+                                        Ok(None) => {
+                                            warn!("typing indicator in an unknown group");
+                                        }
+                                        Err(error) => {
+                                            error!(%error, "failed to find thread of typing indicator");
+                                        }
+                                    }
+                                }
+
+                                // delivery, read and viewed receipts of our sent messages
+                                if let ContentBody::ReceiptMessage(receipt) = &content.body {
+                                    let recipient = content.metadata.sender.raw_uuid();
+                                    match apply_receipt(&mut state.store, recipient, receipt).await
+                                    {
+                                        Ok((delivery_state, messages)) => {
+                                            return Some((
+                                                Received::Receipt {
+                                                    recipient,
+                                                    state: delivery_state,
+                                                    messages,
+                                                },
+                                                state,
+                                            ));
+                                        }
+                                        Err(error) => {
+                                            error!(%error, "failed to apply receipt");
+                                        }
+                                    }
+                                }
+
+                                // messages read on another device
+                                if let ContentBody::SynchronizeMessage(SyncMessage {
+                                    read, ..
+                                }) = &content.body
+                                {
+                                    if !read.is_empty() {
+                                        if let Err(error) = apply_read_syncs(
+                                            &mut state.store,
+                                            read,
+                                            content.timestamp(),
+                                        )
+                                        .await
+                                        {
+                                            error!(%error, "failed to mark messages as read");
+                                        }
+                                    }
+                                }
+
                                 // group update
+                                let mut group_update = None;
                                 if let ContentBody::DataMessage(DataMessage {
-                                    group_v2:
-                                        Some(GroupContextV2 {
-                                            master_key: Some(master_key_bytes),
-                                            revision: Some(revision),
-                                            ..
-                                        }),
+                                    group_v2: Some(group_context),
                                     ..
                                 })
                                 | ContentBody::SynchronizeMessage(SyncMessage {
@@ -729,12 +1489,7 @@ impl<S: Store> Manager<S, Registered> {
                                         Some(sync_message::Sent {
                                             message:
                                                 Some(DataMessage {
-                                                    group_v2:
-                                                        Some(GroupContextV2 {
-                                                            master_key: Some(master_key_bytes),
-                                                            revision: Some(revision),
-                                                            ..
-                                                        }),
+                                                    group_v2: Some(group_context),
                                                     ..
                                                 }),
                                             ..
@@ -742,19 +1497,29 @@ impl<S: Store> Manager<S, Registered> {
                                     ..
                                 }) = &content.body
                                 {
-                                    // there's two things to implement: the group metadata (fetched from HTTP API)
-                                    // and the group changes, which are part of the protobuf messages
-                                    // this means we kinda need our own internal representation of groups inside of presage?
-                                    if let Ok(Some(group)) = upsert_group(
+                                    let changes =
+                                        decrypt_group_changes(&state.groups_manager, group_context);
+                                    if let Ok(Some(group)) = update_group(
                                         &state.store,
+                                        &mut state.push_service,
                                         &mut state.groups_manager,
-                                        master_key_bytes,
-                                        revision,
+                                        group_context,
+                                        changes.as_ref(),
                                     )
                                     .await
                                     {
-                                        trace!(?group, "upserted group");
+                                        trace!(?group, "updated group");
                                     }
+                                    group_update = changes
+                                        .as_ref()
+                                        .map(GroupUpdate::from)
+                                        .zip(group_context.master_key.as_deref())
+                                        .and_then(|(update, master_key)| {
+                                            Some((
+                                                Thread::Group(master_key.try_into().ok()?),
+                                                update,
+                                            ))
+                                        });
                                 }
 
                                 if let Err(error) = save_message(
@@ -768,7 +1533,49 @@ impl<S: Store> Manager<S, Registered> {
                                     error!(%error, "error saving message to store");
                                 }
 
-                                return Some((Received::Content(Box::new(content)), state));
+                                // disappearing messages we sent from another device
+                                if let Err(error) =
+                                    start_synced_expiry(&mut state.store, &content).await
+                                {
+                                    error!(%error, "failed to start disappearing timer");
+                                }
+
+                                if let Some(sender) = &mut state.receipts_sender {
+                                    if content.metadata.needs_receipt
+                                        && content.metadata.sender.raw_uuid() != state.own_aci
+                                        && matches!(content.body, ContentBody::DataMessage(_))
+                                    {
+                                        state
+                                            .pending_receipts
+                                            .entry(content.metadata.sender)
+                                            .or_default()
+                                            .timestamps
+                                            .push(content.timestamp());
+                                    }
+                                    // while synchronizing, receipts are batched until the queue is empty
+                                    if state.queue_empty {
+                                        send_delivery_receipts::<S>(
+                                            sender,
+                                            &mut state.pending_receipts,
+                                        )
+                                        .await;
+                                    }
+                                }
+
+                                let received = if let Some((thread, update)) = group_update {
+                                    Received::GroupUpdate {
+                                        thread,
+                                        timestamp: content.timestamp(),
+                                        update,
+                                    }
+                                } else if let Some(message) =
+                                    Message::from_content(&content, state.own_aci)
+                                {
+                                    Received::Message(Box::new(message))
+                                } else {
+                                    Received::Content(Box::new(content))
+                                };
+                                return Some((received, state));
                             }
                             Ok(None) => {
                                 debug!("empty envelope, message will be skipped!")
@@ -780,6 +1587,10 @@ impl<S: Store> Manager<S, Registered> {
                     }
                     Some(Ok(Incoming::QueueEmpty)) => {
                         debug!("got empty queue");
+                        state.queue_empty = true;
+                        if let Some(sender) = &mut state.receipts_sender {
+                            send_delivery_receipts::<S>(sender, &mut state.pending_receipts).await;
+                        }
                         return Some((Received::QueueEmpty, state));
                     }
                     Some(Err(error)) => {
@@ -791,11 +1602,71 @@ impl<S: Store> Manager<S, Registered> {
         }))
     }
 
-    /// Sends a messages to the provided [ServiceId].
-    /// The timestamp should be set to now and is used by Signal mobile apps
-    /// to order messages later, and apply reactions.
+    /// Deletes disappearing messages from the store once they expired.
     ///
-    /// This method will automatically update the [DataMessage::expire_timer] if it is set to
+    /// Returns a [futures::Stream] of the deleted messages, which never ends: it waits for the
+    /// next message to expire, and regularly checks for timers started in the meantime (e.g. when
+    /// messages are sent or read).
+    pub fn expire_messages(&self) -> impl Stream<Item = ExpiringMessage> {
+        let init = (self.store.clone(), VecDeque::new());
+        futures::stream::unfold(init, |(mut store, mut expired)| async move {
+            loop {
+                if let Some(message) = expired.pop_front() {
+                    return Some((message, (store, expired)));
+                }
+
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_millis() as u64;
+                let mut next_check = now + EXPIRY_CHECK_INTERVAL.as_millis() as u64;
+                match store.expiring_messages().await {
+                    Ok(messages) => {
+                        for message in messages {
+                            match message {
+                                Ok(message) if message.expires_at <= now => {
+                                    match store
+                                        .delete_message(&message.thread, message.timestamp)
+                                        .await
+                                    {
+                                        Ok(true) => {
+                                            trace!(
+                                                thread = %message.thread,
+                                                timestamp = message.timestamp,
+                                                "deleted expired message"
+                                            );
+                                            expired.push_back(message);
+                                        }
+                                        // already deleted in the meantime
+                                        Ok(false) => {}
+                                        Err(error) => {
+                                            error!(%error, "failed to delete expired message")
+                                        }
+                                    }
+                                }
+                                Ok(message) => {
+                                    next_check = next_check.min(message.expires_at);
+                                    break;
+                                }
+                                Err(error) => error!(%error, "failed to load expiring message"),
+                            }
+                        }
+                    }
+                    Err(error) => error!(%error, "failed to load expiring messages"),
+                }
+
+                if expired.is_empty() {
+                    tokio::time::sleep(Duration::from_millis(next_check - now)).await;
+                }
+            }
+        })
+    }
+
+    /// Sends a messages to the provided [ServiceId].
+    /// The timestamp should be set to now and is used by Signal mobile apps
+    /// to order messages later, and apply reactions.
+    ///
+    /// This method will automatically update the [DataMessage::expire_timer] if it is set to
     /// [None] such that the chat will keep the current expire timer. If the expire timer is set,
     /// it will be used as is, and the expire timer version will be incremented.
     pub async fn send_message(
@@ -861,9 +1732,42 @@ impl<S: Store> Manager<S, Registered> {
             },
             body: content_body,
         };
+        // reactions and deletions are applied to the message they target instead of being saved
+        let is_saved_message = matches!(
+            content.body,
+            ContentBody::DataMessage(DataMessage {
+                reaction: None,
+                delete: None,
+                ..
+            })
+        );
+        let expires_at = expires_at(&content, timestamp);
 
         let mut push_service = self.identified_push_service();
-        save_message(&mut self.store, &mut push_service, content, Some(thread)).await?;
+        save_message(
+            &mut self.store,
+            &mut push_service,
+            content,
+            Some(thread.clone()),
+        )
+        .await?;
+
+        if let Some(expires_at) = expires_at {
+            self.store
+                .set_message_expiry(&thread, timestamp, expires_at)
+                .await?;
+        }
+
+        if is_saved_message {
+            self.store
+                .update_delivery_state(
+                    &thread,
+                    timestamp,
+                    recipient.raw_uuid(),
+                    DeliveryState::Sent,
+                )
+                .await?;
+        }
 
         Ok(())
     }
@@ -877,13 +1781,63 @@ impl<S: Store> Manager<S, Registered> {
             return Ok(Vec::new());
         }
         let sender = self.new_message_sender().await?;
-        let upload = future::join_all(attachments.into_iter().map(move |(spec, contents)| {
+        let upload = future::join_all(attachments.into_iter().map(move |(mut spec, contents)| {
             let mut sender = sender.clone();
+            attachments::fill_metadata(&mut spec, &contents);
             async move { sender.upload_attachment(spec, contents).await }
         }));
         Ok(upload.await)
     }
 
+    /// Uploads an attachment read from a file or any reader, see [`AttachmentSource`].
+    ///
+    /// The attachment is encrypted and sent as it is read, so it is never held in memory as a
+    /// whole, except images whose metadata is filled with the `image-metadata` feature.
+    /// `progress` is called with the bytes of the encrypted attachment sent so far, and once it
+    /// is uploaded. The upload can be stopped with `cancel`, in which case
+    /// [`Error::AttachmentUploadCancelled`] is returned.
+    pub async fn upload_attachment_from(
+        &self,
+        mut source: AttachmentSource,
+        progress: impl Fn(UploadProgress),
+        cancel: &CancelUpload,
+    ) -> Result<AttachmentPointer, Error<S::Error>> {
+        source.fill_metadata().await?;
+        if cancel.is_cancelled() {
+            return Err(Error::AttachmentUploadCancelled);
+        }
+
+        let mut service = self.identified_push_service();
+        let form = attachments::upload_form::<S::Error>(&mut service).await?;
+        let cdn_id = form.attachment_id;
+        let EncryptedAttachment {
+            spec,
+            key,
+            length,
+            chunks,
+            mut sent,
+            digest,
+        } = source.encrypt(cancel);
+
+        let mut upload = pin!(attachments::upload(&mut service, form, chunks, length));
+        let uploaded = loop {
+            tokio::select! {
+                uploaded = &mut upload => break uploaded,
+                Some(sent) = sent.next() => progress(UploadProgress::Uploading { sent, length }),
+            }
+        };
+        // reading the attachment may have stopped the upload
+        let digest = match (digest.await, uploaded) {
+            (Ok(Err(interrupted)), _) => return Err(interrupted.into()),
+            (_, Err(error)) => return Err(error.into()),
+            (Ok(Ok(digest)), Ok(())) => digest,
+            (Err(_), Ok(())) => unreachable!("attachments are uploaded once read to the end"),
+        };
+
+        progress(UploadProgress::Done);
+        Ok(attachments::pointer(spec, key, digest, cdn_id))
+    }
+
     /// Sends one message in a group (v2). The `master_key_bytes` is required to have 32 elements.
     ///
     /// This method will automatically update the [DataMessage::expire_timer] if it is set to
@@ -894,17 +1848,98 @@ impl<S: Store> Manager<S, Registered> {
         message: impl Into<ContentBody>,
         timestamp: u64,
     ) -> Result<(), Error<S::Error>> {
-        let mut content_body = message.into();
         let master_key_bytes = master_key_bytes
             .try_into()
             .expect("Master key bytes to be of size 32.");
+        self.send_to_group(master_key_bytes, message.into(), timestamp, &[])
+            .await
+    }
+
+    /// Sends a message to the members of a group and to `others`, and saves it in the group thread
+    async fn send_to_group(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        mut content_body: ContentBody,
+        timestamp: u64,
+        others: &[Uuid],
+    ) -> Result<(), Error<S::Error>> {
         let thread = Thread::Group(master_key_bytes);
 
         self.restore_thread_timer(&thread, &mut content_body).await;
         ensure_data_message_timestamp(&mut content_body, timestamp);
 
         let mut sender = self.new_message_sender().await?;
+        let recipients = self.group_recipients(master_key_bytes, others).await?;
+
+        let online_only = false;
+        let results = sender
+            .send_message_to_group(recipients, content_body.clone(), timestamp, online_only)
+            .await;
+
+        let sent_to: Vec<Uuid> = results
+            .iter()
+            .filter_map(|res| res.as_ref().ok())
+            .map(|sent| sent.recipient.raw_uuid())
+            .collect();
+
+        first_group_send_error(results)?;
+
+        let content = Content {
+            metadata: Metadata {
+                sender: self.state.data.service_ids.aci().into(),
+                destination: self.state.data.service_ids.aci().into(),
+                sender_device: self.state.device_id(),
+                server_guid: None,
+                timestamp,
+                needs_receipt: false, // TODO: this is just wrong
+                unidentified_sender: false,
+            },
+            body: content_body,
+        };
+        // reactions and deletions are applied to the message they target instead of being saved
+        let is_saved_message = matches!(
+            content.body,
+            ContentBody::DataMessage(DataMessage {
+                reaction: None,
+                delete: None,
+                ..
+            })
+        );
+        let expires_at = expires_at(&content, timestamp);
 
+        let mut push_service = self.identified_push_service();
+        save_message(
+            &mut self.store,
+            &mut push_service,
+            content,
+            Some(thread.clone()),
+        )
+        .await?;
+
+        if let Some(expires_at) = expires_at {
+            self.store
+                .set_message_expiry(&thread, timestamp, expires_at)
+                .await?;
+        }
+
+        if is_saved_message {
+            for recipient in sent_to {
+                self.store
+                    .update_delivery_state(&thread, timestamp, recipient, DeliveryState::Sent)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Members of a group and `others` we send messages to, with their unidentified access when
+    /// possible
+    async fn group_recipients(
+        &mut self,
+        master_key_bytes: GroupMasterKeyBytes,
+        others: &[Uuid],
+    ) -> Result<Vec<(ServiceId, Option<UnidentifiedAccess>, bool)>, Error<S::Error>> {
         let mut groups_manager = self.groups_manager()?;
         let Some(group) =
             upsert_group(&self.store, &mut groups_manager, &master_key_bytes, &0).await?
@@ -912,16 +1947,22 @@ impl<S: Store> Manager<S, Registered> {
             return Err(Error::UnknownGroup);
         };
 
+        let mut uuids: Vec<Uuid> = group.members.iter().map(|m| m.uuid).collect();
+        for uuid in others {
+            if !uuids.contains(uuid) {
+                uuids.push(*uuid);
+            }
+        }
+
         let sender_certificate = self.sender_certificate().await?;
         let mut recipients = Vec::new();
-        for member in group
-            .members
+        for uuid in uuids
             .into_iter()
-            .filter(|m| m.uuid != self.state.data.service_ids.aci)
+            .filter(|&uuid| uuid != self.state.data.service_ids.aci)
         {
             let unidentified_access =
                 self.store
-                    .profile_key(&member.uuid)
+                    .profile_key(&uuid)
                     .await?
                     .map(|profile_key| UnidentifiedAccess {
                         key: profile_key.derive_access_key().to_vec(),
@@ -929,51 +1970,273 @@ impl<S: Store> Manager<S, Registered> {
                     });
             let include_pni_signature = false;
             recipients.push((
-                Aci::from(member.uuid).into(),
+                Aci::from(uuid).into(),
                 unidentified_access,
                 include_pni_signature,
             ));
         }
 
-        let online_only = false;
-        let results = sender
-            .send_message_to_group(recipients, content_body.clone(), timestamp, online_only)
-            .await;
+        Ok(recipients)
+    }
 
-        // TODO: Handle the NotFound error in the future by removing all sessions to this UUID and marking it as unregistered, not sending any messages to this contact anymore.
-        results
-            .into_iter()
-            .find(|res| match res {
-                Ok(_) => false,
-                // Ignore any NotFound errors, those mean that e.g. some contact in a group deleted his account.
-                Err(MessageSenderError::NotFound { service_id }) => {
-                    debug!(service_id = %service_id.service_id_string(), "recipient not found, skipping sent message result");
-                    false
-                }
-                // return first error if any
-                Err(_) => true,
+    /// Sends a typing indicator to a contact or to all members of a group.
+    ///
+    /// Typing indicators are only delivered to devices which are currently online, and are not
+    /// saved in the store.
+    pub async fn send_typing(
+        &mut self,
+        thread: &Thread,
+        action: typing_message::Action,
+    ) -> Result<(), Error<S::Error>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+
+        let mut typing = TypingMessage {
+            timestamp: Some(timestamp),
+            action: Some(action.into()),
+            group_id: None,
+        };
+
+        let mut sender = self.new_message_sender().await?;
+        let online_only = true;
+        match thread {
+            Thread::Contact(uuid) => {
+                let recipient: ServiceId = Aci::from(*uuid).into();
+                let sender_certificate = self.sender_certificate().await?;
+                let unidentified_access =
+                    self.store
+                        .profile_key(uuid)
+                        .await?
+                        .map(|profile_key| UnidentifiedAccess {
+                            key: profile_key.derive_access_key().to_vec(),
+                            certificate: sender_certificate.clone(),
+                        });
+                let include_pni_signature = false;
+                sender
+                    .send_message(
+                        &recipient,
+                        unidentified_access,
+                        typing,
+                        timestamp,
+                        online_only,
+                        include_pni_signature,
+                    )
+                    .await?;
+            }
+            Thread::Group(master_key_bytes) => {
+                typing.group_id = Some(group_identifier(*master_key_bytes).to_vec());
+                let recipients = self.group_recipients(*master_key_bytes, &[]).await?;
+                let results = sender
+                    .send_message_to_group(recipients, typing, timestamp, online_only)
+                    .await;
+                first_group_send_error(results)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Edits one of our messages in a contact or group thread.
+    ///
+    /// `message` holds the new contents of the message sent at `target_sent_timestamp`. The
+    /// edit replaces the message in the store, which keeps its previous revisions.
+    pub async fn edit_message(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+        message: DataMessage,
+    ) -> Result<(), Error<S::Error>> {
+        self.check_own_message(thread, target_sent_timestamp)
+            .await?;
+        let edit = EditMessage {
+            target_sent_timestamp: Some(target_sent_timestamp),
+            data_message: Some(message),
+        };
+        self.send_message_to_thread(thread, edit.into()).await
+    }
+
+    /// Deletes one of our messages in a contact or group thread, for all its recipients.
+    ///
+    /// The message is replaced by an empty message in the store.
+    pub async fn delete_message_for_everyone(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+    ) -> Result<(), Error<S::Error>> {
+        self.check_own_message(thread, target_sent_timestamp)
+            .await?;
+        let message = DataMessage {
+            delete: Some(Delete {
+                target_sent_timestamp: Some(target_sent_timestamp),
+            }),
+            ..Default::default()
+        };
+        self.send_message_to_thread(thread, message.into()).await
+    }
+
+    /// Replies to a message in a contact or group thread.
+    ///
+    /// The message sent at `target_sent_timestamp` is quoted in `message`, with its author, text,
+    /// mentions and the content types and file names of its attachments (without thumbnails).
+    pub async fn reply_to(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+        message: DataMessage,
+    ) -> Result<(), Error<S::Error>> {
+        let target = self
+            .store
+            .message(thread, target_sent_timestamp)
+            .await?
+            .ok_or(Error::UnknownMessage)?;
+        let target_message = target.data_message().ok_or(Error::UnknownMessage)?;
+        let attachments = target_message
+            .attachments
+            .iter()
+            .map(|attachment| quote::QuotedAttachment {
+                content_type: attachment.content_type.clone(),
+                file_name: attachment.file_name.clone(),
+                // a thumbnail would have to be a scaled down copy uploaded on its own: the full
+                // size attachment is not one, so quotes go without
+                thumbnail: None,
             })
-            .transpose()?;
+            .collect();
+        let quote = Quote {
+            id: Some(target_sent_timestamp),
+            author_aci: Some(target.metadata.sender.raw_uuid().to_string()),
+            text: target_message.body.clone(),
+            attachments,
+            body_ranges: target_message.body_ranges.clone(),
+            r#type: Some(quote::Type::Normal.into()),
+        };
+        let message = DataMessage {
+            quote: Some(quote),
+            ..message
+        };
+        self.send_message_to_thread(thread, message.into()).await
+    }
 
-        let content = Content {
-            metadata: Metadata {
-                sender: self.state.data.service_ids.aci().into(),
-                destination: self.state.data.service_ids.aci().into(),
-                sender_device: self.state.device_id(),
-                server_guid: None,
-                timestamp,
-                needs_receipt: false, // TODO: this is just wrong
-                unidentified_sender: false,
-            },
-            body: content_body,
+    /// Reacts with an emoji to a message in a contact or group thread.
+    ///
+    /// This replaces our previous reaction to the message, if any.
+    pub async fn react(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+        emoji: impl Into<String>,
+    ) -> Result<(), Error<S::Error>> {
+        self.send_reaction(thread, target_sent_timestamp, emoji.into(), false)
+            .await
+    }
+
+    /// Removes our reaction to a message in a contact or group thread.
+    ///
+    /// This does nothing if we did not react to the message.
+    pub async fn unreact(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+    ) -> Result<(), Error<S::Error>> {
+        let own_aci = self.state.data.service_ids.aci;
+        let Some(reaction) = self
+            .store
+            .reactions(thread, target_sent_timestamp)
+            .await?
+            .remove(&own_aci)
+            .filter(|reaction| !reaction.removed)
+        else {
+            debug!(%thread, target_sent_timestamp, "no reaction to remove from message");
+            return Ok(());
         };
+        self.send_reaction(thread, target_sent_timestamp, reaction.emoji, true)
+            .await
+    }
 
-        let mut push_service = self.identified_push_service();
-        save_message(&mut self.store, &mut push_service, content, Some(thread)).await?;
+    async fn send_reaction(
+        &mut self,
+        thread: &Thread,
+        target_sent_timestamp: u64,
+        emoji: String,
+        remove: bool,
+    ) -> Result<(), Error<S::Error>> {
+        let target = self
+            .store
+            .message(thread, target_sent_timestamp)
+            .await?
+            .ok_or(Error::UnknownMessage)?;
+        let message = DataMessage {
+            reaction: Some(Reaction {
+                emoji: Some(emoji),
+                remove: Some(remove),
+                target_author_aci: Some(target.metadata.sender.raw_uuid().to_string()),
+                target_sent_timestamp: Some(target_sent_timestamp),
+            }),
+            ..Default::default()
+        };
+        self.send_message_to_thread(thread, message.into()).await
+    }
 
+    /// Checks that a message of a thread exists and was sent by us
+    async fn check_own_message(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<(), Error<S::Error>> {
+        let message = self
+            .store
+            .message(thread, timestamp)
+            .await?
+            .ok_or(Error::UnknownMessage)?;
+        if message.metadata.sender.raw_uuid() != self.state.data.service_ids.aci {
+            return Err(Error::NotOwnMessage);
+        }
         Ok(())
     }
 
+    /// Sends a message now to a contact, or to all members of a group with the group context
+    /// added to its data message.
+    async fn send_message_to_thread(
+        &mut self,
+        thread: &Thread,
+        mut content_body: ContentBody,
+    ) -> Result<(), Error<S::Error>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+
+        match thread {
+            Thread::Contact(uuid) => {
+                self.send_message(Aci::from(*uuid), content_body, timestamp)
+                    .await
+            }
+            Thread::Group(master_key_bytes) => {
+                let revision = self
+                    .store
+                    .group(*master_key_bytes)
+                    .await?
+                    .map(|group| group.revision)
+                    .unwrap_or_default();
+                if let ContentBody::DataMessage(data_message)
+                | ContentBody::EditMessage(EditMessage {
+                    data_message: Some(data_message),
+                    ..
+                }) = &mut content_body
+                {
+                    data_message.group_v2.get_or_insert(GroupContextV2 {
+                        master_key: Some(master_key_bytes.to_vec()),
+                        revision: Some(revision),
+                        ..Default::default()
+                    });
+                }
+                self.send_message_to_group(master_key_bytes, content_body, timestamp)
+                    .await
+            }
+        }
+    }
+
     async fn restore_thread_timer(&mut self, thread: &Thread, content_body: &mut ContentBody) {
         let store_expire_timer = self.store.expire_timer(thread).await.unwrap_or_default();
 
@@ -1006,33 +2269,98 @@ impl<S: Store> Manager<S, Registered> {
         Ok(())
     }
 
-    /// Downloads and decrypts a single attachment.
+    /// Downloads and decrypts a single attachment to a file in the attachments directory.
+    ///
+    /// Attachments are only downloaded once: the location of their file is saved in the
+    /// [`AttachmentsStore`] and returned from there afterwards, as long as the file is still
+    /// there. Concurrent calls for the same attachment wait for a single download, and
+    /// interrupted downloads are resumed, see [`Manager::set_attachments_dir`].
     pub async fn get_attachment(
         &self,
         attachment_pointer: &AttachmentPointer,
-    ) -> Result<Vec<u8>, Error<S::Error>> {
+    ) -> Result<DownloadedAttachment, Error<S::Error>> {
         let expected_digest = attachment_pointer
             .digest
             .as_ref()
             .ok_or_else(|| Error::UnexpectedAttachmentChecksum)?;
+        let id = AttachmentId::from_digest(expected_digest);
 
-        let mut service = self.identified_push_service();
-        let mut attachment_stream = service.get_attachment(attachment_pointer).await?;
+        if let Some(attachment) = self.downloaded_attachment(&id).await? {
+            return Ok(attachment);
+        }
 
-        // We need the whole file for the crypto to check out
-        let mut ciphertext = Vec::new();
-        let size_bytes = attachment_stream.read_to_end(&mut ciphertext).await?;
-        trace!(size_bytes, "downloaded encrypted attachment");
+        let downloads = &self.state.attachment_downloads;
+        let guard = downloads.lock(&id).await;
+        let result = self
+            .download_attachment(attachment_pointer, &id, expected_digest)
+            .await;
+        downloads.unlock(&id, guard);
+        result
+    }
+
+    /// The downloaded attachment saved in the store, unless its file was removed since
+    async fn downloaded_attachment(
+        &self,
+        id: &AttachmentId,
+    ) -> Result<Option<DownloadedAttachment>, Error<S::Error>> {
+        let Some(attachment) = self.store.attachment(id).await? else {
+            return Ok(None);
+        };
+        match tokio::fs::metadata(&attachment.path).await {
+            Ok(metadata) if metadata.len() == attachment.size => Ok(Some(attachment)),
+            _ => {
+                debug!(%id, path =% attachment.path.display(), "downloaded attachment is gone");
+                self.store.clone().remove_attachment(id).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn download_attachment(
+        &self,
+        attachment_pointer: &AttachmentPointer,
+        id: &AttachmentId,
+        expected_digest: &[u8],
+    ) -> Result<DownloadedAttachment, Error<S::Error>> {
+        // another call may have downloaded it while we were waiting
+        if let Some(attachment) = self.downloaded_attachment(id).await? {
+            return Ok(attachment);
+        }
+
+        let downloads = &self.state.attachment_downloads;
+        let (encrypted, decrypted) = downloads.files(id).ok_or(Error::NoAttachmentsDir)?;
+        let _permit = downloads.permit().await;
 
-        let digest = sha2::Sha256::digest(&ciphertext);
-        if &digest[..] != expected_digest {
-            return Err(Error::UnexpectedAttachmentChecksum);
+        if let Partial::Incomplete { len, hasher } =
+            attachments::resume(&encrypted, expected_digest).await?
+        {
+            let mut service = self.identified_push_service();
+            let ciphertext = attachments::request(&mut service, attachment_pointer, len).await?;
+            let digest = attachments::download(&encrypted, ciphertext, len, hasher).await?;
+            if digest != expected_digest {
+                tokio::fs::remove_file(&encrypted).await?;
+                return Err(Error::UnexpectedAttachmentChecksum);
+            }
         }
 
         let key: [u8; 64] = attachment_pointer.key().try_into()?;
-        decrypt_in_place(key, &mut ciphertext)?;
+        let size = attachment_pointer.size.map(u64::from);
+        let size = match attachments::decrypt(&encrypted, &decrypted, &key, size).await {
+            Ok(size) => size,
+            Err(error) => {
+                let _ = tokio::fs::remove_file(&decrypted).await;
+                return Err(error);
+            }
+        };
+
+        let attachment = DownloadedAttachment {
+            path: decrypted,
+            size,
+        };
+        self.store.clone().save_attachment(id, &attachment).await?;
+        tokio::fs::remove_file(&encrypted).await?;
 
-        Ok(ciphertext)
+        Ok(attachment)
     }
 
     /// Gets the metadata of a sticker
@@ -1188,27 +2516,132 @@ impl<S: Store> Manager<S, Registered> {
         )
     }
 
+    /// Marks all messages of a thread up to `timestamp` (included) as read.
+    ///
+    /// Read receipts are sent to the senders of the newly read messages when they asked for
+    /// them, and our other devices are notified so they mark the messages as read too. The
+    /// messages are marked as read in the store first: if sending fails, the first error is
+    /// returned once all receipts were attempted.
+    pub async fn mark_read(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> Result<(), Error<S::Error>> {
+        let last_read = self
+            .store
+            .thread_activity(thread)
+            .await?
+            .map(|activity| activity.last_read_timestamp)
+            .unwrap_or_default();
+        if timestamp <= last_read {
+            return Ok(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+
+        let own_aci = self.state.data.service_ids.aci;
+        let mut receipts: HashMap<ServiceId, Vec<u64>> = HashMap::new();
+        let mut read_messages = Vec::new();
+        let mut expiries = Vec::new();
+        for message in self
+            .store
+            .messages(thread, last_read + 1..=timestamp)
+            .await?
+        {
+            let message = message?;
+            if !is_unread(&message, Some(own_aci)) {
+                continue;
+            }
+            let sender = message.metadata.sender;
+            if message.metadata.needs_receipt {
+                receipts
+                    .entry(sender)
+                    .or_default()
+                    .push(message.timestamp());
+            }
+            read_messages.push(sync_message::Read {
+                sender_aci: Some(sender.raw_uuid().to_string()),
+                timestamp: Some(message.timestamp()),
+            });
+            // the disappearing timer of received messages starts when they are read
+            if let Some(expires_at) = expires_at(&message, now) {
+                expiries.push((message.timestamp(), expires_at));
+            }
+        }
+
+        // the messages are read locally whether or not notifying others works out
+        self.store.mark_thread_read(thread, timestamp).await?;
+        for (message_timestamp, expires_at) in expiries {
+            self.store
+                .set_message_expiry(thread, message_timestamp, expires_at)
+                .await?;
+        }
+
+        // every message we send gets its own timestamp
+        let mut timestamps = now..;
+        let mut first_error = None;
+        for (sender, read_timestamps) in receipts {
+            trace!(
+                sender = %sender.service_id_string(),
+                count = read_timestamps.len(),
+                "sending read receipt"
+            );
+            let receipt = ReceiptMessage {
+                r#type: Some(receipt_message::Type::Read.into()),
+                timestamp: read_timestamps,
+            };
+            let sent_at = timestamps.next().expect("endless range");
+            if let Err(error) = self.send_message(sender, receipt, sent_at).await {
+                warn!(%error, sender = %sender.service_id_string(), "failed to send read receipt");
+                first_error.get_or_insert(error);
+            }
+        }
+
+        if !read_messages.is_empty() {
+            let sync_message = SyncMessage {
+                read: read_messages,
+                ..SyncMessage::with_padding(&mut thread_rng())
+            };
+            let sent_at = timestamps.next().expect("endless range");
+            if let Err(error) = self
+                .send_message(self.state.data.service_ids.aci(), sync_message, sent_at)
+                .await
+            {
+                warn!(%error, "failed to send read sync");
+                first_error.get_or_insert(error);
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+
     /// Returns the title of a thread (contact or group).
     pub async fn thread_title(&self, thread: &Thread) -> Result<String, Error<S::Error>> {
-        match thread {
-            Thread::Contact(uuid) => {
-                let contact = match self.store.contact_by_id(uuid).await {
-                    Ok(contact) => contact,
-                    Err(error) => {
-                        info!(%error, %uuid, "error getting contact by id");
-                        None
-                    }
-                };
-                Ok(match contact {
-                    Some(contact) => contact.name,
-                    None => uuid.to_string(),
-                })
+        Ok(self.store.thread_title(thread).await?)
+    }
+
+    /// Renders the mentions of a text with the names of the mentioned contacts.
+    ///
+    /// See [`text::render_mentions`] for how mentions are rendered.
+    pub async fn render_mentions(
+        &self,
+        text: &str,
+        body_ranges: &[BodyRange],
+    ) -> Result<String, Error<S::Error>> {
+        let mut names = HashMap::new();
+        for aci in text::mentions(body_ranges) {
+            if let Some(contact) = self.store.contact_by_id(&aci).await? {
+                if !contact.name.is_empty() {
+                    names.insert(aci, contact.name);
+                }
             }
-            Thread::Group(id) => match self.store.group(*id).await? {
-                Some(group) => Ok(group.title),
-                None => Ok("".to_string()),
-            },
         }
+        Ok(text::render_mentions(text, body_ranges, |aci| {
+            names.get(aci).cloned()
+        }))
     }
 
     /// Returns how this client was registered, either as a primary or secondary device.
@@ -1270,6 +2703,37 @@ impl<S: Store> Manager<S, Registered> {
     }
 }
 
+/// Timestamp of a revision of a message: when it was edited, or sent if it was never edited
+fn revision_timestamp(content: &Content) -> u64 {
+    match &content.body {
+        ContentBody::EditMessage(EditMessage {
+            data_message:
+                Some(DataMessage {
+                    timestamp: Some(timestamp),
+                    ..
+                }),
+            ..
+        })
+        | ContentBody::SynchronizeMessage(SyncMessage {
+            sent:
+                Some(sync_message::Sent {
+                    edit_message:
+                        Some(EditMessage {
+                            data_message:
+                                Some(DataMessage {
+                                    timestamp: Some(timestamp),
+                                    ..
+                                }),
+                            ..
+                        }),
+                    ..
+                }),
+            ..
+        }) => *timestamp,
+        _ => content.timestamp(),
+    }
+}
+
 /// Set the timestamp in any DataMessage so it matches its envelope's
 fn ensure_data_message_timestamp(content_body: &mut ContentBody, timestamp: u64) {
     match content_body {
@@ -1335,6 +2799,127 @@ async fn upsert_group<S: Store>(
     Ok(store.group(master_key_bytes.try_into()?).await?)
 }
 
+/// Decrypts the group change carried by a group context, if any
+fn decrypt_group_changes(
+    groups_manager: &GroupsManager<InMemoryCredentialsCache>,
+    group_context: &GroupContextV2,
+) -> Option<GroupChanges> {
+    group_context.group_change.as_ref()?;
+    match groups_manager.decrypt_group_context(group_context.clone()) {
+        Ok(changes) => changes,
+        Err(error) => {
+            warn!(%error, "failed to decrypt group change");
+            None
+        }
+    }
+}
+
+/// Brings the stored group up to the revision of a received group context
+///
+/// When the context carries the change from the stored revision to the next one, the change is
+/// applied locally. Otherwise (missed revisions, or a change that cannot be applied), the changes
+/// are fetched from the group logs and applied one by one. The whole group is only fetched again
+/// when it is unknown, or when the logs cannot be fetched or applied to the stored group.
+async fn update_group<S: Store>(
+    store: &S,
+    push_service: &mut PushService,
+    groups_manager: &mut GroupsManager<InMemoryCredentialsCache>,
+    group_context: &GroupContextV2,
+    changes: Option<&GroupChanges>,
+) -> Result<Option<Group>, Error<S::Error>> {
+    let (Some(master_key_bytes), Some(revision)) =
+        (&group_context.master_key, group_context.revision)
+    else {
+        return Ok(None);
+    };
+    let master_key: GroupMasterKeyBytes = master_key_bytes.as_slice().try_into()?;
+
+    let stored_group = match store.group(master_key).await {
+        Ok(group) => group,
+        Err(error) => {
+            warn!(%error, "failed to retrieve group from local db");
+            None
+        }
+    };
+
+    if let Some(mut group) = stored_group {
+        if group.revision >= revision {
+            return Ok(Some(group));
+        }
+        if let Some(changes) = changes.filter(|_| group.revision + 1 == revision) {
+            match group.apply_changes(changes) {
+                Ok(()) => {
+                    debug!(revision, "applied group change");
+                    store.save_group(master_key, group.clone()).await?;
+                    return Ok(Some(group));
+                }
+                // the changes are fetched from the group logs below
+                Err(error) => debug!(%error, revision, "failed to apply group change"),
+            }
+        }
+        let current = group.revision;
+        match apply_group_logs::<S::Error>(
+            push_service,
+            groups_manager,
+            master_key,
+            &mut group,
+            revision,
+        )
+        .await
+        {
+            Ok(()) => {
+                debug!(current, revision, "applied group changes from logs");
+                store.save_group(master_key, group.clone()).await?;
+                return Ok(Some(group));
+            }
+            // the whole group is fetched again below
+            Err(error) => warn!(%error, current, revision, "failed to apply group logs"),
+        }
+    }
+
+    upsert_group(store, groups_manager, master_key_bytes, &revision).await
+}
+
+/// Fetches the changes of a group from the group logs, and applies them up to a revision
+///
+/// On error, the group is left at the last revision whose changes were applied.
+async fn apply_group_logs<S: std::error::Error>(
+    push_service: &mut PushService,
+    groups_manager: &mut GroupsManager<InMemoryCredentialsCache>,
+    master_key: GroupMasterKeyBytes,
+    group: &mut Group,
+    revision: u32,
+) -> Result<(), Error<S>> {
+    let secret_params = group_secret_params(master_key);
+    while group.revision < revision {
+        let auth = groups_manager
+            .get_authorization_for_today(&mut thread_rng(), secret_params)
+            .await?;
+        let logs = groups::fetch_logs::<S>(push_service, auth, group.revision + 1).await?;
+        let current = group.revision;
+        for state in logs.group_changes {
+            let group_change = state.group_change.ok_or(Error::MissingGroupChange)?;
+            // the signed change is decrypted the same way as the one carried by messages
+            let group_context = GroupContextV2 {
+                master_key: Some(master_key.to_vec()),
+                revision: None,
+                group_change: Some(group_change.encode_to_vec()),
+            };
+            let changes = decrypt_group_changes(groups_manager, &group_context)
+                .ok_or(Error::MissingGroupChange)?;
+            match group.apply_changes(&changes) {
+                Ok(()) | Err(GroupChangesError::Stale { .. }) => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        if group.revision == current {
+            // the logs do not go any further
+            return Err(GroupChangesError::RevisionGap { current, revision }.into());
+        }
+    }
+    Ok(())
+}
+
 /// Download and decrypt a sticker manifest
 async fn download_sticker_pack<C: ContentsStore>(
     mut store: C,
@@ -1409,6 +2994,258 @@ async fn download_sticker<C: ContentsStore>(
     Ok(ciphertext)
 }
 
+/// Updates the delivery state of the messages we sent acknowledged by a receipt.
+///
+/// Returns the new delivery state and the messages which were found.
+async fn apply_receipt<S: Store>(
+    store: &mut S,
+    recipient: Uuid,
+    receipt: &ReceiptMessage,
+) -> Result<(DeliveryState, Vec<(Thread, u64)>), Error<S::Error>> {
+    let delivery_state = DeliveryState::from(receipt.r#type());
+    let mut messages = Vec::new();
+    for &timestamp in &receipt.timestamp {
+        let Some(thread) = find_sent_message_thread(store, recipient, timestamp).await? else {
+            debug!(%recipient, timestamp, "could not find message acknowledged by receipt");
+            continue;
+        };
+        store
+            .update_delivery_state(&thread, timestamp, recipient, delivery_state)
+            .await?;
+        messages.push((thread, timestamp));
+    }
+    Ok((delivery_state, messages))
+}
+
+/// Returns the first error of sending a message to a group, if any
+///
+/// Recipients which were not found are skipped, they are e.g. contacts who deleted their account.
+fn first_group_send_error(
+    results: Vec<Result<SentMessage, MessageSenderError>>,
+) -> Result<(), MessageSenderError> {
+    // TODO: Handle the NotFound error in the future by removing all sessions to this UUID and marking it as unregistered, not sending any messages to this contact anymore.
+    results
+        .into_iter()
+        .find(|res| match res {
+            Ok(_) => false,
+            // Ignore any NotFound errors, those mean that e.g. some contact in a group deleted his account.
+            Err(MessageSenderError::NotFound { service_id }) => {
+                debug!(service_id = %service_id.service_id_string(), "recipient not found, skipping sent message result");
+                false
+            }
+            // return first error if any
+            Err(_) => true,
+        })
+        .transpose()?;
+    Ok(())
+}
+
+/// Identifier of a group, as found in messages which do not carry its master key
+fn group_identifier(master_key_bytes: GroupMasterKeyBytes) -> [u8; 32] {
+    group_secret_params(master_key_bytes).get_group_identifier()
+}
+
+fn group_secret_params(master_key_bytes: GroupMasterKeyBytes) -> GroupSecretParams {
+    GroupSecretParams::derive_from_master_key(GroupMasterKey::new(master_key_bytes))
+}
+
+/// Finds the thread of a group from its identifier, among the groups in the store
+async fn find_group_thread<S: Store>(
+    store: &S,
+    group_id: &[u8],
+) -> Result<Option<Thread>, Error<S::Error>> {
+    for group in store.groups().await? {
+        let (master_key_bytes, _) = group?;
+        if group_identifier(master_key_bytes) == group_id {
+            return Ok(Some(Thread::Group(master_key_bytes)));
+        }
+    }
+    Ok(None)
+}
+
+/// Starts the disappearing timer of a message we sent from another device
+async fn start_synced_expiry<S: Store>(
+    store: &mut S,
+    content: &Content,
+) -> Result<(), Error<S::Error>> {
+    let ContentBody::SynchronizeMessage(SyncMessage {
+        sent: Some(sent), ..
+    }) = &content.body
+    else {
+        return Ok(());
+    };
+    let started_at = sent
+        .expiration_start_timestamp
+        .unwrap_or_else(|| content.timestamp());
+    if let Some(expires_at) = expires_at(content, started_at) {
+        let thread = Thread::try_from(content)?;
+        store
+            .set_message_expiry(&thread, content.timestamp(), expires_at)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Maximum number of times a batch of delivery receipts is sent before giving up on it
+const DELIVERY_RECEIPT_ATTEMPTS: u32 = 3;
+
+/// Time between two attempts at sending delivery receipts which failed, when no message comes in
+const DELIVERY_RECEIPT_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Delivery receipts waiting to be sent to one sender
+#[derive(Default)]
+struct PendingReceipts {
+    timestamps: Vec<u64>,
+    failed_attempts: u32,
+}
+
+/// Sends one delivery receipt per sender for all the pending messages
+///
+/// Batches which could not be sent are kept and retried on the next call, until they failed
+/// [`DELIVERY_RECEIPT_ATTEMPTS`] times. Calls happen for every received message, and every
+/// [`DELIVERY_RECEIPT_RETRY_INTERVAL`] while batches are pending.
+async fn send_delivery_receipts<S: Store>(
+    sender: &mut MessageSender<S::AciStore>,
+    pending: &mut HashMap<ServiceId, PendingReceipts>,
+) {
+    if pending.is_empty() {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64;
+
+    let mut failed = HashMap::new();
+    for (recipient, mut receipts) in pending.drain() {
+        trace!(
+            recipient = %recipient.service_id_string(),
+            count = receipts.timestamps.len(),
+            "sending delivery receipt"
+        );
+        let receipt = ReceiptMessage {
+            r#type: Some(receipt_message::Type::Delivery.into()),
+            timestamp: receipts.timestamps.clone(),
+        };
+        if let Err(error) = sender
+            .send_message(&recipient, None, receipt, timestamp, false, false)
+            .await
+        {
+            receipts.failed_attempts += 1;
+            if receipts.failed_attempts < DELIVERY_RECEIPT_ATTEMPTS {
+                warn!(
+                    %error,
+                    attempt = receipts.failed_attempts,
+                    "failed to send delivery receipt, will retry"
+                );
+                failed.insert(recipient, receipts);
+            } else {
+                error!(%error, "failed to send delivery receipt, giving up");
+            }
+        }
+    }
+    *pending = failed;
+}
+
+/// Finds the thread of a message we sent to `recipient`, identified by its timestamp.
+async fn find_sent_message_thread<S: Store>(
+    store: &S,
+    recipient: Uuid,
+    timestamp: u64,
+) -> Result<Option<Thread>, Error<S::Error>> {
+    let thread = Thread::Contact(recipient);
+    if store
+        .delivery_states(&thread, timestamp)
+        .await?
+        .contains_key(&recipient)
+    {
+        return Ok(Some(thread));
+    }
+
+    for summary in store.threads().await? {
+        let thread = summary?.thread;
+        if matches!(thread, Thread::Group(_))
+            && store
+                .delivery_states(&thread, timestamp)
+                .await?
+                .contains_key(&recipient)
+        {
+            return Ok(Some(thread));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Marks messages read on another of our devices as read.
+async fn apply_read_syncs<S: Store>(
+    store: &mut S,
+    read_messages: &[sync_message::Read],
+    read_at: u64,
+) -> Result<(), Error<S::Error>> {
+    // the read marker of each thread moves to its most recent read message
+    let mut read_markers: HashMap<Thread, u64> = HashMap::new();
+    for read in read_messages {
+        let sender = read
+            .sender_aci
+            .as_deref()
+            .and_then(|aci| Uuid::parse_str(aci).ok());
+        let (Some(sender), Some(timestamp)) = (sender, read.timestamp) else {
+            warn!(?read, "invalid read message");
+            continue;
+        };
+        let Some(thread) = find_message_thread(store, sender, timestamp).await? else {
+            debug!(%sender, timestamp, "could not find read message");
+            continue;
+        };
+        if let Some(message) = store.message(&thread, timestamp).await? {
+            if let Some(expires_at) = expires_at(&message, read_at) {
+                store
+                    .set_message_expiry(&thread, timestamp, expires_at)
+                    .await?;
+            }
+        }
+        let read_marker = read_markers.entry(thread).or_default();
+        *read_marker = timestamp.max(*read_marker);
+    }
+
+    for (thread, timestamp) in read_markers {
+        trace!(%thread, timestamp, "marking thread as read from another device");
+        store.mark_thread_read(&thread, timestamp).await?;
+    }
+
+    Ok(())
+}
+
+/// Finds the thread of a message identified by its sender and timestamp.
+async fn find_message_thread<S: Store>(
+    store: &S,
+    sender: Uuid,
+    timestamp: u64,
+) -> Result<Option<Thread>, Error<S::Error>> {
+    let thread = Thread::Contact(sender);
+    if store.message(&thread, timestamp).await?.is_some() {
+        return Ok(Some(thread));
+    }
+
+    for summary in store.threads().await? {
+        let thread = summary?.thread;
+        if !matches!(thread, Thread::Group(_)) {
+            continue;
+        }
+        if store
+            .message(&thread, timestamp)
+            .await?
+            .is_some_and(|message| message.metadata.sender.raw_uuid() == sender)
+        {
+            return Ok(Some(thread));
+        }
+    }
+
+    Ok(None)
+}
+
 /// Save a message into the store.
 /// Note that `override_thread` can be used to specify the thread the message will be stored in.
 /// This is required when storing outgoing messages, as in this case the appropriate storage place cannot be derived from the message itself.
@@ -1526,12 +3363,49 @@ async fn save_message<S: Store>(
                         None
                     }
                 }
+                DataMessage {
+                    reaction:
+                        Some(Reaction {
+                            emoji,
+                            remove,
+                            target_sent_timestamp: Some(ts),
+                            ..
+                        }),
+                    ..
+                } => {
+                    // attach the reaction to the message it reacts to
+                    if store.message(&thread, *ts).await?.is_some() {
+                        let author = message.metadata.sender.raw_uuid();
+                        let timestamp = message.timestamp();
+                        // reactions may arrive out of order, keep the latest one (even removed)
+                        let is_outdated = store
+                            .reactions(&thread, *ts)
+                            .await?
+                            .get(&author)
+                            .is_some_and(|reaction| reaction.timestamp > timestamp);
+                        if !is_outdated {
+                            // a reaction without emoji can only be removed
+                            let reaction = MessageReaction {
+                                emoji: emoji.clone().unwrap_or_default(),
+                                timestamp,
+                                removed: remove.unwrap_or_default() || emoji.is_none(),
+                            };
+                            store
+                                .update_reaction(&thread, *ts, author, Some(reaction))
+                                .await?;
+                            debug!(%thread, ts, "reaction to message in thread updated");
+                        }
+                    } else {
+                        warn!(%thread, ts, "could not find message to react to in thread");
+                    }
+                    None
+                }
                 _ => Some(message),
             }
         }
         ContentBody::EditMessage(EditMessage {
             target_sent_timestamp: Some(ts),
-            data_message: Some(data_message),
+            data_message: Some(_),
         })
         | ContentBody::SynchronizeMessage(SyncMessage {
             sent:
@@ -1539,18 +3413,26 @@ async fn save_message<S: Store>(
                     edit_message:
                         Some(EditMessage {
                             target_sent_timestamp: Some(ts),
-                            data_message: Some(data_message),
+                            data_message: Some(_),
                         }),
                     ..
                 }),
             ..
         }) => {
-            if let Some(mut existing_msg) = store.message(&thread, ts).await? {
-                existing_msg.metadata = message.metadata;
-                existing_msg.body = ContentBody::DataMessage(data_message);
-                // TODO: find a way to mark the message as edited (so that it's visible in a client)
-                trace!(%thread, ts, "message in thread edited");
-                Some(existing_msg)
+            // the edit replaces the message, which is kept as a previous revision
+            if let Some(existing_msg) = store.message(&thread, ts).await? {
+                if revision_timestamp(&existing_msg) > revision_timestamp(&message) {
+                    // edits may arrive out of order, keep the latest one as the message
+                    store.add_message_revision(&thread, ts, message).await?;
+                    trace!(%thread, ts, "kept outdated edit of message in thread");
+                    None
+                } else {
+                    store
+                        .add_message_revision(&thread, ts, existing_msg)
+                        .await?;
+                    trace!(%thread, ts, "message in thread edited");
+                    Some(message)
+                }
             } else {
                 warn!(%thread, ts, "could not find edited message");
                 None
@@ -1,13 +1,25 @@
+use std::fmt;
+
+use base64::prelude::*;
 use derivative::Derivative;
 use libsignal_service::{
-    groups_v2::Role,
-    prelude::{AccessControl, Member, ProfileKey, Timer, Uuid},
+    groups_v2::{GroupChange, GroupChanges, Role},
+    prelude::{AccessControl, Member, ProfileKey, ProtobufMessage, Timer, Uuid},
+    proto::{
+        access_control::AccessRequired,
+        group_invite_link::{Contents, GroupInviteLinkContentsV1},
+        GroupInviteLink,
+    },
+    protocol::{Aci, Pni, ServiceId},
+    zkgroup::GroupMasterKeyBytes,
 };
 use serde::{Deserialize, Serialize};
+use tracing::debug;
+use url::Url;
 
 use super::ServiceIdType;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Group {
     pub title: String,
     pub avatar: String,
@@ -21,7 +33,344 @@ pub struct Group {
     pub description: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+const INVITE_LINK_HOST: &str = "signal.group";
+
+/// A link inviting to join a group, like `https://signal.group/#CjQKI...`
+#[derive(Clone, PartialEq, Eq)]
+pub struct InviteLink {
+    pub master_key: GroupMasterKeyBytes,
+    pub password: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InviteLinkError {
+    #[error("invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("not a signal.group link")]
+    NotAnInviteLink,
+    #[error("invalid base64 data: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("invalid invite link contents: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+    #[error("unsupported invite link version")]
+    UnsupportedVersion,
+    #[error("invalid group master key")]
+    InvalidMasterKey,
+}
+
+impl InviteLink {
+    /// Parses a link in the shape Signal apps share them, `https://signal.group/#` followed by
+    /// the contents of the link in URL-safe base64
+    pub fn parse(link: &str) -> Result<Self, InviteLinkError> {
+        let url = Url::parse(link)?;
+        let fragment = match (url.scheme(), url.host_str(), url.fragment()) {
+            ("https", Some(INVITE_LINK_HOST), Some(fragment))
+                if !fragment.is_empty()
+                    && url.path() == "/"
+                    && url.query().is_none()
+                    && url.port().is_none()
+                    && url.username().is_empty()
+                    && url.password().is_none() =>
+            {
+                fragment
+            }
+            _ => return Err(InviteLinkError::NotAnInviteLink),
+        };
+        let bytes = BASE64_URL_SAFE_NO_PAD.decode(fragment.trim_end_matches('='))?;
+        let Some(Contents::ContentsV1(contents)) =
+            GroupInviteLink::decode(bytes.as_slice())?.contents
+        else {
+            return Err(InviteLinkError::UnsupportedVersion);
+        };
+        Ok(Self {
+            master_key: contents
+                .group_master_key
+                .as_slice()
+                .try_into()
+                .map_err(|_| InviteLinkError::InvalidMasterKey)?,
+            password: contents.invite_link_password,
+        })
+    }
+}
+
+impl fmt::Display for InviteLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let invite_link = GroupInviteLink {
+            contents: Some(Contents::ContentsV1(GroupInviteLinkContentsV1 {
+                group_master_key: self.master_key.to_vec(),
+                invite_link_password: self.password.clone(),
+            })),
+        };
+        let fragment = BASE64_URL_SAFE_NO_PAD.encode(invite_link.encode_to_vec());
+        write!(f, "https://{INVITE_LINK_HOST}/#{fragment}")
+    }
+}
+
+impl fmt::Debug for InviteLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InviteLink").finish_non_exhaustive()
+    }
+}
+
+/// Preview of a group from its invite link, before joining it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupJoinInfo {
+    pub title: String,
+    pub description: Option<String>,
+    pub avatar: String,
+    pub member_count: u32,
+    pub revision: u32,
+    /// `Any` to join directly, `Administrator` to request to join
+    pub add_from_invite_link: AccessRequired,
+    /// Whether we already requested to join
+    pub pending_admin_approval: bool,
+}
+
+/// How we joined a group by its invite link
+#[derive(Debug, Clone)]
+pub enum GroupJoin {
+    /// We are a member of the group
+    Joined(Group),
+    /// An administrator needs to approve our request to join
+    Requested,
+}
+
+/// A change of a group, made by `editor` and bringing the group to `revision`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupUpdate {
+    pub editor: Uuid,
+    pub revision: u32,
+    pub events: Vec<GroupEvent>,
+}
+
+/// What changed in a group, as shown to users
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupEvent {
+    MemberAdded(Uuid),
+    MemberRemoved(Uuid),
+    MemberRoleChanged {
+        uuid: Uuid,
+        role: Role,
+    },
+    MemberInvited(Uuid),
+    InviteRevoked(Uuid),
+    InviteAccepted(Uuid),
+    JoinRequested(Uuid),
+    JoinRequestDenied(Uuid),
+    JoinRequestApproved(Uuid),
+    TitleChanged(String),
+    AvatarChanged,
+    DescriptionChanged(Option<String>),
+    /// New duration of disappearing messages in seconds, if enabled
+    TimerChanged(Option<u32>),
+    AccessControlChanged,
+    InviteLinkReset,
+    AnnouncementsOnlyChanged(bool),
+}
+
+impl From<&GroupChanges> for GroupUpdate {
+    fn from(changes: &GroupChanges) -> Self {
+        Self {
+            editor: changes.editor,
+            revision: changes.revision,
+            events: changes
+                .changes
+                .iter()
+                .filter_map(GroupEvent::from_change)
+                .collect(),
+        }
+    }
+}
+
+impl GroupEvent {
+    /// Changes that are not visible to users, like profile key updates, have no event
+    fn from_change(change: &GroupChange) -> Option<Self> {
+        let event = match change {
+            GroupChange::NewMember(member) => Self::MemberAdded(member.uuid),
+            GroupChange::DeleteMember(uuid) => Self::MemberRemoved(*uuid),
+            GroupChange::ModifyMemberRole { uuid, role } => Self::MemberRoleChanged {
+                uuid: *uuid,
+                role: *role,
+            },
+            GroupChange::NewPendingMember(pending_member) => {
+                Self::MemberInvited(pending_member.address.raw_uuid())
+            }
+            GroupChange::DeletePendingMember(address) => Self::InviteRevoked(address.raw_uuid()),
+            GroupChange::PromotePendingMember { address, .. } => {
+                Self::InviteAccepted(address.raw_uuid())
+            }
+            GroupChange::NewRequestingMember(requesting_member) => {
+                Self::JoinRequested(requesting_member.uuid)
+            }
+            GroupChange::DeleteRequestingMember(uuid) => Self::JoinRequestDenied(*uuid),
+            GroupChange::PromoteRequestingMember { uuid, .. } => Self::JoinRequestApproved(*uuid),
+            GroupChange::Title(title) => Self::TitleChanged(title.clone()),
+            GroupChange::Avatar(_) => Self::AvatarChanged,
+            GroupChange::Description(description) => Self::DescriptionChanged(description.clone()),
+            GroupChange::Timer(timer) => Self::TimerChanged(
+                timer
+                    .as_ref()
+                    .map(|timer| timer.duration)
+                    .filter(|duration| *duration > 0),
+            ),
+            GroupChange::AttributeAccess(_)
+            | GroupChange::MemberAccess(_)
+            | GroupChange::AddFromInviteLinkAccess(_) => Self::AccessControlChanged,
+            GroupChange::InviteLinkPassword(_) => Self::InviteLinkReset,
+            GroupChange::AnnouncementOnly(announcements_only) => {
+                Self::AnnouncementsOnlyChanged(*announcements_only)
+            }
+            GroupChange::ModifyMemberProfileKey { .. }
+            | GroupChange::NewBannedMember(_)
+            | GroupChange::DeleteBannedMember(_) => return None,
+        };
+        Some(event)
+    }
+}
+
+impl Group {
+    /// The invite link of the group, if joining by link is enabled
+    pub fn invite_link(&self, master_key: GroupMasterKeyBytes) -> Option<InviteLink> {
+        (!self.invite_link_password.is_empty()).then(|| InviteLink {
+            master_key,
+            password: self.invite_link_password.clone(),
+        })
+    }
+
+    /// Applies the changes of the next revision of the group
+    ///
+    /// Changes are applied entirely or not at all: on error, the group is left untouched and
+    /// should be fetched again from the server.
+    pub fn apply_changes(&mut self, changes: &GroupChanges) -> Result<(), GroupChangesError> {
+        let current = self.revision;
+        if changes.revision <= current {
+            return Err(GroupChangesError::Stale {
+                current,
+                revision: changes.revision,
+            });
+        }
+        if changes.revision != current + 1 {
+            return Err(GroupChangesError::RevisionGap {
+                current,
+                revision: changes.revision,
+            });
+        }
+
+        let mut group = self.clone();
+        for change in &changes.changes {
+            group.apply_change(change.clone(), changes.revision)?;
+        }
+        group.revision = changes.revision;
+        *self = group;
+        Ok(())
+    }
+
+    fn apply_change(
+        &mut self,
+        change: GroupChange,
+        revision: u32,
+    ) -> Result<(), GroupChangesError> {
+        match change {
+            GroupChange::NewMember(member) => {
+                self.members.retain(|m| m.uuid != member.uuid);
+                self.members.push(member);
+            }
+            GroupChange::DeleteMember(uuid) => self.members.retain(|m| m.uuid != uuid),
+            GroupChange::ModifyMemberRole { uuid, role } => {
+                if let Some(member) = self.members.iter_mut().find(|m| m.uuid == uuid) {
+                    member.role = role;
+                }
+            }
+            GroupChange::ModifyMemberProfileKey { uuid, profile_key } => {
+                if let Some(member) = self.members.iter_mut().find(|m| m.uuid == uuid) {
+                    member.profile_key = profile_key;
+                }
+            }
+            GroupChange::NewPendingMember(pending_member) => {
+                self.pending_members.push(pending_member.into())
+            }
+            GroupChange::DeletePendingMember(address) => self
+                .pending_members
+                .retain(|m| m.uuid != address.raw_uuid()),
+            GroupChange::PromotePendingMember {
+                address,
+                profile_key,
+            } => {
+                let uuid = address.raw_uuid();
+                let role = self
+                    .pending_members
+                    .iter()
+                    .find(|m| m.uuid == uuid)
+                    .map(|m| m.role)
+                    .unwrap_or(Role::Default);
+                self.pending_members.retain(|m| m.uuid != uuid);
+                self.members.push(Member {
+                    uuid,
+                    role,
+                    profile_key,
+                    joined_at_revision: revision,
+                });
+            }
+            GroupChange::NewRequestingMember(requesting_member) => {
+                self.requesting_members.push(requesting_member.into())
+            }
+            GroupChange::DeleteRequestingMember(uuid) => {
+                self.requesting_members.retain(|m| m.uuid != uuid)
+            }
+            GroupChange::PromoteRequestingMember { uuid, role } => {
+                let Some(position) = self.requesting_members.iter().position(|m| m.uuid == uuid)
+                else {
+                    debug!(%uuid, "promoted requesting member is unknown");
+                    return Ok(());
+                };
+                let requesting_member = self.requesting_members.remove(position);
+                self.members.push(Member {
+                    uuid,
+                    role,
+                    profile_key: requesting_member.profile_key,
+                    joined_at_revision: revision,
+                });
+            }
+            GroupChange::Title(title) => self.title = title,
+            GroupChange::Avatar(avatar) => self.avatar = avatar,
+            GroupChange::Timer(timer) => self.disappearing_messages_timer = timer,
+            GroupChange::Description(description) => self.description = description,
+            GroupChange::AttributeAccess(access) => self.access_control()?.attributes = access,
+            GroupChange::MemberAccess(access) => self.access_control()?.members = access,
+            GroupChange::AddFromInviteLinkAccess(access) => {
+                self.access_control()?.add_from_invite_link = access
+            }
+            GroupChange::InviteLinkPassword(password) => {
+                self.invite_link_password = password.into()
+            }
+            // not part of our model of groups
+            GroupChange::AnnouncementOnly(_)
+            | GroupChange::NewBannedMember(_)
+            | GroupChange::DeleteBannedMember(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Only one of the access rights changes at a time, the others must be known
+    fn access_control(&mut self) -> Result<&mut AccessControl, GroupChangesError> {
+        self.access_control
+            .as_mut()
+            .ok_or(GroupChangesError::UnknownAccessControl)
+    }
+}
+
+/// Why changes could not be applied to a group, see [`Group::apply_changes`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum GroupChangesError {
+    #[error("group is already at revision {current}, changes are for revision {revision}")]
+    Stale { current: u32, revision: u32 },
+    #[error("group is at revision {current}, changes are for revision {revision}")]
+    RevisionGap { current: u32, revision: u32 },
+    #[error("access control of the group is unknown")]
+    UnknownAccessControl,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PendingMember {
     // for backwards compatibility
     pub uuid: Uuid,
@@ -32,6 +381,16 @@ pub struct PendingMember {
     pub timestamp: u64,
 }
 
+impl PendingMember {
+    /// The service id the user is invited with, their ACI or their PNI
+    pub fn service_id(&self) -> ServiceId {
+        match self.service_id_type {
+            ServiceIdType::AccountIdentity => Aci::from(self.uuid).into(),
+            ServiceIdType::PhoneNumberIdentity => Pni::from(self.uuid).into(),
+        }
+    }
+}
+
 #[derive(Derivative, Clone, Deserialize, Serialize)]
 #[derivative(Debug)]
 pub struct RequestingMember {
@@ -78,3 +437,496 @@ impl From<libsignal_service::groups_v2::RequestingMember> for RequestingMember {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libsignal_service::groups_v2::{
+        PendingMember as ServicePendingMember, RequestingMember as ServiceRequestingMember,
+    };
+
+    use super::*;
+
+    const ADMIN: Uuid = Uuid::from_u128(1);
+    const MEMBER: Uuid = Uuid::from_u128(2);
+    const INVITED: Uuid = Uuid::from_u128(3);
+    const REQUESTING: Uuid = Uuid::from_u128(4);
+    const NEWCOMER: Uuid = Uuid::from_u128(5);
+
+    fn profile_key(uuid: Uuid) -> ProfileKey {
+        ProfileKey::create([uuid.as_bytes()[15]; 32])
+    }
+
+    fn member(uuid: Uuid, role: Role) -> Member {
+        Member {
+            uuid,
+            role,
+            profile_key: profile_key(uuid),
+            joined_at_revision: 0,
+        }
+    }
+
+    /// A group at revision 1, with an admin, a member, an invited and a requesting member
+    fn group() -> Group {
+        Group {
+            title: "Group".into(),
+            avatar: String::new(),
+            disappearing_messages_timer: None,
+            access_control: Some(AccessControl {
+                attributes: AccessRequired::Member,
+                members: AccessRequired::Member,
+                add_from_invite_link: AccessRequired::Unsatisfiable,
+            }),
+            revision: 1,
+            members: vec![
+                member(ADMIN, Role::Administrator),
+                member(MEMBER, Role::Default),
+            ],
+            pending_members: vec![PendingMember {
+                uuid: INVITED,
+                service_id_type: Default::default(),
+                role: Role::Default,
+                added_by_uuid: ADMIN,
+                timestamp: 0,
+            }],
+            requesting_members: vec![RequestingMember {
+                uuid: REQUESTING,
+                profile_key: profile_key(REQUESTING),
+                timestamp: 0,
+            }],
+            invite_link_password: Vec::new(),
+            description: None,
+        }
+    }
+
+    fn changes(revision: u32, changes: Vec<GroupChange>) -> GroupChanges {
+        GroupChanges {
+            editor: ADMIN,
+            revision,
+            changes,
+            change_epoch: 0,
+        }
+    }
+
+    fn aci(uuid: Uuid) -> ServiceId {
+        Aci::from(uuid).into()
+    }
+
+    fn member_uuids(group: &Group) -> Vec<Uuid> {
+        group.members.iter().map(|m| m.uuid).collect()
+    }
+
+    #[test]
+    fn apply_each_change() {
+        let cases: &[(GroupChange, fn(&Group) -> bool)] = &[
+            (
+                GroupChange::NewMember(member(NEWCOMER, Role::Default)),
+                |g| member_uuids(g) == [ADMIN, MEMBER, NEWCOMER],
+            ),
+            (GroupChange::DeleteMember(MEMBER), |g| {
+                member_uuids(g) == [ADMIN]
+            }),
+            (
+                GroupChange::ModifyMemberRole {
+                    uuid: MEMBER,
+                    role: Role::Administrator,
+                },
+                |g| g.members[1].role == Role::Administrator,
+            ),
+            (
+                GroupChange::ModifyMemberProfileKey {
+                    uuid: MEMBER,
+                    profile_key: profile_key(NEWCOMER),
+                },
+                |g| g.members[1].profile_key.get_bytes() == profile_key(NEWCOMER).get_bytes(),
+            ),
+            (
+                GroupChange::NewPendingMember(ServicePendingMember {
+                    address: aci(NEWCOMER),
+                    role: Role::Default,
+                    added_by_uuid: ADMIN,
+                    timestamp: 0,
+                }),
+                |g| g.pending_members.len() == 2 && g.pending_members[1].uuid == NEWCOMER,
+            ),
+            (GroupChange::DeletePendingMember(aci(INVITED)), |g| {
+                g.pending_members.is_empty()
+            }),
+            (
+                GroupChange::PromotePendingMember {
+                    address: aci(INVITED),
+                    profile_key: profile_key(INVITED),
+                },
+                |g| {
+                    g.pending_members.is_empty()
+                        && member_uuids(g) == [ADMIN, MEMBER, INVITED]
+                        && g.members[2].joined_at_revision == 2
+                },
+            ),
+            (
+                GroupChange::NewRequestingMember(ServiceRequestingMember {
+                    uuid: NEWCOMER,
+                    profile_key: profile_key(NEWCOMER),
+                    timestamp: 0,
+                }),
+                |g| g.requesting_members.len() == 2,
+            ),
+            (GroupChange::DeleteRequestingMember(REQUESTING), |g| {
+                g.requesting_members.is_empty() && member_uuids(g) == [ADMIN, MEMBER]
+            }),
+            (
+                GroupChange::PromoteRequestingMember {
+                    uuid: REQUESTING,
+                    role: Role::Default,
+                },
+                |g| {
+                    g.requesting_members.is_empty()
+                        && member_uuids(g) == [ADMIN, MEMBER, REQUESTING]
+                        && g.members[2].profile_key.get_bytes()
+                            == profile_key(REQUESTING).get_bytes()
+                },
+            ),
+            (GroupChange::Title("Renamed".into()), |g| {
+                g.title == "Renamed"
+            }),
+            (GroupChange::Avatar("groups/avatar".into()), |g| {
+                g.avatar == "groups/avatar"
+            }),
+            (GroupChange::Description(Some("About".into())), |g| {
+                g.description.as_deref() == Some("About")
+            }),
+            (GroupChange::Timer(Some(Timer { duration: 60 })), |g| {
+                g.disappearing_messages_timer.as_ref().map(|t| t.duration) == Some(60)
+            }),
+            (
+                GroupChange::AttributeAccess(AccessRequired::Administrator),
+                |g| g.access_control.as_ref().unwrap().attributes == AccessRequired::Administrator,
+            ),
+            (
+                GroupChange::MemberAccess(AccessRequired::Administrator),
+                |g| g.access_control.as_ref().unwrap().members == AccessRequired::Administrator,
+            ),
+            (
+                GroupChange::AddFromInviteLinkAccess(AccessRequired::Any),
+                |g| g.access_control.as_ref().unwrap().add_from_invite_link == AccessRequired::Any,
+            ),
+            (GroupChange::InviteLinkPassword("secret".into()), |g| {
+                g.invite_link_password == b"secret"
+            }),
+            // not part of our model of groups, only the revision changes
+            (GroupChange::AnnouncementOnly(true), |_| true),
+            (GroupChange::DeleteBannedMember(NEWCOMER), |_| true),
+        ];
+
+        for (change, check) in cases {
+            let mut group = group();
+            group
+                .apply_changes(&changes(2, vec![change.clone()]))
+                .unwrap_or_else(|error| panic!("{change:?}: {error}"));
+            assert_eq!(group.revision, 2, "{change:?}");
+            assert!(check(&group), "{change:?}: {group:?}");
+        }
+    }
+
+    #[test]
+    fn group_events() {
+        let cases = [
+            (
+                GroupChange::NewMember(member(NEWCOMER, Role::Default)),
+                Some(GroupEvent::MemberAdded(NEWCOMER)),
+            ),
+            (
+                GroupChange::DeleteMember(MEMBER),
+                Some(GroupEvent::MemberRemoved(MEMBER)),
+            ),
+            (
+                GroupChange::ModifyMemberRole {
+                    uuid: MEMBER,
+                    role: Role::Administrator,
+                },
+                Some(GroupEvent::MemberRoleChanged {
+                    uuid: MEMBER,
+                    role: Role::Administrator,
+                }),
+            ),
+            (
+                GroupChange::NewPendingMember(ServicePendingMember {
+                    address: aci(NEWCOMER),
+                    role: Role::Default,
+                    added_by_uuid: ADMIN,
+                    timestamp: 0,
+                }),
+                Some(GroupEvent::MemberInvited(NEWCOMER)),
+            ),
+            (
+                GroupChange::DeletePendingMember(aci(INVITED)),
+                Some(GroupEvent::InviteRevoked(INVITED)),
+            ),
+            (
+                GroupChange::PromotePendingMember {
+                    address: aci(INVITED),
+                    profile_key: profile_key(INVITED),
+                },
+                Some(GroupEvent::InviteAccepted(INVITED)),
+            ),
+            (
+                GroupChange::NewRequestingMember(ServiceRequestingMember {
+                    uuid: NEWCOMER,
+                    profile_key: profile_key(NEWCOMER),
+                    timestamp: 0,
+                }),
+                Some(GroupEvent::JoinRequested(NEWCOMER)),
+            ),
+            (
+                GroupChange::DeleteRequestingMember(REQUESTING),
+                Some(GroupEvent::JoinRequestDenied(REQUESTING)),
+            ),
+            (
+                GroupChange::PromoteRequestingMember {
+                    uuid: REQUESTING,
+                    role: Role::Default,
+                },
+                Some(GroupEvent::JoinRequestApproved(REQUESTING)),
+            ),
+            (
+                GroupChange::Title("Renamed".into()),
+                Some(GroupEvent::TitleChanged("Renamed".into())),
+            ),
+            (
+                GroupChange::Avatar("groups/avatar".into()),
+                Some(GroupEvent::AvatarChanged),
+            ),
+            (
+                GroupChange::Description(None),
+                Some(GroupEvent::DescriptionChanged(None)),
+            ),
+            (
+                GroupChange::Timer(Some(Timer { duration: 60 })),
+                Some(GroupEvent::TimerChanged(Some(60))),
+            ),
+            // a timer of 0 disables disappearing messages
+            (
+                GroupChange::Timer(Some(Timer { duration: 0 })),
+                Some(GroupEvent::TimerChanged(None)),
+            ),
+            (
+                GroupChange::Timer(None),
+                Some(GroupEvent::TimerChanged(None)),
+            ),
+            (
+                GroupChange::AttributeAccess(AccessRequired::Administrator),
+                Some(GroupEvent::AccessControlChanged),
+            ),
+            (
+                GroupChange::MemberAccess(AccessRequired::Administrator),
+                Some(GroupEvent::AccessControlChanged),
+            ),
+            (
+                GroupChange::AddFromInviteLinkAccess(AccessRequired::Any),
+                Some(GroupEvent::AccessControlChanged),
+            ),
+            (
+                GroupChange::InviteLinkPassword("secret".into()),
+                Some(GroupEvent::InviteLinkReset),
+            ),
+            (
+                GroupChange::AnnouncementOnly(true),
+                Some(GroupEvent::AnnouncementsOnlyChanged(true)),
+            ),
+            // not shown to users
+            (
+                GroupChange::ModifyMemberProfileKey {
+                    uuid: MEMBER,
+                    profile_key: profile_key(NEWCOMER),
+                },
+                None,
+            ),
+            (GroupChange::DeleteBannedMember(NEWCOMER), None),
+        ];
+
+        for (change, event) in cases {
+            assert_eq!(GroupEvent::from_change(&change), event, "{change:?}");
+        }
+    }
+
+    #[test]
+    fn group_update() {
+        let update = GroupUpdate::from(&changes(
+            7,
+            vec![
+                GroupChange::Title("Renamed".into()),
+                GroupChange::ModifyMemberProfileKey {
+                    uuid: MEMBER,
+                    profile_key: profile_key(NEWCOMER),
+                },
+                GroupChange::DeleteMember(MEMBER),
+            ],
+        ));
+        assert_eq!(
+            update,
+            GroupUpdate {
+                editor: ADMIN,
+                revision: 7,
+                events: vec![
+                    GroupEvent::TitleChanged("Renamed".into()),
+                    GroupEvent::MemberRemoved(MEMBER),
+                ],
+            }
+        );
+
+        let invisible =
+            GroupUpdate::from(&changes(8, vec![GroupChange::DeleteBannedMember(MEMBER)]));
+        assert!(invisible.events.is_empty());
+    }
+
+    #[test]
+    fn apply_changes_with_revision_gap() {
+        let mut group = group();
+        assert_eq!(
+            group.apply_changes(&changes(3, vec![GroupChange::Title("Renamed".into())])),
+            Err(GroupChangesError::RevisionGap {
+                current: 1,
+                revision: 3
+            })
+        );
+        assert_eq!(group.title, "Group");
+        assert_eq!(group.revision, 1);
+    }
+
+    #[test]
+    fn apply_stale_changes() {
+        let mut group = group();
+        for revision in [0, 1] {
+            assert_eq!(
+                group.apply_changes(&changes(revision, vec![GroupChange::DeleteMember(MEMBER)])),
+                Err(GroupChangesError::Stale {
+                    current: 1,
+                    revision
+                })
+            );
+        }
+        assert_eq!(member_uuids(&group), [ADMIN, MEMBER]);
+    }
+
+    #[test]
+    fn apply_access_change_without_access_control() {
+        let mut group = Group {
+            access_control: None,
+            ..group()
+        };
+        // the title must not change alone: changes are applied entirely or not at all
+        let result = group.apply_changes(&changes(
+            2,
+            vec![
+                GroupChange::Title("Renamed".into()),
+                GroupChange::MemberAccess(AccessRequired::Administrator),
+            ],
+        ));
+        assert_eq!(result, Err(GroupChangesError::UnknownAccessControl));
+        assert_eq!(group.title, "Group");
+        assert_eq!(group.revision, 1);
+    }
+
+    #[test]
+    fn invite_link_roundtrip() {
+        let link = invite_link();
+        let url = link.to_string();
+        assert!(url.starts_with("https://signal.group/#"));
+        assert_eq!(InviteLink::parse(&url).unwrap(), link);
+        // padding is tolerated
+        assert_eq!(InviteLink::parse(&format!("{url}==")).unwrap(), link);
+    }
+
+    fn invite_link() -> InviteLink {
+        InviteLink {
+            master_key: [42; 32],
+            password: vec![1, 2, 3, 4],
+        }
+    }
+
+    fn link_with_contents(contents: &[u8]) -> String {
+        format!(
+            "https://signal.group/#{}",
+            BASE64_URL_SAFE_NO_PAD.encode(contents)
+        )
+    }
+
+    #[test]
+    fn invite_link_with_malformed_fragment() {
+        for link in ["https://signal.group/", "https://signal.group/#"] {
+            assert!(
+                matches!(
+                    InviteLink::parse(link),
+                    Err(InviteLinkError::NotAnInviteLink)
+                ),
+                "{link}"
+            );
+        }
+        assert!(matches!(
+            InviteLink::parse(&link_with_contents(&[0xff, 0xff, 0xff])),
+            Err(InviteLinkError::Protobuf(_))
+        ));
+        // only an unknown field, no contents
+        assert!(matches!(
+            InviteLink::parse(&link_with_contents(&[0x40, 0x01])),
+            Err(InviteLinkError::UnsupportedVersion)
+        ));
+        let short_master_key = GroupInviteLink {
+            contents: Some(Contents::ContentsV1(GroupInviteLinkContentsV1 {
+                group_master_key: vec![42; 16],
+                invite_link_password: vec![1, 2, 3, 4],
+            })),
+        };
+        assert!(matches!(
+            InviteLink::parse(&link_with_contents(&short_master_key.encode_to_vec())),
+            Err(InviteLinkError::InvalidMasterKey)
+        ));
+    }
+
+    #[test]
+    fn invite_link_with_wrong_host() {
+        let url = invite_link().to_string();
+        let fragment = url.split_once('#').unwrap().1;
+        for link in [
+            format!("https://signal.me/#{fragment}"),
+            format!("https://www.signal.group/#{fragment}"),
+            format!("https://signal.group.example.com/#{fragment}"),
+            format!("sgnl://signal.group/#{fragment}"),
+            format!("http://signal.group/#{fragment}"),
+            format!("https://signal.group/join#{fragment}"),
+            format!("https://signal.group/?join#{fragment}"),
+            format!("https://signal.group:8443/#{fragment}"),
+            format!("https://user@signal.group/#{fragment}"),
+        ] {
+            assert!(
+                matches!(
+                    InviteLink::parse(&link),
+                    Err(InviteLinkError::NotAnInviteLink)
+                ),
+                "{link}"
+            );
+        }
+        assert!(matches!(
+            InviteLink::parse(&format!("signal.group/#{fragment}")),
+            Err(InviteLinkError::Url(_))
+        ));
+    }
+
+    #[test]
+    fn invite_link_with_bad_base64() {
+        let url = invite_link().to_string();
+        let fragment = url.split_once('#').unwrap().1;
+        // the standard alphabet is not URL-safe
+        let standard = fragment.replace('-', "+").replace('_', "/") + "+/";
+        for link in [
+            "https://signal.group/#not*base64".to_string(),
+            // no base64 text is one character longer than a multiple of four
+            format!("https://signal.group/#{}", &fragment[..fragment.len() - 3]),
+            format!("https://signal.group/#{standard}"),
+        ] {
+            assert!(
+                matches!(InviteLink::parse(&link), Err(InviteLinkError::Base64(_))),
+                "{link}"
+            );
+        }
+    }
+}
@@ -1,4 +1,19 @@
-use libsignal_service::prelude::Content;
+use libsignal_service::{
+    content::{ContentBody, DataMessageFlags},
+    prelude::{Content, Uuid},
+    proto::{
+        call_message::{self, hangup},
+        data_message::{self, Delete, Reaction},
+        receipt_message,
+        sync_message::{self, call_event, Sent},
+        typing_message, AttachmentPointer, BodyRange, CallMessage, DataMessage, EditMessage,
+        GroupContextV2, SyncMessage,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use super::groups::GroupUpdate;
+use crate::store::{ContentExt, Thread};
 
 #[derive(Debug)]
 pub enum Received {
@@ -10,6 +25,585 @@ pub enum Received {
     /// Contacts can be later queried in the store.
     Contacts,
 
-    /// Incoming decrypted message with metadata and content
+    /// Incoming decrypted message, see [`Message`]
+    Message(Box<Message>),
+
+    /// Any other incoming decrypted content, with its metadata
     Content(Box<Content>),
+
+    /// A recipient received, read or viewed messages we sent
+    ///
+    /// The new delivery state of the messages, identified by their thread and timestamp, is
+    /// already saved in the store.
+    Receipt {
+        recipient: Uuid,
+        state: DeliveryState,
+        messages: Vec<(Thread, u64)>,
+    },
+
+    /// A group changed, see [`GroupUpdate`]
+    ///
+    /// The message carrying the change is saved in the group thread, and its update can be read
+    /// again later with [`Manager::group_update`](crate::Manager::group_update).
+    GroupUpdate {
+        thread: Thread,
+        timestamp: u64,
+        update: GroupUpdate,
+    },
+
+    /// A contact started or stopped typing in a thread
+    Typing {
+        thread: Thread,
+        sender: Uuid,
+        action: typing_message::Action,
+    },
+}
+
+/// Delivery state of a message we sent, for one of its recipients
+///
+/// States are ordered: a message is first sent, then delivered to the devices of the recipient,
+/// then read and finally viewed (for view-once messages and media).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DeliveryState {
+    Sent,
+    Delivered,
+    Read,
+    Viewed,
+}
+
+/// The reaction of somebody to a message, see
+/// [`ContentsStore::reactions`](crate::store::ContentsStore::reactions)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageReaction {
+    pub emoji: String,
+    /// Timestamp of the message carrying the reaction
+    pub timestamp: u64,
+    /// Whether the reaction was removed
+    ///
+    /// Removed reactions are kept so that reactions arriving out of order don't come back.
+    #[serde(default)]
+    pub removed: bool,
+}
+
+impl From<receipt_message::Type> for DeliveryState {
+    fn from(receipt_type: receipt_message::Type) -> Self {
+        match receipt_type {
+            receipt_message::Type::Delivery => Self::Delivered,
+            receipt_message::Type::Read => Self::Read,
+            receipt_message::Type::Viewed => Self::Viewed,
+        }
+    }
+}
+
+/// A message of a thread, decoded from a [`Content`]
+///
+/// This covers what users see in a conversation: messages received from others, and messages we
+/// sent (from this device or another one). Other contents, like receipts or typing indicators,
+/// are not messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub thread: Thread,
+    pub sender: Uuid,
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub body: MessageBody,
+}
+
+/// Whether a [`Message`] was received from somebody else or sent by us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    /// Sent from this device, or from another one of our devices
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageBody {
+    /// Text, possibly quoting another message
+    ///
+    /// Mentions and styles of the text are in its body ranges, see [`super::text`].
+    Text {
+        text: String,
+        body_ranges: Vec<BodyRange>,
+        quote: Option<Quote>,
+    },
+    /// Files, with an optional caption, possibly quoting another message
+    Attachment {
+        attachments: Vec<AttachmentPointer>,
+        caption: Option<String>,
+        body_ranges: Vec<BodyRange>,
+        quote: Option<Quote>,
+    },
+    Sticker(data_message::Sticker),
+    /// A reaction to another message, or the removal of a reaction
+    Reaction {
+        emoji: String,
+        target_author: Option<Uuid>,
+        target_sent_timestamp: u64,
+        remove: bool,
+    },
+    /// A new version of a message we already have
+    Edit {
+        target_sent_timestamp: u64,
+        body: Box<MessageBody>,
+    },
+    /// A message deleted for everyone
+    Delete {
+        target_sent_timestamp: u64,
+    },
+    /// The group changed, to the given revision, see
+    /// [`Manager::group_update`](crate::Manager::group_update)
+    GroupUpdate {
+        revision: u32,
+    },
+    Call(CallEvent),
+    /// The disappearing messages timer of the thread changed, 0 disables it
+    ExpirationTimerUpdate {
+        seconds: u32,
+    },
+}
+
+/// A step of a 1:1 call that users see
+///
+/// Calls are identified by their id across their steps. Signaling that only matters to set up the
+/// call (ICE candidates, opaque messages) is not a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEvent {
+    /// The caller starts a voice or video call
+    Offer { id: u64, video: bool },
+    /// The callee answered
+    Answer { id: u64 },
+    /// The callee is already in another call
+    Busy { id: u64 },
+    /// The call ended, was declined, or was answered on another device of the callee
+    Hangup { id: u64, reason: hangup::Type },
+    /// One of our other devices accepted, declined or deleted a call, or saw it end
+    Synced {
+        id: u64,
+        video: bool,
+        event: call_event::Event,
+    },
+}
+
+impl CallEvent {
+    fn from_call_message(call_message: &CallMessage) -> Option<Self> {
+        let event = match call_message {
+            CallMessage {
+                offer: Some(offer), ..
+            } => Self::Offer {
+                id: offer.id(),
+                video: offer.r#type() == call_message::offer::Type::OfferVideoCall,
+            },
+            CallMessage {
+                answer: Some(answer),
+                ..
+            } => Self::Answer { id: answer.id() },
+            CallMessage {
+                busy: Some(busy), ..
+            } => Self::Busy { id: busy.id() },
+            CallMessage {
+                hangup: Some(hangup),
+                ..
+            } => Self::Hangup {
+                id: hangup.id(),
+                reason: hangup.r#type(),
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    fn from_sync_call_event(call_event: &sync_message::CallEvent) -> Self {
+        Self::Synced {
+            id: call_event.id(),
+            video: call_event.r#type() == call_event::Type::VideoCall,
+            event: call_event.event(),
+        }
+    }
+}
+
+/// A message quoted in a reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    /// Timestamp of the quoted message
+    pub timestamp: u64,
+    pub author: Option<Uuid>,
+    pub text: Option<String>,
+}
+
+impl Message {
+    /// Decodes a message, or returns `None` if the content is not a message
+    ///
+    /// `own_aci` is our own ACI, to tell outgoing messages apart. Messages we sent from this
+    /// device are in the thread of their destination, like the ones saved by
+    /// [`Manager::send_message`](crate::Manager::send_message).
+    pub fn from_content(content: &Content, own_aci: Uuid) -> Option<Self> {
+        let mut sender = content.metadata.sender.raw_uuid();
+        let mut direction = match &content.body {
+            ContentBody::SynchronizeMessage(_) => Direction::Outgoing,
+            _ if sender == own_aci => Direction::Outgoing,
+            _ => Direction::Incoming,
+        };
+        let mut thread = Thread::try_from(content).ok()?;
+        let body = match &content.body {
+            ContentBody::DataMessage(data_message)
+            | ContentBody::SynchronizeMessage(SyncMessage {
+                sent:
+                    Some(Sent {
+                        message: Some(data_message),
+                        ..
+                    }),
+                ..
+            }) => MessageBody::from_data_message(data_message)?,
+            ContentBody::EditMessage(edit_message)
+            | ContentBody::SynchronizeMessage(SyncMessage {
+                sent:
+                    Some(Sent {
+                        edit_message: Some(edit_message),
+                        ..
+                    }),
+                ..
+            }) => MessageBody::from_edit_message(edit_message)?,
+            ContentBody::CallMessage(call_message) => {
+                MessageBody::Call(CallEvent::from_call_message(call_message)?)
+            }
+            ContentBody::SynchronizeMessage(SyncMessage {
+                call_event: Some(call_event),
+                ..
+            }) => {
+                // group calls are identified by the group id, which does not tell the thread
+                let peer = Uuid::from_slice(call_event.conversation_id()).ok()?;
+                thread = Thread::Contact(peer);
+                if call_event.direction() == call_event::Direction::Incoming {
+                    sender = peer;
+                    direction = Direction::Incoming;
+                }
+                MessageBody::Call(CallEvent::from_sync_call_event(call_event))
+            }
+            _ => return None,
+        };
+        // unlike sync messages, messages sent from this device do not name their thread
+        if direction == Direction::Outgoing
+            && !matches!(content.body, ContentBody::SynchronizeMessage(_))
+            && matches!(thread, Thread::Contact(_))
+        {
+            thread = Thread::Contact(content.metadata.destination.raw_uuid());
+        }
+        Some(Self {
+            thread,
+            sender,
+            timestamp: content.timestamp(),
+            direction,
+            body,
+        })
+    }
+}
+
+impl MessageBody {
+    fn from_data_message(data_message: &DataMessage) -> Option<Self> {
+        let quote = data_message.quote.as_ref().map(|quote| Quote {
+            timestamp: quote.id(),
+            author: quote
+                .author_aci
+                .as_deref()
+                .and_then(|aci| Uuid::parse_str(aci).ok()),
+            text: quote.text.clone(),
+        });
+        let body = match data_message {
+            DataMessage {
+                flags: Some(flags), ..
+            } if flags & DataMessageFlags::ExpirationTimerUpdate as u32 != 0 => {
+                Self::ExpirationTimerUpdate {
+                    seconds: data_message.expire_timer(),
+                }
+            }
+            DataMessage {
+                reaction:
+                    Some(Reaction {
+                        emoji: Some(emoji),
+                        remove,
+                        target_author_aci,
+                        target_sent_timestamp: Some(target_sent_timestamp),
+                        ..
+                    }),
+                ..
+            } => Self::Reaction {
+                emoji: emoji.clone(),
+                target_author: target_author_aci
+                    .as_deref()
+                    .and_then(|aci| Uuid::parse_str(aci).ok()),
+                target_sent_timestamp: *target_sent_timestamp,
+                remove: remove.unwrap_or_default(),
+            },
+            DataMessage {
+                delete:
+                    Some(Delete {
+                        target_sent_timestamp: Some(target_sent_timestamp),
+                        ..
+                    }),
+                ..
+            } => Self::Delete {
+                target_sent_timestamp: *target_sent_timestamp,
+            },
+            DataMessage {
+                sticker: Some(sticker),
+                ..
+            } => Self::Sticker(sticker.clone()),
+            DataMessage {
+                attachments,
+                body,
+                body_ranges,
+                ..
+            } if !attachments.is_empty() => Self::Attachment {
+                attachments: attachments.clone(),
+                caption: body.clone(),
+                body_ranges: body_ranges.clone(),
+                quote,
+            },
+            DataMessage {
+                body: Some(text),
+                body_ranges,
+                ..
+            } => Self::Text {
+                text: text.clone(),
+                body_ranges: body_ranges.clone(),
+                quote,
+            },
+            DataMessage {
+                group_v2:
+                    Some(GroupContextV2 {
+                        revision: Some(revision),
+                        group_change: Some(_),
+                        ..
+                    }),
+                ..
+            } => Self::GroupUpdate {
+                revision: *revision,
+            },
+            _ => return None,
+        };
+        Some(body)
+    }
+
+    fn from_edit_message(edit_message: &EditMessage) -> Option<Self> {
+        let EditMessage {
+            target_sent_timestamp: Some(target_sent_timestamp),
+            data_message: Some(data_message),
+            ..
+        } = edit_message
+        else {
+            return None;
+        };
+        Some(Self::Edit {
+            target_sent_timestamp: *target_sent_timestamp,
+            body: Box::new(Self::from_data_message(data_message)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libsignal_service::{content::Metadata, proto::ReceiptMessage, protocol::Aci};
+
+    use super::*;
+
+    const OWN_ACI: Uuid = Uuid::from_u128(1);
+    const CONTACT: Uuid = Uuid::from_u128(2);
+
+    fn content(sender: Uuid, body: impl Into<ContentBody>) -> Content {
+        sent_content(sender, OWN_ACI, body)
+    }
+
+    fn sent_content(sender: Uuid, destination: Uuid, body: impl Into<ContentBody>) -> Content {
+        Content {
+            metadata: Metadata {
+                sender: Aci::from(sender).into(),
+                destination: Aci::from(destination).into(),
+                sender_device: 1,
+                timestamp: 42,
+                needs_receipt: false,
+                unidentified_sender: false,
+                server_guid: None,
+            },
+            body: body.into(),
+        }
+    }
+
+    #[test]
+    fn incoming_text() {
+        let data_message = DataMessage {
+            body: Some("Hello".into()),
+            timestamp: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(
+            Message::from_content(&content(CONTACT, data_message), OWN_ACI),
+            Some(Message {
+                thread: Thread::Contact(CONTACT),
+                sender: CONTACT,
+                timestamp: 42,
+                direction: Direction::Incoming,
+                body: MessageBody::Text {
+                    text: "Hello".into(),
+                    body_ranges: Vec::new(),
+                    quote: None,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn outgoing_text() {
+        // as saved by `Manager::send_message`
+        let data_message = DataMessage {
+            body: Some("Hello".into()),
+            timestamp: Some(42),
+            ..Default::default()
+        };
+        let message =
+            Message::from_content(&sent_content(OWN_ACI, CONTACT, data_message), OWN_ACI).unwrap();
+        assert_eq!(message.thread, Thread::Contact(CONTACT));
+        assert_eq!(message.sender, OWN_ACI);
+        assert_eq!(message.direction, Direction::Outgoing);
+    }
+
+    #[test]
+    fn note_to_self() {
+        let data_message = DataMessage {
+            body: Some("Remember".into()),
+            ..Default::default()
+        };
+        let message =
+            Message::from_content(&sent_content(OWN_ACI, OWN_ACI, data_message), OWN_ACI).unwrap();
+        assert_eq!(message.thread, Thread::Contact(OWN_ACI));
+        assert_eq!(message.direction, Direction::Outgoing);
+    }
+
+    #[test]
+    fn incoming_call() {
+        let call_message = CallMessage {
+            offer: Some(call_message::Offer {
+                id: Some(7),
+                r#type: Some(call_message::offer::Type::OfferVideoCall.into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let message = Message::from_content(&content(CONTACT, call_message), OWN_ACI).unwrap();
+        assert_eq!(message.thread, Thread::Contact(CONTACT));
+        assert_eq!(message.direction, Direction::Incoming);
+        assert_eq!(
+            message.body,
+            MessageBody::Call(CallEvent::Offer { id: 7, video: true })
+        );
+
+        let ice_update = CallMessage {
+            ice_update: vec![Default::default()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Message::from_content(&content(CONTACT, ice_update), OWN_ACI),
+            None
+        );
+    }
+
+    #[test]
+    fn call_synced_from_another_device() {
+        let sync_message = SyncMessage {
+            call_event: Some(sync_message::CallEvent {
+                conversation_id: Some(CONTACT.as_bytes().to_vec()),
+                id: Some(7),
+                timestamp: Some(42),
+                r#type: Some(call_event::Type::AudioCall.into()),
+                direction: Some(call_event::Direction::Incoming.into()),
+                event: Some(call_event::Event::Accepted.into()),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            Message::from_content(&content(OWN_ACI, sync_message), OWN_ACI),
+            Some(Message {
+                thread: Thread::Contact(CONTACT),
+                sender: CONTACT,
+                timestamp: 42,
+                direction: Direction::Incoming,
+                body: MessageBody::Call(CallEvent::Synced {
+                    id: 7,
+                    video: false,
+                    event: call_event::Event::Accepted,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn reaction_sent_from_another_device() {
+        let sync_message = SyncMessage {
+            sent: Some(Sent {
+                destination_service_id: Some(CONTACT.to_string()),
+                timestamp: Some(42),
+                message: Some(DataMessage {
+                    reaction: Some(Reaction {
+                        emoji: Some("👍".into()),
+                        target_author_aci: Some(CONTACT.to_string()),
+                        target_sent_timestamp: Some(21),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            Message::from_content(&content(OWN_ACI, sync_message), OWN_ACI),
+            Some(Message {
+                thread: Thread::Contact(CONTACT),
+                sender: OWN_ACI,
+                timestamp: 42,
+                direction: Direction::Outgoing,
+                body: MessageBody::Reaction {
+                    emoji: "👍".into(),
+                    target_author: Some(CONTACT),
+                    target_sent_timestamp: 21,
+                    remove: false,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn edit() {
+        let edit_message = EditMessage {
+            target_sent_timestamp: Some(21),
+            data_message: Some(DataMessage {
+                body: Some("Hello, again".into()),
+                ..Default::default()
+            }),
+        };
+        let message = Message::from_content(&content(CONTACT, edit_message), OWN_ACI).unwrap();
+        assert_eq!(
+            message.body,
+            MessageBody::Edit {
+                target_sent_timestamp: 21,
+                body: Box::new(MessageBody::Text {
+                    text: "Hello, again".into(),
+                    body_ranges: Vec::new(),
+                    quote: None,
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn receipts_are_not_messages() {
+        let receipt = ReceiptMessage {
+            r#type: Some(receipt_message::Type::Read.into()),
+            timestamp: vec![21],
+        };
+        assert_eq!(
+            Message::from_content(&content(CONTACT, receipt), OWN_ACI),
+            None
+        );
+    }
 }
@@ -5,8 +5,9 @@ pub mod contacts;
 pub mod groups;
 pub mod identity;
 pub mod messages;
+pub mod text;
 
-#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ServiceIdType {
     /// Account Identity (ACI)
     ///
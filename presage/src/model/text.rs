@@ -0,0 +1,176 @@
+//! Text of messages, with mentions and styles
+//!
+//! Mentions and styles are [`BodyRange`]s over the text of a message. Their start and length are
+//! counted in UTF-16 code units, and each mention stands for a single [`MENTION_PLACEHOLDER`] in
+//! the text.
+
+use std::collections::HashMap;
+
+use libsignal_service::{
+    prelude::Uuid,
+    proto::{
+        body_range::{AssociatedValue, Style},
+        BodyRange, DataMessage,
+    },
+};
+
+/// Character standing for a mention in the text of a message
+pub const MENTION_PLACEHOLDER: char = '\u{fffc}';
+
+/// Builder of a text with mentions and styles
+///
+/// The built text can be sent as is, see [`DataMessage::from`], or put in a data message with
+/// [`TextBuilder::build`].
+#[derive(Debug, Clone, Default)]
+pub struct TextBuilder {
+    text: String,
+    body_ranges: Vec<BodyRange>,
+}
+
+impl TextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends plain text
+    pub fn text(mut self, text: &str) -> Self {
+        self.text.push_str(text);
+        self
+    }
+
+    /// Appends a mention of the user with the given ACI
+    pub fn mention(mut self, aci: Uuid) -> Self {
+        self.body_ranges.push(BodyRange {
+            start: Some(utf16_len(&self.text)),
+            length: Some(1),
+            associated_value: Some(AssociatedValue::MentionAci(aci.to_string())),
+        });
+        self.text.push(MENTION_PLACEHOLDER);
+        self
+    }
+
+    /// Appends text with a style, like bold or spoiler
+    pub fn styled(mut self, text: &str, style: Style) -> Self {
+        self.body_ranges.push(BodyRange {
+            start: Some(utf16_len(&self.text)),
+            length: Some(utf16_len(text)),
+            associated_value: Some(AssociatedValue::Style(style.into())),
+        });
+        self.text.push_str(text);
+        self
+    }
+
+    /// The text with its mentions and styles
+    pub fn build(self) -> (String, Vec<BodyRange>) {
+        (self.text, self.body_ranges)
+    }
+}
+
+impl From<TextBuilder> for DataMessage {
+    fn from(builder: TextBuilder) -> Self {
+        let (body, body_ranges) = builder.build();
+        DataMessage {
+            body: Some(body),
+            body_ranges,
+            ..Default::default()
+        }
+    }
+}
+
+/// ACIs of the users mentioned in a text
+pub fn mentions(body_ranges: &[BodyRange]) -> impl Iterator<Item = Uuid> + '_ {
+    body_ranges
+        .iter()
+        .filter_map(|range| match &range.associated_value {
+            Some(AssociatedValue::MentionAci(aci)) => Uuid::parse_str(aci).ok(),
+            _ => None,
+        })
+}
+
+/// Replaces the mentions of a text with `@` followed by the name returned by `name`
+///
+/// Users without a name are shown with their ACI instead.
+pub fn render_mentions(
+    text: &str,
+    body_ranges: &[BodyRange],
+    name: impl Fn(&Uuid) -> Option<String>,
+) -> String {
+    let mentions: HashMap<u32, Uuid> = body_ranges
+        .iter()
+        .filter_map(|range| match range {
+            BodyRange {
+                start: Some(start),
+                associated_value: Some(AssociatedValue::MentionAci(aci)),
+                ..
+            } => Some((*start, Uuid::parse_str(aci).ok()?)),
+            _ => None,
+        })
+        .collect();
+
+    let mut rendered = String::with_capacity(text.len());
+    let mut offset = 0;
+    for c in text.chars() {
+        match mentions.get(&offset) {
+            Some(aci) if c == MENTION_PLACEHOLDER => {
+                rendered.push('@');
+                rendered.push_str(&name(aci).unwrap_or_else(|| aci.to_string()));
+            }
+            _ => rendered.push(c),
+        }
+        offset += c.len_utf16() as u32;
+    }
+    rendered
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: Uuid = Uuid::from_u128(1);
+    const BOB: Uuid = Uuid::from_u128(2);
+
+    #[test]
+    fn build_mentions_and_styles() {
+        let (text, body_ranges) = TextBuilder::new()
+            .text("🎉 ")
+            .mention(ALICE)
+            .text(" is ")
+            .styled("back", Style::Bold)
+            .build();
+        assert_eq!(text, "🎉 \u{fffc} is back");
+        assert_eq!(
+            body_ranges,
+            [
+                BodyRange {
+                    start: Some(3),
+                    length: Some(1),
+                    associated_value: Some(AssociatedValue::MentionAci(ALICE.to_string())),
+                },
+                BodyRange {
+                    start: Some(8),
+                    length: Some(4),
+                    associated_value: Some(AssociatedValue::Style(Style::Bold.into())),
+                },
+            ]
+        );
+        assert_eq!(mentions(&body_ranges).collect::<Vec<_>>(), [ALICE]);
+    }
+
+    #[test]
+    fn render_with_names() {
+        let (text, body_ranges) = TextBuilder::new()
+            .text("🎉 ")
+            .mention(ALICE)
+            .text(" and ")
+            .mention(BOB)
+            .build();
+        let rendered = render_mentions(&text, &body_ranges, |aci| {
+            (*aci == ALICE).then(|| "Alice".to_owned())
+        });
+        assert_eq!(rendered, format!("🎉 @Alice and @{BOB}"));
+    }
+}
@@ -1,29 +1,49 @@
 //! Traits that are used by the manager for storing the data.
 
-use std::{fmt, future::Future, ops::RangeBounds, time::SystemTime};
+pub mod attachments;
+pub mod expiry;
+pub mod migration;
+pub mod search;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod threads;
+
+use std::{collections::BTreeMap, fmt, future::Future, ops::RangeBounds, time::SystemTime};
 
 use libsignal_service::{
     content::{ContentBody, Metadata},
     groups_v2::Timer,
-    pre_keys::PreKeysStore,
+    pre_keys::{KyberPreKeyStoreExt, PreKeysStore},
     prelude::{Content, ProfileKey, Uuid, UuidError},
     proto::{
         sync_message::{self, Sent},
         verified, DataMessage, EditMessage, GroupContextV2, SyncMessage, Verified,
     },
     protocol::{
-        IdentityKey, IdentityKeyPair, ProtocolAddress, ProtocolStore, SenderKeyStore, ServiceId,
+        IdentityKey, IdentityKeyPair, KyberPreKeyRecord, PreKeyRecord, ProtocolAddress,
+        ProtocolStore, SenderKeyRecord, SenderKeyStore, ServiceId, SessionRecord,
+        SignalProtocolError, SignedPreKeyRecord,
     },
     session_store::SessionStoreExt,
     zkgroup::GroupMasterKeyBytes,
     Profile,
 };
 use serde::{Deserialize, Serialize};
-use tracing::trace;
+use tracing::{info, trace};
 
+use self::{
+    attachments::{AttachmentId, DownloadedAttachment},
+    expiry::ExpiringMessage,
+    search::MessageSearch,
+    threads::{ThreadActivity, ThreadSummary},
+};
 use crate::{
     manager::RegistrationData,
-    model::{contacts::Contact, groups::Group},
+    model::{
+        contacts::Contact,
+        groups::Group,
+        messages::{DeliveryState, MessageReaction},
+    },
     AvatarBytes,
 };
 
@@ -59,9 +79,48 @@ pub trait StateStore {
     fn is_registered(&self) -> impl Future<Output = bool>;
 
     /// Clear registration data (including keys), but keep received messages, groups and contacts.
+    ///
+    /// The identities of others saved by the protocol stores are kept too.
     fn clear_registration(&mut self) -> impl Future<Output = Result<(), Self::StateStoreError>>;
 }
 
+/// Stores where downloaded attachments were decrypted to
+///
+/// This is a cache: attachments can be downloaded again as long as the server keeps them. The
+/// files themselves are not managed by the store, see
+/// [`Manager::set_attachments_dir`](crate::Manager::set_attachments_dir).
+pub trait AttachmentsStore {
+    type AttachmentsStoreError: StoreError;
+
+    /// Get a downloaded attachment
+    fn attachment(
+        &self,
+        id: &AttachmentId,
+    ) -> impl Future<Output = Result<Option<DownloadedAttachment>, Self::AttachmentsStoreError>>;
+
+    /// Save a downloaded attachment, replacing any attachment with the same id
+    fn save_attachment(
+        &mut self,
+        id: &AttachmentId,
+        attachment: &DownloadedAttachment,
+    ) -> impl Future<Output = Result<(), Self::AttachmentsStoreError>>;
+
+    /// Remove a downloaded attachment, returns whether it was found
+    ///
+    /// Its file is left untouched.
+    fn remove_attachment(
+        &mut self,
+        id: &AttachmentId,
+    ) -> impl Future<Output = Result<bool, Self::AttachmentsStoreError>>;
+
+    /// Remove all downloaded attachments
+    ///
+    /// Their files are left untouched.
+    fn clear_attachments(
+        &mut self,
+    ) -> impl Future<Output = Result<(), Self::AttachmentsStoreError>>;
+}
+
 /// Stores messages, contacts, groups and profiles
 pub trait ContentsStore: Send + Sync {
     type ContentsStoreError: StoreError;
@@ -80,6 +139,20 @@ pub trait ContentsStore: Send + Sync {
     /// Iterator over all stored sticker packs
     type StickerPacksIter: Iterator<Item = Result<StickerPack, Self::ContentsStoreError>>;
 
+    /// Iterator over all threads containing messages
+    type ThreadsIter: Iterator<Item = Result<ThreadSummary, Self::ContentsStoreError>>;
+
+    /// Iterator over all stored profile keys
+    type ProfileKeysIter: Iterator<Item = Result<(Uuid, ProfileKey), Self::ContentsStoreError>>;
+
+    /// Iterator over the results of a full-text search
+    ///
+    /// Each item is a tuple consisting of the thread of a message and the message itself.
+    type SearchResultsIter: Iterator<Item = Result<(Thread, Content), Self::ContentsStoreError>>;
+
+    /// Iterator over messages whose disappearing timer was started, soonest to expire first
+    type ExpiringMessagesIter: Iterator<Item = Result<ExpiringMessage, Self::ContentsStoreError>>;
+
     // Clear all profiles
     fn clear_profiles(&mut self) -> impl Future<Output = Result<(), Self::ContentsStoreError>>;
 
@@ -113,6 +186,9 @@ pub trait ContentsStore: Send + Sync {
     ) -> impl Future<Output = Result<bool, Self::ContentsStoreError>>;
 
     /// Retrieve a message from a [Thread] by its timestamp.
+    ///
+    /// Edited messages are stored as their latest edit, an [`EditMessage`] or a [`SyncMessage`]
+    /// sent from another device: use [`ContentExt::data_message`] to read any message.
     fn message(
         &self,
         thread: &Thread,
@@ -126,6 +202,143 @@ pub trait ContentsStore: Send + Sync {
         range: impl RangeBounds<u64>,
     ) -> impl Future<Output = Result<Self::MessagesIter, Self::ContentsStoreError>>;
 
+    /// Get the delivery state of a message we sent, for each of its recipients
+    ///
+    /// Delivery states are deleted along with the message.
+    fn delivery_states(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<BTreeMap<Uuid, DeliveryState>, Self::ContentsStoreError>>;
+
+    /// Update the delivery state of a message we sent, for one of its recipients
+    ///
+    /// States never move backwards, since receipts may arrive out of order. Returns whether the
+    /// state was updated.
+    fn update_delivery_state(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        recipient: Uuid,
+        state: DeliveryState,
+    ) -> impl Future<Output = Result<bool, Self::ContentsStoreError>>;
+
+    /// Start the disappearing timer of a message, which then expires at `expires_at`
+    ///
+    /// A timer which was already started is kept, and nothing happens if the message is not
+    /// saved. Expiry times are deleted along with the message.
+    fn set_message_expiry(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        expires_at: u64,
+    ) -> impl Future<Output = Result<(), Self::ContentsStoreError>>;
+
+    /// Get an iterator on all messages whose disappearing timer was started, soonest to expire
+    /// first
+    fn expiring_messages(
+        &self,
+    ) -> impl Future<Output = Result<Self::ExpiringMessagesIter, Self::ContentsStoreError>>;
+
+    /// Get the reactions to a message, by the author of each reaction
+    ///
+    /// This includes removed reactions, see [`MessageReaction::removed`].
+    /// Reactions are deleted along with the message.
+    fn reactions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<BTreeMap<Uuid, MessageReaction>, Self::ContentsStoreError>>;
+
+    /// Set the reaction of `author` to a message, or remove it with `None`
+    ///
+    /// Everybody reacts at most once to a message: a new reaction replaces the previous one.
+    fn update_reaction(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        author: Uuid,
+        reaction: Option<MessageReaction>,
+    ) -> impl Future<Output = Result<(), Self::ContentsStoreError>>;
+
+    /// Get the previous revisions of an edited message, in the order they were added
+    ///
+    /// The first one is usually the original message, and the message itself is its latest
+    /// revision. Revisions are deleted along with the message.
+    fn message_revisions(
+        &self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<Vec<Content>, Self::ContentsStoreError>>;
+
+    /// Keep a previous revision of a message, before it is replaced by an edit
+    ///
+    /// This does nothing if the message is not saved.
+    fn add_message_revision(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+        revision: Content,
+    ) -> impl Future<Output = Result<(), Self::ContentsStoreError>>;
+
+    /// Get an iterator on all threads in which at least one message was saved
+    ///
+    /// Threads are listed with their title and recent activity, most recently active first.
+    fn threads(&self) -> impl Future<Output = Result<Self::ThreadsIter, Self::ContentsStoreError>>;
+
+    /// Get the recent activity of a thread, if any message was ever saved in it
+    fn thread_activity(
+        &self,
+        thread: &Thread,
+    ) -> impl Future<Output = Result<Option<ThreadActivity>, Self::ContentsStoreError>>;
+
+    /// Marks all messages of a thread up to `timestamp` (included) as read
+    ///
+    /// The read marker of a thread never moves backwards, and nothing happens if no message was
+    /// ever saved in the thread.
+    fn mark_thread_read(
+        &mut self,
+        thread: &Thread,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<(), Self::ContentsStoreError>>;
+
+    /// Returns the title of a thread: the name of the contact or the title of the group.
+    fn thread_title(
+        &self,
+        thread: &Thread,
+    ) -> impl Future<Output = Result<String, Self::ContentsStoreError>> {
+        async move {
+            match thread {
+                Thread::Contact(uuid) => {
+                    let contact = match self.contact_by_id(uuid).await {
+                        Ok(contact) => contact,
+                        Err(error) => {
+                            info!(%error, %uuid, "error getting contact by id");
+                            None
+                        }
+                    };
+                    Ok(match contact {
+                        Some(contact) => contact.name,
+                        None => uuid.to_string(),
+                    })
+                }
+                Thread::Group(id) => match self.group(*id).await? {
+                    Some(group) => Ok(group.title),
+                    None => Ok("".to_string()),
+                },
+            }
+        }
+    }
+
+    /// Search the text of messages across threads
+    ///
+    /// Results are ordered by timestamp, most recent first. See [`search`] for how messages are
+    /// matched.
+    fn search_messages(
+        &self,
+        search: &MessageSearch,
+    ) -> impl Future<Output = Result<Self::SearchResultsIter, Self::ContentsStoreError>>;
+
     /// Get the expire timer from a [Thread], which corresponds to either [Contact::expire_timer]
     /// or [Group::disappearing_messages_timer].
     fn expire_timer(
@@ -253,6 +466,11 @@ pub trait ContentsStore: Send + Sync {
         uuid: &Uuid,
     ) -> impl Future<Output = Result<Option<ProfileKey>, Self::ContentsStoreError>>;
 
+    /// Get an iterator on all stored profile keys
+    fn profile_keys(
+        &self,
+    ) -> impl Future<Output = Result<Self::ProfileKeysIter, Self::ContentsStoreError>>;
+
     /// Save a profile by [Uuid] and [ProfileKey].
     fn save_profile(
         &mut self,
@@ -313,14 +531,27 @@ pub trait ContentsStore: Send + Sync {
 pub trait Store:
     StateStore<StateStoreError = Self::Error>
     + ContentsStore<ContentsStoreError = Self::Error>
+    + AttachmentsStore<AttachmentsStoreError = Self::Error>
     + Send
     + Sync
     + Clone
     + 'static
 {
     type Error: StoreError;
-    type AciStore: ProtocolStore + PreKeysStore + SenderKeyStore + SessionStoreExt + Sync + Clone;
-    type PniStore: ProtocolStore + PreKeysStore + SenderKeyStore + SessionStoreExt + Sync + Clone;
+    type AciStore: ProtocolStore
+        + PreKeysStore
+        + SenderKeyStore
+        + SessionStoreExt
+        + ProtocolStoreExt
+        + Sync
+        + Clone;
+    type PniStore: ProtocolStore
+        + PreKeysStore
+        + SenderKeyStore
+        + SessionStoreExt
+        + ProtocolStoreExt
+        + Sync
+        + Clone;
 
     /// Clear the entire store
     ///
@@ -334,6 +565,53 @@ pub trait Store:
     fn pni_protocol_store(&self) -> Self::PniStore;
 }
 
+/// Bulk access to all the records of a protocol store
+///
+/// This is what allows copying the cryptographic state of an account from one store to another,
+/// see [`migration::migrate`].
+pub trait ProtocolStoreExt:
+    ProtocolStore + PreKeysStore + KyberPreKeyStoreExt + SenderKeyStore
+{
+    /// Get all sessions, with the address of the device they were established with
+    fn all_sessions(
+        &self,
+    ) -> impl Future<Output = Result<Vec<(ProtocolAddress, SessionRecord)>, SignalProtocolError>>;
+
+    /// Get all known identity keys of other users
+    fn all_identities(
+        &self,
+    ) -> impl Future<Output = Result<Vec<(ProtocolAddress, IdentityKey)>, SignalProtocolError>>;
+
+    /// Save the identity key of another user as-is
+    ///
+    /// Unlike [`IdentityKeyStore::save_identity`](libsignal_service::protocol::IdentityKeyStore::save_identity),
+    /// this never saves a message about the identity having changed.
+    fn restore_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity_key: &IdentityKey,
+    ) -> impl Future<Output = Result<(), SignalProtocolError>>;
+
+    /// Get all pre-keys
+    fn all_pre_keys(&self) -> impl Future<Output = Result<Vec<PreKeyRecord>, SignalProtocolError>>;
+
+    /// Get all signed pre-keys
+    fn all_signed_pre_keys(
+        &self,
+    ) -> impl Future<Output = Result<Vec<SignedPreKeyRecord>, SignalProtocolError>>;
+
+    /// Get all one-time Kyber pre-keys, or all last-resort ones
+    fn all_kyber_pre_keys(
+        &self,
+        last_resort: bool,
+    ) -> impl Future<Output = Result<Vec<KyberPreKeyRecord>, SignalProtocolError>>;
+
+    /// Get all sender keys, with their sender and distribution id
+    fn all_sender_keys(
+        &self,
+    ) -> impl Future<Output = Result<Vec<(ProtocolAddress, Uuid, SenderKeyRecord)>, SignalProtocolError>>;
+}
+
 /// A thread specifies where a message was sent, either to or from a contact or in a group.
 #[derive(Debug, Hash, Eq, PartialEq, Clone, Deserialize, Serialize)]
 pub enum Thread {
@@ -441,18 +719,35 @@ impl TryFrom<&Content> for Thread {
 }
 
 /// Extension trait of [`Content`]
+///
+/// Stored messages may be data messages, sync messages or edits, and these methods read them
+/// all the same way.
 pub trait ContentExt {
     fn timestamp(&self) -> u64;
+
+    /// The data message of the message, if any
+    fn data_message(&self) -> Option<&DataMessage>;
+
+    /// The text of the message, if any
+    fn text(&self) -> Option<&str>;
+
+    /// Whether the message is an edit, which is how edited messages are saved
+    fn is_edited(&self) -> bool;
 }
 
 impl ContentExt for Content {
     /// The original timestamp of the message.
     fn timestamp(&self) -> u64 {
         match self.body {
+            // edits are saved in place of the message they edit
             ContentBody::SynchronizeMessage(SyncMessage {
                 sent:
                     Some(sync_message::Sent {
-                        timestamp: Some(ts),
+                        edit_message:
+                            Some(EditMessage {
+                                target_sent_timestamp: Some(ts),
+                                ..
+                            }),
                         ..
                     }),
                 ..
@@ -460,11 +755,7 @@ impl ContentExt for Content {
             ContentBody::SynchronizeMessage(SyncMessage {
                 sent:
                     Some(sync_message::Sent {
-                        edit_message:
-                            Some(EditMessage {
-                                target_sent_timestamp: Some(ts),
-                                ..
-                            }),
+                        timestamp: Some(ts),
                         ..
                     }),
                 ..
@@ -476,6 +767,57 @@ impl ContentExt for Content {
             _ => self.metadata.timestamp,
         }
     }
+
+    /// The data message, either received, sent from another device, or edited.
+    fn data_message(&self) -> Option<&DataMessage> {
+        match &self.body {
+            ContentBody::DataMessage(data_message) => Some(data_message),
+            ContentBody::EditMessage(EditMessage {
+                data_message: Some(data_message),
+                ..
+            }) => Some(data_message),
+            ContentBody::SynchronizeMessage(SyncMessage {
+                sent:
+                    Some(sync_message::Sent {
+                        message: Some(data_message),
+                        ..
+                    }),
+                ..
+            }) => Some(data_message),
+            ContentBody::SynchronizeMessage(SyncMessage {
+                sent:
+                    Some(sync_message::Sent {
+                        edit_message:
+                            Some(EditMessage {
+                                data_message: Some(data_message),
+                                ..
+                            }),
+                        ..
+                    }),
+                ..
+            }) => Some(data_message),
+            _ => None,
+        }
+    }
+
+    /// The body of a data message, either received, sent from another device, or edited.
+    fn text(&self) -> Option<&str> {
+        self.data_message()?.body.as_deref()
+    }
+
+    fn is_edited(&self) -> bool {
+        matches!(
+            self.body,
+            ContentBody::EditMessage(_)
+                | ContentBody::SynchronizeMessage(SyncMessage {
+                    sent: Some(sync_message::Sent {
+                        edit_message: Some(_),
+                        ..
+                    }),
+                    ..
+                })
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
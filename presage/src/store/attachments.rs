@@ -0,0 +1,45 @@
+//! Downloaded attachments
+//!
+//! Attachments are only downloaded once, then decrypted to a file whose location is kept in the
+//! [`AttachmentsStore`](super::AttachmentsStore), see
+//! [`Manager::get_attachment`](crate::Manager::get_attachment).
+
+use std::{fmt, path::PathBuf};
+
+use libsignal_service::proto::AttachmentPointer;
+use serde::{Deserialize, Serialize};
+
+/// Identifies an attachment by the digest of its ciphertext
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AttachmentId(String);
+
+impl AttachmentId {
+    pub fn from_digest(digest: &[u8]) -> Self {
+        Self(hex::encode(digest))
+    }
+
+    /// The id of the attachment a pointer points to, if the pointer has a digest
+    pub fn of(attachment_pointer: &AttachmentPointer) -> Option<Self> {
+        attachment_pointer.digest.as_deref().map(Self::from_digest)
+    }
+
+    /// The hex-encoded digest
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AttachmentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A downloaded attachment, decrypted to a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadedAttachment {
+    /// Path of the decrypted attachment
+    pub path: PathBuf,
+    /// Length of the decrypted attachment in bytes
+    pub size: u64,
+}
@@ -0,0 +1,54 @@
+//! Disappearing messages
+//!
+//! The disappearing timer of a message starts when it is sent, or when it is read for messages
+//! received from others. Stores keep the time at which each message expires, see
+//! [`ContentsStore::set_message_expiry`](super::ContentsStore::set_message_expiry), and the
+//! [`Manager`](crate::Manager) deletes expired messages.
+
+use libsignal_service::{
+    content::{Content, ContentBody},
+    proto::{sync_message::Sent, DataMessage, SyncMessage},
+};
+
+use super::Thread;
+
+/// A message whose disappearing timer was started
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiringMessage {
+    pub thread: Thread,
+    /// Timestamp of the message
+    pub timestamp: u64,
+    /// Time at which the message expires, in milliseconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// The disappearing timer of a message, in seconds, if it disappears
+///
+/// This is the timer of the thread at the time the message was sent.
+pub fn expire_timer(message: &Content) -> Option<u32> {
+    let data_message = match &message.body {
+        ContentBody::DataMessage(data_message)
+        | ContentBody::SynchronizeMessage(SyncMessage {
+            sent:
+                Some(Sent {
+                    message: Some(data_message),
+                    ..
+                }),
+            ..
+        }) => data_message,
+        _ => return None,
+    };
+    match data_message {
+        DataMessage {
+            expire_timer: Some(timer),
+            ..
+        } if *timer > 0 => Some(*timer),
+        _ => None,
+    }
+}
+
+/// Time at which a message expires, when its disappearing timer starts at `started_at`
+pub fn expires_at(message: &Content, started_at: u64) -> Option<u64> {
+    let timer = expire_timer(message)?;
+    Some(started_at.saturating_add(u64::from(timer) * 1000))
+}
@@ -0,0 +1,487 @@
+//! Copy all the data of an account from a [`Store`] to another
+//!
+//! This allows switching from one store implementation to another (e.g. from sled to SQLite)
+//! without having to link or register again.
+//!
+//! Downloaded attachments are not copied: they are a cache, and are downloaded again on demand.
+
+use std::{collections::BTreeMap, fmt};
+
+use libsignal_service::{
+    pre_keys::KyberPreKeyStoreExt,
+    protocol::{
+        GenericSignedPreKey, IdentityKeyPair, IdentityKeyStore, KyberPreKeyStore, PreKeyStore,
+        SenderKeyStore, SessionStore, SignalProtocolError, SignedPreKeyStore,
+    },
+};
+use tracing::{debug, info};
+
+use super::{
+    expiry::ExpiringMessage, threads::ThreadSummary, ContentExt, ContentsStore, ProtocolStoreExt,
+    StateStore, Store, StoreError,
+};
+
+/// The kinds of records copied by [`migrate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecordCategory {
+    RegistrationData,
+    IdentityKeyPairs,
+    Sessions,
+    Identities,
+    PreKeys,
+    SignedPreKeys,
+    KyberPreKeys,
+    LastResortKyberPreKeys,
+    SenderKeys,
+    Contacts,
+    Groups,
+    GroupAvatars,
+    Messages,
+    ProfileKeys,
+    Profiles,
+    ProfileAvatars,
+    StickerPacks,
+}
+
+impl fmt::Display for RecordCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::RegistrationData => "registration data",
+            Self::IdentityKeyPairs => "identity key pairs",
+            Self::Sessions => "sessions",
+            Self::Identities => "identities",
+            Self::PreKeys => "pre-keys",
+            Self::SignedPreKeys => "signed pre-keys",
+            Self::KyberPreKeys => "kyber pre-keys",
+            Self::LastResortKyberPreKeys => "last resort kyber pre-keys",
+            Self::SenderKeys => "sender keys",
+            Self::Contacts => "contacts",
+            Self::Groups => "groups",
+            Self::GroupAvatars => "group avatars",
+            Self::Messages => "messages",
+            Self::ProfileKeys => "profile keys",
+            Self::Profiles => "profiles",
+            Self::ProfileAvatars => "profile avatars",
+            Self::StickerPacks => "sticker packs",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Number of records per category, as found in the source store of a migration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    counts: BTreeMap<RecordCategory, usize>,
+}
+
+impl MigrationReport {
+    /// Number of records of a given category
+    pub fn count(&self, category: RecordCategory) -> usize {
+        self.counts.get(&category).copied().unwrap_or_default()
+    }
+
+    /// Iterate over all categories with their number of records
+    pub fn iter(&self) -> impl Iterator<Item = (RecordCategory, usize)> + '_ {
+        self.counts
+            .iter()
+            .map(|(category, count)| (*category, *count))
+    }
+
+    fn add(&mut self, category: RecordCategory, count: usize) {
+        *self.counts.entry(category).or_default() += count;
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError<S: StoreError, D: StoreError> {
+    #[error("the source store is not registered or linked")]
+    SourceNotRegistered,
+    #[error("the destination store is not empty")]
+    DestinationNotEmpty,
+    #[error("source store error: {0}")]
+    Source(S),
+    #[error("destination store error: {0}")]
+    Destination(D),
+    #[error("protocol error: {0}")]
+    Protocol(#[from] SignalProtocolError),
+    #[error("found {expected} {category} in the source store but only {actual} were migrated")]
+    CountMismatch {
+        category: RecordCategory,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// Copy all the data of an account from `source` into `destination`
+///
+/// The destination store must not be registered. Both the ACI and PNI protocol stores are
+/// copied along with registration data and all contents (contacts, groups, messages, profiles
+/// and sticker packs). Registration data is written after everything but messages, so a failed
+/// migration never leaves a destination that looks usable: messages come last so that the
+/// destination knows which of them we sent when counting unread messages, and registration data
+/// is cleared again if copying them fails.
+///
+/// Once everything is copied, records are counted again in the destination store and the
+/// migration fails if any category has fewer records than in the source. The destination may
+/// end up with more records than the source when it shares some of them between identities
+/// (the sled store keeps a single table of identities for ACI and PNI).
+pub async fn migrate<S: Store, D: Store>(
+    source: &S,
+    destination: &mut D,
+) -> Result<MigrationReport, MigrationError<S::Error, D::Error>> {
+    if destination.is_registered().await {
+        return Err(MigrationError::DestinationNotEmpty);
+    }
+
+    let registration_data = source
+        .load_registration_data()
+        .await
+        .map_err(MigrationError::Source)?
+        .ok_or(MigrationError::SourceNotRegistered)?;
+
+    let expected = count_records(source, MigrationError::Source).await?;
+    info!(
+        "migrating {} records",
+        expected.counts.values().sum::<usize>()
+    );
+
+    // protocol stores
+    if let Some(key_pair) = identity_key_pair(&source.aci_protocol_store()).await? {
+        destination
+            .set_aci_identity_key_pair(key_pair)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+    if let Some(key_pair) = identity_key_pair(&source.pni_protocol_store()).await? {
+        destination
+            .set_pni_identity_key_pair(key_pair)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+    copy_protocol_store(
+        &source.aci_protocol_store(),
+        &mut destination.aci_protocol_store(),
+    )
+    .await?;
+    copy_protocol_store(
+        &source.pni_protocol_store(),
+        &mut destination.pni_protocol_store(),
+    )
+    .await?;
+    debug!("migrated protocol stores");
+
+    // contents
+    for contact in source.contacts().await.map_err(MigrationError::Source)? {
+        let contact = contact.map_err(MigrationError::Source)?;
+        destination
+            .save_contact(&contact)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+
+    for group in source.groups().await.map_err(MigrationError::Source)? {
+        let (master_key, group) = group.map_err(MigrationError::Source)?;
+        destination
+            .save_group(master_key, group)
+            .await
+            .map_err(MigrationError::Destination)?;
+        if let Some(avatar) = source
+            .group_avatar(master_key)
+            .await
+            .map_err(MigrationError::Source)?
+        {
+            destination
+                .save_group_avatar(master_key, &avatar)
+                .await
+                .map_err(MigrationError::Destination)?;
+        }
+    }
+
+    for profile_key in source
+        .profile_keys()
+        .await
+        .map_err(MigrationError::Source)?
+    {
+        let (uuid, key) = profile_key.map_err(MigrationError::Source)?;
+        destination
+            .upsert_profile_key(&uuid, key)
+            .await
+            .map_err(MigrationError::Destination)?;
+        if let Some(profile) = source
+            .profile(uuid, key)
+            .await
+            .map_err(MigrationError::Source)?
+        {
+            destination
+                .save_profile(uuid, key, profile)
+                .await
+                .map_err(MigrationError::Destination)?;
+        }
+        if let Some(avatar) = source
+            .profile_avatar(uuid, key)
+            .await
+            .map_err(MigrationError::Source)?
+        {
+            destination
+                .save_profile_avatar(uuid, key, &avatar)
+                .await
+                .map_err(MigrationError::Destination)?;
+        }
+    }
+
+    for sticker_pack in source
+        .sticker_packs()
+        .await
+        .map_err(MigrationError::Source)?
+    {
+        let sticker_pack = sticker_pack.map_err(MigrationError::Source)?;
+        destination
+            .add_sticker_pack(&sticker_pack)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+    debug!("migrated contents");
+
+    destination
+        .save_registration_data(&registration_data)
+        .await
+        .map_err(MigrationError::Destination)?;
+
+    if let Err(error) = copy_messages(source, destination).await {
+        destination
+            .clear_registration()
+            .await
+            .map_err(MigrationError::Destination)?;
+        return Err(error);
+    }
+    debug!("migrated messages");
+
+    let actual = count_records(destination, MigrationError::Destination).await?;
+    for (category, expected) in expected.iter() {
+        let actual = actual.count(category);
+        if actual < expected {
+            return Err(MigrationError::CountMismatch {
+                category,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    info!("migration complete");
+    Ok(expected)
+}
+
+/// Identity key pair of a protocol store, if any was ever set
+async fn identity_key_pair(
+    store: &impl IdentityKeyStore,
+) -> Result<Option<IdentityKeyPair>, SignalProtocolError> {
+    match store.get_identity_key_pair().await {
+        Ok(key_pair) => Ok(Some(key_pair)),
+        Err(SignalProtocolError::InvalidState(..)) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+async fn copy_protocol_store(
+    from: &impl ProtocolStoreExt,
+    to: &mut impl ProtocolStoreExt,
+) -> Result<(), SignalProtocolError> {
+    for (address, record) in from.all_sessions().await? {
+        to.store_session(&address, &record).await?;
+    }
+    for (address, identity_key) in from.all_identities().await? {
+        to.restore_identity(&address, &identity_key).await?;
+    }
+    for record in from.all_pre_keys().await? {
+        to.save_pre_key(record.id()?, &record).await?;
+    }
+    for record in from.all_signed_pre_keys().await? {
+        to.save_signed_pre_key(record.id()?, &record).await?;
+    }
+    for record in from.all_kyber_pre_keys(false).await? {
+        to.save_kyber_pre_key(record.id()?, &record).await?;
+    }
+    for record in from.all_kyber_pre_keys(true).await? {
+        to.store_last_resort_kyber_pre_key(record.id()?, &record)
+            .await?;
+    }
+    for (sender, distribution_id, record) in from.all_sender_keys().await? {
+        to.store_sender_key(&sender, distribution_id, &record)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn count_protocol_records(
+    store: &impl ProtocolStoreExt,
+    report: &mut MigrationReport,
+) -> Result<(), SignalProtocolError> {
+    if identity_key_pair(store).await?.is_some() {
+        report.add(RecordCategory::IdentityKeyPairs, 1);
+    }
+    report.add(RecordCategory::Sessions, store.all_sessions().await?.len());
+    report.add(
+        RecordCategory::Identities,
+        store.all_identities().await?.len(),
+    );
+    report.add(RecordCategory::PreKeys, store.all_pre_keys().await?.len());
+    report.add(
+        RecordCategory::SignedPreKeys,
+        store.all_signed_pre_keys().await?.len(),
+    );
+    report.add(
+        RecordCategory::KyberPreKeys,
+        store.all_kyber_pre_keys(false).await?.len(),
+    );
+    report.add(
+        RecordCategory::LastResortKyberPreKeys,
+        store.all_kyber_pre_keys(true).await?.len(),
+    );
+    report.add(
+        RecordCategory::SenderKeys,
+        store.all_sender_keys().await?.len(),
+    );
+    Ok(())
+}
+
+async fn copy_messages<S: Store, D: Store>(
+    source: &S,
+    destination: &mut D,
+) -> Result<(), MigrationError<S::Error, D::Error>> {
+    for summary in source.threads().await.map_err(MigrationError::Source)? {
+        let ThreadSummary {
+            thread, activity, ..
+        } = summary.map_err(MigrationError::Source)?;
+        for message in source
+            .messages(&thread, ..)
+            .await
+            .map_err(MigrationError::Source)?
+        {
+            let message = message.map_err(MigrationError::Source)?;
+            let timestamp = message.timestamp();
+            destination
+                .save_message(&thread, message)
+                .await
+                .map_err(MigrationError::Destination)?;
+            for (recipient, state) in source
+                .delivery_states(&thread, timestamp)
+                .await
+                .map_err(MigrationError::Source)?
+            {
+                destination
+                    .update_delivery_state(&thread, timestamp, recipient, state)
+                    .await
+                    .map_err(MigrationError::Destination)?;
+            }
+            for (author, reaction) in source
+                .reactions(&thread, timestamp)
+                .await
+                .map_err(MigrationError::Source)?
+            {
+                destination
+                    .update_reaction(&thread, timestamp, author, Some(reaction))
+                    .await
+                    .map_err(MigrationError::Destination)?;
+            }
+            for revision in source
+                .message_revisions(&thread, timestamp)
+                .await
+                .map_err(MigrationError::Source)?
+            {
+                destination
+                    .add_message_revision(&thread, timestamp, revision)
+                    .await
+                    .map_err(MigrationError::Destination)?;
+            }
+        }
+        destination
+            .mark_thread_read(&thread, activity.last_read_timestamp)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+    for expiring in source
+        .expiring_messages()
+        .await
+        .map_err(MigrationError::Source)?
+    {
+        let ExpiringMessage {
+            thread,
+            timestamp,
+            expires_at,
+        } = expiring.map_err(MigrationError::Source)?;
+        destination
+            .set_message_expiry(&thread, timestamp, expires_at)
+            .await
+            .map_err(MigrationError::Destination)?;
+    }
+    Ok(())
+}
+
+/// Count all records of a store, mapping its errors with `map_err`
+async fn count_records<St: Store, S: StoreError, D: StoreError>(
+    store: &St,
+    map_err: impl Fn(St::Error) -> MigrationError<S, D>,
+) -> Result<MigrationReport, MigrationError<S, D>> {
+    let mut report = MigrationReport::default();
+
+    if store
+        .load_registration_data()
+        .await
+        .map_err(&map_err)?
+        .is_some()
+    {
+        report.add(RecordCategory::RegistrationData, 1);
+    }
+    count_protocol_records(&store.aci_protocol_store(), &mut report).await?;
+    count_protocol_records(&store.pni_protocol_store(), &mut report).await?;
+
+    for contact in store.contacts().await.map_err(&map_err)? {
+        contact.map_err(&map_err)?;
+        report.add(RecordCategory::Contacts, 1);
+    }
+
+    for group in store.groups().await.map_err(&map_err)? {
+        let (master_key, _) = group.map_err(&map_err)?;
+        report.add(RecordCategory::Groups, 1);
+        if store
+            .group_avatar(master_key)
+            .await
+            .map_err(&map_err)?
+            .is_some()
+        {
+            report.add(RecordCategory::GroupAvatars, 1);
+        }
+    }
+
+    for thread in store.threads().await.map_err(&map_err)? {
+        let thread = thread.map_err(&map_err)?.thread;
+        for message in store.messages(&thread, ..).await.map_err(&map_err)? {
+            message.map_err(&map_err)?;
+            report.add(RecordCategory::Messages, 1);
+        }
+    }
+
+    for profile_key in store.profile_keys().await.map_err(&map_err)? {
+        let (uuid, key) = profile_key.map_err(&map_err)?;
+        report.add(RecordCategory::ProfileKeys, 1);
+        if store.profile(uuid, key).await.map_err(&map_err)?.is_some() {
+            report.add(RecordCategory::Profiles, 1);
+        }
+        if store
+            .profile_avatar(uuid, key)
+            .await
+            .map_err(&map_err)?
+            .is_some()
+        {
+            report.add(RecordCategory::ProfileAvatars, 1);
+        }
+    }
+
+    for sticker_pack in store.sticker_packs().await.map_err(&map_err)? {
+        sticker_pack.map_err(&map_err)?;
+        report.add(RecordCategory::StickerPacks, 1);
+    }
+
+    Ok(report)
+}
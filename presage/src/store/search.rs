@@ -0,0 +1,110 @@
+//! Full-text search of messages
+//!
+//! Stores index the text of messages (see [`ContentExt::text`]) split into words by [`tokenize`].
+//! A message matches a [`MessageSearch`] when it contains every word of the query, regardless of
+//! their case and order.
+
+use libsignal_service::{content::Content, prelude::Uuid};
+
+use super::{ContentExt, Thread};
+
+/// Criteria of a full-text search, see [`ContentsStore::search_messages`](super::ContentsStore::search_messages)
+#[derive(Debug, Clone, Default)]
+pub struct MessageSearch {
+    /// Words that must all appear in the text of a message
+    pub query: String,
+    /// Only search messages of this thread
+    pub thread: Option<Thread>,
+    /// Only search messages sent by this user
+    pub sender: Option<Uuid>,
+    /// Only search messages sent at or after this timestamp (in milliseconds)
+    pub from: Option<u64>,
+    /// Only search messages sent at or before this timestamp (in milliseconds)
+    pub until: Option<u64>,
+}
+
+impl MessageSearch {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The words of the query, deduplicated
+    ///
+    /// A search without any word never matches anything.
+    pub fn terms(&self) -> Vec<String> {
+        let mut terms = tokenize(&self.query);
+        terms.sort_unstable();
+        terms.dedup();
+        terms
+    }
+
+    /// Whether the timestamp of a message is within the date range of the search
+    pub fn in_range(&self, timestamp: u64) -> bool {
+        self.from.map_or(true, |from| timestamp >= from)
+            && self.until.map_or(true, |until| timestamp <= until)
+    }
+
+    /// Whether a message saved in `thread` matches all criteria of the search
+    pub fn matches(&self, thread: &Thread, content: &Content) -> bool {
+        if self.thread.as_ref().is_some_and(|t| t != thread)
+            || self
+                .sender
+                .is_some_and(|sender| sender != content.metadata.sender.raw_uuid())
+            || !self.in_range(content.timestamp())
+        {
+            return false;
+        }
+
+        let Some(text) = content.text() else {
+            return false;
+        };
+        let words = tokenize(text);
+        let terms = self.terms();
+        !terms.is_empty() && terms.iter().all(|term| words.contains(term))
+    }
+}
+
+/// Splits a text into lowercase words
+///
+/// Words are sequences of alphanumeric characters, everything else is a separator.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_words() {
+        assert_eq!(
+            tokenize("Hello, World! it's 2 o'clock"),
+            ["hello", "world", "it", "s", "2", "o", "clock"]
+        );
+        assert!(tokenize(" ,;- ").is_empty());
+    }
+
+    #[test]
+    fn terms_are_deduplicated() {
+        assert_eq!(MessageSearch::new("b a B").terms(), ["a", "b"]);
+    }
+
+    #[test]
+    fn date_range() {
+        let search = MessageSearch {
+            from: Some(10),
+            until: Some(20),
+            ..MessageSearch::new("a")
+        };
+        assert!(!search.in_range(9));
+        assert!(search.in_range(10));
+        assert!(search.in_range(20));
+        assert!(!search.in_range(21));
+    }
+}
@@ -0,0 +1,93 @@
+//! Conformance tests for [`Store`] implementations
+//!
+//! This module is only available with the `testing` feature. It exercises the whole
+//! [`StateStore`], [`ContentsStore`], [`AttachmentsStore`] and protocol store contract against
+//! stores created by a factory, so any implementation can check that it behaves like the
+//! reference `sled` store:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn store_conformance() {
+//!     presage::store::testing::run_all(|| async { MyStore::temporary().await.unwrap() }).await;
+//! }
+//! ```
+//!
+//! Every property is checked against a fresh store with randomly generated inputs. The number of
+//! runs per property can be tuned with the `QUICKCHECK_TESTS` environment variable.
+//!
+//! [`StateStore`]: crate::store::StateStore
+//! [`ContentsStore`]: crate::store::ContentsStore
+//! [`AttachmentsStore`]: crate::store::AttachmentsStore
+
+use std::{fmt, future::Future, panic::AssertUnwindSafe};
+
+use futures::FutureExt;
+use quickcheck::{Arbitrary, Gen};
+use tracing::debug;
+
+use crate::store::Store;
+
+mod attachments;
+mod contents;
+mod fixtures;
+mod migration;
+mod protocol;
+mod state;
+
+pub use attachments::attachments_contract;
+pub use contents::contents_contract;
+pub use migration::migration_contract;
+pub use protocol::protocol_contract;
+pub use state::state_contract;
+
+const DEFAULT_TESTS: usize = 20;
+const GEN_SIZE: usize = 32;
+
+/// Runs every conformance property against stores created by `new_store`.
+///
+/// Panics on the first property that does not hold, with the offending input.
+pub async fn run_all<S, F, Fut>(new_store: F)
+where
+    S: Store,
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+{
+    protocol_contract(&new_store).await;
+    state_contract(&new_store).await;
+    contents_contract(&new_store).await;
+    attachments_contract(&new_store).await;
+    migration_contract(&new_store, &new_store).await;
+}
+
+/// Checks `property` against freshly created stores and random inputs.
+async fn check<S, F, Fut, T, P, PFut>(new_store: &F, name: &str, property: P)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+    T: Arbitrary + fmt::Debug,
+    P: Fn(S, T) -> PFut,
+    PFut: Future<Output = ()>,
+{
+    let tests = std::env::var("QUICKCHECK_TESTS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_TESTS);
+    let mut g = Gen::new(GEN_SIZE);
+
+    debug!(name, tests, "checking store property");
+    for _ in 0..tests {
+        let input = T::arbitrary(&mut g);
+        let store = new_store().await;
+        if let Err(panic) = AssertUnwindSafe(property(store, input.clone()))
+            .catch_unwind()
+            .await
+        {
+            let reason = panic
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| panic.downcast_ref::<&str>().copied())
+                .unwrap_or("unknown reason");
+            panic!("store property `{name}` failed with input {input:?}: {reason}");
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Properties of the [`AttachmentsStore`]
+
+use std::future::Future;
+
+use super::{
+    check,
+    fixtures::{ArbContent, ArbDownloadedAttachment, ArbThread},
+};
+use crate::store::{attachments::AttachmentId, AttachmentsStore, ContentsStore, Store};
+
+/// Checks the [`AttachmentsStore`] contract.
+pub async fn attachments_contract<S, F, Fut>(new_store: &F)
+where
+    S: Store,
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+{
+    check(new_store, "attachments", attachment_roundtrip).await;
+    check(new_store, "clear attachments", clear_attachments).await;
+}
+
+async fn attachment_roundtrip<S: Store>(
+    mut store: S,
+    (digest, attachment, replacement): (Vec<u8>, ArbDownloadedAttachment, ArbDownloadedAttachment),
+) {
+    let id = AttachmentId::from_digest(&digest);
+    assert!(store.attachment(&id).await.unwrap().is_none());
+
+    store.save_attachment(&id, &attachment.0).await.unwrap();
+    assert_eq!(store.attachment(&id).await.unwrap(), Some(attachment.0));
+
+    store.save_attachment(&id, &replacement.0).await.unwrap();
+    assert_eq!(store.attachment(&id).await.unwrap(), Some(replacement.0));
+
+    assert!(store.remove_attachment(&id).await.unwrap());
+    assert!(!store.remove_attachment(&id).await.unwrap());
+    assert!(store.attachment(&id).await.unwrap().is_none());
+}
+
+async fn clear_attachments<S: Store>(
+    mut store: S,
+    (digests, attachment, thread, content): (
+        Vec<Vec<u8>>,
+        ArbDownloadedAttachment,
+        ArbThread,
+        ArbContent,
+    ),
+) {
+    store
+        .save_message(&thread.0, content.0.clone())
+        .await
+        .unwrap();
+    let ids: Vec<AttachmentId> = digests
+        .iter()
+        .map(|digest| AttachmentId::from_digest(digest))
+        .collect();
+    for id in &ids {
+        store.save_attachment(id, &attachment.0).await.unwrap();
+    }
+
+    store.clear_attachments().await.unwrap();
+    for id in &ids {
+        assert!(store.attachment(id).await.unwrap().is_none());
+    }
+    assert!(store
+        .message(&thread.0, content.0.metadata.timestamp)
+        .await
+        .unwrap()
+        .is_some());
+}
@@ -0,0 +1,873 @@
+//! Properties of the [`ContentsStore`]
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+};
+
+use libsignal_service::prelude::Uuid;
+
+use super::{
+    check,
+    fixtures::{
+        all_messages, assert_same_message, register, sent_by, ArbContact, ArbContent, ArbGroup,
+        ArbProfileKey, ArbStickerPack, ArbThread, ArbUuid, DistinctThreads, DistinctUuids,
+    },
+};
+use crate::{
+    model::messages::{DeliveryState, MessageReaction},
+    store::{
+        expiry::ExpiringMessage,
+        search::MessageSearch,
+        threads::{ThreadActivity, ThreadSummary, PREVIEW_LENGTH},
+        ContentExt, ContentsStore, Store, Thread,
+    },
+};
+
+/// Checks the [`ContentsStore`] contract.
+pub async fn contents_contract<S, F, Fut>(new_store: &F)
+where
+    S: Store,
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+{
+    check(new_store, "message", message_roundtrip).await;
+    check(new_store, "message ranges", message_ranges).await;
+    check(new_store, "delete message", delete_message).await;
+    check(new_store, "clear thread", clear_thread).await;
+    check(new_store, "threads", threads_listing).await;
+    check(new_store, "thread activity", thread_activity).await;
+    check(new_store, "read markers", read_markers).await;
+    check(new_store, "delivery states", delivery_states).await;
+    check(new_store, "message expiry", message_expiry).await;
+    check(new_store, "reactions", reactions).await;
+    check(new_store, "message revisions", message_revisions).await;
+    check(new_store, "search messages", search_messages).await;
+    check(new_store, "contacts", contact_roundtrip).await;
+    check(new_store, "groups", group_roundtrip).await;
+    check(new_store, "profile keys", profile_key_roundtrip).await;
+    check(new_store, "profile avatars", profile_avatar_roundtrip).await;
+    check(new_store, "sticker packs", sticker_pack_roundtrip).await;
+}
+
+async fn message_roundtrip<S: Store>(store: S, (thread, content): (ArbThread, ArbContent)) {
+    let timestamp = content.0.metadata.timestamp;
+    assert!(store.message(&thread.0, timestamp).await.unwrap().is_none());
+    store
+        .save_message(&thread.0, content.0.clone())
+        .await
+        .unwrap();
+    let loaded = store
+        .message(&thread.0, timestamp)
+        .await
+        .unwrap()
+        .expect("saved message");
+    assert_same_message(&loaded, &content.0);
+}
+
+async fn message_ranges<S: Store>(store: S, (thread, content): (ArbThread, ArbContent)) {
+    let thread = thread.0;
+    for ts in [1678295210, 1678295220, 1678295230, 1678295240, 1678280000] {
+        store
+            .save_message(&thread, content.with_timestamp(ts))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(store.messages(&thread, ..).await.unwrap().count(), 5);
+    assert_eq!(store.messages(&thread, 0..).await.unwrap().count(), 5);
+    assert_eq!(
+        store.messages(&thread, 1678280000..).await.unwrap().count(),
+        5
+    );
+    assert_eq!(
+        store
+            .messages(&thread, 0..1678280000)
+            .await
+            .unwrap()
+            .count(),
+        0
+    );
+    assert_eq!(
+        store
+            .messages(&thread, 0..1678295210)
+            .await
+            .unwrap()
+            .count(),
+        1
+    );
+    assert_eq!(
+        store
+            .messages(&thread, 1678295210..1678295240)
+            .await
+            .unwrap()
+            .count(),
+        3
+    );
+    assert_eq!(
+        store
+            .messages(&thread, 1678295210..=1678295240)
+            .await
+            .unwrap()
+            .count(),
+        4
+    );
+    assert_eq!(
+        store.messages(&thread, ..=u64::MAX).await.unwrap().count(),
+        5
+    );
+    assert_eq!(
+        store
+            .messages(&thread, 1678295230..u64::MAX)
+            .await
+            .unwrap()
+            .count(),
+        2
+    );
+
+    let timestamps: Vec<u64> = store
+        .messages(&thread, ..)
+        .await
+        .unwrap()
+        .map(|message| message.unwrap().metadata.timestamp)
+        .collect();
+    assert_eq!(
+        timestamps,
+        [1678280000, 1678295210, 1678295220, 1678295230, 1678295240],
+        "messages are sorted by timestamp"
+    );
+
+    let other_thread = Thread::Contact(Uuid::new_v4());
+    assert_eq!(store.messages(&other_thread, ..).await.unwrap().count(), 0);
+}
+
+async fn delete_message<S: Store>(mut store: S, (thread, content): (ArbThread, ArbContent)) {
+    let timestamp = content.0.metadata.timestamp;
+    assert!(!store.delete_message(&thread.0, timestamp).await.unwrap());
+    store.save_message(&thread.0, content.0).await.unwrap();
+    assert!(store.delete_message(&thread.0, timestamp).await.unwrap());
+    assert!(store.message(&thread.0, timestamp).await.unwrap().is_none());
+}
+
+async fn clear_thread<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content): (DistinctThreads, ArbContent),
+) {
+    store
+        .save_message(&thread, content.0.clone())
+        .await
+        .unwrap();
+    store
+        .save_message(&other_thread, content.0.clone())
+        .await
+        .unwrap();
+
+    store.clear_thread(&thread).await.unwrap();
+    assert_eq!(store.messages(&thread, ..).await.unwrap().count(), 0);
+    assert_eq!(store.messages(&other_thread, ..).await.unwrap().count(), 1);
+
+    store.clear_messages().await.unwrap();
+    assert_eq!(store.messages(&other_thread, ..).await.unwrap().count(), 0);
+}
+
+async fn threads_listing<S: Store>(mut store: S, (threads, content): (Vec<ArbThread>, ArbContent)) {
+    assert_eq!(store.threads().await.unwrap().count(), 0);
+    for thread in &threads {
+        store
+            .save_message(&thread.0, content.0.clone())
+            .await
+            .unwrap();
+    }
+
+    let expected: HashSet<Thread> = threads.into_iter().map(|thread| thread.0).collect();
+    let listed: HashSet<Thread> = store
+        .threads()
+        .await
+        .unwrap()
+        .map(|summary| summary.unwrap().thread)
+        .collect();
+    assert_eq!(listed, expected);
+
+    if let Some(thread) = expected.iter().next() {
+        store.clear_thread(thread).await.unwrap();
+        assert!(!store
+            .threads()
+            .await
+            .unwrap()
+            .any(|listed| listed.unwrap().thread == *thread));
+    }
+
+    store.clear_messages().await.unwrap();
+    assert_eq!(store.threads().await.unwrap().count(), 0);
+}
+
+async fn thread_activity<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content, own_aci): (
+        DistinctThreads,
+        ArbContent,
+        ArbUuid,
+    ),
+) {
+    if own_aci.0 == content.0.metadata.sender.raw_uuid() {
+        return;
+    }
+    register(&mut store, own_aci.0).await;
+
+    let ts = content.0.timestamp();
+    let long_text = "a".repeat(PREVIEW_LENGTH + 1);
+    store
+        .save_message(&thread, content.with_text(ts, "first"))
+        .await
+        .unwrap();
+    store
+        .save_message(&thread, content.with_text(ts + 2, &long_text))
+        .await
+        .unwrap();
+    store
+        .save_message(&other_thread, content.with_text(ts + 1, "other"))
+        .await
+        .unwrap();
+    // replaced messages are not unread again
+    store
+        .save_message(&thread, content.with_text(ts, "first, edited"))
+        .await
+        .unwrap();
+    // our own messages are never unread
+    let own_message = sent_by(content.with_text(ts, "mine"), own_aci.0);
+    store
+        .save_message(&other_thread, own_message)
+        .await
+        .unwrap();
+
+    let summaries: Vec<ThreadSummary> =
+        store.threads().await.unwrap().map(Result::unwrap).collect();
+    // most recently active first
+    assert_eq!(
+        summaries
+            .iter()
+            .map(|summary| summary.thread.clone())
+            .collect::<Vec<_>>(),
+        [thread.clone(), other_thread.clone()]
+    );
+    assert_eq!(
+        summaries[0].title,
+        store.thread_title(&thread).await.unwrap()
+    );
+    assert_eq!(
+        summaries[0].activity,
+        ThreadActivity {
+            last_message_timestamp: ts + 2,
+            last_message_preview: Some("a".repeat(PREVIEW_LENGTH)),
+            unread_count: 2,
+            last_read_timestamp: 0,
+        }
+    );
+    assert_eq!(
+        summaries[1].activity,
+        ThreadActivity {
+            last_message_timestamp: ts + 1,
+            last_message_preview: Some("other".to_owned()),
+            unread_count: 1,
+            last_read_timestamp: 0,
+        }
+    );
+
+    // deleting the last message brings back the previous one
+    assert!(store.delete_message(&thread, ts + 2).await.unwrap());
+    let summary = store
+        .threads()
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|summary| summary.thread == thread)
+        .unwrap();
+    assert_eq!(summary.activity.last_message_timestamp, ts);
+    assert_eq!(
+        summary.activity.last_message_preview.as_deref(),
+        Some("first, edited")
+    );
+}
+
+async fn read_markers<S: Store>(
+    mut store: S,
+    (thread, content, own_aci): (ArbThread, ArbContent, ArbUuid),
+) {
+    if own_aci.0 == content.0.metadata.sender.raw_uuid() {
+        return;
+    }
+    register(&mut store, own_aci.0).await;
+
+    // nothing to mark as read in a thread without messages
+    store.mark_thread_read(&thread.0, 1).await.unwrap();
+    assert!(store.thread_activity(&thread.0).await.unwrap().is_none());
+
+    let ts = content.0.timestamp();
+    for offset in 0..3 {
+        store
+            .save_message(&thread.0, content.with_timestamp(ts + offset))
+            .await
+            .unwrap();
+    }
+    let own_message = sent_by(content.with_timestamp(ts + 3), own_aci.0);
+    store.save_message(&thread.0, own_message).await.unwrap();
+
+    let unread_count = |activity: Option<ThreadActivity>| {
+        let activity = activity.expect("thread activity");
+        (activity.last_read_timestamp, activity.unread_count)
+    };
+    assert_eq!(
+        unread_count(store.thread_activity(&thread.0).await.unwrap()),
+        (0, 3)
+    );
+
+    store.mark_thread_read(&thread.0, ts + 1).await.unwrap();
+    assert_eq!(
+        unread_count(store.thread_activity(&thread.0).await.unwrap()),
+        (ts + 1, 1)
+    );
+
+    // the read marker never moves backwards
+    store.mark_thread_read(&thread.0, ts).await.unwrap();
+    assert_eq!(
+        unread_count(store.thread_activity(&thread.0).await.unwrap()),
+        (ts + 1, 1)
+    );
+
+    // messages older than the read marker are already read
+    store
+        .save_message(&thread.0, content.with_timestamp(ts + 4))
+        .await
+        .unwrap();
+    store.delete_message(&thread.0, ts).await.unwrap();
+    store
+        .save_message(&thread.0, content.with_timestamp(ts))
+        .await
+        .unwrap();
+    assert_eq!(
+        unread_count(store.thread_activity(&thread.0).await.unwrap()),
+        (ts + 1, 2)
+    );
+
+    store.mark_thread_read(&thread.0, ts + 4).await.unwrap();
+    assert_eq!(
+        store
+            .threads()
+            .await
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .activity,
+        ThreadActivity {
+            last_message_timestamp: ts + 4,
+            last_message_preview: content
+                .0
+                .text()
+                .map(|text| text.chars().take(PREVIEW_LENGTH).collect()),
+            unread_count: 0,
+            last_read_timestamp: ts + 4,
+        }
+    );
+}
+
+async fn delivery_states<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content, DistinctUuids(recipient, other_recipient)): (
+        DistinctThreads,
+        ArbContent,
+        DistinctUuids,
+    ),
+) {
+    let ts = content.0.timestamp();
+    store
+        .save_message(&thread, content.0.clone())
+        .await
+        .unwrap();
+    assert!(store.delivery_states(&thread, ts).await.unwrap().is_empty());
+
+    for recipient in [recipient, other_recipient] {
+        assert!(store
+            .update_delivery_state(&thread, ts, recipient, DeliveryState::Sent)
+            .await
+            .unwrap());
+    }
+    assert!(store
+        .update_delivery_state(&thread, ts, recipient, DeliveryState::Delivered)
+        .await
+        .unwrap());
+    assert!(store
+        .update_delivery_state(&thread, ts, other_recipient, DeliveryState::Read)
+        .await
+        .unwrap());
+    // states never move backwards
+    assert!(!store
+        .update_delivery_state(&thread, ts, other_recipient, DeliveryState::Delivered)
+        .await
+        .unwrap());
+    assert!(!store
+        .update_delivery_state(&thread, ts, recipient, DeliveryState::Delivered)
+        .await
+        .unwrap());
+
+    assert_eq!(
+        store.delivery_states(&thread, ts).await.unwrap(),
+        BTreeMap::from([
+            (recipient, DeliveryState::Delivered),
+            (other_recipient, DeliveryState::Read)
+        ])
+    );
+    assert!(store
+        .delivery_states(&other_thread, ts)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(store
+        .delivery_states(&thread, ts + 1)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // delivery states are deleted along with their message
+    assert!(store.delete_message(&thread, ts).await.unwrap());
+    assert!(store.delivery_states(&thread, ts).await.unwrap().is_empty());
+}
+
+async fn message_expiry<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content): (DistinctThreads, ArbContent),
+) {
+    let ts = content.0.timestamp();
+    for (thread, message) in [
+        (&thread, content.with_timestamp(ts)),
+        (&thread, content.with_timestamp(ts + 1)),
+        (&other_thread, content.with_timestamp(ts)),
+    ] {
+        store.save_message(thread, message).await.unwrap();
+    }
+    let expiring = |thread: &Thread, timestamp, expires_at| ExpiringMessage {
+        thread: thread.clone(),
+        timestamp,
+        expires_at,
+    };
+    async fn expiring_messages<S: Store>(store: &S) -> Vec<ExpiringMessage> {
+        store
+            .expiring_messages()
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+    assert!(expiring_messages(&store).await.is_empty());
+
+    store.set_message_expiry(&thread, ts, 300).await.unwrap();
+    store
+        .set_message_expiry(&thread, ts + 1, 100)
+        .await
+        .unwrap();
+    store
+        .set_message_expiry(&other_thread, ts, 200)
+        .await
+        .unwrap();
+    // started timers are kept, and messages which are not saved are ignored
+    store.set_message_expiry(&thread, ts, 50).await.unwrap();
+    store.set_message_expiry(&thread, ts + 2, 50).await.unwrap();
+
+    assert_eq!(
+        expiring_messages(&store).await,
+        [
+            expiring(&thread, ts + 1, 100),
+            expiring(&other_thread, ts, 200),
+            expiring(&thread, ts, 300),
+        ]
+    );
+
+    // expiry times are deleted along with their message
+    assert!(store.delete_message(&thread, ts + 1).await.unwrap());
+    store.clear_thread(&other_thread).await.unwrap();
+    assert_eq!(
+        expiring_messages(&store).await,
+        [expiring(&thread, ts, 300)]
+    );
+}
+
+async fn reactions<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content, DistinctUuids(author, other_author)): (
+        DistinctThreads,
+        ArbContent,
+        DistinctUuids,
+    ),
+) {
+    let ts = content.0.timestamp();
+    store
+        .save_message(&thread, content.0.clone())
+        .await
+        .unwrap();
+    assert!(store.reactions(&thread, ts).await.unwrap().is_empty());
+
+    let reaction = |emoji: &str, timestamp| MessageReaction {
+        emoji: emoji.to_owned(),
+        timestamp,
+        removed: false,
+    };
+    store
+        .update_reaction(&thread, ts, author, Some(reaction("👍", ts + 1)))
+        .await
+        .unwrap();
+    store
+        .update_reaction(&thread, ts, other_author, Some(reaction("😂", ts + 2)))
+        .await
+        .unwrap();
+    // a new reaction replaces the previous one of the same author
+    store
+        .update_reaction(&thread, ts, author, Some(reaction("❤️", ts + 3)))
+        .await
+        .unwrap();
+    assert_eq!(
+        store.reactions(&thread, ts).await.unwrap(),
+        BTreeMap::from([
+            (author, reaction("❤️", ts + 3)),
+            (other_author, reaction("😂", ts + 2))
+        ])
+    );
+    assert!(store.reactions(&other_thread, ts).await.unwrap().is_empty());
+    assert!(store.reactions(&thread, ts + 1).await.unwrap().is_empty());
+
+    store
+        .update_reaction(&thread, ts, other_author, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.reactions(&thread, ts).await.unwrap(),
+        BTreeMap::from([(author, reaction("❤️", ts + 3))])
+    );
+
+    // removed reactions are kept with their removal timestamp
+    let removed = MessageReaction {
+        removed: true,
+        ..reaction("❤️", ts + 4)
+    };
+    store
+        .update_reaction(&thread, ts, author, Some(removed.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        store.reactions(&thread, ts).await.unwrap(),
+        BTreeMap::from([(author, removed)])
+    );
+
+    // reactions are deleted along with their message
+    assert!(store.delete_message(&thread, ts).await.unwrap());
+    assert!(store.reactions(&thread, ts).await.unwrap().is_empty());
+}
+
+async fn message_revisions<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content): (DistinctThreads, ArbContent),
+) {
+    let ts = content.0.timestamp();
+    let original = content.with_text(ts, "hello");
+    let edits = [
+        content.with_edit(ts, ts + 1, "hallo"),
+        content.with_edit(ts, ts + 2, "hi"),
+    ];
+
+    // revisions of messages which are not saved are ignored
+    store
+        .add_message_revision(&thread, ts, original.clone())
+        .await
+        .unwrap();
+    assert!(store
+        .message_revisions(&thread, ts)
+        .await
+        .unwrap()
+        .is_empty());
+
+    store.save_message(&thread, original.clone()).await.unwrap();
+    let mut current = original.clone();
+    for edit in &edits {
+        store
+            .add_message_revision(&thread, ts, current)
+            .await
+            .unwrap();
+        store.save_message(&thread, edit.clone()).await.unwrap();
+        current = edit.clone();
+    }
+
+    // edits replace the message they edit
+    let messages = all_messages(&store, &thread).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].timestamp(), ts);
+    assert!(messages[0].is_edited());
+    assert_eq!(messages[0].text(), Some("hi"));
+    assert!(!original.is_edited());
+
+    let revisions = store.message_revisions(&thread, ts).await.unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_same_message(&revisions[0], &original);
+    assert_same_message(&revisions[1], &edits[0]);
+    assert!(store
+        .message_revisions(&other_thread, ts)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // revisions are deleted along with their message
+    assert!(store.delete_message(&thread, ts).await.unwrap());
+    assert!(store
+        .message_revisions(&thread, ts)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn search_messages<S: Store>(
+    mut store: S,
+    (DistinctThreads(thread, other_thread), content): (DistinctThreads, ArbContent),
+) {
+    let ts = content.0.timestamp();
+    let messages = [
+        (&thread, content.with_text(ts, "Hello brave new World!")),
+        (&thread, content.with_text(ts + 1, "goodbye, world")),
+        (&other_thread, content.with_text(ts + 2, "hello world")),
+    ];
+    for (thread, message) in &messages {
+        store.save_message(thread, message.clone()).await.unwrap();
+    }
+
+    async fn found<S: Store>(store: &S, search: MessageSearch) -> Vec<(Thread, u64)> {
+        store
+            .search_messages(&search)
+            .await
+            .unwrap()
+            .map(|result| {
+                let (thread, content) = result.unwrap();
+                (thread, content.timestamp())
+            })
+            .collect()
+    }
+
+    // most recent first, words in any order and case
+    assert_eq!(
+        found(&store, MessageSearch::new("WORLD hello")).await,
+        [(other_thread.clone(), ts + 2), (thread.clone(), ts)]
+    );
+    assert_eq!(
+        found(&store, MessageSearch::new("world")).await,
+        [
+            (other_thread.clone(), ts + 2),
+            (thread.clone(), ts + 1),
+            (thread.clone(), ts)
+        ]
+    );
+    // whole words only
+    assert!(found(&store, MessageSearch::new("worl")).await.is_empty());
+    assert!(found(&store, MessageSearch::new("hello goodbye"))
+        .await
+        .is_empty());
+    assert!(found(&store, MessageSearch::new(" ,")).await.is_empty());
+
+    assert_eq!(
+        found(
+            &store,
+            MessageSearch {
+                thread: Some(thread.clone()),
+                ..MessageSearch::new("hello")
+            }
+        )
+        .await,
+        [(thread.clone(), ts)]
+    );
+    assert_eq!(
+        found(
+            &store,
+            MessageSearch {
+                from: Some(ts + 1),
+                until: Some(ts + 1),
+                ..MessageSearch::new("world")
+            }
+        )
+        .await,
+        [(thread.clone(), ts + 1)]
+    );
+    assert_eq!(
+        found(
+            &store,
+            MessageSearch {
+                from: Some(ts + 1),
+                until: Some(u64::MAX),
+                ..MessageSearch::new("world")
+            }
+        )
+        .await,
+        [(other_thread.clone(), ts + 2), (thread.clone(), ts + 1)]
+    );
+    let sender = content.0.metadata.sender.raw_uuid();
+    assert_eq!(
+        found(
+            &store,
+            MessageSearch {
+                sender: Some(sender),
+                ..MessageSearch::new("goodbye")
+            }
+        )
+        .await,
+        [(thread.clone(), ts + 1)]
+    );
+    assert!(found(
+        &store,
+        MessageSearch {
+            sender: Some(Uuid::from_u128(sender.as_u128().wrapping_add(1))),
+            ..MessageSearch::new("goodbye")
+        }
+    )
+    .await
+    .is_empty());
+
+    // deleted messages are not found anymore
+    store.delete_message(&thread, ts + 1).await.unwrap();
+    assert!(found(&store, MessageSearch::new("goodbye"))
+        .await
+        .is_empty());
+    store.clear_messages().await.unwrap();
+    assert!(found(&store, MessageSearch::new("world")).await.is_empty());
+}
+
+async fn contact_roundtrip<S: Store>(mut store: S, contacts: Vec<ArbContact>) {
+    for contact in &contacts {
+        store.save_contact(&contact.to_contact()).await.unwrap();
+    }
+    for contact in &contacts {
+        // the last saved version of a contact wins
+        let Some(expected) = contacts.iter().rev().find(|c| c.uuid == contact.uuid) else {
+            unreachable!()
+        };
+        let loaded = store
+            .contact_by_id(&contact.uuid)
+            .await
+            .unwrap()
+            .expect("saved contact");
+        expected.assert_eq(&loaded);
+    }
+
+    let mut uuids: Vec<Uuid> = contacts.iter().map(|c| c.uuid).collect();
+    uuids.sort_unstable();
+    uuids.dedup();
+    let mut stored_uuids: Vec<Uuid> = store
+        .contacts()
+        .await
+        .unwrap()
+        .map(|c| c.unwrap().uuid)
+        .collect();
+    stored_uuids.sort_unstable();
+    assert_eq!(stored_uuids, uuids);
+
+    store.clear_contacts().await.unwrap();
+    assert_eq!(store.contacts().await.unwrap().count(), 0);
+}
+
+async fn group_roundtrip<S: Store>(
+    mut store: S,
+    (group, master_key, avatar): (ArbGroup, ArbUuid, Vec<u8>),
+) {
+    // any 32 bytes will do, groups are never decrypted here
+    let mut master_key_bytes = [0u8; 32];
+    master_key_bytes[..16].copy_from_slice(master_key.0.as_bytes());
+
+    assert!(store.group(master_key_bytes).await.unwrap().is_none());
+    store
+        .save_group(master_key_bytes, group.to_group())
+        .await
+        .unwrap();
+    let loaded = store
+        .group(master_key_bytes)
+        .await
+        .unwrap()
+        .expect("saved group");
+    group.assert_eq(&loaded);
+
+    let groups: Vec<_> = store
+        .groups()
+        .await
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].0, master_key_bytes);
+
+    store
+        .save_group_avatar(master_key_bytes, &avatar)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.group_avatar(master_key_bytes).await.unwrap(),
+        Some(avatar)
+    );
+
+    store.clear_groups().await.unwrap();
+    assert!(store.group(master_key_bytes).await.unwrap().is_none());
+}
+
+async fn profile_key_roundtrip<S: Store>(mut store: S, (uuid, key): (ArbUuid, ArbProfileKey)) {
+    assert!(store.profile_key(&uuid.0).await.unwrap().is_none());
+    assert!(
+        !store.upsert_profile_key(&uuid.0, key.0).await.unwrap(),
+        "no key was replaced"
+    );
+    assert!(store.upsert_profile_key(&uuid.0, key.0).await.unwrap());
+    let loaded = store
+        .profile_key(&uuid.0)
+        .await
+        .unwrap()
+        .expect("saved profile key");
+    assert_eq!(loaded.get_bytes(), key.0.get_bytes());
+
+    let keys: Vec<_> = store
+        .profile_keys()
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].0, uuid.0);
+    assert_eq!(keys[0].1.get_bytes(), key.0.get_bytes());
+}
+
+async fn profile_avatar_roundtrip<S: Store>(
+    mut store: S,
+    (uuid, key, avatar): (ArbUuid, ArbProfileKey, Vec<u8>),
+) {
+    assert!(store.profile_avatar(uuid.0, key.0).await.unwrap().is_none());
+    store
+        .save_profile_avatar(uuid.0, key.0, &avatar)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.profile_avatar(uuid.0, key.0).await.unwrap(),
+        Some(avatar)
+    );
+
+    store.clear_profiles().await.unwrap();
+    assert!(store.profile_avatar(uuid.0, key.0).await.unwrap().is_none());
+}
+
+async fn sticker_pack_roundtrip<S: Store>(mut store: S, pack: ArbStickerPack) {
+    let pack = pack.0;
+    assert!(store.sticker_pack(&pack.id).await.unwrap().is_none());
+    store.add_sticker_pack(&pack).await.unwrap();
+
+    let loaded = store
+        .sticker_pack(&pack.id)
+        .await
+        .unwrap()
+        .expect("saved sticker pack");
+    assert_eq!(loaded.key, pack.key);
+    assert_eq!(loaded.manifest.title, pack.manifest.title);
+    assert_eq!(loaded.manifest.author, pack.manifest.author);
+    assert_eq!(store.sticker_packs().await.unwrap().count(), 1);
+
+    assert!(store.remove_sticker_pack(&pack.id).await.unwrap());
+    assert!(!store.remove_sticker_pack(&pack.id).await.unwrap());
+    assert!(store.sticker_pack(&pack.id).await.unwrap().is_none());
+}
@@ -0,0 +1,402 @@
+//! Inputs and helpers shared by the properties of all contracts
+
+use std::{fmt, path::PathBuf};
+
+use libsignal_service::{
+    configuration::SignalServers,
+    content::{ContentBody, Metadata},
+    prelude::{phonenumber, Content, ProfileKey, Uuid},
+    proto::{DataMessage, EditMessage},
+    protocol::{self, PreKeyRecord, ServiceId, SignedPreKeyRecord, Timestamp},
+    push_service::ServiceIds,
+};
+use quickcheck::{Arbitrary, Gen};
+
+use crate::{
+    manager::RegistrationData,
+    model::{contacts::Contact, groups::Group},
+    store::{
+        attachments::DownloadedAttachment, ContentsStore, StateStore, StickerPack,
+        StickerPackManifest, Store, Thread,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbProtocolAddress(pub(super) protocol::ProtocolAddress);
+
+impl Arbitrary for ArbProtocolAddress {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let name: String = Arbitrary::arbitrary(g);
+        let device_id: u32 = Arbitrary::arbitrary(g);
+        Self(protocol::ProtocolAddress::new(name, device_id.into()))
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ArbKeyPair(pub(super) protocol::KeyPair);
+
+impl fmt::Debug for ArbKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0.public_key.serialize()))
+    }
+}
+
+impl Arbitrary for ArbKeyPair {
+    fn arbitrary(_g: &mut Gen) -> Self {
+        // Gen is not rand::CryptoRng here, see https://github.com/BurntSushi/quickcheck/issues/241
+        Self(protocol::KeyPair::generate(&mut rand::thread_rng()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbUuid(pub(super) Uuid);
+
+impl Arbitrary for ArbUuid {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self(Uuid::from_u128(Arbitrary::arbitrary(g)))
+    }
+}
+
+/// Two different UUIDs, for instance two recipients of a message
+#[derive(Debug, Clone)]
+pub(super) struct DistinctUuids(pub(super) Uuid, pub(super) Uuid);
+
+impl Arbitrary for DistinctUuids {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let first = ArbUuid::arbitrary(g).0;
+        let second = Uuid::from_u128(first.as_u128().wrapping_add(1 + u64::arbitrary(g) as u128));
+        Self(first, second)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbPreKeyRecord(pub(super) PreKeyRecord);
+
+impl Arbitrary for ArbPreKeyRecord {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let id = u32::arbitrary(g);
+        let key_pair = ArbKeyPair::arbitrary(g);
+        Self(PreKeyRecord::new(id.into(), &key_pair.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbSignedPreKeyRecord(pub(super) SignedPreKeyRecord);
+
+impl Arbitrary for ArbSignedPreKeyRecord {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let id = u32::arbitrary(g);
+        let timestamp = Arbitrary::arbitrary(g);
+        let key_pair = ArbKeyPair::arbitrary(g);
+        let signature: Vec<u8> = Arbitrary::arbitrary(g);
+        Self(SignedPreKeyRecord::new(
+            id.into(),
+            Timestamp::from_epoch_millis(timestamp),
+            &key_pair.0,
+            &signature,
+        ))
+    }
+}
+
+/// A thread, either with a contact or in a group
+#[derive(Debug, Clone)]
+pub(super) struct ArbThread(pub(super) Thread);
+
+impl Arbitrary for ArbThread {
+    fn arbitrary(g: &mut Gen) -> Self {
+        if bool::arbitrary(g) {
+            Self(Thread::Contact(ArbUuid::arbitrary(g).0))
+        } else {
+            let mut master_key = [0u8; 32];
+            master_key.iter_mut().for_each(|b| *b = u8::arbitrary(g));
+            Self(Thread::Group(master_key))
+        }
+    }
+}
+
+/// Two different threads, to check that what happens in one does not leak into the other
+#[derive(Debug, Clone)]
+pub(super) struct DistinctThreads(pub(super) Thread, pub(super) Thread);
+
+impl Arbitrary for DistinctThreads {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let thread = ArbThread::arbitrary(g).0;
+        loop {
+            let other_thread = ArbThread::arbitrary(g).0;
+            if other_thread != thread {
+                return Self(thread, other_thread);
+            }
+        }
+    }
+}
+
+/// A data message with a realistic timestamp (milliseconds that fit in 53 bits)
+#[derive(Debug, Clone)]
+pub(super) struct ArbContent(pub(super) Content);
+
+impl ArbContent {
+    pub(super) fn with_timestamp(&self, timestamp: u64) -> Content {
+        let ContentBody::DataMessage(data_message) = &self.0.body else {
+            unreachable!("only data messages are generated")
+        };
+        Content {
+            metadata: Metadata {
+                timestamp,
+                ..self.0.metadata.clone()
+            },
+            body: ContentBody::DataMessage(DataMessage {
+                timestamp: Some(timestamp),
+                ..data_message.clone()
+            }),
+        }
+    }
+
+    pub(super) fn with_text(&self, timestamp: u64, text: &str) -> Content {
+        let mut content = self.with_timestamp(timestamp);
+        if let ContentBody::DataMessage(data_message) = &mut content.body {
+            data_message.body = Some(text.to_owned());
+        }
+        content
+    }
+
+    /// An edit of the message sent at `timestamp`, with the new text
+    pub(super) fn with_edit(&self, timestamp: u64, edited_at: u64, text: &str) -> Content {
+        let Content { metadata, body } = self.with_text(edited_at, text);
+        let ContentBody::DataMessage(data_message) = body else {
+            unreachable!("only data messages are generated")
+        };
+        Content {
+            metadata,
+            body: ContentBody::EditMessage(EditMessage {
+                target_sent_timestamp: Some(timestamp),
+                data_message: Some(data_message),
+            }),
+        }
+    }
+}
+
+impl Arbitrary for ArbContent {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let timestamp = u64::arbitrary(g) >> 11;
+        let metadata = Metadata {
+            sender: ServiceId::Aci(ArbUuid::arbitrary(g).0.into()),
+            destination: ServiceId::Aci(ArbUuid::arbitrary(g).0.into()),
+            sender_device: Arbitrary::arbitrary(g),
+            server_guid: None,
+            timestamp,
+            needs_receipt: Arbitrary::arbitrary(g),
+            unidentified_sender: false,
+        };
+        let body = ContentBody::DataMessage(DataMessage {
+            body: Arbitrary::arbitrary(g),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        });
+        Self(Content::from_body(body, metadata))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbContact {
+    pub(super) uuid: Uuid,
+    name: String,
+    color: Option<String>,
+    profile_key: Vec<u8>,
+    expire_timer: u32,
+    expire_timer_version: u32,
+    inbox_position: u32,
+    archived: bool,
+}
+
+impl ArbContact {
+    pub(super) fn to_contact(&self) -> Contact {
+        Contact {
+            uuid: self.uuid,
+            phone_number: None,
+            name: self.name.clone(),
+            color: self.color.clone(),
+            verified: Default::default(),
+            profile_key: self.profile_key.clone(),
+            expire_timer: self.expire_timer,
+            expire_timer_version: self.expire_timer_version,
+            inbox_position: self.inbox_position,
+            archived: self.archived,
+            avatar: None,
+        }
+    }
+
+    pub(super) fn assert_eq(&self, contact: &Contact) {
+        assert_eq!(contact.uuid, self.uuid);
+        assert_eq!(contact.name, self.name);
+        assert_eq!(contact.color, self.color);
+        assert_eq!(contact.profile_key, self.profile_key);
+        assert_eq!(contact.expire_timer, self.expire_timer);
+        assert_eq!(contact.expire_timer_version, self.expire_timer_version);
+        assert_eq!(contact.inbox_position, self.inbox_position);
+        assert_eq!(contact.archived, self.archived);
+    }
+}
+
+impl Arbitrary for ArbContact {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self {
+            uuid: ArbUuid::arbitrary(g).0,
+            name: Arbitrary::arbitrary(g),
+            color: Arbitrary::arbitrary(g),
+            profile_key: Arbitrary::arbitrary(g),
+            expire_timer: Arbitrary::arbitrary(g),
+            expire_timer_version: Arbitrary::arbitrary(g),
+            inbox_position: Arbitrary::arbitrary(g),
+            archived: Arbitrary::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbGroup {
+    title: String,
+    avatar: String,
+    disappearing_messages_timer: Option<u32>,
+    revision: u32,
+    invite_link_password: Vec<u8>,
+    description: Option<String>,
+}
+
+impl ArbGroup {
+    pub(super) fn to_group(&self) -> Group {
+        Group {
+            title: self.title.clone(),
+            avatar: self.avatar.clone(),
+            disappearing_messages_timer: self
+                .disappearing_messages_timer
+                .map(|duration| libsignal_service::groups_v2::Timer { duration }),
+            access_control: None,
+            revision: self.revision,
+            members: Vec::new(),
+            pending_members: Vec::new(),
+            requesting_members: Vec::new(),
+            invite_link_password: self.invite_link_password.clone(),
+            description: self.description.clone(),
+        }
+    }
+
+    pub(super) fn assert_eq(&self, group: &Group) {
+        assert_eq!(group.title, self.title);
+        assert_eq!(group.avatar, self.avatar);
+        assert_eq!(
+            group
+                .disappearing_messages_timer
+                .as_ref()
+                .map(|t| t.duration),
+            self.disappearing_messages_timer
+        );
+        assert_eq!(group.revision, self.revision);
+        assert_eq!(group.invite_link_password, self.invite_link_password);
+        assert_eq!(group.description, self.description);
+    }
+}
+
+impl Arbitrary for ArbGroup {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self {
+            title: Arbitrary::arbitrary(g),
+            avatar: Arbitrary::arbitrary(g),
+            disappearing_messages_timer: Arbitrary::arbitrary(g),
+            revision: Arbitrary::arbitrary(g),
+            invite_link_password: Arbitrary::arbitrary(g),
+            description: Arbitrary::arbitrary(g),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbProfileKey(pub(super) ProfileKey);
+
+impl Arbitrary for ArbProfileKey {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes.iter_mut().for_each(|b| *b = u8::arbitrary(g));
+        Self(ProfileKey::create(bytes))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbStickerPack(pub(super) StickerPack);
+
+impl Arbitrary for ArbStickerPack {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self(StickerPack {
+            id: Arbitrary::arbitrary(g),
+            key: Arbitrary::arbitrary(g),
+            manifest: StickerPackManifest {
+                title: Arbitrary::arbitrary(g),
+                author: Arbitrary::arbitrary(g),
+                cover: None,
+                stickers: Vec::new(),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ArbDownloadedAttachment(pub(super) DownloadedAttachment);
+
+impl Arbitrary for ArbDownloadedAttachment {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let file_name: String = String::arbitrary(g).replace('\0', "");
+        Self(DownloadedAttachment {
+            path: PathBuf::from("attachments").join(file_name),
+            size: Arbitrary::arbitrary(g),
+        })
+    }
+}
+
+pub(super) fn registration_data(aci: Uuid, pni: Uuid, registration_id: u32) -> RegistrationData {
+    let mut signaling_key = [0u8; 52];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut signaling_key);
+    RegistrationData {
+        signal_servers: SignalServers::Staging,
+        device_name: Some("presage".to_owned()),
+        phone_number: phonenumber::parse(None, "+41446681800").unwrap(),
+        service_ids: ServiceIds { aci, pni },
+        password: "password".to_owned(),
+        signaling_key,
+        device_id: Some(2),
+        registration_id,
+        pni_registration_id: Some(registration_id.wrapping_add(1)),
+        profile_key: ProfileKey::generate(rand::random()),
+    }
+}
+
+/// The same message, sent by `sender`
+pub(super) fn sent_by(mut content: Content, sender: Uuid) -> Content {
+    content.metadata.sender = ServiceId::Aci(sender.into());
+    content
+}
+
+/// Registers the store as `own_aci`, which tells our own messages apart
+pub(super) async fn register<S: Store>(store: &mut S, own_aci: Uuid) {
+    store
+        .save_registration_data(&registration_data(own_aci, own_aci, 1))
+        .await
+        .unwrap();
+}
+
+/// All the messages of a thread, oldest first
+pub(super) async fn all_messages<S: Store>(store: &S, thread: &Thread) -> Vec<Content> {
+    store
+        .messages(thread, ..)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+}
+
+pub(super) fn assert_same_message(left: &Content, right: &Content) {
+    assert_eq!(left.metadata.timestamp, right.metadata.timestamp);
+    assert_eq!(left.metadata.sender, right.metadata.sender);
+    assert_eq!(
+        left.body.clone().into_proto(),
+        right.body.clone().into_proto()
+    );
+}
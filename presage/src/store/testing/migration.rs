@@ -0,0 +1,128 @@
+//! Properties of [`migrate`]
+
+use std::future::Future;
+
+use libsignal_service::{
+    prelude::Uuid,
+    protocol::{
+        IdentityKey, IdentityKeyPair, IdentityKeyStore, PreKeyRecord, PreKeyStore, SessionStore,
+    },
+};
+
+use super::{
+    check,
+    fixtures::{
+        all_messages, assert_same_message, registration_data, ArbContact, ArbContent, ArbKeyPair,
+        ArbProfileKey, ArbProtocolAddress, ArbStickerPack, ArbThread,
+    },
+};
+use crate::store::{
+    migration::{migrate, MigrationError, RecordCategory},
+    ContentsStore, ProtocolStoreExt, StateStore, Store,
+};
+
+/// Checks that [`migrate`] copies everything from stores created by `new_source` into stores
+/// created by `new_destination`.
+pub async fn migration_contract<S, D, FS, FutS, FD, FutD>(new_source: &FS, new_destination: &FD)
+where
+    S: Store,
+    D: Store,
+    FS: Fn() -> FutS,
+    FutS: Future<Output = S>,
+    FD: Fn() -> FutD,
+    FutD: Future<Output = D>,
+{
+    let new_stores = || async move { (new_source().await, new_destination().await) };
+    check(&new_stores, "migration", migrate_all_records).await;
+}
+
+#[allow(clippy::type_complexity)]
+async fn migrate_all_records<S: Store, D: Store>(
+    (mut source, mut destination): (S, D),
+    (contact, thread, content, addr, key_pair, profile_key, pack): (
+        ArbContact,
+        ArbThread,
+        ArbContent,
+        ArbProtocolAddress,
+        ArbKeyPair,
+        ArbProfileKey,
+        ArbStickerPack,
+    ),
+) {
+    let identity_key = IdentityKey::new(key_pair.0.public_key);
+    let pre_key = PreKeyRecord::new(0.into(), &key_pair.0);
+
+    let data = registration_data(contact.uuid, Uuid::new_v4(), 1);
+    source.save_registration_data(&data).await.unwrap();
+    source
+        .set_aci_identity_key_pair(IdentityKeyPair::from(key_pair.0))
+        .await
+        .unwrap();
+    let mut aci = source.aci_protocol_store();
+    aci.store_session(&addr.0, &SessionRecord::new_fresh())
+        .await
+        .unwrap();
+    aci.restore_identity(&addr.0, &identity_key).await.unwrap();
+    aci.save_pre_key(0.into(), &pre_key).await.unwrap();
+    source.save_contact(&contact.to_contact()).await.unwrap();
+    source
+        .save_message(&thread.0, content.0.clone())
+        .await
+        .unwrap();
+    source
+        .upsert_profile_key(&contact.uuid, profile_key.0)
+        .await
+        .unwrap();
+    source.add_sticker_pack(&pack.0).await.unwrap();
+
+    let report = migrate(&source, &mut destination).await.unwrap();
+    for (category, count) in [
+        (RecordCategory::RegistrationData, 1),
+        (RecordCategory::IdentityKeyPairs, 1),
+        (RecordCategory::Sessions, 1),
+        (RecordCategory::PreKeys, 1),
+        (RecordCategory::Contacts, 1),
+        (RecordCategory::Messages, 1),
+        (RecordCategory::ProfileKeys, 1),
+        (RecordCategory::StickerPacks, 1),
+    ] {
+        assert_eq!(report.count(category), count, "{category}");
+    }
+
+    assert!(destination.is_registered().await);
+    let aci = destination.aci_protocol_store();
+    assert_eq!(
+        aci.get_identity_key_pair().await.unwrap().serialize(),
+        IdentityKeyPair::from(key_pair.0).serialize()
+    );
+    assert!(aci.load_session(&addr.0).await.unwrap().is_some());
+    assert_eq!(aci.get_identity(&addr.0).await.unwrap(), Some(identity_key));
+    assert_eq!(
+        aci.get_pre_key(0.into())
+            .await
+            .unwrap()
+            .serialize()
+            .unwrap(),
+        pre_key.serialize().unwrap()
+    );
+    contact.assert_eq(
+        &destination
+            .contact_by_id(&contact.uuid)
+            .await
+            .unwrap()
+            .expect("migrated contact"),
+    );
+    let messages = all_messages(&destination, &thread.0).await;
+    assert_eq!(messages.len(), 1);
+    assert_same_message(&messages[0], &content.0);
+    assert!(destination
+        .sticker_pack(&pack.0.id)
+        .await
+        .unwrap()
+        .is_some());
+
+    assert!(matches!(
+        migrate(&source, &mut destination).await,
+        Err(MigrationError::DestinationNotEmpty)
+    ));
+}
@@ -0,0 +1,358 @@
+//! Properties of the protocol stores
+
+use std::future::Future;
+
+use libsignal_service::{
+    pre_keys::{KyberPreKeyStoreExt, PreKeysStore},
+    protocol::{
+        self, kem, Direction, GenericSignedPreKey, IdentityKey, IdentityKeyStore,
+        KyberPreKeyRecord, KyberPreKeyStore, PreKeyRecord, PreKeyStore, SenderKeyStore, ServiceId,
+        SessionRecord, SessionStore, SignedPreKeyStore,
+    },
+    push_service::DEFAULT_DEVICE_ID,
+    session_store::SessionStoreExt,
+};
+
+use super::{
+    check,
+    fixtures::{ArbKeyPair, ArbPreKeyRecord, ArbProtocolAddress, ArbSignedPreKeyRecord, ArbUuid},
+};
+use crate::store::{ProtocolStoreExt, Store};
+
+/// Checks the [`ProtocolStore`](protocol::ProtocolStore) contract of both the ACI and PNI
+/// protocol stores.
+pub async fn protocol_contract<S, F, Fut>(new_store: &F)
+where
+    S: Store,
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+{
+    check(
+        new_store,
+        "identity (aci)",
+        |store: S, (addr, key_pair): (ArbProtocolAddress, ArbKeyPair)| {
+            identity_roundtrip(store.aci_protocol_store(), addr, key_pair)
+        },
+    )
+    .await;
+    check(
+        new_store,
+        "identity (pni)",
+        |store: S, (addr, key_pair): (ArbProtocolAddress, ArbKeyPair)| {
+            identity_roundtrip(store.pni_protocol_store(), addr, key_pair)
+        },
+    )
+    .await;
+    check(
+        new_store,
+        "session (aci)",
+        |store: S, addr: ArbProtocolAddress| session_roundtrip(store.aci_protocol_store(), addr),
+    )
+    .await;
+    check(
+        new_store,
+        "session (pni)",
+        |store: S, addr: ArbProtocolAddress| session_roundtrip(store.pni_protocol_store(), addr),
+    )
+    .await;
+    check(
+        new_store,
+        "sessions are per identity",
+        sessions_are_per_identity,
+    )
+    .await;
+    check(
+        new_store,
+        "sub-device sessions",
+        |store: S, (uuid, devices): (ArbUuid, Vec<u8>)| {
+            sub_device_sessions(store.aci_protocol_store(), uuid, devices)
+        },
+    )
+    .await;
+    check(
+        new_store,
+        "pre key",
+        |store: S, (id, key_pair): (u32, ArbKeyPair)| {
+            pre_key_roundtrip(store.aci_protocol_store(), id, key_pair)
+        },
+    )
+    .await;
+    check(new_store, "signed pre key", |store: S, input| {
+        signed_pre_key_roundtrip(store.aci_protocol_store(), input)
+    })
+    .await;
+    check(
+        new_store,
+        "kyber pre key",
+        |store: S, (id, key_pair): (u32, ArbKeyPair)| {
+            kyber_pre_key_roundtrip(store.aci_protocol_store(), id, key_pair)
+        },
+    )
+    .await;
+    check(new_store, "next pre key ids", |store: S, records| {
+        next_pre_key_ids(store.aci_protocol_store(), records)
+    })
+    .await;
+    check(new_store, "next pre key id is max", |store: S, input| {
+        next_pre_key_id_is_max(store.aci_protocol_store(), input)
+    })
+    .await;
+    check(
+        new_store,
+        "sender key",
+        |store: S, (addr, distribution_id): (ArbProtocolAddress, ArbUuid)| {
+            sender_key_roundtrip(store.aci_protocol_store(), addr, distribution_id)
+        },
+    )
+    .await;
+    check(new_store, "protocol records (aci)", |store: S, input| {
+        protocol_records(store.aci_protocol_store(), input)
+    })
+    .await;
+    check(new_store, "protocol records (pni)", |store: S, input| {
+        protocol_records(store.pni_protocol_store(), input)
+    })
+    .await;
+}
+
+async fn identity_roundtrip(
+    mut store: impl IdentityKeyStore,
+    addr: ArbProtocolAddress,
+    key_pair: ArbKeyPair,
+) {
+    let identity_key = IdentityKey::new(key_pair.0.public_key);
+    // like the sled store, saving an identity always reports it as saved
+    assert!(store.save_identity(&addr.0, &identity_key).await.unwrap());
+    assert!(store.save_identity(&addr.0, &identity_key).await.unwrap());
+    let id = store.get_identity(&addr.0).await.unwrap();
+    assert_eq!(id, Some(identity_key));
+    assert!(store
+        .is_trusted_identity(&addr.0, &identity_key, Direction::Receiving)
+        .await
+        .unwrap());
+}
+
+async fn session_roundtrip(
+    mut store: impl SessionStore + SessionStoreExt,
+    addr: ArbProtocolAddress,
+) {
+    let session = SessionRecord::new_fresh();
+    store.store_session(&addr.0, &session).await.unwrap();
+    let loaded_session = store
+        .load_session(&addr.0)
+        .await
+        .unwrap()
+        .expect("stored session");
+    assert_eq!(
+        session.serialize().unwrap(),
+        loaded_session.serialize().unwrap()
+    );
+
+    store.delete_session(&addr.0).await.unwrap();
+    assert!(store.load_session(&addr.0).await.unwrap().is_none());
+}
+
+async fn sessions_are_per_identity<S: Store>(store: S, addr: ArbProtocolAddress) {
+    store
+        .aci_protocol_store()
+        .store_session(&addr.0, &SessionRecord::new_fresh())
+        .await
+        .unwrap();
+    assert!(store
+        .pni_protocol_store()
+        .load_session(&addr.0)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn sub_device_sessions(
+    mut store: impl SessionStore + SessionStoreExt,
+    uuid: ArbUuid,
+    device_ids: Vec<u8>,
+) {
+    let service_id = ServiceId::Aci(uuid.0.into());
+    let mut device_ids: Vec<u32> = device_ids.into_iter().map(u32::from).collect();
+    device_ids.push(DEFAULT_DEVICE_ID);
+    for &device_id in &device_ids {
+        let addr = protocol::ProtocolAddress::new(service_id.service_id_string(), device_id.into());
+        store
+            .store_session(&addr, &SessionRecord::new_fresh())
+            .await
+            .unwrap();
+    }
+
+    let mut sub_devices = store.get_sub_device_sessions(&service_id).await.unwrap();
+    sub_devices.sort_unstable();
+    device_ids.retain(|&id| id != DEFAULT_DEVICE_ID);
+    device_ids.sort_unstable();
+    device_ids.dedup();
+    assert_eq!(sub_devices, device_ids);
+}
+
+async fn pre_key_roundtrip(mut store: impl PreKeyStore, id: u32, key_pair: ArbKeyPair) {
+    let id = id.into();
+    let pre_key_record = PreKeyRecord::new(id, &key_pair.0);
+    store.save_pre_key(id, &pre_key_record).await.unwrap();
+    assert_eq!(
+        store.get_pre_key(id).await.unwrap().serialize().unwrap(),
+        pre_key_record.serialize().unwrap()
+    );
+
+    store.remove_pre_key(id).await.unwrap();
+    assert!(store.get_pre_key(id).await.is_err());
+}
+
+async fn signed_pre_key_roundtrip(
+    mut store: impl SignedPreKeyStore,
+    record: ArbSignedPreKeyRecord,
+) {
+    let id = record.0.id().unwrap();
+    store.save_signed_pre_key(id, &record.0).await.unwrap();
+    assert_eq!(
+        store
+            .get_signed_pre_key(id)
+            .await
+            .unwrap()
+            .serialize()
+            .unwrap(),
+        record.0.serialize().unwrap()
+    );
+}
+
+async fn kyber_pre_key_roundtrip(mut store: impl KyberPreKeyStore, id: u32, key_pair: ArbKeyPair) {
+    let id = id.into();
+    let record =
+        KyberPreKeyRecord::generate(kem::KeyType::Kyber1024, id, &key_pair.0.private_key).unwrap();
+    store.save_kyber_pre_key(id, &record).await.unwrap();
+    assert_eq!(
+        store
+            .get_kyber_pre_key(id)
+            .await
+            .unwrap()
+            .serialize()
+            .unwrap(),
+        record.serialize().unwrap()
+    );
+}
+
+async fn next_pre_key_ids(
+    mut store: impl PreKeyStore + SignedPreKeyStore + PreKeysStore,
+    (key1, key2, signed_key): (ArbPreKeyRecord, ArbPreKeyRecord, ArbSignedPreKeyRecord),
+) {
+    assert_eq!(store.next_pre_key_id().await.unwrap(), 0);
+    assert_eq!(store.next_pq_pre_key_id().await.unwrap(), 0);
+    assert_eq!(store.next_signed_pre_key_id().await.unwrap(), 0);
+
+    store.save_pre_key(0.into(), &key1.0).await.unwrap();
+    store.save_pre_key(1.into(), &key2.0).await.unwrap();
+    store
+        .save_signed_pre_key(0.into(), &signed_key.0)
+        .await
+        .unwrap();
+
+    assert_eq!(store.next_pre_key_id().await.unwrap(), 2);
+    assert_eq!(store.next_pq_pre_key_id().await.unwrap(), 0);
+    assert_eq!(store.next_signed_pre_key_id().await.unwrap(), 1);
+}
+
+async fn next_pre_key_id_is_max(
+    mut store: impl PreKeyStore + PreKeysStore,
+    (keys, record): (Vec<u32>, ArbPreKeyRecord),
+) {
+    // the next identifier of u32::MAX would overflow
+    let keys: Vec<u32> = keys.into_iter().filter(|&id| id != u32::MAX).collect();
+    for &key in &keys {
+        store.save_pre_key(key.into(), &record.0).await.unwrap();
+    }
+    assert_eq!(
+        store.next_pre_key_id().await.unwrap(),
+        keys.iter().copied().max().map(|id| id + 1).unwrap_or(0)
+    );
+}
+
+async fn protocol_records(
+    mut store: impl ProtocolStoreExt,
+    (addr, distribution_id, key_pair, id): (ArbProtocolAddress, ArbUuid, ArbKeyPair, u32),
+) {
+    let identity_key = IdentityKey::new(key_pair.0.public_key);
+    let pre_key = PreKeyRecord::new(id.into(), &key_pair.0);
+    let kyber_pre_key =
+        KyberPreKeyRecord::generate(kem::KeyType::Kyber1024, id.into(), &key_pair.0.private_key)
+            .unwrap();
+
+    store
+        .store_session(&addr.0, &SessionRecord::new_fresh())
+        .await
+        .unwrap();
+    store
+        .restore_identity(&addr.0, &identity_key)
+        .await
+        .unwrap();
+    store.save_pre_key(id.into(), &pre_key).await.unwrap();
+    store
+        .store_last_resort_kyber_pre_key(id.into(), &kyber_pre_key)
+        .await
+        .unwrap();
+    protocol::create_sender_key_distribution_message(
+        &addr.0,
+        distribution_id.0,
+        &mut store,
+        &mut rand::thread_rng(),
+    )
+    .await
+    .unwrap();
+
+    let sessions = store.all_sessions().await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].0, addr.0);
+    assert_eq!(
+        store.all_identities().await.unwrap(),
+        vec![(addr.0.clone(), identity_key)]
+    );
+
+    let pre_keys = store.all_pre_keys().await.unwrap();
+    assert_eq!(pre_keys.len(), 1);
+    assert_eq!(
+        pre_keys[0].serialize().unwrap(),
+        pre_key.serialize().unwrap()
+    );
+    assert!(store.all_signed_pre_keys().await.unwrap().is_empty());
+    assert!(store.all_kyber_pre_keys(false).await.unwrap().is_empty());
+    let kyber_pre_keys = store.all_kyber_pre_keys(true).await.unwrap();
+    assert_eq!(kyber_pre_keys.len(), 1);
+    assert_eq!(
+        kyber_pre_keys[0].serialize().unwrap(),
+        kyber_pre_key.serialize().unwrap()
+    );
+
+    let sender_keys = store.all_sender_keys().await.unwrap();
+    assert_eq!(sender_keys.len(), 1);
+    assert_eq!(sender_keys[0].0, addr.0);
+    assert_eq!(sender_keys[0].1, distribution_id.0);
+}
+
+async fn sender_key_roundtrip(
+    mut store: impl SenderKeyStore,
+    addr: ArbProtocolAddress,
+    distribution_id: ArbUuid,
+) {
+    assert!(store
+        .load_sender_key(&addr.0, distribution_id.0)
+        .await
+        .unwrap()
+        .is_none());
+    protocol::create_sender_key_distribution_message(
+        &addr.0,
+        distribution_id.0,
+        &mut store,
+        &mut rand::thread_rng(),
+    )
+    .await
+    .unwrap();
+    assert!(store
+        .load_sender_key(&addr.0, distribution_id.0)
+        .await
+        .unwrap()
+        .is_some());
+}
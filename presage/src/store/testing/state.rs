@@ -0,0 +1,133 @@
+//! Properties of the [`StateStore`]
+
+use std::future::Future;
+
+use libsignal_service::{
+    prelude::Uuid,
+    protocol::{IdentityKey, IdentityKeyPair, IdentityKeyStore},
+};
+
+use super::{
+    check,
+    fixtures::{
+        registration_data, ArbContact, ArbContent, ArbKeyPair, ArbProtocolAddress, ArbThread,
+        ArbUuid,
+    },
+};
+use crate::store::{ContentsStore, StateStore, Store};
+
+/// Checks the [`StateStore`] contract.
+pub async fn state_contract<S, F, Fut>(new_store: &F)
+where
+    S: Store,
+    F: Fn() -> Fut,
+    Fut: Future<Output = S>,
+{
+    check(new_store, "identity key pairs", identity_key_pairs).await;
+    check(new_store, "registration data", registration_roundtrip).await;
+    check(
+        new_store,
+        "clear registration",
+        clear_registration_keeps_contents,
+    )
+    .await;
+    check(
+        new_store,
+        "clear registration keeps identities",
+        clear_registration_keeps_identities,
+    )
+    .await;
+}
+
+async fn identity_key_pairs<S: Store>(store: S, (aci, pni): (ArbKeyPair, ArbKeyPair)) {
+    let aci = IdentityKeyPair::from(aci.0);
+    let pni = IdentityKeyPair::from(pni.0);
+
+    store.set_aci_identity_key_pair(aci).await.unwrap();
+    assert!(store
+        .pni_protocol_store()
+        .get_identity_key_pair()
+        .await
+        .is_err());
+    store.set_pni_identity_key_pair(pni).await.unwrap();
+
+    let loaded_aci = store
+        .aci_protocol_store()
+        .get_identity_key_pair()
+        .await
+        .unwrap();
+    let loaded_pni = store
+        .pni_protocol_store()
+        .get_identity_key_pair()
+        .await
+        .unwrap();
+    assert_eq!(loaded_aci.serialize(), aci.serialize());
+    assert_eq!(loaded_pni.serialize(), pni.serialize());
+}
+
+async fn registration_roundtrip<S: Store>(
+    mut store: S,
+    (aci, pni, registration_id): (ArbUuid, ArbUuid, u32),
+) {
+    assert!(!store.is_registered().await);
+    assert!(store.load_registration_data().await.unwrap().is_none());
+
+    let data = registration_data(aci.0, pni.0, registration_id);
+    store.save_registration_data(&data).await.unwrap();
+    assert!(store.is_registered().await);
+
+    let loaded = store
+        .load_registration_data()
+        .await
+        .unwrap()
+        .expect("saved registration data");
+    assert_eq!(
+        serde_json::to_value(&loaded).unwrap(),
+        serde_json::to_value(&data).unwrap()
+    );
+}
+
+async fn clear_registration_keeps_contents<S: Store>(
+    mut store: S,
+    (contact, thread, content): (ArbContact, ArbThread, ArbContent),
+) {
+    let data = registration_data(contact.uuid, Uuid::new_v4(), 1);
+    store.save_registration_data(&data).await.unwrap();
+    store.save_contact(&contact.to_contact()).await.unwrap();
+    store
+        .save_message(&thread.0, content.0.clone())
+        .await
+        .unwrap();
+
+    store.clear_registration().await.unwrap();
+    assert!(!store.is_registered().await);
+    assert!(store.contact_by_id(&contact.uuid).await.unwrap().is_some());
+    assert!(store
+        .message(&thread.0, content.0.metadata.timestamp)
+        .await
+        .unwrap()
+        .is_some());
+}
+
+async fn clear_registration_keeps_identities<S: Store>(
+    mut store: S,
+    (addr, key_pair): (ArbProtocolAddress, ArbKeyPair),
+) {
+    let identity_key = IdentityKey::new(key_pair.0.public_key);
+    store
+        .aci_protocol_store()
+        .save_identity(&addr.0, &identity_key)
+        .await
+        .unwrap();
+    store
+        .pni_protocol_store()
+        .save_identity(&addr.0, &identity_key)
+        .await
+        .unwrap();
+
+    store.clear_registration().await.unwrap();
+    let aci_identity = store.aci_protocol_store().get_identity(&addr.0).await;
+    assert_eq!(aci_identity.unwrap(), Some(identity_key));
+    let pni_identity = store.pni_protocol_store().get_identity(&addr.0).await;
+    assert_eq!(pni_identity.unwrap(), Some(identity_key));
+}
@@ -0,0 +1,108 @@
+//! Summaries of conversation threads, to render an inbox
+//!
+//! Stores keep a [`ThreadActivity`] per thread, updated every time a message is saved, so that
+//! [`ContentsStore::threads`](super::ContentsStore::threads) does not have to scan all messages.
+
+use libsignal_service::{
+    content::{Content, ContentBody},
+    prelude::Uuid,
+    proto::DataMessage,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{ContentExt, Thread};
+
+/// Maximum number of characters kept in [`ThreadActivity::last_message_preview`]
+pub const PREVIEW_LENGTH: usize = 100;
+
+/// A thread, as listed by [`ContentsStore::threads`](super::ContentsStore::threads)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadSummary {
+    pub thread: Thread,
+    /// See [`ContentsStore::thread_title`](super::ContentsStore::thread_title)
+    pub title: String,
+    pub activity: ThreadActivity,
+}
+
+/// Recent activity of a thread, maintained incrementally when saving messages
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadActivity {
+    /// Timestamp of the most recent message
+    pub last_message_timestamp: u64,
+    /// Beginning of the text of the most recent message, if it has any
+    pub last_message_preview: Option<String>,
+    /// Number of messages received from others which were not read yet
+    pub unread_count: u32,
+    /// Timestamp up to which all messages were read, see
+    /// [`ContentsStore::mark_thread_read`](super::ContentsStore::mark_thread_read)
+    #[serde(default)]
+    pub last_read_timestamp: u64,
+}
+
+impl ThreadActivity {
+    /// Records a message saved in the thread
+    ///
+    /// `replaced` is whether the message overwrote a previously saved one (e.g. edits and
+    /// deletions), in which case it is not counted as unread again. Messages sent by `own_aci`
+    /// are never unread.
+    pub fn record(&mut self, message: &Content, replaced: bool, own_aci: Option<Uuid>) {
+        let timestamp = message.timestamp();
+        if timestamp >= self.last_message_timestamp {
+            self.last_message_timestamp = timestamp;
+            self.last_message_preview = preview(message);
+        }
+
+        if !replaced && timestamp > self.last_read_timestamp && is_unread(message, own_aci) {
+            self.unread_count = self.unread_count.saturating_add(1);
+        }
+    }
+
+    /// Moves the read marker forward to `timestamp`
+    ///
+    /// Unread messages are counted again among `later_messages`, which must contain at least all
+    /// messages of the thread saved after `timestamp`. The marker never moves backwards.
+    pub fn mark_read<'a>(
+        &mut self,
+        timestamp: u64,
+        later_messages: impl IntoIterator<Item = &'a Content>,
+        own_aci: Option<Uuid>,
+    ) {
+        if timestamp <= self.last_read_timestamp {
+            return;
+        }
+        self.last_read_timestamp = timestamp;
+        let unread_count = later_messages
+            .into_iter()
+            .filter(|message| message.timestamp() > timestamp && is_unread(message, own_aci))
+            .count();
+        self.unread_count = unread_count.try_into().unwrap_or(u32::MAX);
+    }
+
+    /// Sets the most recent message, e.g. after the previous one was deleted
+    pub fn set_last_message(&mut self, message: Option<&Content>) {
+        self.last_message_timestamp = message.map(ContentExt::timestamp).unwrap_or_default();
+        self.last_message_preview = message.and_then(preview);
+    }
+}
+
+fn preview(message: &Content) -> Option<String> {
+    message
+        .text()
+        .map(|text| text.chars().take(PREVIEW_LENGTH).collect())
+}
+
+/// Whether a message is unread until the user reads it
+///
+/// Only actual messages received from others are: not our own messages (sent by `own_aci`),
+/// reactions, deletions or calls.
+pub fn is_unread(message: &Content, own_aci: Option<Uuid>) -> bool {
+    own_aci != Some(message.metadata.sender.raw_uuid())
+        && matches!(
+            &message.body,
+            ContentBody::DataMessage(DataMessage {
+                reaction: None,
+                delete: None,
+                ..
+            })
+        )
+}